@@ -0,0 +1,240 @@
+//! # The `lin_kv` Benchmark
+//!
+//! Compares the key-value store nodes that serve the `lin-kv` workload: the Raft-based `lin_kv`,
+//! the chain-replicated `chain_kv` and the quorum-replicated `quorum_kv`, on 3 nodes, without a nemesis.
+//!
+//! The nodes run as child processes, on a local network that routes their messages without any latency,
+//! and that plays Maelstrom's `lin-kv` service. A single client sends them 2000 operations
+//! (50% reads, 30% writes, 20% compare-and-sets, over 5 keys), one at a time, at up to 100 per second,
+//! to random nodes. The messages per operation count all the messages that the nodes send to each other
+//! and to `lin-kv`, including heartbeats and polling, but not their responses to the client.
+//!
+//! Run as:
+//!
+//! ```
+//! cargo bench --bench lin_kv
+//! ```
+
+use gossip_glomers::rng::Rng;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const NODE_COUNT: usize = 3;
+const OPERATIONS: usize = 2000;
+const RATE: f64 = 100.0;
+const KEYS: u64 = 5;
+/// How long the nodes are left to elect leaders and settle their configurations before the first operation.
+const SETTLE_TIME: Duration = Duration::from_secs(3);
+/// How long the client waits for a response.
+const TIMEOUT: Duration = Duration::from_secs(2);
+const CLIENT: &str = "c1";
+const LIN_KV: &str = "lin-kv";
+
+/// The nodes, and the network between them.
+struct Network {
+    node_ids: Vec<String>,
+    children: Vec<Child>,
+    stdins: HashMap<String, ChildStdin>,
+    /// All the messages that the nodes send.
+    outbox: Receiver<Value>,
+    /// The `lin-kv` service's store, by the keys' JSON.
+    store: HashMap<String, Value>,
+    /// How many messages the nodes have sent, other than to the client.
+    messages: usize,
+    next_msg_id: u64,
+}
+
+impl Network {
+    fn start(binary: &str) -> Self {
+        let node_ids: Vec<String> = (0..NODE_COUNT).map(|i| format!("n{i}")).collect();
+        let (tx, outbox) = mpsc::channel();
+        let mut children = Vec::new();
+        let mut stdins = HashMap::new();
+        for node_id in &node_ids {
+            let mut child = Command::new(binary)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("expected the node to start");
+            stdins.insert(node_id.clone(), child.stdin.take().unwrap());
+            let stdout = BufReader::new(child.stdout.take().unwrap());
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for line in stdout.lines().map_while(Result::ok) {
+                    let message = serde_json::from_str(&line).expect("expected a JSON message");
+                    if tx.send(message).is_err() {
+                        break;
+                    }
+                }
+            });
+            children.push(child);
+        }
+
+        let mut network = Self {
+            node_ids: node_ids.clone(),
+            children,
+            stdins,
+            outbox,
+            store: HashMap::new(),
+            messages: 0,
+            next_msg_id: 1,
+        };
+        for node_id in &node_ids {
+            let body =
+                json!({"type": "init", "msg_id": 0, "node_id": node_id, "node_ids": node_ids});
+            network.send(json!({"src": "c0", "dest": node_id, "body": body}));
+        }
+        network
+    }
+
+    fn send(&mut self, message: Value) {
+        let stdin = self
+            .stdins
+            .get_mut(message["dest"].as_str().unwrap())
+            .expect("expected a node");
+        writeln!(stdin, "{message}").expect("expected the node to be running");
+    }
+
+    /// Routes the nodes' messages until `deadline`, or until the client gets the response to `msg_id`.
+    fn route(&mut self, deadline: Instant, msg_id: Option<u64>) -> Option<Value> {
+        loop {
+            let message = match self
+                .outbox
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => return None,
+                Err(RecvTimeoutError::Disconnected) => panic!("expected the nodes to be running"),
+            };
+
+            let dest = message["dest"].as_str().unwrap().to_string();
+            if dest == CLIENT {
+                if msg_id.is_some() && message["body"]["in_reply_to"].as_u64() == msg_id {
+                    return Some(message["body"].clone());
+                }
+                continue;
+            }
+
+            self.messages += 1;
+            if dest == LIN_KV {
+                let body = self.lin_kv(&message["body"]);
+                self.send(json!({"src": LIN_KV, "dest": message["src"], "body": body}));
+            } else if self.stdins.contains_key(&dest) {
+                self.send(message);
+            }
+        }
+    }
+
+    /// Serves a request to the `lin-kv` service.
+    fn lin_kv(&mut self, request: &Value) -> Value {
+        let key = request["key"].to_string();
+        let mut response = match request["type"].as_str().unwrap() {
+            "read" => match self.store.get(&key) {
+                Some(value) => json!({"type": "read_ok", "value": value}),
+                None => json!({"type": "error", "code": 20}),
+            },
+            "write" => {
+                self.store.insert(key, request["value"].clone());
+                json!({"type": "write_ok"})
+            }
+            "cas" => match self.store.get(&key) {
+                Some(value) if *value == request["from"] => {
+                    self.store.insert(key, request["to"].clone());
+                    json!({"type": "cas_ok"})
+                }
+                Some(_) => json!({"type": "error", "code": 22}),
+                None if request["create_if_not_exists"] == true => {
+                    self.store.insert(key, request["to"].clone());
+                    json!({"type": "cas_ok"})
+                }
+                None => json!({"type": "error", "code": 20}),
+            },
+            other => panic!("unexpected lin-kv request: {other}"),
+        };
+        response["in_reply_to"] = request["msg_id"].clone();
+        response
+    }
+
+    /// Sends a request from the client to the node `dest`, and waits for the response.
+    fn rpc(&mut self, dest: &str, mut body: Value) -> Option<Value> {
+        let msg_id = self.next_msg_id;
+        self.next_msg_id += 1;
+        body["msg_id"] = msg_id.into();
+        self.send(json!({"src": CLIENT, "dest": dest, "body": body}));
+        self.route(Instant::now() + TIMEOUT, Some(msg_id))
+    }
+
+    fn stop(mut self) {
+        // The nodes exit at the end of their input.
+        self.stdins.clear();
+        for child in &mut self.children {
+            child.wait().expect("expected the node to exit");
+        }
+    }
+}
+
+/// Runs the workload against the node, and prints a row of the table in the README.
+fn run(name: &str, binary: &str) {
+    let mut network = Network::start(binary);
+    network.route(Instant::now() + SETTLE_TIME, None);
+    network.messages = 0;
+
+    let mut rng = Rng::with_seed(1);
+    let mut latencies = Vec::with_capacity(OPERATIONS);
+    let mut failures = 0;
+    let start = Instant::now();
+    for i in 0..OPERATIONS {
+        network.route(start + Duration::from_secs_f64(i as f64 / RATE), None);
+
+        let key = rng.gen_range(0..KEYS);
+        let body = match rng.gen_range(0..10) {
+            0..5 => json!({"type": "read", "key": key}),
+            5..8 => json!({"type": "write", "key": key, "value": rng.gen_range(0..KEYS)}),
+            _ => {
+                let from = rng.gen_range(0..KEYS);
+                json!({"type": "cas", "key": key, "from": from, "to": rng.gen_range(0..KEYS)})
+            }
+        };
+        let dest = network.node_ids[rng.gen_range(0..NODE_COUNT as u64) as usize].clone();
+
+        let sent = Instant::now();
+        let response = network.rpc(&dest, body);
+        latencies.push(sent.elapsed());
+        // Keys that don't exist and compare-and-sets that don't match are expected.
+        let failed = response.is_none_or(|body| {
+            body["type"] == "error" && !matches!(body["code"].as_u64(), Some(20 | 22))
+        });
+        failures += usize::from(failed);
+    }
+    let messages = network.messages;
+    network.stop();
+
+    latencies.sort();
+    let percentile = |p: f64| {
+        let latency = latencies[((p * OPERATIONS as f64) as usize).min(OPERATIONS - 1)];
+        format!("{:.2} ms", latency.as_secs_f64() * 1000.0)
+    };
+    println!(
+        "| {:<11} | {:>14} | {:>11} | {:>22.2} |",
+        format!("`{name}`"),
+        percentile(0.5),
+        percentile(0.99),
+        messages as f64 / OPERATIONS as f64
+    );
+    if failures > 0 {
+        eprintln!("{name}: {failures} of {OPERATIONS} operations failed or timed out");
+    }
+}
+
+fn main() {
+    println!("| node        | median latency | p99 latency | messages per operation |");
+    println!("|-------------|---------------:|------------:|-----------------------:|");
+    run("lin_kv", env!("CARGO_BIN_EXE_lin_kv"));
+    run("chain_kv", env!("CARGO_BIN_EXE_chain_kv"));
+    run("quorum_kv", env!("CARGO_BIN_EXE_quorum_kv"));
+}
@@ -0,0 +1,56 @@
+//! # The `read_ok` Benchmark
+//!
+//! Compares two ways for a broadcast node to respond to a `read`, with 10k and 100k messages:
+//! cloning its set of messages into the response, like the node used to, and sharing the set with the response,
+//! like it does now. Both responses are serialized the same way, into a sink, so the difference is the clone.
+//!
+//! Run as:
+//!
+//! ```
+//! cargo bench --bench read_ok
+//! ```
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use gossip_glomers::message::{Body, BroadcastPayload, Message, Payload};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Writes a `read_ok` response, as a node does.
+fn respond(messages: Arc<HashSet<usize>>) {
+    let response = Message {
+        src: "n0".to_string(),
+        dest: "c1".to_string(),
+        body: Body {
+            msg_id: Some(1),
+            in_reply_to: Some(1),
+            clock: None,
+            payload: Payload::Broadcast(BroadcastPayload::ReadOk { messages }),
+        },
+    };
+    let mut sink = io::sink();
+    serde_json::to_writer(&mut sink, &response).expect("expected a serializable response");
+    sink.write_all(b"\n").expect("expected a writable sink");
+}
+
+fn read_ok(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read_ok");
+    for size in [10_000, 100_000] {
+        let messages: Arc<HashSet<usize>> = Arc::new((0..size).collect());
+
+        group.bench_with_input(
+            BenchmarkId::new("cloned", size),
+            &messages,
+            |b, messages| b.iter(|| respond(Arc::new(HashSet::clone(black_box(messages))))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("shared", size),
+            &messages,
+            |b, messages| b.iter(|| respond(Arc::clone(black_box(messages)))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, read_ok);
+criterion_main!(benches);
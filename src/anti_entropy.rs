@@ -0,0 +1,445 @@
+//! # Anti-Entropy
+//!
+//! Set reconciliation between two replicas of a set of integers, which lets a node discover and repair
+//! the values it missed, without shipping the whole set.
+//!
+//! Reconciliation with digests goes like this, between a node `A` and its peer `B`:
+//! 1. `A` sends `B` the digest of its whole set: the count and a hash of the values.
+//! 2. If that matches `B`'s digest, the sets are (almost certainly) equal, and we are done.
+//!    Otherwise, `B` splits its set into buckets and sends `A` a hash of each bucket.
+//! 3. `A` splits its set into the same buckets and sends `B` the values of the buckets whose hashes differ.
+//! 4. `B` adds those values, and sends back the values of those buckets that `A` doesn't have.
+//!
+//! The hashes of the values are computed with a fixed function, which is the same on every node.
+//!
+//! Alternatively, for large sets, reconciliation can use a Bloom filter, in a single round trip:
+//! 1. `A` sends `B` a Bloom filter of its set.
+//! 2. `B` sends back its values that aren't in the filter, which `A` is definitely missing.
+//!
+//! False positives make `B` hold back some of the values that `A` is missing, so every filter is built
+//! with a fresh salt, which makes different values false positives in the next round.
+//! Values that `B` is missing are found when `B` sends its own filter to `A`.
+//!
+//! The strategy is selected at startup, through the [`STRATEGY_VAR`] environment variable.
+
+use crate::rng::Rng;
+use anyhow::{bail, Result};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashSet;
+
+/// The environment variable that selects the anti-entropy strategy: `digest` (the default) or `bloom`.
+pub const STRATEGY_VAR: &str = "ANTI_ENTROPY";
+
+/// The environment variable that sets the false-positive rate of Bloom filters, e.g., `0.01`.
+pub const FP_RATE_VAR: &str = "BLOOM_FP_RATE";
+
+/// The default false-positive rate of Bloom filters.
+pub const DEFAULT_FP_RATE: f64 = 0.01;
+
+/// The available anti-entropy strategies.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Strategy {
+    /// Set digests, then bucket hashes, then values; see [`SetDigest`].
+    #[default]
+    Digest,
+    /// A Bloom filter, then values; see [`BloomFilter`].
+    Bloom {
+        /// The false-positive rate of the filters.
+        fp_rate: f64,
+    },
+}
+
+impl Strategy {
+    /// Reads the strategy from the [`STRATEGY_VAR`] and [`FP_RATE_VAR`] environment variables.
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(STRATEGY_VAR).as_deref() {
+            Err(_) | Ok("digest") => Ok(Self::Digest),
+            Ok("bloom") => {
+                let fp_rate = match std::env::var(FP_RATE_VAR) {
+                    Ok(fp_rate) => fp_rate.parse()?,
+                    Err(_) => DEFAULT_FP_RATE,
+                };
+                if !(fp_rate > 0.0 && fp_rate < 1.0) {
+                    bail!("the false-positive rate must be between 0 and 1, not {fp_rate}");
+                }
+                Ok(Self::Bloom { fp_rate })
+            }
+            Ok(other) => bail!("unknown anti-entropy strategy: {other}"),
+        }
+    }
+}
+
+/// The average number of values per bucket that [`bucket_count()`] aims for.
+pub const VALUES_PER_BUCKET: usize = 16;
+
+/// The maximum number of buckets.
+pub const MAX_BUCKETS: usize = 1 << 12;
+
+/// The maximum number of hash functions of a Bloom filter; that's enough for false-positive rates
+/// far below any that's useful.
+pub const MAX_HASHES: u32 = 32;
+
+/// A well-mixed 64-bit hash of a value (SplitMix64's finalizer), which is the same on every node.
+pub fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// A compact digest of a whole set: its size and an order-independent hash of its values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SetDigest {
+    pub count: usize,
+    pub hash: u64,
+}
+
+impl SetDigest {
+    pub fn of(values: &HashSet<usize>) -> Self {
+        Self {
+            count: values.len(),
+            hash: values
+                .iter()
+                .fold(0, |hash, &value| hash.wrapping_add(mix(value as u64))),
+        }
+    }
+}
+
+/// The number of buckets to split sets of about `len` values into: a power of two.
+pub fn bucket_count(len: usize) -> usize {
+    (len / VALUES_PER_BUCKET)
+        .next_power_of_two()
+        .min(MAX_BUCKETS)
+}
+
+/// The bucket that `value` belongs to, out of `bucket_count` buckets.
+pub fn bucket_of(value: usize, bucket_count: usize) -> usize {
+    // The hash of the value within its bucket uses all the bits, so the bucket takes different ones.
+    (mix(value as u64).rotate_left(32) % bucket_count as u64) as usize
+}
+
+/// The hash of each of the `bucket_count` buckets of the set.
+pub fn bucket_hashes(values: &HashSet<usize>, bucket_count: usize) -> Vec<u64> {
+    let mut hashes = vec![0u64; bucket_count];
+    for &value in values {
+        let bucket = bucket_of(value, bucket_count);
+        hashes[bucket] = hashes[bucket].wrapping_add(mix(value as u64));
+    }
+    hashes
+}
+
+/// The indices of the buckets whose hashes differ.
+pub fn differing_buckets(ours: &[u64], theirs: &[u64]) -> Vec<usize> {
+    ours.iter()
+        .zip(theirs)
+        .enumerate()
+        .filter(|(_, (ours, theirs))| ours != theirs)
+        .map(|(bucket, _)| bucket)
+        .collect()
+}
+
+/// The values of the set that belong to the given buckets, out of `bucket_count` buckets.
+pub fn values_in_buckets(
+    values: &HashSet<usize>,
+    bucket_count: usize,
+    buckets: &[usize],
+) -> HashSet<usize> {
+    let buckets: HashSet<usize> = buckets.iter().copied().collect();
+    values
+        .iter()
+        .filter(|&&value| buckets.contains(&bucket_of(value, bucket_count)))
+        .copied()
+        .collect()
+}
+
+/// # A Bloom Filter
+///
+/// A compact, probabilistic representation of a set: it never claims that a value which was inserted is absent,
+/// but it may claim that a value which wasn't inserted is present, with the false-positive rate it was sized for.
+///
+/// The bits are serialized as a hexadecimal string, which is more compact than a list of numbers.
+/// A filter from another node is checked as it's deserialized, so that a malformed one can't make us panic.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedBloomFilter")]
+pub struct BloomFilter {
+    /// The number of bits.
+    num_bits: usize,
+    /// The number of hash functions.
+    num_hashes: u32,
+    /// Mixed into the hashes, so that different filters have different false positives.
+    salt: u64,
+    #[serde(serialize_with = "to_hex")]
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for `capacity` values with the false-positive rate `fp_rate`,
+    /// which must be between `0` and `1`.
+    pub fn new(capacity: usize, fp_rate: f64, salt: u64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-capacity * fp_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let num_hashes = ((num_bits as f64 / capacity) * ln2)
+            .round()
+            .clamp(1.0, MAX_HASHES as f64) as u32;
+
+        Self {
+            num_bits,
+            num_hashes,
+            salt,
+            bits: vec![0; num_bits.div_ceil(64)],
+        }
+    }
+
+    /// Creates a filter of all the values, with a random salt.
+    pub fn of(values: &HashSet<usize>, fp_rate: f64, rng: &mut Rng) -> Self {
+        let mut filter = Self::new(values.len(), fp_rate, rng.next_u64());
+        for &value in values {
+            filter.insert(value);
+        }
+        filter
+    }
+
+    /// The bit positions of the value, by double hashing.
+    fn positions(&self, value: usize) -> impl Iterator<Item = usize> + '_ {
+        let h1 = mix(value as u64 ^ self.salt);
+        let h2 = mix(h1 ^ self.salt.rotate_left(17)) | 1;
+        (0..self.num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits as u64) as usize)
+    }
+
+    pub fn insert(&mut self, value: usize) {
+        let positions: Vec<usize> = self.positions(value).collect();
+        for position in positions {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+    }
+
+    /// Whether the value may be in the set; if not, it definitely isn't.
+    pub fn contains(&self, value: usize) -> bool {
+        self.positions(value)
+            .all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+
+    /// The values that are definitely not in the filter.
+    pub fn missing<'a>(&'a self, values: &'a HashSet<usize>) -> impl Iterator<Item = usize> + 'a {
+        values
+            .iter()
+            .copied()
+            .filter(|&value| !self.contains(value))
+    }
+}
+
+/// A [`BloomFilter`] as it comes over the wire, before it's checked.
+#[derive(Deserialize)]
+struct UncheckedBloomFilter {
+    num_bits: usize,
+    num_hashes: u32,
+    salt: u64,
+    #[serde(deserialize_with = "from_hex")]
+    bits: Vec<u64>,
+}
+
+impl TryFrom<UncheckedBloomFilter> for BloomFilter {
+    type Error = String;
+
+    fn try_from(filter: UncheckedBloomFilter) -> Result<Self, Self::Error> {
+        let UncheckedBloomFilter {
+            num_bits,
+            num_hashes,
+            salt,
+            bits,
+        } = filter;
+        if num_bits == 0 {
+            return Err("a Bloom filter must have bits".to_string());
+        }
+        if bits.len() != num_bits.div_ceil(64) {
+            return Err(format!(
+                "a Bloom filter of {num_bits} bits must have {} words of them, not {}",
+                num_bits.div_ceil(64),
+                bits.len()
+            ));
+        }
+        if !(1..=MAX_HASHES).contains(&num_hashes) {
+            return Err(format!(
+                "a Bloom filter must have between 1 and {MAX_HASHES} hash functions, not {num_hashes}"
+            ));
+        }
+
+        Ok(Self {
+            num_bits,
+            num_hashes,
+            salt,
+            bits,
+        })
+    }
+}
+
+fn to_hex<S: Serializer>(words: &[u64], serializer: S) -> Result<S::Ok, S::Error> {
+    let hex: String = words.iter().map(|word| format!("{word:016x}")).collect();
+    serializer.serialize_str(&hex)
+}
+
+fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u64>, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    if hex.len() % 16 != 0 || !hex.is_ascii() {
+        return Err(de::Error::custom("malformed Bloom filter bits"));
+    }
+    (0..hex.len())
+        .step_by(16)
+        .map(|i| u64::from_str_radix(&hex[i..i + 16], 16).map_err(de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn values(range: std::ops::Range<usize>) -> HashSet<usize> {
+        range.collect()
+    }
+
+    /// Syncs `b` with `a` the way the broadcast node does, and returns the number of messages sent.
+    fn sync(a: &mut HashSet<usize>, b: &mut HashSet<usize>) -> usize {
+        // `a` sends `b` its digest.
+        let SetDigest { count, hash } = SetDigest::of(a);
+        if (SetDigest { count, hash }) == SetDigest::of(b) {
+            return 1;
+        }
+
+        // `b` sends `a` the hashes of its buckets.
+        let bucket_count = bucket_count(count.max(b.len()));
+        let buckets = bucket_hashes(b, bucket_count);
+
+        // `a` sends `b` its values in the buckets that differ.
+        let ours = bucket_hashes(a, buckets.len());
+        let differing = differing_buckets(&ours, &buckets);
+        let values = values_in_buckets(a, bucket_count, &differing);
+
+        // `b` sends `a` the values that it has and `a` doesn't, if any.
+        let missing: HashSet<usize> = values_in_buckets(b, bucket_count, &differing)
+            .difference(&values)
+            .copied()
+            .collect();
+        b.extend(values);
+        if missing.is_empty() {
+            return 3;
+        }
+        a.extend(missing);
+        4
+    }
+
+    #[test]
+    fn divergent_sets_reconcile_to_their_union() {
+        let mut a = values(0..1000);
+        let mut b = values(500..1500);
+        b.extend([5000, 6000]);
+        let union: HashSet<usize> = a.union(&b).copied().collect();
+
+        assert_eq!(sync(&mut a, &mut b), 4);
+        assert_eq!(a, union);
+        assert_eq!(b, union);
+        assert_eq!(sync(&mut a, &mut b), 1);
+    }
+
+    #[test]
+    fn a_subset_reconciles_without_a_reply() {
+        let mut a = values(0..100);
+        let mut b = values(0..90);
+
+        assert_eq!(sync(&mut a, &mut b), 3);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn identical_sets_only_exchange_their_digest() {
+        for len in [0, 1, 1000] {
+            let mut a = values(0..len);
+            let mut b: HashSet<usize> = (0..len).rev().collect();
+            assert_eq!(SetDigest::of(&a), SetDigest::of(&b));
+            assert_eq!(sync(&mut a, &mut b), 1);
+        }
+    }
+
+    #[test]
+    fn only_the_buckets_with_differences_are_sent() {
+        let a = values(0..10_000);
+        let mut b = a.clone();
+        b.remove(&1234);
+        let bucket_count = bucket_count(a.len());
+        let differing = differing_buckets(
+            &bucket_hashes(&a, bucket_count),
+            &bucket_hashes(&b, bucket_count),
+        );
+        assert_eq!(differing, [bucket_of(1234, bucket_count)]);
+        let values = values_in_buckets(&a, bucket_count, &differing);
+        assert!(values.contains(&1234));
+        assert!(values.len() < a.len() / 100);
+    }
+
+    #[test]
+    fn a_bloom_filter_has_no_false_negatives() {
+        let mut rng = Rng::with_seed(1);
+        for len in [0, 1, 10, 1000] {
+            let values = values(0..len);
+            let filter = BloomFilter::of(&values, 0.01, &mut rng);
+            assert!(values.iter().all(|&value| filter.contains(value)));
+            assert_eq!(filter.missing(&values).count(), 0);
+        }
+    }
+
+    #[test]
+    fn a_bloom_filter_has_about_its_false_positive_rate() {
+        let mut rng = Rng::with_seed(2);
+        for fp_rate in [0.1, 0.01] {
+            let filter = BloomFilter::of(&values(0..10_000), fp_rate, &mut rng);
+            let probes = 100_000;
+            let false_positives = (10_000..10_000 + probes)
+                .filter(|&value| filter.contains(value))
+                .count();
+            let rate = false_positives as f64 / probes as f64;
+            assert!(
+                rate > fp_rate / 2.0 && rate < fp_rate * 2.0,
+                "expected a false-positive rate of about {fp_rate}, got {rate}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_bloom_filter_round_trips_through_hex() {
+        let filter = BloomFilter::of(&values(0..100), 0.01, &mut Rng::with_seed(3));
+        let json = serde_json::to_value(&filter).unwrap();
+        let bits = json["bits"]
+            .as_str()
+            .expect("expected the bits as a string");
+        assert_eq!(bits.len(), filter.bits.len() * 16);
+        assert!(bits.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(serde_json::from_value::<BloomFilter>(json).unwrap(), filter);
+    }
+
+    #[test]
+    fn malformed_bloom_filters_are_rejected() {
+        let filter = |num_bits: usize, num_hashes: u32, bits: &str| {
+            serde_json::from_value::<BloomFilter>(json!({
+                "num_bits": num_bits,
+                "num_hashes": num_hashes,
+                "salt": 0,
+                "bits": bits,
+            }))
+        };
+        let word = "0".repeat(16);
+        assert!(filter(100, 3, &word.repeat(2)).is_ok());
+
+        assert!(filter(0, 3, "").is_err(), "no bits");
+        assert!(filter(100, 3, &word).is_err(), "too few words");
+        assert!(filter(100, 3, &word.repeat(3)).is_err(), "too many words");
+        assert!(filter(100, 0, &word.repeat(2)).is_err(), "no hashes");
+        assert!(
+            filter(100, MAX_HASHES + 1, &word.repeat(2)).is_err(),
+            "too many hashes"
+        );
+        assert!(filter(100, 3, &"0".repeat(31)).is_err(), "a partial word");
+        assert!(filter(100, 3, &"g".repeat(32)).is_err(), "not hex");
+    }
+}
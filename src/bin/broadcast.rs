@@ -16,6 +16,49 @@
 //!
 //! [Workload: Broadcast](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-broadcast)
 //!
+//! Values spread by delta-state gossip: in every gossip round, a node sends each neighbor only the values
+//! that the neighbor hasn't acknowledged yet (and that aren't already on their way to it).
+//! The neighbor acknowledges every gossip message in its response, and values that it sends to us itself
+//! are known to it, too. So, once the cluster has converged, gossip rounds are silent.
+//! Unacknowledged values are sent again after a few rounds, which also carries them over network partitions.
+//!
+//! Alternatively, values can spread along a broadcast tree, with the Plumtree protocol: they are pushed
+//! to the neighbors in the tree at once, and only announced to the other neighbors, which ask for them
+//! if they don't arrive over the tree in time. See [`gossip_glomers::plumtree`].
+//! The broadcast strategy is selected with the `BROADCAST_STRATEGY` environment variable:
+//! `delta` (the default) or `plumtree`.
+//!
+//! The neighbors are either the ones from the `topology` message, or, with the HyParView membership protocol,
+//! a few random peers, which are replaced when they fail or get partitioned away.
+//! See [`gossip_glomers::hyparview`]. The membership is selected with the `MEMBERSHIP` environment variable:
+//! `topology` (the default) or `hyparview`.
+//!
+//! Optionally, a failure detector tells which neighbors are probably down or partitioned away,
+//! and they are skipped by gossip and anti-entropy until they are heard from again. Then, they are caught up
+//! at once: the gossip that was in flight to them is sent again, and the node reconciles its set with theirs.
+//! See [`gossip_glomers::failure_detector`]. It's selected with the `FAILURE_DETECTOR` environment variable:
+//! `phi-accrual` or `timeout`; there is none by default.
+//!
+//! Values are delivered, i.e., added to the set that `read` returns, as soon as they arrive, in no particular order.
+//! Alternatively, with the delta-state gossip strategy, they can be delivered in causal order: a value that
+//! a node broadcast after it had delivered another is delivered after that one everywhere.
+//! Gossip then carries the causal dependencies of every value, and a value that arrives before its dependencies
+//! waits for them. Anti-entropy is skipped, because it doesn't carry them, and the acknowledged gossip
+//! carries values over network partitions on its own. See [`gossip_glomers::causal`].
+//! The delivery order is selected with the `DELIVERY` environment variable: `unordered` (the default) or `causal`.
+//!
+//! Optionally, the node keeps logical clocks, which its messages to other nodes carry;
+//! they are selected with the `CLOCKS` environment variable. See [`gossip_glomers::clock`].
+//!
+//! As a last line of defense, every so often, every node also reconciles its set with a random neighbor's,
+//! by exchanging digests of the sets and then only the values of the parts that differ.
+//! For large sets, a Bloom filter of the set can be sent instead, and the neighbor responds with
+//! the values that aren't in it. See [`gossip_glomers::anti_entropy`].
+//!
+//! The anti-entropy strategy is selected with the `ANTI_ENTROPY` environment variable:
+//! `digest` (the default) or `bloom`. The false-positive rate of the Bloom filters is set with
+//! the `BLOOM_FP_RATE` environment variable (`0.01` by default).
+//!
 //! Run as:
 //!
 //! ```
@@ -25,12 +68,52 @@
 //! ```
 
 use anyhow::{bail, Result};
+use gossip_glomers::anti_entropy::{self, BloomFilter, SetDigest, Strategy};
+use gossip_glomers::causal::CausalBroadcast;
+use gossip_glomers::clock::Clocks;
+use gossip_glomers::failure_detector::{self, Detection, FailureDetector, Status};
+use gossip_glomers::hyparview::{self, HyParView};
 use gossip_glomers::logic::main_loop;
 use gossip_glomers::message::{BroadcastPayload, Message, Payload};
 use gossip_glomers::node::Node;
+use gossip_glomers::plumtree::{self, Plumtree};
+use gossip_glomers::rng::Rng;
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::io::StdoutLock;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The environment variable that selects the broadcast strategy: `delta` (the default) or `plumtree`.
+const BROADCAST_STRATEGY_VAR: &str = "BROADCAST_STRATEGY";
+
+/// The environment variable that selects the delivery order: `unordered` (the default) or `causal`.
+const DELIVERY_VAR: &str = "DELIVERY";
+
+/// The environment variable that selects the membership: `topology` (the default) or `hyparview`.
+const MEMBERSHIP_VAR: &str = "MEMBERSHIP";
+
+/// How often a node gossips to its neighbors.
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(150);
+
+/// After how many gossip rounds values that haven't been acknowledged are sent again.
+const ACK_TIMEOUT_ROUNDS: usize = 4;
+
+/// Every how many gossip rounds a node reconciles its set with a random neighbor's.
+const ANTI_ENTROPY_EVERY: usize = 10;
+
+/// A gossip message that hasn't been acknowledged yet.
+#[derive(Clone, Debug)]
+struct InFlight {
+    /// The neighbor it was sent to.
+    neighbor: String,
+    /// The values it carried.
+    messages: HashSet<usize>,
+    /// The gossip round in which it was sent.
+    round: usize,
+}
 
 /// # The Broadcast Node (Server)
 ///
@@ -45,16 +128,240 @@ struct BroadcastNode {
     /// Network topology sent to us by Maelstrom - a map of node IDs to list of their neighbor node IDs
     pub topology: HashMap<String, Vec<String>>,
     /// Broadcast messages
-    pub messages: HashSet<usize>,
+    ///
+    /// They are shared with the `read_ok` responses, which are serialized straight from them, without a copy.
+    /// A response is written out before the next message is handled, so by the time the set is modified,
+    /// it isn't shared anymore, and [`Arc::make_mut()`] doesn't have to clone it.
+    pub messages: Arc<HashSet<usize>>,
+    /// For picking the neighbors to reconcile with.
+    pub rng: Rng,
+    /// The anti-entropy strategy, selected at startup.
+    pub strategy: Strategy,
+    /// The Plumtree module, if that's the broadcast strategy; otherwise, values spread by delta-state gossip.
+    pub plumtree: Option<Plumtree>,
+    /// The HyParView module, if that's the membership; otherwise, the neighbors come from the topology.
+    pub hyparview: Option<HyParView>,
+    /// The failure detector that monitors our neighbors, if any.
+    pub failure_detector: Option<FailureDetector>,
+    /// The status changes of our neighbors that the failure detector has reported, but we haven't acted on yet.
+    status_changes: Rc<RefCell<Vec<(String, Status)>>>,
+    /// Our logical clocks, if any.
+    pub clocks: Option<Clocks>,
+    /// The causal delivery layer, if that's the delivery order; otherwise, values are delivered as they arrive.
+    pub causal: Option<CausalBroadcast>,
+    /// For each neighbor, the values that it is known to have.
+    pub known: HashMap<String, HashSet<usize>>,
+    /// Gossip messages that haven't been acknowledged yet, by their `msg_id`s.
+    in_flight: HashMap<usize, InFlight>,
+    /// The number of gossip rounds so far.
+    pub rounds: usize,
+}
+
+impl BroadcastNode {
+    /// The broadcast messages, for adding to them.
+    fn messages_mut(&mut self) -> &mut HashSet<usize> {
+        Arc::make_mut(&mut self.messages)
+    }
+
+    /// Our neighbors: the active view of the HyParView module, or our neighbors in the topology.
+    fn neighbors(&self) -> Vec<String> {
+        if let Some(hyparview) = &self.hyparview {
+            return hyparview.active_view().to_vec();
+        }
+        let node_id = self.node_id.clone().expect("expected some self.node_id");
+        self.topology.get(&node_id).cloned().unwrap_or_default()
+    }
+
+    /// Adds values that came by anti-entropy; with Plumtree, the new ones are spread over the broadcast tree.
+    fn deliver(&mut self, values: HashSet<usize>, output_lock: &mut StdoutLock) -> Result<()> {
+        let new: HashSet<usize> = values.difference(&self.messages).copied().collect();
+        if new.is_empty() {
+            return Ok(());
+        }
+
+        self.messages_mut().extend(new.iter().copied());
+        if let Some(plumtree) = &mut self.plumtree {
+            let outbox = plumtree.broadcast(new, None);
+            self.send(outbox, output_lock)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the messages of the HyParView module, and lets the Plumtree module know about the new neighbors.
+    fn send_membership(
+        &mut self,
+        outbox: hyparview::Outbox,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        for (dest, payload) in outbox {
+            self.request(dest, Payload::HyParView(payload), output_lock, "hyparview")?;
+        }
+        self.update_peers();
+
+        Ok(())
+    }
+
+    /// Our neighbors that the failure detector, if any, doesn't suspect.
+    fn live_neighbors(&self) -> Vec<String> {
+        let mut neighbors = self.neighbors();
+        if let Some(failure_detector) = &self.failure_detector {
+            neighbors.retain(|neighbor| !failure_detector.is_suspected(neighbor));
+        }
+        neighbors
+    }
+
+    /// Lets the Plumtree module and the failure detector, if any, know about the current neighbors.
+    fn update_peers(&mut self) {
+        let neighbors = self.neighbors();
+        if let Some(failure_detector) = &mut self.failure_detector {
+            failure_detector.set_peers(neighbors.clone());
+        }
+        if let Some(plumtree) = &mut self.plumtree {
+            plumtree.set_peers(neighbors);
+        }
+    }
+
+    /// Sends the heartbeats of the failure detector.
+    fn send_heartbeats(
+        &mut self,
+        outbox: failure_detector::Outbox,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        for (dest, payload) in outbox {
+            self.request(dest, Payload::Heartbeat(payload), output_lock, "heartbeat")?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the messages of the Plumtree module.
+    fn send(&mut self, outbox: plumtree::Outbox, output_lock: &mut StdoutLock) -> Result<()> {
+        for (dest, payload) in outbox {
+            self.request(dest, Payload::Plumtree(payload), output_lock, "plumtree")?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends every neighbor the values that it doesn't have, as far as we know.
+    fn gossip(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        // Unacknowledged values are sent again only after a while; they may still be on their way.
+        let round = self.rounds;
+        self.in_flight
+            .retain(|_, in_flight| round - in_flight.round < ACK_TIMEOUT_ROUNDS);
+
+        for neighbor in self.live_neighbors() {
+            let known = self.known.entry(neighbor.clone()).or_default();
+            let mut delta: HashSet<usize> = self.messages.difference(known).copied().collect();
+            for in_flight in self.in_flight.values() {
+                if in_flight.neighbor == neighbor {
+                    delta.retain(|message| !in_flight.messages.contains(message));
+                }
+            }
+            if delta.is_empty() {
+                continue;
+            }
+
+            self.in_flight.insert(
+                self.msg_id,
+                InFlight {
+                    neighbor: neighbor.clone(),
+                    messages: delta.clone(),
+                    round,
+                },
+            );
+            let payload = match &self.causal {
+                Some(causal) => Payload::Broadcast(BroadcastPayload::CausalGossip {
+                    messages: delta
+                        .iter()
+                        .filter_map(|&value| causal.message(value).cloned())
+                        .collect(),
+                }),
+                None => Payload::Broadcast(BroadcastPayload::Gossip { messages: delta }),
+            };
+            self.request(neighbor, payload, output_lock, "gossip")?;
+        }
+
+        Ok(())
+    }
+
+    /// Catches up the neighbors that the failure detector trusts again: forgets the gossip in flight to them,
+    /// so that the next round sends it again, and starts reconciling our set with theirs.
+    fn on_status_changes(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        let status_changes = std::mem::take(&mut *self.status_changes.borrow_mut());
+        for (neighbor, status) in status_changes {
+            if status == Status::Alive {
+                self.in_flight
+                    .retain(|_, in_flight| in_flight.neighbor != neighbor);
+                if self.causal.is_none() {
+                    self.sync_with(neighbor, output_lock)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts reconciling our set with a random neighbor's.
+    fn sync(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        let neighbors = self.live_neighbors();
+        if neighbors.is_empty() {
+            return Ok(());
+        }
+
+        let neighbor = neighbors[self.rng.gen_range(0..neighbors.len() as u64) as usize].clone();
+        self.sync_with(neighbor, output_lock)
+    }
+
+    /// Starts reconciling our set with the neighbor's.
+    fn sync_with(&mut self, neighbor: String, output_lock: &mut StdoutLock) -> Result<()> {
+        match self.strategy {
+            Strategy::Digest => {
+                let SetDigest { count, hash } = SetDigest::of(&self.messages);
+                let payload = Payload::Broadcast(BroadcastPayload::Sync { count, hash });
+                self.request(neighbor, payload, output_lock, "sync")
+            }
+            Strategy::Bloom { fp_rate } => {
+                let filter = BloomFilter::of(&self.messages, fp_rate, &mut self.rng);
+                let payload = Payload::Broadcast(BroadcastPayload::SyncBloom { filter });
+                self.request(neighbor, payload, output_lock, "sync_bloom")
+            }
+        }
+    }
 }
 
 impl Node for BroadcastNode {
+    type Payload = Payload;
+
     fn new() -> Self {
+        let status_changes = Rc::new(RefCell::new(Vec::new()));
+        let mut failure_detector = Detection::from_env()
+            .expect("expected a valid failure detector")
+            .map(FailureDetector::new);
+        if let Some(failure_detector) = &mut failure_detector {
+            let status_changes = Rc::clone(&status_changes);
+            failure_detector.on_status_change(move |peer, status| {
+                status_changes.borrow_mut().push((peer.to_string(), status));
+            });
+        }
+
         Self {
             node_id: None,
             msg_id: 0,
             topology: HashMap::new(),
-            messages: HashSet::new(),
+            messages: Arc::new(HashSet::new()),
+            rng: Rng::new(),
+            strategy: Strategy::from_env().expect("expected a valid anti-entropy strategy"),
+            plumtree: plumtree_from_env().expect("expected a valid broadcast strategy"),
+            hyparview: None,
+            failure_detector,
+            status_changes,
+            clocks: None,
+            causal: None,
+            known: HashMap::new(),
+            in_flight: HashMap::new(),
+            rounds: 0,
         }
     }
 
@@ -74,11 +381,47 @@ impl Node for BroadcastNode {
         self.node_id = value;
     }
 
-    fn step(&mut self, request: Message<Payload>, output_lock: &mut StdoutLock) -> Result<()> {
+    fn set_node_ids(&mut self, node_ids: Vec<String>) {
+        let node_id = self.node_id.clone().expect("expected some self.node_id");
+        self.clocks =
+            Clocks::from_env(node_id.clone(), node_ids.clone()).expect("expected valid clocks");
+        self.causal = causal_from_env(node_id.clone()).expect("expected a valid delivery order");
+        assert!(
+            self.causal.is_none() || self.plumtree.is_none(),
+            "causal delivery needs the delta broadcast strategy"
+        );
+        self.hyparview =
+            hyparview_from_env(node_id, node_ids).expect("expected a valid membership");
+    }
+
+    fn clocks(&mut self) -> Option<&mut Clocks> {
+        self.clocks.as_mut()
+    }
+
+    fn step(
+        &mut self,
+        request: Message<Self::Payload>,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        if let Some(hyparview) = &mut self.hyparview {
+            hyparview.heard_from(&request.src);
+        }
+        if let Some(failure_detector) = &mut self.failure_detector {
+            failure_detector.heard_from(&request.src);
+        }
+        self.on_status_changes(output_lock)?;
+
         match request.body.payload {
             Payload::Broadcast(broadcast_paylod) => match broadcast_paylod {
                 BroadcastPayload::Broadcast { message } => {
-                    self.messages.insert(message);
+                    let new = match &mut self.causal {
+                        Some(causal) => causal.broadcast(message).is_some(),
+                        None => true,
+                    } && self.messages_mut().insert(message);
+                    if let (true, Some(plumtree)) = (new, &mut self.plumtree) {
+                        let outbox = plumtree.broadcast(HashSet::from([message]), None);
+                        self.send(outbox, output_lock)?;
+                    }
 
                     let payload = Payload::Broadcast(BroadcastPayload::BroadcastOk);
                     self.respond(
@@ -88,19 +431,10 @@ impl Node for BroadcastNode {
                         output_lock,
                         "broadcast_ok",
                     )?;
-
-                    let node_id = self.node_id.clone().expect("expected some self.node_id");
-                    if let Some(neighbors) = self.topology.get(&node_id) {
-                        for neighbor in neighbors.clone() {
-                            let payload =
-                                Payload::Broadcast(BroadcastPayload::Broadcast { message });
-                            self.request(neighbor, payload, output_lock, "broadcast")?;
-                        }
-                    }
                 }
                 BroadcastPayload::Read => {
                     let payload = Payload::Broadcast(BroadcastPayload::ReadOk {
-                        messages: self.messages.clone(),
+                        messages: Arc::clone(&self.messages),
                     });
                     self.respond(
                         request.src,
@@ -112,6 +446,7 @@ impl Node for BroadcastNode {
                 }
                 BroadcastPayload::Topology { topology } => {
                     self.topology = topology;
+                    self.update_peers();
 
                     let payload = Payload::Broadcast(BroadcastPayload::TopologyOk);
                     self.respond(
@@ -122,15 +457,195 @@ impl Node for BroadcastNode {
                         "topology_ok",
                     )?;
                 }
+                BroadcastPayload::Gossip { messages } => {
+                    self.messages_mut().extend(messages.iter().copied());
+                    self.known
+                        .entry(request.src.clone())
+                        .or_default()
+                        .extend(messages);
+
+                    let payload = Payload::Broadcast(BroadcastPayload::GossipOk);
+                    self.respond(
+                        request.src,
+                        request.body.msg_id,
+                        payload,
+                        output_lock,
+                        "gossip_ok",
+                    )?;
+                }
+                BroadcastPayload::CausalGossip { messages } => {
+                    let known = self.known.entry(request.src.clone()).or_default();
+                    known.extend(messages.iter().map(|message| message.value));
+                    if let Some(causal) = &mut self.causal {
+                        let delivered: Vec<usize> = messages
+                            .into_iter()
+                            .flat_map(|message| causal.receive(message))
+                            .collect();
+                        Arc::make_mut(&mut self.messages).extend(delivered);
+                    }
+
+                    let payload = Payload::Broadcast(BroadcastPayload::GossipOk);
+                    self.respond(
+                        request.src,
+                        request.body.msg_id,
+                        payload,
+                        output_lock,
+                        "gossip_ok",
+                    )?;
+                }
+                BroadcastPayload::GossipOk => {
+                    let in_flight = request
+                        .body
+                        .in_reply_to
+                        .and_then(|in_reply_to| self.in_flight.remove(&in_reply_to));
+                    if let Some(InFlight {
+                        neighbor, messages, ..
+                    }) = in_flight
+                    {
+                        self.known.entry(neighbor).or_default().extend(messages);
+                    }
+                }
+                BroadcastPayload::Sync { count, hash } => {
+                    if (SetDigest { count, hash }) != SetDigest::of(&self.messages) {
+                        let bucket_count =
+                            anti_entropy::bucket_count(count.max(self.messages.len()));
+                        let payload = Payload::Broadcast(BroadcastPayload::SyncBuckets {
+                            buckets: anti_entropy::bucket_hashes(&self.messages, bucket_count),
+                        });
+                        self.request(request.src, payload, output_lock, "sync_buckets")?;
+                    }
+                }
+                BroadcastPayload::SyncBuckets { buckets } => {
+                    let bucket_count = buckets.len();
+                    let ours = anti_entropy::bucket_hashes(&self.messages, bucket_count);
+                    let differing = anti_entropy::differing_buckets(&ours, &buckets);
+                    let payload = Payload::Broadcast(BroadcastPayload::SyncValues {
+                        bucket_count,
+                        messages: anti_entropy::values_in_buckets(
+                            &self.messages,
+                            bucket_count,
+                            &differing,
+                        ),
+                        buckets: differing,
+                    });
+                    self.request(request.src, payload, output_lock, "sync_values")?;
+                }
+                BroadcastPayload::SyncValues {
+                    bucket_count,
+                    buckets,
+                    messages,
+                } => {
+                    let missing: HashSet<usize> =
+                        anti_entropy::values_in_buckets(&self.messages, bucket_count, &buckets)
+                            .difference(&messages)
+                            .copied()
+                            .collect();
+                    self.deliver(messages, output_lock)?;
+
+                    if !missing.is_empty() {
+                        let payload = Payload::Broadcast(BroadcastPayload::SyncValuesOk {
+                            messages: missing,
+                        });
+                        self.request(request.src, payload, output_lock, "sync_values_ok")?;
+                    }
+                }
+                BroadcastPayload::SyncBloom { filter } => {
+                    let missing: HashSet<usize> = filter.missing(&self.messages).collect();
+                    if !missing.is_empty() {
+                        let payload = Payload::Broadcast(BroadcastPayload::SyncValuesOk {
+                            messages: missing,
+                        });
+                        self.request(request.src, payload, output_lock, "sync_values_ok")?;
+                    }
+                }
+                BroadcastPayload::SyncValuesOk { messages } => {
+                    self.deliver(messages, output_lock)?;
+                }
                 BroadcastPayload::BroadcastOk
                 | BroadcastPayload::ReadOk { .. }
                 | BroadcastPayload::TopologyOk => {}
             },
+            Payload::Heartbeat(heartbeat_payload) => {
+                if let Some(failure_detector) = &mut self.failure_detector {
+                    failure_detector.handle(request.src, heartbeat_payload);
+                }
+            }
+            Payload::HyParView(hyparview_payload) => {
+                if let Some(hyparview) = &mut self.hyparview {
+                    let outbox = hyparview.handle(request.src, hyparview_payload);
+                    self.send_membership(outbox, output_lock)?;
+                }
+            }
+            Payload::Plumtree(plumtree_payload) => {
+                if let Some(plumtree) = &mut self.plumtree {
+                    let delivered = Arc::make_mut(&mut self.messages);
+                    let outbox = plumtree.handle(request.src, plumtree_payload, delivered);
+                    self.send(outbox, output_lock)?;
+                }
+            }
             other => bail!("received unexpected request message type: {other:?}"),
         }
 
         Ok(())
     }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(GOSSIP_INTERVAL)
+    }
+
+    fn tick(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        self.rounds += 1;
+
+        if let Some(hyparview) = &mut self.hyparview {
+            let outbox = hyparview.tick();
+            self.send_membership(outbox, output_lock)?;
+        }
+        if let Some(failure_detector) = &mut self.failure_detector {
+            let outbox = failure_detector.tick();
+            self.send_heartbeats(outbox, output_lock)?;
+        }
+        self.on_status_changes(output_lock)?;
+
+        match &mut self.plumtree {
+            Some(plumtree) => {
+                let outbox = plumtree.tick(&self.messages);
+                self.send(outbox, output_lock)?;
+            }
+            None => self.gossip(output_lock)?,
+        }
+        if self.causal.is_none() && self.rounds.is_multiple_of(ANTI_ENTROPY_EVERY) {
+            self.sync(output_lock)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the broadcast strategy from the [`BROADCAST_STRATEGY_VAR`] environment variable.
+fn plumtree_from_env() -> Result<Option<Plumtree>> {
+    match std::env::var(BROADCAST_STRATEGY_VAR).as_deref() {
+        Err(_) | Ok("delta") => Ok(None),
+        Ok("plumtree") => Ok(Some(Plumtree::new())),
+        Ok(other) => bail!("unknown broadcast strategy: {other}"),
+    }
+}
+
+/// Reads the delivery order from the [`DELIVERY_VAR`] environment variable.
+fn causal_from_env(node_id: String) -> Result<Option<CausalBroadcast>> {
+    match std::env::var(DELIVERY_VAR).as_deref() {
+        Err(_) | Ok("unordered") => Ok(None),
+        Ok("causal") => Ok(Some(CausalBroadcast::new(node_id))),
+        Ok(other) => bail!("unknown delivery order: {other}"),
+    }
+}
+
+/// Reads the membership from the [`MEMBERSHIP_VAR`] environment variable.
+fn hyparview_from_env(node_id: String, node_ids: Vec<String>) -> Result<Option<HyParView>> {
+    match std::env::var(MEMBERSHIP_VAR).as_deref() {
+        Err(_) | Ok("topology") => Ok(None),
+        Ok("hyparview") => Ok(Some(HyParView::new(node_id, node_ids))),
+        Ok(other) => bail!("unknown membership: {other}"),
+    }
 }
 
 fn main() -> Result<()> {
@@ -0,0 +1,419 @@
+//! # The Chain-Replicated Key-Value Store Node (Server)
+//!
+//! A key-value store for the `lin-kv` workload, like the `lin_kv` node, but replicated with chain replication
+//! instead of consensus; see [`gossip_glomers::chain`].
+//!
+//! Clients may send their requests to any node: writes (`write`, `cas`) are forwarded to the head of the chain,
+//! and reads to its tail. A write takes one message per link of the chain, and a read none,
+//! whereas every operation takes a round trip from the leader to a majority with Raft or Multi-Paxos.
+//! On the other hand, every write waits for the whole chain, so a slow node slows down all writes.
+//!
+//! [Workload: Lin-kv](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-lin-kv)
+//!
+//! The configuration master is Maelstrom's `lin-kv` service, which keeps the chain under [`CONFIG_KEY`].
+//! Every node polls it, and monitors all other nodes with a failure detector. A node that can reach a majority
+//! of the cluster compare-and-sets the configuration to take out the chain members that it suspects,
+//! and a node that has been taken out adds itself back at the tail once it can reach a majority and the tail.
+//!
+//! Run as:
+//!
+//! ```
+//! ~/maelstrom/maelstrom test -w lin-kv --bin target/debug/chain_kv --node-count 3 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
+//!
+//! cargo build --bin chain_kv && ~/maelstrom/maelstrom test -w lin-kv --bin target/debug/chain_kv --node-count 3 --concurrency 2n --time-limit 3 --rate 100 --nemesis partition
+//! ```
+//!
+//! Maelstrom reports the latencies in `store/latest/latency-raw.png`, and the messages per operation under `:net`
+//! in `store/latest/results.edn`, for comparing the chain with the `lin_kv` node.
+
+use anyhow::{Context, Result};
+use gossip_glomers::chain::{self, ChainConfig, ChainReplication, Replies, CONFIG_KEY};
+use gossip_glomers::failure_detector::{self, Detection, FailureDetector, DEFAULT_TIMEOUT};
+use gossip_glomers::logic::main_loop;
+use gossip_glomers::message::{
+    ChainNodePayload, ClientCommand, ErrorCode, ErrorPayload, KvPayload, Message, LIN_KV,
+};
+use gossip_glomers::node::Node;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::StdoutLock;
+use std::time::{Duration, Instant};
+
+/// How often the node ticks its chain replication module and its failure detector.
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often the node reads the configuration from `lin-kv`.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// After how long an unanswered configuration request is given up on.
+const CONFIG_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Where a configuration request currently stands.
+#[derive(Clone, Debug)]
+enum ConfigStep {
+    /// Waiting for the current configuration.
+    Reading,
+    /// Waiting for the configuration to be compare-and-set to `config`.
+    Swapping { config: ChainConfig },
+}
+
+/// A configuration request in progress.
+#[derive(Clone, Debug)]
+struct ConfigRequest {
+    /// The `msg_id` of the request to `lin-kv` that we are waiting for.
+    msg_id: usize,
+    step: ConfigStep,
+    sent: Instant,
+}
+
+/// # The Chain-Replicated Key-Value Store Node (Server)
+#[derive(Debug)]
+struct ChainKvNode {
+    /// A unique node name. Maelstrom sets the node ID for our node(s), during the initialization phase.
+    pub node_id: Option<String>,
+    /// A locally-unique integer identifier for a message from a node. It isn't globally-unique.
+    pub msg_id: usize,
+    /// All nodes in the cluster, including this one.
+    pub node_ids: Vec<String>,
+    /// The chain replication module; it's created during the initialization phase, when we learn about the cluster.
+    pub chain: Option<ChainReplication>,
+    /// Monitors all other nodes.
+    pub failure_detector: FailureDetector,
+    /// The configuration request in progress, if any.
+    config_request: Option<ConfigRequest>,
+    /// When we last read the configuration.
+    last_poll: Instant,
+    /// Requests that we forwarded to the head or the tail, by the `msg_id` of the forwarded request:
+    /// the original client and the original `msg_id`, so that we can relay the response.
+    pub forwarded: HashMap<usize, (String, Option<usize>)>,
+}
+
+/// Builds an error payload.
+fn error(code: ErrorCode, text: &str) -> ChainNodePayload {
+    ChainNodePayload::Error(ErrorPayload {
+        code,
+        text: Some(text.to_string()),
+    })
+}
+
+impl ChainKvNode {
+    fn chain(&mut self) -> &mut ChainReplication {
+        self.chain.as_mut().expect("expected some self.chain")
+    }
+
+    /// Sends the chain messages to their destinations, and the responses to the clients.
+    fn send(
+        &mut self,
+        outbox: chain::Outbox,
+        replies: Replies,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        for (dest, payload) in outbox {
+            self.request(dest, ChainNodePayload::Chain(payload), output_lock, "chain")?;
+        }
+        for (client, msg_id, output) in replies {
+            let payload = match output {
+                Ok(payload) => ChainNodePayload::Kv(payload),
+                Err(error) => ChainNodePayload::Error(error),
+            };
+            self.respond(client, msg_id, payload, output_lock, "output")?;
+        }
+
+        Ok(())
+    }
+
+    fn send_heartbeats(
+        &mut self,
+        outbox: failure_detector::Outbox,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        for (dest, payload) in outbox {
+            let payload = ChainNodePayload::Heartbeat(payload);
+            self.request(dest, payload, output_lock, "heartbeat")?;
+        }
+
+        Ok(())
+    }
+
+    /// Serves a client request, or forwards it to the head (writes) or to the tail (reads).
+    ///
+    /// Requests forwarded by other nodes aren't forwarded again: their configuration may be newer,
+    /// or ours may be, and they would just bounce back and forth until the two of us agree.
+    fn serve(
+        &mut self,
+        src: String,
+        msg_id: Option<usize>,
+        op: KvPayload,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        let is_read = match op {
+            KvPayload::Read { .. } => true,
+            KvPayload::Write { .. } | KvPayload::Cas { .. } => false,
+            _ => {
+                let payload = error(ErrorCode::NotSupported, "operation not supported");
+                return self.respond(src, msg_id, payload, output_lock, "error");
+            }
+        };
+
+        if is_read && self.chain().can_read() {
+            let payload = match self.chain().read(op) {
+                Ok(payload) => ChainNodePayload::Kv(payload),
+                Err(error) => ChainNodePayload::Error(error),
+            };
+            return self.respond(src, msg_id, payload, output_lock, "read output");
+        }
+        if !is_read && self.chain().can_write() {
+            let command = ClientCommand {
+                client: src,
+                msg_id,
+                op,
+            };
+            let mut replies = Replies::new();
+            let outbox = self.chain().write(command, &mut replies);
+            return self.send(outbox, replies, output_lock);
+        }
+
+        let config = self.chain().config();
+        let target = if is_read {
+            config.tail()
+        } else {
+            config.head()
+        }
+        .map(str::to_string);
+        match target {
+            Some(target)
+                if Some(&target) != self.node_id.as_ref() && !self.node_ids.contains(&src) =>
+            {
+                self.forwarded.insert(self.msg_id, (src, msg_id));
+                let payload = ChainNodePayload::Kv(op);
+                self.request(target, payload, output_lock, "forwarded kv")
+            }
+            _ => {
+                let payload = error(
+                    ErrorCode::TemporarilyUnavailable,
+                    "the chain is being reconfigured",
+                );
+                self.respond(src, msg_id, payload, output_lock, "error")
+            }
+        }
+    }
+
+    /// Asks `lin-kv` for the current configuration.
+    fn read_config(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        self.last_poll = Instant::now();
+        self.config_request = Some(ConfigRequest {
+            msg_id: self.msg_id,
+            step: ConfigStep::Reading,
+            sent: Instant::now(),
+        });
+        let payload = ChainNodePayload::Kv(KvPayload::Read {
+            key: Value::from(CONFIG_KEY),
+        });
+        self.request(LIN_KV.to_string(), payload, output_lock, "read")
+    }
+
+    /// Proposes a change of the configuration, if we see the need for one and can reach a majority of the cluster:
+    /// takes out the chain members that we suspect, or adds us back at the tail, if we have been taken out.
+    fn maybe_reconfigure(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        let node_id = self.node_id.clone().expect("expected some self.node_id");
+        let reachable = self
+            .node_ids
+            .iter()
+            .filter(|node| !self.failure_detector.is_suspected(node))
+            .count();
+        if reachable <= self.node_ids.len() / 2 {
+            return Ok(());
+        }
+
+        let current = self.chain().config().clone();
+        let config = if current.contains(&node_id) {
+            let suspected: Vec<String> = current
+                .nodes
+                .iter()
+                .filter(|node| self.failure_detector.is_suspected(node))
+                .cloned()
+                .collect();
+            if suspected.is_empty() {
+                return Ok(());
+            }
+            current.without(&suspected)
+        } else {
+            let tail_reachable = current
+                .tail()
+                .is_some_and(|tail| !self.failure_detector.is_suspected(tail));
+            if !tail_reachable {
+                return Ok(());
+            }
+            current.with_tail(node_id)
+        };
+
+        self.config_request = Some(ConfigRequest {
+            msg_id: self.msg_id,
+            step: ConfigStep::Swapping {
+                config: config.clone(),
+            },
+            sent: Instant::now(),
+        });
+        let payload = ChainNodePayload::Kv(KvPayload::Cas {
+            key: Value::from(CONFIG_KEY),
+            from: serde_json::to_value(&current)?,
+            to: serde_json::to_value(&config)?,
+            // The first change ever creates the configuration.
+            create_if_not_exists: current.epoch == 0,
+        });
+        self.request(LIN_KV.to_string(), payload, output_lock, "cas")
+    }
+
+    /// Handles a response from `lin-kv` to a configuration request.
+    fn on_config_response(
+        &mut self,
+        payload: ChainNodePayload,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        let request = self
+            .config_request
+            .take()
+            .expect("expected some self.config_request");
+
+        match (request.step, payload) {
+            (ConfigStep::Reading, ChainNodePayload::Kv(KvPayload::ReadOk { value })) => {
+                let config = serde_json::from_value(value).context("expected a chain config")?;
+                self.chain().on_config(config, request.sent);
+                self.maybe_reconfigure(output_lock)?;
+            }
+            (ConfigStep::Reading, ChainNodePayload::Error(error))
+                if error.code == ErrorCode::KeyDoesNotExist =>
+            {
+                // Nobody has changed the initial configuration yet.
+                let config = ChainConfig::initial(&self.node_ids);
+                self.chain().on_config(config, request.sent);
+                self.maybe_reconfigure(output_lock)?;
+            }
+            (ConfigStep::Swapping { config }, ChainNodePayload::Kv(KvPayload::CasOk)) => {
+                self.chain().on_config(config, request.sent);
+            }
+            // Somebody else changed the configuration first, or `lin-kv` failed;
+            // we'll see about it on the next poll.
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl Node for ChainKvNode {
+    type Payload = ChainNodePayload;
+
+    fn new() -> Self {
+        Self {
+            node_id: None,
+            msg_id: 0,
+            node_ids: Vec::new(),
+            chain: None,
+            failure_detector: FailureDetector::new(Detection::Timeout(DEFAULT_TIMEOUT)),
+            config_request: None,
+            last_poll: Instant::now(),
+            forwarded: HashMap::new(),
+        }
+    }
+
+    fn get_msg_id(&self) -> usize {
+        self.msg_id
+    }
+
+    fn incr_msg_id(&mut self) {
+        self.msg_id += 1;
+    }
+
+    fn get_node_id(&self) -> Option<String> {
+        self.node_id.clone()
+    }
+
+    fn set_node_id(&mut self, value: Option<String>) {
+        self.node_id = value;
+    }
+
+    fn set_node_ids(&mut self, node_ids: Vec<String>) {
+        let node_id = self.node_id.clone().expect("expected some self.node_id");
+        self.chain = Some(ChainReplication::new(node_id.clone(), &node_ids));
+        self.failure_detector.set_peers(
+            node_ids
+                .iter()
+                .filter(|node| **node != node_id)
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+        self.node_ids = node_ids;
+    }
+
+    fn step(
+        &mut self,
+        request: Message<Self::Payload>,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        self.failure_detector.heard_from(&request.src);
+
+        match request.body.payload {
+            payload @ (ChainNodePayload::Kv(_) | ChainNodePayload::Error(_))
+                if request.body.in_reply_to.is_some() =>
+            {
+                let in_reply_to = request.body.in_reply_to;
+                let is_config_response = self
+                    .config_request
+                    .as_ref()
+                    .is_some_and(|config_request| Some(config_request.msg_id) == in_reply_to);
+                if is_config_response {
+                    self.on_config_response(payload, output_lock)?;
+                } else if let Some((client, msg_id)) =
+                    in_reply_to.and_then(|in_reply_to| self.forwarded.remove(&in_reply_to))
+                {
+                    // A response to a request that we forwarded; relay it to the client.
+                    self.respond(client, msg_id, payload, output_lock, "relayed kv")?;
+                }
+            }
+            ChainNodePayload::Kv(op) => {
+                self.serve(request.src, request.body.msg_id, op, output_lock)?;
+            }
+            ChainNodePayload::Error(_) => {}
+            ChainNodePayload::Chain(chain_payload) => {
+                let mut replies = Replies::new();
+                let outbox = self
+                    .chain()
+                    .handle(request.src, chain_payload, &mut replies);
+                self.send(outbox, replies, output_lock)?;
+            }
+            ChainNodePayload::Heartbeat(heartbeat_payload) => {
+                self.failure_detector.handle(request.src, heartbeat_payload);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(TICK_INTERVAL)
+    }
+
+    fn tick(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        let outbox = self.failure_detector.tick();
+        self.send_heartbeats(outbox, output_lock)?;
+
+        let outbox = self.chain().tick();
+        self.send(outbox, Replies::new(), output_lock)?;
+
+        let idle = self
+            .config_request
+            .as_ref()
+            .is_none_or(|config_request| config_request.sent.elapsed() >= CONFIG_TIMEOUT);
+        if idle && self.last_poll.elapsed() >= CONFIG_POLL_INTERVAL {
+            self.read_config(output_lock)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    main_loop::<ChainKvNode>()
+}
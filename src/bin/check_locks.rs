@@ -0,0 +1,49 @@
+//! # The Lock Checker
+//!
+//! Checks a history of lock operations, as recorded by the `lock_workload` binary: that no two clients ever
+//! held the same lock at the same time, and that the fencing tokens of the grants of a lock increased
+//! in real-time order. See [`gossip_glomers::lock::check_locks()`].
+//!
+//! It isn't a node; it's run after the test, on the history, one JSON event per line,
+//! which is read from the path given as the only argument, or from `STDIN`.
+//!
+//! Run as:
+//!
+//! ```
+//! target/debug/check_locks lock_history.jsonl
+//! ```
+
+use anyhow::{Context, Result};
+use gossip_glomers::lock::{check_locks, Event};
+use std::io::{self, Read};
+
+fn main() -> Result<()> {
+    let mut history = String::new();
+    match std::env::args().nth(1) {
+        Some(path) => {
+            history = std::fs::read_to_string(&path).context(format!("failed to read {path}"))?;
+        }
+        None => {
+            io::stdin()
+                .read_to_string(&mut history)
+                .context("failed to read the history from stdin")?;
+        }
+    }
+
+    let events = history
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str::<Event>(line)
+                .with_context(|| format!("expected an event: {line}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let granted = check_locks(&events)?;
+
+    println!(
+        "All {granted} granted leases, out of {} events, were mutually exclusive, with increasing fencing tokens. ヽ(‘ー`)ノ",
+        events.len()
+    );
+
+    Ok(())
+}
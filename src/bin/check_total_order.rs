@@ -0,0 +1,66 @@
+//! # The Total-Order Checker
+//!
+//! Checks the history of a `broadcast` test of the total-order broadcast node: that the values
+//! of all successful reads, from all nodes, are prefixes of one another, i.e., that all nodes
+//! delivered the same values in the same order. See [`gossip_glomers::total_order::check_prefixes()`].
+//!
+//! It isn't a node; it's run after the test, on Maelstrom's `history.txt` (or `history.edn`),
+//! which is read from the path given as the only argument, or from `STDIN`.
+//!
+//! Run as:
+//!
+//! ```
+//! target/debug/check_total_order store/latest/history.txt
+//! ```
+
+use anyhow::{Context, Result};
+use gossip_glomers::total_order::check_prefixes;
+use std::io::{self, Read};
+
+/// The values of a successful read, if the line of the history is one; e.g., `3 :ok :read [0 1 2]`.
+fn read_values(line: &str) -> Option<Result<Vec<usize>>> {
+    let (_, after_read) = line.split_once(":read")?;
+    if !line.contains(":ok") {
+        return None;
+    }
+
+    let start = after_read.find('[')?;
+    let end = start + after_read[start..].find(']')?;
+    let values = after_read[start + 1..end]
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            value
+                .parse()
+                .with_context(|| format!("expected an integer value, not {value}"))
+        })
+        .collect();
+    Some(values)
+}
+
+fn main() -> Result<()> {
+    let mut history = String::new();
+    match std::env::args().nth(1) {
+        Some(path) => {
+            history = std::fs::read_to_string(&path).context(format!("failed to read {path}"))?;
+        }
+        None => {
+            io::stdin()
+                .read_to_string(&mut history)
+                .context("failed to read the history from stdin")?;
+        }
+    }
+
+    let reads = history
+        .lines()
+        .filter_map(read_values)
+        .collect::<Result<Vec<_>>>()?;
+    check_prefixes(reads.iter().map(Vec::as_slice))?;
+
+    println!(
+        "All {} reads are prefixes of one another. ヽ(‘ー`)ノ",
+        reads.len()
+    );
+
+    Ok(())
+}
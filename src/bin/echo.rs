@@ -44,6 +44,8 @@ pub struct EchoNode {
 }
 
 impl Node for EchoNode {
+    type Payload = Payload;
+
     fn new() -> Self {
         Self {
             node_id: None,
@@ -67,7 +69,11 @@ impl Node for EchoNode {
         self.node_id = value;
     }
 
-    fn step(&mut self, request: Message<Payload>, output_lock: &mut StdoutLock) -> Result<()> {
+    fn step(
+        &mut self,
+        request: Message<Self::Payload>,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
         match request.body.payload {
             Payload::Echo(echo_payload) => match echo_payload {
                 EchoPayload::Echo { echo } => {
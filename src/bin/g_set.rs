@@ -0,0 +1,161 @@
+//! # The Grow-Only Set Node (Server)
+//!
+//! A grow-only set, replicated as a state-based CRDT: every node accepts `add` requests locally,
+//! and the nodes converge by gossiping their states to each other.
+//!
+//! Gossip comes in two flavors:
+//! - delta gossip: frequently, every node sends its peers only the elements added since the previous round;
+//! - full-state gossip: less often, every node sends its peers its whole set, which repairs
+//!   whatever deltas got lost, e.g., during network partitions.
+//!
+//! [Workload: G-set](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-g-set)
+//!
+//! Run as:
+//!
+//! ```
+//! ~/maelstrom/maelstrom test -w g-set --bin target/debug/g_set --node-count 3 --time-limit 20 --rate 10 --nemesis partition
+//!
+//! cargo build --bin g_set && ~/maelstrom/maelstrom test -w g-set --bin target/debug/g_set --node-count 3 --time-limit 3 --rate 10 --nemesis partition
+//! ```
+
+use anyhow::Result;
+use gossip_glomers::crdt::{Crdt, GSet};
+use gossip_glomers::logic::main_loop;
+use gossip_glomers::message::{GSetPayload, Message};
+use gossip_glomers::node::Node;
+use std::fmt::Debug;
+use std::io::StdoutLock;
+use std::time::Duration;
+
+/// How often the node gossips deltas to its peers.
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Every how many gossip rounds the node gossips its full state instead of a delta.
+const FULL_STATE_EVERY: usize = 10;
+
+/// # The Grow-Only Set Node (Server)
+///
+/// Replicates a grow-only set by periodic delta and full-state gossip.
+#[derive(Default, Debug)]
+struct GSetNode {
+    /// A unique node name. Maelstrom sets the node ID for our node(s), during the initialization phase.
+    pub node_id: Option<String>,
+    /// A locally-unique integer identifier for a message from a node. It isn't globally-unique.
+    pub msg_id: usize,
+    /// All other nodes in the cluster.
+    pub peers: Vec<String>,
+    /// The replicated set.
+    pub set: GSet<usize>,
+    /// Elements added locally since the last gossip round.
+    pub delta: GSet<usize>,
+    /// The number of gossip rounds so far.
+    pub rounds: usize,
+}
+
+impl Node for GSetNode {
+    type Payload = GSetPayload;
+
+    fn new() -> Self {
+        Self {
+            node_id: None,
+            msg_id: 0,
+            peers: Vec::new(),
+            set: GSet::new(),
+            delta: GSet::new(),
+            rounds: 0,
+        }
+    }
+
+    fn get_msg_id(&self) -> usize {
+        self.msg_id
+    }
+
+    fn incr_msg_id(&mut self) {
+        self.msg_id += 1;
+    }
+
+    fn get_node_id(&self) -> Option<String> {
+        self.node_id.clone()
+    }
+
+    fn set_node_id(&mut self, value: Option<String>) {
+        self.node_id = value;
+    }
+
+    fn set_node_ids(&mut self, node_ids: Vec<String>) {
+        let node_id = self.node_id.clone().expect("expected some self.node_id");
+        self.peers = node_ids.into_iter().filter(|id| *id != node_id).collect();
+    }
+
+    fn step(
+        &mut self,
+        request: Message<Self::Payload>,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        match request.body.payload {
+            GSetPayload::Add { element } => {
+                if self.set.insert(element) {
+                    self.delta.insert(element);
+                }
+
+                self.respond(
+                    request.src,
+                    request.body.msg_id,
+                    GSetPayload::AddOk,
+                    output_lock,
+                    "add_ok",
+                )?;
+            }
+            GSetPayload::Read => {
+                let payload = GSetPayload::ReadOk {
+                    value: self.set.clone(),
+                };
+                self.respond(
+                    request.src,
+                    request.body.msg_id,
+                    payload,
+                    output_lock,
+                    "read_ok",
+                )?;
+            }
+            GSetPayload::Replicate { value } => {
+                // Elements that we learn from peers aren't part of our delta: the senders gossip them themselves.
+                self.set.merge(&value);
+            }
+            GSetPayload::AddOk | GSetPayload::ReadOk { .. } => {}
+        }
+
+        Ok(())
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(GOSSIP_INTERVAL)
+    }
+
+    fn tick(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        self.rounds += 1;
+
+        let value = if self.rounds.is_multiple_of(FULL_STATE_EVERY) {
+            self.delta.take();
+            self.set.clone()
+        } else {
+            self.delta.take()
+        };
+        if value.is_empty() {
+            return Ok(());
+        }
+
+        for peer in self.peers.clone() {
+            let payload = GSetPayload::Replicate {
+                value: value.clone(),
+            };
+            self.request(peer, payload, output_lock, "replicate")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    main_loop::<GSetNode>()
+}
@@ -0,0 +1,25 @@
+//! # The Linearizable Key-Value Store Node (Server)
+//!
+//! A key-value store that provides linearizable `read`, `write` and `cas` operations,
+//! by replicating the operations through a consensus log: Raft, or Multi-Paxos with `CONSENSUS=multi-paxos`.
+//!
+//! It's a replica of a [`gossip_glomers::rsm::KvStore`]; see [`gossip_glomers::rsm`].
+//!
+//! [Workload: Lin-kv](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-lin-kv)
+//!
+//! Run as:
+//!
+//! ```
+//! ~/maelstrom/maelstrom test -w lin-kv --bin target/debug/lin_kv --node-count 3 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
+//!
+//! CONSENSUS=multi-paxos ~/maelstrom/maelstrom test -w lin-kv --bin target/debug/lin_kv --node-count 3 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
+//!
+//! cargo build --bin lin_kv && ~/maelstrom/maelstrom test -w lin-kv --bin target/debug/lin_kv --node-count 3 --concurrency 2n --time-limit 3 --rate 100 --nemesis partition
+//! ```
+
+use anyhow::Result;
+use gossip_glomers::rsm::{replica_main_loop, KvStore};
+
+fn main() -> Result<()> {
+    replica_main_loop::<KvStore>()
+}
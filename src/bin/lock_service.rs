@@ -0,0 +1,27 @@
+//! # The Lock Service Node (Server)
+//!
+//! Grants named locks as leases, with fencing tokens, through the `acquire`, `release` and `renew` requests
+//! of its own vocabulary; see [`gossip_glomers::message::LockPayload`]. The leases are replicated through
+//! a consensus log (Raft, or Multi-Paxos with `CONSENSUS=multi-paxos`), and they expire if they aren't renewed in time.
+//!
+//! It's a replica of a [`gossip_glomers::lock::LockTable`]; see [`gossip_glomers::lock`] and [`gossip_glomers::rsm`].
+//!
+//! Maelstrom has no lock workload, so the `lock_workload` binary runs the nodes and their clients instead,
+//! and records a history for the `check_locks` binary to check.
+//!
+//! Run as:
+//!
+//! ```
+//! target/debug/lock_workload target/debug/lock_service 3 5 20 partition > lock_history.jsonl
+//! target/debug/check_locks lock_history.jsonl
+//!
+//! cargo build --bin lock_service --bin lock_workload --bin check_locks && target/debug/lock_workload target/debug/lock_service 3 5 3 partition | target/debug/check_locks
+//! ```
+
+use anyhow::Result;
+use gossip_glomers::lock::LockTable;
+use gossip_glomers::rsm::replica_main_loop;
+
+fn main() -> Result<()> {
+    replica_main_loop::<LockTable>()
+}
@@ -0,0 +1,488 @@
+//! # The Lock Workload
+//!
+//! Maelstrom has no workload for the lock service, so this runs one: it starts the `lock_service` nodes,
+//! routes the messages between them, and plays the clients, which acquire, renew and release a few locks,
+//! and which sometimes let their leases expire instead of releasing them.
+//!
+//! It isn't a node. It takes, as the arguments, the path to the node binary, the number of nodes (`3` by default),
+//! the number of clients (`5` by default), the time limit in seconds (`10` by default), and the nemesis:
+//! `none`, the default, or `partition`, which every few seconds cuts a random node off from the others, or heals.
+//!
+//! It writes the history of the clients' operations to `STDOUT`, one JSON event per line, for the `check_locks`
+//! binary to check; see [`gossip_glomers::lock::Event`]. It measures all times with its own clock, so the history
+//! is consistent with real time.
+//!
+//! Run as:
+//!
+//! ```
+//! target/debug/lock_workload target/debug/lock_service 3 5 20 partition > lock_history.jsonl
+//! target/debug/check_locks lock_history.jsonl
+//!
+//! target/debug/lock_workload target/debug/lock_service 3 5 3 partition | target/debug/check_locks
+//! ```
+
+use anyhow::{bail, Context, Result};
+use gossip_glomers::lock::{Event, EventKind, LockOp};
+use gossip_glomers::message::{Body, ErrorCode, ErrorPayload, InitPayload, LockPayload, Message};
+use gossip_glomers::rng::Rng;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, StdoutLock, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The locks that the clients compete for.
+const LOCKS: [&str; 2] = ["a", "b"];
+
+/// How long the clients' leases last.
+const TTL: Duration = Duration::from_millis(500);
+
+/// After how long an unanswered request is given up on, with an unknown outcome.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The longest a client waits before it tries to acquire a lock again.
+const MAX_PAUSE: Duration = Duration::from_millis(50);
+
+/// How often the `partition` nemesis cuts a node off, or heals.
+const NEMESIS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long the nodes have to respond to their `init` messages.
+const INIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The client that initializes the nodes; the workload's clients are `c1`, `c2`, ...
+const ADMIN: &str = "c0";
+
+/// Just the addressing of a message, for routing it as it is.
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    src: String,
+    dest: String,
+}
+
+/// A response to a client.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Response {
+    Lock(LockPayload),
+    Error(ErrorPayload),
+}
+
+/// A node process.
+#[derive(Debug)]
+struct Node {
+    process: Child,
+    stdin: ChildStdin,
+}
+
+/// A lease that a client holds, as far as it knows.
+#[derive(Debug)]
+struct Holding {
+    lock: String,
+    token: u64,
+    /// When the client invoked the last successful `acquire` or `renew`, which its lease lasts from.
+    since: Instant,
+    /// When the client is done with the lock.
+    release_at: Instant,
+    /// Whether the client lets its lease expire, instead of renewing and releasing it.
+    abandon: bool,
+}
+
+/// A client's request in flight.
+#[derive(Debug)]
+struct Pending {
+    msg_id: usize,
+    sent: Instant,
+    invocation: Event,
+}
+
+#[derive(Debug)]
+struct Client {
+    id: String,
+    pending: Option<Pending>,
+    holding: Option<Holding>,
+    /// When to try to acquire a lock next, while the client doesn't hold one.
+    next_at: Instant,
+}
+
+/// # The Lock Workload
+struct Workload<'a> {
+    nodes: Vec<Node>,
+    clients: Vec<Client>,
+    /// The node that the `partition` nemesis has cut off from the others, if any.
+    isolated: Option<usize>,
+    rng: Rng,
+    start: Instant,
+    msg_id: usize,
+    history: StdoutLock<'a>,
+}
+
+impl Workload<'_> {
+    /// Sends a message from `src` to the node `node`.
+    fn send<P: Serialize>(&mut self, src: &str, node: usize, payload: P) -> Result<()> {
+        let message = Message {
+            src: src.to_string(),
+            dest: format!("n{node}"),
+            body: Body {
+                msg_id: Some(self.msg_id),
+                in_reply_to: None,
+                clock: None,
+                payload,
+            },
+        };
+        self.msg_id += 1;
+        let stdin = &mut self.nodes[node].stdin;
+        serde_json::to_writer(&mut *stdin, &message).context("serialization of request failed")?;
+        stdin.write_all(b"\n").context("failed to write to a node")
+    }
+
+    fn record(&mut self, event: &Event) -> Result<()> {
+        serde_json::to_writer(&mut self.history, event).context("serialization of event failed")?;
+        self.history
+            .write_all(b"\n")
+            .context("failed to write the history")
+    }
+
+    fn micros(&self, instant: Instant) -> u64 {
+        instant.duration_since(self.start).as_micros() as u64
+    }
+
+    /// Sends the client's request to a random node, and records its invocation.
+    fn invoke(&mut self, client: usize, f: LockOp, lock: String, token: Option<u64>) -> Result<()> {
+        let ttl = TTL.as_millis() as u64;
+        let payload = match f {
+            LockOp::Acquire => LockPayload::Acquire {
+                lock: lock.clone(),
+                ttl,
+                time: None,
+            },
+            LockOp::Release => LockPayload::Release {
+                lock: lock.clone(),
+                token: token.expect("expected a token to release"),
+            },
+            LockOp::Renew => LockPayload::Renew {
+                lock: lock.clone(),
+                token: token.expect("expected a token to renew"),
+                ttl,
+                time: None,
+            },
+        };
+
+        let now = Instant::now();
+        let invocation = Event {
+            time: self.micros(now),
+            client: self.clients[client].id.clone(),
+            kind: EventKind::Invoke,
+            f,
+            lock,
+            token,
+            ttl: (f != LockOp::Release).then_some(ttl),
+        };
+        self.record(&invocation)?;
+
+        let node = self.rng.gen_range(0..self.nodes.len() as u64) as usize;
+        let msg_id = self.msg_id;
+        let src = self.clients[client].id.clone();
+        self.send(&src, node, payload)?;
+        self.clients[client].pending = Some(Pending {
+            msg_id,
+            sent: now,
+            invocation,
+        });
+
+        Ok(())
+    }
+
+    /// Records the completion of the client's request, and decides what the client does next.
+    fn complete(&mut self, client: usize, kind: EventKind, token: Option<u64>) -> Result<()> {
+        let now = Instant::now();
+        let Some(pending) = self.clients[client].pending.take() else {
+            return Ok(());
+        };
+        let f = pending.invocation.f;
+        let event = Event {
+            time: self.micros(now),
+            kind,
+            token: if f == LockOp::Acquire { token } else { None },
+            ttl: None,
+            ..pending.invocation
+        };
+        self.record(&event)?;
+
+        let pause = Duration::from_micros(self.rng.gen_range(0..MAX_PAUSE.as_micros() as u64));
+        let hold = Duration::from_micros(self.rng.gen_range(0..2 * TTL.as_micros() as u64));
+        let abandon = self.rng.gen_range(0..5) == 0;
+        let client = &mut self.clients[client];
+        match (f, kind, token) {
+            (LockOp::Acquire, EventKind::Ok, Some(token)) => {
+                client.holding = Some(Holding {
+                    lock: event.lock,
+                    token,
+                    since: pending.sent,
+                    release_at: pending.sent + hold,
+                    abandon,
+                });
+            }
+            (LockOp::Renew, EventKind::Ok, _) => {
+                if let Some(holding) = &mut client.holding {
+                    holding.since = pending.sent;
+                }
+            }
+            _ => {
+                // A failed renewal, or one with an unknown outcome, means the lease is as good as lost.
+                client.holding = None;
+                client.next_at = now + pause;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles a response to the client.
+    fn on_response(&mut self, client: usize, message: Message<Response>) -> Result<()> {
+        let expected = self.clients[client]
+            .pending
+            .as_ref()
+            .map(|pending| pending.msg_id);
+        if message.body.in_reply_to.is_none() || message.body.in_reply_to != expected {
+            return Ok(());
+        }
+
+        match message.body.payload {
+            Response::Lock(LockPayload::AcquireOk { token }) => {
+                self.complete(client, EventKind::Ok, Some(token))
+            }
+            Response::Lock(LockPayload::ReleaseOk | LockPayload::RenewOk) => {
+                self.complete(client, EventKind::Ok, None)
+            }
+            Response::Error(error)
+                if matches!(error.code, ErrorCode::Timeout | ErrorCode::Crash) =>
+            {
+                self.complete(client, EventKind::Info, None)
+            }
+            Response::Error(_) => self.complete(client, EventKind::Fail, None),
+            Response::Lock(other) => bail!("unexpected response: {other:?}"),
+        }
+    }
+
+    /// Times out the client's request, or starts its next one, if it's due.
+    fn step(&mut self, client: usize, running: bool) -> Result<()> {
+        let now = Instant::now();
+        if let Some(pending) = &self.clients[client].pending {
+            if now >= pending.sent + REQUEST_TIMEOUT {
+                self.complete(client, EventKind::Info, None)?;
+            }
+            return Ok(());
+        }
+        if !running {
+            return Ok(());
+        }
+
+        match &self.clients[client].holding {
+            Some(holding) if holding.abandon => {
+                if now >= holding.since + TTL {
+                    let pause = self.rng.gen_range(0..MAX_PAUSE.as_micros() as u64);
+                    let client = &mut self.clients[client];
+                    client.holding = None;
+                    client.next_at = now + Duration::from_micros(pause);
+                }
+                Ok(())
+            }
+            Some(holding) if now >= holding.release_at => {
+                let (lock, token) = (holding.lock.clone(), holding.token);
+                self.invoke(client, LockOp::Release, lock, Some(token))?;
+                self.clients[client].holding = None;
+                Ok(())
+            }
+            Some(holding) if now >= holding.since + TTL / 2 => {
+                let (lock, token) = (holding.lock.clone(), holding.token);
+                self.invoke(client, LockOp::Renew, lock, Some(token))
+            }
+            Some(_) => Ok(()),
+            None if now >= self.clients[client].next_at => {
+                let lock = LOCKS[self.rng.gen_range(0..LOCKS.len() as u64) as usize].to_string();
+                self.invoke(client, LockOp::Acquire, lock, None)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Routes a message from a node: to another node, unless the nemesis has cut them off from each other,
+    /// or to a client.
+    fn route(&mut self, line: &str) -> Result<()> {
+        let envelope: Envelope =
+            serde_json::from_str(line).with_context(|| format!("expected a message: {line}"))?;
+        if let Some(node) = node_index(&envelope.dest, self.nodes.len()) {
+            let src = node_index(&envelope.src, self.nodes.len());
+            if self.isolated.is_some() && (self.isolated == src) != (self.isolated == Some(node)) {
+                return Ok(());
+            }
+            let stdin = &mut self.nodes[node].stdin;
+            stdin
+                .write_all(line.as_bytes())
+                .and_then(|_| stdin.write_all(b"\n"))
+                .context("failed to write to a node")
+        } else if let Some(client) = self
+            .clients
+            .iter()
+            .position(|client| client.id == envelope.dest)
+        {
+            let message: Message<Response> = serde_json::from_str(line)
+                .with_context(|| format!("expected a response: {line}"))?;
+            self.on_response(client, message)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Initializes the nodes, and waits for all of them to respond.
+    fn init(&mut self, messages: &Receiver<String>) -> Result<()> {
+        let node_ids: Vec<String> = (0..self.nodes.len())
+            .map(|node| format!("n{node}"))
+            .collect();
+        for node in 0..self.nodes.len() {
+            let payload = InitPayload::Init {
+                node_id: node_ids[node].clone(),
+                node_ids: node_ids.clone(),
+            };
+            self.send(ADMIN, node, payload)?;
+        }
+
+        let deadline = Instant::now() + INIT_TIMEOUT;
+        let mut initialized = 0;
+        while initialized < self.nodes.len() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let line = messages
+                .recv_timeout(timeout)
+                .context("the nodes didn't respond to init in time")?;
+            let message: Message<InitPayload> = serde_json::from_str(&line)
+                .with_context(|| format!("expected an init_ok message: {line}"))?;
+            if matches!(message.body.payload, InitPayload::InitOk) {
+                initialized += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Workload<'_> {
+    fn drop(&mut self) {
+        for node in &mut self.nodes {
+            let _ = node.process.kill();
+            let _ = node.process.wait();
+        }
+    }
+}
+
+/// The index of the node `id`, if it's one of the `count` nodes `n0`, `n1`, ...
+fn node_index(id: &str, count: usize) -> Option<usize> {
+    id.strip_prefix('n')
+        .and_then(|index| index.parse().ok())
+        .filter(|index| *index < count)
+}
+
+/// Parses the argument at `index`, or takes the default.
+fn arg(index: usize, default: usize) -> Result<usize> {
+    match std::env::args().nth(index) {
+        Some(arg) => arg
+            .parse()
+            .with_context(|| format!("expected a number, not {arg}")),
+        None => Ok(default),
+    }
+}
+
+fn main() -> Result<()> {
+    let Some(bin) = std::env::args().nth(1) else {
+        bail!("expected the path to the lock service binary");
+    };
+    let node_count = arg(2, 3)?;
+    let client_count = arg(3, 5)?;
+    let time_limit = Duration::from_secs(arg(4, 10)? as u64);
+    let partition = match std::env::args().nth(5).as_deref() {
+        None | Some("none") => false,
+        Some("partition") => true,
+        Some(other) => bail!("unknown nemesis: {other}"),
+    };
+
+    let (sender, messages) = mpsc::channel();
+    let mut nodes = Vec::with_capacity(node_count);
+    for _ in 0..node_count {
+        let mut process = Command::new(&bin)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to start {bin}"))?;
+        let stdin = process.stdin.take().expect("expected a piped stdin");
+        let stdout = process.stdout.take().expect("expected a piped stdout");
+        let sender = sender.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        nodes.push(Node { process, stdin });
+    }
+    drop(sender);
+
+    let start = Instant::now();
+    let mut workload = Workload {
+        nodes,
+        clients: (1..=client_count)
+            .map(|client| Client {
+                id: format!("c{client}"),
+                pending: None,
+                holding: None,
+                next_at: start,
+            })
+            .collect(),
+        isolated: None,
+        rng: Rng::new(),
+        start,
+        msg_id: 0,
+        history: io::stdout().lock(),
+    };
+    workload.init(&messages)?;
+
+    let end = Instant::now() + time_limit;
+    let mut next_nemesis = Instant::now() + NEMESIS_INTERVAL;
+    loop {
+        match messages.recv_timeout(Duration::from_millis(1)) {
+            Ok(line) => workload.route(&line)?,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => bail!("the nodes have exited"),
+        }
+
+        let now = Instant::now();
+        let running = now < end;
+        if partition && running && now >= next_nemesis {
+            next_nemesis += NEMESIS_INTERVAL;
+            workload.isolated = match workload.isolated {
+                Some(_) => None,
+                None => Some(workload.rng.gen_range(0..node_count as u64) as usize),
+            };
+        }
+        if !running {
+            workload.isolated = None;
+        }
+
+        for client in 0..workload.clients.len() {
+            workload.step(client, running)?;
+        }
+        if !running
+            && workload
+                .clients
+                .iter()
+                .all(|client| client.pending.is_none())
+        {
+            break;
+        }
+    }
+
+    workload
+        .history
+        .flush()
+        .context("failed to write the history")
+}
@@ -0,0 +1,138 @@
+//! # The PN-Counter Node (Server)
+//!
+//! A counter that supports both increments and decrements, replicated as a state-based CRDT:
+//! every node accepts `add` requests locally, into its own entries of the counter,
+//! and the nodes converge by periodically gossiping their whole counter states to each other.
+//!
+//! The state is small - two entries per node - so it's always gossiped in full,
+//! which also makes the nodes catch up on their own after network partitions heal.
+//! It doesn't depend on any of Maelstrom's key-value services.
+//!
+//! [Workload: Pn-counter](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-pn-counter)
+//!
+//! Run as:
+//!
+//! ```
+//! ~/maelstrom/maelstrom test -w pn-counter --bin target/debug/pn_counter --node-count 3 --time-limit 20 --rate 100 --nemesis partition
+//!
+//! cargo build --bin pn_counter && ~/maelstrom/maelstrom test -w pn-counter --bin target/debug/pn_counter --node-count 3 --time-limit 3 --rate 100 --nemesis partition
+//! ```
+
+use anyhow::Result;
+use gossip_glomers::crdt::{Crdt, PNCounter};
+use gossip_glomers::logic::main_loop;
+use gossip_glomers::message::{Message, PnCounterPayload};
+use gossip_glomers::node::Node;
+use std::fmt::Debug;
+use std::io::StdoutLock;
+use std::time::Duration;
+
+/// How often the node gossips its counter state to its peers.
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(250);
+
+/// # The PN-Counter Node (Server)
+///
+/// Replicates a PN-counter by periodic full-state gossip.
+#[derive(Default, Debug)]
+struct PnCounterNode {
+    /// A unique node name. Maelstrom sets the node ID for our node(s), during the initialization phase.
+    pub node_id: Option<String>,
+    /// A locally-unique integer identifier for a message from a node. It isn't globally-unique.
+    pub msg_id: usize,
+    /// All other nodes in the cluster.
+    pub peers: Vec<String>,
+    /// The replicated counter.
+    pub counter: PNCounter,
+}
+
+impl Node for PnCounterNode {
+    type Payload = PnCounterPayload;
+
+    fn new() -> Self {
+        Self {
+            node_id: None,
+            msg_id: 0,
+            peers: Vec::new(),
+            counter: PNCounter::new(),
+        }
+    }
+
+    fn get_msg_id(&self) -> usize {
+        self.msg_id
+    }
+
+    fn incr_msg_id(&mut self) {
+        self.msg_id += 1;
+    }
+
+    fn get_node_id(&self) -> Option<String> {
+        self.node_id.clone()
+    }
+
+    fn set_node_id(&mut self, value: Option<String>) {
+        self.node_id = value;
+    }
+
+    fn set_node_ids(&mut self, node_ids: Vec<String>) {
+        let node_id = self.node_id.clone().expect("expected some self.node_id");
+        self.peers = node_ids.into_iter().filter(|id| *id != node_id).collect();
+    }
+
+    fn step(
+        &mut self,
+        request: Message<Self::Payload>,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        match request.body.payload {
+            PnCounterPayload::Add { delta } => {
+                let node_id = self.node_id.clone().expect("expected some self.node_id");
+                self.counter.add(&node_id, delta);
+
+                self.respond(
+                    request.src,
+                    request.body.msg_id,
+                    PnCounterPayload::AddOk,
+                    output_lock,
+                    "add_ok",
+                )?;
+            }
+            PnCounterPayload::Read => {
+                let payload = PnCounterPayload::ReadOk {
+                    value: self.counter.value(),
+                };
+                self.respond(
+                    request.src,
+                    request.body.msg_id,
+                    payload,
+                    output_lock,
+                    "read_ok",
+                )?;
+            }
+            PnCounterPayload::Replicate { value } => {
+                self.counter.merge(&value);
+            }
+            PnCounterPayload::AddOk | PnCounterPayload::ReadOk { .. } => {}
+        }
+
+        Ok(())
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(GOSSIP_INTERVAL)
+    }
+
+    fn tick(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        for peer in self.peers.clone() {
+            let payload = PnCounterPayload::Replicate {
+                value: self.counter.clone(),
+            };
+            self.request(peer, payload, output_lock, "replicate")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    main_loop::<PnCounterNode>()
+}
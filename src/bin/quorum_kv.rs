@@ -0,0 +1,180 @@
+//! # The Quorum-Replicated Key-Value Store Node (Server)
+//!
+//! A last-write-wins key-value store, like Maelstrom's own `lww-kv` service, replicated Dynamo-style:
+//! every node coordinates the requests that it receives, with quorums of the key's replicas;
+//! see [`gossip_glomers::quorum`].
+//!
+//! It speaks the protocol of Maelstrom's key-value services (`read`, `write` and `cas`). Maelstrom doesn't have
+//! an `lww-kv` workload, so it runs under the `lin-kv` workload, whose checker tells how far from linearizable
+//! the store is for a given `N`, `R` and `W`: even with `R + W > N`, sloppy quorums and concurrent `cas`es
+//! can break linearizability.
+//!
+//! [Workload: Lin-kv](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-lin-kv)
+//!
+//! `N`, `R` and `W` are set through the `QUORUM_N`, `QUORUM_R` and `QUORUM_W` environment variables;
+//! they are `3`, `2` and `2` by default.
+//!
+//! Run as:
+//!
+//! ```
+//! ~/maelstrom/maelstrom test -w lin-kv --bin target/debug/quorum_kv --node-count 5 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
+//!
+//! cargo build --bin quorum_kv && QUORUM_N=3 QUORUM_R=1 QUORUM_W=1 ~/maelstrom/maelstrom test -w lin-kv --bin target/debug/quorum_kv --node-count 5 --concurrency 2n --time-limit 3 --rate 100 --nemesis partition
+//! ```
+
+use anyhow::Result;
+use gossip_glomers::failure_detector::{self, Detection, FailureDetector, DEFAULT_TIMEOUT};
+use gossip_glomers::logic::main_loop;
+use gossip_glomers::message::{Message, QuorumNodePayload};
+use gossip_glomers::node::Node;
+use gossip_glomers::quorum::{self, QuorumConfig, QuorumReplication, Replies};
+use std::fmt::Debug;
+use std::io::StdoutLock;
+use std::time::Duration;
+
+/// How often the node ticks its quorum replication module and its failure detector.
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// # The Quorum-Replicated Key-Value Store Node (Server)
+#[derive(Debug)]
+struct QuorumKvNode {
+    /// A unique node name. Maelstrom sets the node ID for our node(s), during the initialization phase.
+    pub node_id: Option<String>,
+    /// A locally-unique integer identifier for a message from a node. It isn't globally-unique.
+    pub msg_id: usize,
+    /// `N`, `R` and `W`.
+    pub config: QuorumConfig,
+    /// The quorum replication module; it's created during the initialization phase, when we learn about the cluster.
+    pub quorum: Option<QuorumReplication>,
+    /// Monitors all other nodes, so that requests for the suspected ones go to stand-ins.
+    pub failure_detector: FailureDetector,
+}
+
+impl QuorumKvNode {
+    /// Sends the quorum messages to their destinations, and the responses to the clients.
+    fn send(
+        &mut self,
+        outbox: quorum::Outbox,
+        replies: Replies,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        for (dest, payload) in outbox {
+            let payload = QuorumNodePayload::Quorum(payload);
+            self.request(dest, payload, output_lock, "quorum")?;
+        }
+        for (client, msg_id, output) in replies {
+            let payload = match output {
+                Ok(payload) => QuorumNodePayload::Kv(payload),
+                Err(error) => QuorumNodePayload::Error(error),
+            };
+            self.respond(client, msg_id, payload, output_lock, "output")?;
+        }
+
+        Ok(())
+    }
+
+    fn send_heartbeats(
+        &mut self,
+        outbox: failure_detector::Outbox,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        for (dest, payload) in outbox {
+            let payload = QuorumNodePayload::Heartbeat(payload);
+            self.request(dest, payload, output_lock, "heartbeat")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Node for QuorumKvNode {
+    type Payload = QuorumNodePayload;
+
+    fn new() -> Self {
+        Self {
+            node_id: None,
+            msg_id: 0,
+            config: QuorumConfig::from_env().expect("expected a valid quorum configuration"),
+            quorum: None,
+            failure_detector: FailureDetector::new(Detection::Timeout(DEFAULT_TIMEOUT)),
+        }
+    }
+
+    fn get_msg_id(&self) -> usize {
+        self.msg_id
+    }
+
+    fn incr_msg_id(&mut self) {
+        self.msg_id += 1;
+    }
+
+    fn get_node_id(&self) -> Option<String> {
+        self.node_id.clone()
+    }
+
+    fn set_node_id(&mut self, value: Option<String>) {
+        self.node_id = value;
+    }
+
+    fn set_node_ids(&mut self, node_ids: Vec<String>) {
+        let node_id = self.node_id.clone().expect("expected some self.node_id");
+        self.quorum = Some(QuorumReplication::new(
+            node_id.clone(),
+            &node_ids,
+            self.config,
+        ));
+        self.failure_detector
+            .set_peers(node_ids.into_iter().filter(|node| *node != node_id));
+    }
+
+    fn step(
+        &mut self,
+        request: Message<Self::Payload>,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        self.failure_detector.heard_from(&request.src);
+
+        let quorum = self.quorum.as_mut().expect("expected some self.quorum");
+        let mut replies = Replies::new();
+        let outbox = match request.body.payload {
+            QuorumNodePayload::Kv(op) => quorum.request(
+                request.src,
+                request.body.msg_id,
+                op,
+                &self.failure_detector,
+                &mut replies,
+            ),
+            QuorumNodePayload::Quorum(quorum_payload) => quorum.handle(
+                request.src,
+                quorum_payload,
+                &self.failure_detector,
+                &mut replies,
+            ),
+            QuorumNodePayload::Heartbeat(heartbeat_payload) => {
+                self.failure_detector.handle(request.src, heartbeat_payload);
+                Vec::new()
+            }
+            QuorumNodePayload::Error(_) => Vec::new(),
+        };
+
+        self.send(outbox, replies, output_lock)
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(TICK_INTERVAL)
+    }
+
+    fn tick(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        let outbox = self.failure_detector.tick();
+        self.send_heartbeats(outbox, output_lock)?;
+
+        let quorum = self.quorum.as_mut().expect("expected some self.quorum");
+        let mut replies = Replies::new();
+        let outbox = quorum.tick(&self.failure_detector, &mut replies);
+        self.send(outbox, replies, output_lock)
+    }
+}
+
+fn main() -> Result<()> {
+    main_loop::<QuorumKvNode>()
+}
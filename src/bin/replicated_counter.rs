@@ -0,0 +1,25 @@
+//! # The Replicated Counter Node (Server)
+//!
+//! A counter for the `pn-counter` workload that, unlike the CRDT-based `pn_counter` node,
+//! replicates the additions through a consensus log (Raft, or Multi-Paxos with `CONSENSUS=multi-paxos`),
+//! so its reads are linearizable,
+//! at the cost of being unavailable without a majority of the nodes.
+//!
+//! It's a replica of a [`gossip_glomers::rsm::Counter`]; see [`gossip_glomers::rsm`].
+//!
+//! [Workload: Pn-counter](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-pn-counter)
+//!
+//! Run as:
+//!
+//! ```
+//! ~/maelstrom/maelstrom test -w pn-counter --bin target/debug/replicated_counter --node-count 3 --time-limit 20 --rate 100 --nemesis partition
+//!
+//! cargo build --bin replicated_counter && ~/maelstrom/maelstrom test -w pn-counter --bin target/debug/replicated_counter --node-count 3 --time-limit 3 --rate 100 --nemesis partition
+//! ```
+
+use anyhow::Result;
+use gossip_glomers::rsm::{replica_main_loop, Counter};
+
+fn main() -> Result<()> {
+    replica_main_loop::<Counter>()
+}
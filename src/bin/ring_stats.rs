@@ -0,0 +1,71 @@
+//! # The Ring Statistics
+//!
+//! Shows how evenly a consistent-hash ring spreads keys over its nodes, and how many keys move when a node
+//! joins or leaves; see [`gossip_glomers::ring`].
+//!
+//! It isn't a node. It builds a ring of the nodes `n0`, `n1`, ..., like Maelstrom names them, from the arguments:
+//! the number of nodes (`5` by default), the number of virtual nodes per node (the ring's default),
+//! and the number of sample keys (`100000` by default).
+//!
+//! Run as:
+//!
+//! ```
+//! target/debug/ring_stats 5 64 100000
+//! ```
+
+use anyhow::{Context, Result};
+use gossip_glomers::ring::{Ring, DEFAULT_VNODES};
+
+/// Parses the argument at `index`, or takes the default.
+fn arg(index: usize, default: usize) -> Result<usize> {
+    match std::env::args().nth(index) {
+        Some(arg) => arg
+            .parse()
+            .with_context(|| format!("expected a number, not {arg}")),
+        None => Ok(default),
+    }
+}
+
+fn main() -> Result<()> {
+    let node_count = arg(1, 5)?;
+    let vnodes = arg(2, DEFAULT_VNODES)?;
+    let key_count = arg(3, 100_000)?;
+
+    let ring = Ring::with_nodes((0..node_count).map(|node| format!("n{node}")), vnodes);
+    let keys: Vec<String> = (0..key_count).map(|key| key.to_string()).collect();
+    let distribution = ring.distribution(keys.iter().map(String::as_str));
+
+    println!("{node_count} nodes, {vnodes} virtual nodes each, {key_count} keys");
+    println!();
+    println!("node    ownership   keys");
+    let ownership = ring.ownership();
+    for (node, count) in &distribution.counts {
+        println!("{node:<7} {:>8.2}%   {count}", ownership[node] * 100.0);
+    }
+    println!();
+    println!(
+        "keys per node: mean {:.1}, std dev {:.1}, max/mean {:.3}",
+        distribution.mean(),
+        distribution.std_dev(),
+        distribution.max_over_mean()
+    );
+
+    let mut joined = ring.clone();
+    joined.add(format!("n{node_count}"));
+    println!(
+        "keys moved when a node joins: {:.2}% (ideally {:.2}%)",
+        ring.moved(&joined) * 100.0,
+        100.0 / (node_count + 1) as f64
+    );
+    if node_count > 1 {
+        let mut left = ring.clone();
+        left.remove("n0");
+        println!(
+            "keys moved when a node leaves: {:.2}% (ideally {:.2}%)",
+            ring.moved(&left) * 100.0,
+            100.0 / node_count as f64
+        );
+    }
+
+    Ok(())
+}
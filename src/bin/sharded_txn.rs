@@ -0,0 +1,204 @@
+//! # The Sharded Transactional List-Append Node (Server)
+//!
+//! Executes strictly-serializable transactions over lists, like the `txn_list_append` node, but keeps the lists
+//! itself, sharded across the nodes, and commits the transactions that span several shards with two-phase commit;
+//! see [`gossip_glomers::two_phase_commit`]. Only the commit decisions go to Maelstrom's `lin-kv` service.
+//!
+//! A transaction that runs into another one's locks is aborted with a `txn-conflict` error,
+//! and one that can't be prepared in time, e.g., because a shard is partitioned away, with an `abort` error.
+//!
+//! [Workload: Txn-list-append](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-txn-list-append)
+//!
+//! Run as:
+//!
+//! ```
+//! ~/maelstrom/maelstrom test -w txn-list-append --bin target/debug/sharded_txn --node-count 3 --time-limit 20 --rate 100 --nemesis partition
+//!
+//! cargo build --bin sharded_txn && ~/maelstrom/maelstrom test -w txn-list-append --bin target/debug/sharded_txn --node-count 3 --time-limit 3 --rate 100 --nemesis partition
+//! ```
+
+use anyhow::Result;
+use gossip_glomers::logic::main_loop;
+use gossip_glomers::message::{
+    Decision, ErrorCode, KvPayload, Message, ShardedTxnNodePayload, TxnPayload, LIN_KV,
+};
+use gossip_glomers::node::Node;
+use gossip_glomers::two_phase_commit::{self, record_key, Records, Replies, TwoPhaseCommit};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::StdoutLock;
+use std::time::Duration;
+
+/// How often the node ticks its two-phase commit module.
+const TICK_INTERVAL: Duration = Duration::from_millis(20);
+
+/// # The Sharded Transactional List-Append Node (Server)
+#[derive(Debug)]
+struct ShardedTxnNode {
+    /// A unique node name. Maelstrom sets the node ID for our node(s), during the initialization phase.
+    pub node_id: Option<String>,
+    /// A locally-unique integer identifier for a message from a node. It isn't globally-unique.
+    pub msg_id: usize,
+    /// The two-phase commit module; it's created during the initialization phase, when we learn about the cluster.
+    pub two_phase_commit: Option<TwoPhaseCommit>,
+    /// The decisions that we are recording in `lin-kv`, by the `msg_id`s of the requests;
+    /// only the latest request for every transaction.
+    recording: HashMap<usize, (String, Decision)>,
+}
+
+impl ShardedTxnNode {
+    fn two_phase_commit(&mut self) -> &mut TwoPhaseCommit {
+        self.two_phase_commit
+            .as_mut()
+            .expect("expected some self.two_phase_commit")
+    }
+
+    /// Sends the two-phase commit messages to their destinations, the decisions to `lin-kv`,
+    /// and the responses to the clients.
+    fn send(
+        &mut self,
+        outbox: two_phase_commit::Outbox,
+        records: Records,
+        replies: Replies,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        for (dest, payload) in outbox {
+            let payload = ShardedTxnNodePayload::TwoPhase(payload);
+            self.request(dest, payload, output_lock, "two-phase commit")?;
+        }
+        for (txn_id, decision) in records {
+            // A retry: the response to the earlier request is lost, or late, and the one to this request tells the same.
+            self.recording
+                .retain(|_, (recording, _)| *recording != txn_id);
+            self.recording
+                .insert(self.msg_id, (txn_id.clone(), decision));
+            let decision = serde_json::to_value(decision)?;
+            let payload = ShardedTxnNodePayload::Kv(KvPayload::Cas {
+                key: Value::from(record_key(&txn_id)),
+                from: decision.clone(),
+                to: decision,
+                create_if_not_exists: true,
+            });
+            self.request(LIN_KV.to_string(), payload, output_lock, "cas")?;
+        }
+        for (client, msg_id, output) in replies {
+            let payload = match output {
+                Ok(payload) => ShardedTxnNodePayload::Txn(payload),
+                Err(error) => ShardedTxnNodePayload::Error(error),
+            };
+            self.respond(client, msg_id, payload, output_lock, "txn output")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Node for ShardedTxnNode {
+    type Payload = ShardedTxnNodePayload;
+
+    fn new() -> Self {
+        Self {
+            node_id: None,
+            msg_id: 0,
+            two_phase_commit: None,
+            recording: HashMap::new(),
+        }
+    }
+
+    fn get_msg_id(&self) -> usize {
+        self.msg_id
+    }
+
+    fn incr_msg_id(&mut self) {
+        self.msg_id += 1;
+    }
+
+    fn get_node_id(&self) -> Option<String> {
+        self.node_id.clone()
+    }
+
+    fn set_node_id(&mut self, value: Option<String>) {
+        self.node_id = value;
+    }
+
+    fn set_node_ids(&mut self, node_ids: Vec<String>) {
+        let node_id = self.node_id.clone().expect("expected some self.node_id");
+        self.two_phase_commit = Some(TwoPhaseCommit::new(node_id, &node_ids));
+    }
+
+    fn step(
+        &mut self,
+        request: Message<Self::Payload>,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        let mut replies = Replies::new();
+        let mut records = Records::new();
+
+        let outbox = match request.body.payload {
+            ShardedTxnNodePayload::Txn(TxnPayload::Txn { txn }) => self.two_phase_commit().begin(
+                request.src,
+                request.body.msg_id,
+                txn,
+                &mut replies,
+                &mut records,
+            ),
+            ShardedTxnNodePayload::Txn(TxnPayload::TxnOk { .. }) => Vec::new(),
+            ShardedTxnNodePayload::TwoPhase(two_phase_payload) => self.two_phase_commit().handle(
+                request.src,
+                two_phase_payload,
+                &mut replies,
+                &mut records,
+            ),
+            payload @ (ShardedTxnNodePayload::Kv(_) | ShardedTxnNodePayload::Error(_)) => {
+                let Some((txn_id, decision)) = request
+                    .body
+                    .in_reply_to
+                    .and_then(|in_reply_to| self.recording.remove(&in_reply_to))
+                else {
+                    return Ok(());
+                };
+                // The compare-and-set only fails its precondition if the other decision is recorded.
+                let recorded = match payload {
+                    ShardedTxnNodePayload::Kv(KvPayload::CasOk) => decision,
+                    ShardedTxnNodePayload::Error(error)
+                        if error.code == ErrorCode::PreconditionFailed =>
+                    {
+                        match decision {
+                            Decision::Commit => Decision::Abort,
+                            Decision::Abort => Decision::Commit,
+                        }
+                    }
+                    // Unknown; the decision is recorded again later.
+                    _ => return Ok(()),
+                };
+                self.two_phase_commit()
+                    .on_recorded(txn_id, recorded, &mut replies, &mut records)
+            }
+        };
+
+        self.send(outbox, records, replies, output_lock)
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(TICK_INTERVAL)
+    }
+
+    fn tick(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        let mut replies = Replies::new();
+        let mut records = Records::new();
+        let outbox = self.two_phase_commit().tick(&mut replies, &mut records);
+        // Decided some other way, e.g., by the coordinator, while the response to our request is lost.
+        let two_phase_commit = self
+            .two_phase_commit
+            .as_ref()
+            .expect("expected some self.two_phase_commit");
+        self.recording
+            .retain(|_, (txn_id, _)| two_phase_commit.is_recording(txn_id));
+        self.send(outbox, records, replies, output_lock)
+    }
+}
+
+fn main() -> Result<()> {
+    main_loop::<ShardedTxnNode>()
+}
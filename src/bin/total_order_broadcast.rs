@@ -0,0 +1,178 @@
+//! # The Total-Order Broadcast Node (Server)
+//!
+//! A variant of the broadcast node, for the same workload, in which every node delivers the values
+//! in the same order, and `read_ok` returns them in that order.
+//!
+//! The order is agreed on with Raft; see [`gossip_glomers::total_order`]. A `broadcast` is acknowledged
+//! as soon as the node has taken the value on; it's delivered once a majority of the nodes is reachable.
+//!
+//! [Workload: Broadcast](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-broadcast)
+//!
+//! Maelstrom's broadcast checker doesn't look at the order of the values, so the `check_total_order` binary
+//! checks the test's history afterwards: that all reads are prefixes of one another.
+//!
+//! Run as:
+//!
+//! ```
+//! ~/maelstrom/maelstrom test -w broadcast --bin target/debug/total_order_broadcast --node-count 5 --time-limit 20 --rate 10 --nemesis partition
+//! target/debug/check_total_order store/latest/history.txt
+//!
+//! cargo build --bins && ~/maelstrom/maelstrom test -w broadcast --bin target/debug/total_order_broadcast --node-count 5 --time-limit 3 --rate 10 --nemesis partition
+//! ```
+
+use anyhow::Result;
+use gossip_glomers::logic::main_loop;
+use gossip_glomers::message::{Message, TotalOrderNodePayload, TotalOrderPayload};
+use gossip_glomers::node::Node;
+use gossip_glomers::raft::Outbox;
+use gossip_glomers::total_order::TotalOrderBroadcast;
+use std::fmt::Debug;
+use std::io::StdoutLock;
+use std::time::Duration;
+
+/// How often the node ticks its total-order broadcast module.
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// # The Total-Order Broadcast Node (Server)
+#[derive(Default, Debug)]
+struct TotalOrderBroadcastNode {
+    /// A unique node name. Maelstrom sets the node ID for our node(s), during the initialization phase.
+    pub node_id: Option<String>,
+    /// A locally-unique integer identifier for a message from a node. It isn't globally-unique.
+    pub msg_id: usize,
+    /// The total-order broadcast module; it's created during the initialization phase,
+    /// when we learn about the cluster.
+    pub broadcast: Option<TotalOrderBroadcast>,
+}
+
+impl TotalOrderBroadcastNode {
+    fn broadcast(&mut self) -> &mut TotalOrderBroadcast {
+        self.broadcast
+            .as_mut()
+            .expect("expected some self.broadcast")
+    }
+
+    /// Sends the Raft messages to their destinations.
+    fn send(&mut self, outbox: Outbox<usize>, output_lock: &mut StdoutLock) -> Result<()> {
+        for (dest, payload) in outbox {
+            self.request(
+                dest,
+                TotalOrderNodePayload::Raft(payload),
+                output_lock,
+                "raft",
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Node for TotalOrderBroadcastNode {
+    type Payload = TotalOrderNodePayload;
+
+    fn new() -> Self {
+        Self {
+            node_id: None,
+            msg_id: 0,
+            broadcast: None,
+        }
+    }
+
+    fn get_msg_id(&self) -> usize {
+        self.msg_id
+    }
+
+    fn incr_msg_id(&mut self) {
+        self.msg_id += 1;
+    }
+
+    fn get_node_id(&self) -> Option<String> {
+        self.node_id.clone()
+    }
+
+    fn set_node_id(&mut self, value: Option<String>) {
+        self.node_id = value;
+    }
+
+    fn set_node_ids(&mut self, node_ids: Vec<String>) {
+        let node_id = self.node_id.clone().expect("expected some self.node_id");
+        self.broadcast = Some(TotalOrderBroadcast::new(node_id, node_ids));
+    }
+
+    fn step(
+        &mut self,
+        request: Message<Self::Payload>,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        match request.body.payload {
+            TotalOrderNodePayload::Broadcast(payload) => match payload {
+                TotalOrderPayload::Broadcast { message } => {
+                    self.broadcast().broadcast([message]);
+
+                    let payload = TotalOrderNodePayload::Broadcast(TotalOrderPayload::BroadcastOk);
+                    self.respond(
+                        request.src,
+                        request.body.msg_id,
+                        payload,
+                        output_lock,
+                        "broadcast_ok",
+                    )?;
+                }
+                TotalOrderPayload::Read => {
+                    let payload = TotalOrderNodePayload::Broadcast(TotalOrderPayload::ReadOk {
+                        messages: self.broadcast().delivered().to_vec(),
+                    });
+                    self.respond(
+                        request.src,
+                        request.body.msg_id,
+                        payload,
+                        output_lock,
+                        "read_ok",
+                    )?;
+                }
+                TotalOrderPayload::Topology { .. } => {
+                    let payload = TotalOrderNodePayload::Broadcast(TotalOrderPayload::TopologyOk);
+                    self.respond(
+                        request.src,
+                        request.body.msg_id,
+                        payload,
+                        output_lock,
+                        "topology_ok",
+                    )?;
+                }
+                TotalOrderPayload::Forward { messages } => {
+                    self.broadcast().broadcast(messages);
+                }
+                TotalOrderPayload::BroadcastOk
+                | TotalOrderPayload::ReadOk { .. }
+                | TotalOrderPayload::TopologyOk => {}
+            },
+            TotalOrderNodePayload::Raft(raft_payload) => {
+                let outbox = self.broadcast().handle(request.src, raft_payload);
+                self.send(outbox, output_lock)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(TICK_INTERVAL)
+    }
+
+    fn tick(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        let outbox = self.broadcast().tick();
+        self.send(outbox, output_lock)?;
+
+        if let Some((leader, messages)) = self.broadcast().forward() {
+            let payload = TotalOrderNodePayload::Broadcast(TotalOrderPayload::Forward { messages });
+            self.request(leader, payload, output_lock, "forward")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    main_loop::<TotalOrderBroadcastNode>()
+}
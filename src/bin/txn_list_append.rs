@@ -0,0 +1,474 @@
+//! # The Transactional List-Append Node (Server)
+//!
+//! Executes strictly-serializable transactions over lists, using Maelstrom's key-value services for storage.
+//!
+//! The database is a persistent (immutable) data structure, made of "thunks" - values that are written
+//! only once, under fresh, unique keys, into the eventually-consistent `lww-kv` service:
+//! - every list is stored in a thunk of its own;
+//! - a map from keys to the thunks of their lists is stored in another thunk;
+//! - the single mutable piece of state is the root pointer - the key of the current map thunk -
+//!   which lives in the linearizable `lin-kv` service.
+//!
+//! A transaction reads the root pointer, the map and the lists it needs, and executes locally.
+//! If it appends anything, it writes new copies of the affected lists and of the map (copy-on-write),
+//! and then atomically swings the root pointer from the map it read to the new one, with a compare-and-set.
+//! If the root pointer has moved in the meantime, the transaction is aborted with a `txn-conflict` error.
+//!
+//! Because thunks never change, a thunk that is visible in `lww-kv` at all is up to date,
+//! and it can be cached forever. One that is not visible yet is simply read again a bit later.
+//!
+//! [Workload: Txn-list-append](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-txn-list-append)
+//!
+//! Run as:
+//!
+//! ```
+//! ~/maelstrom/maelstrom test -w txn-list-append --bin target/debug/txn_list_append --node-count 2 --time-limit 20 --rate 100 --nemesis partition
+//!
+//! cargo build --bin txn_list_append && ~/maelstrom/maelstrom test -w txn-list-append --bin target/debug/txn_list_append --node-count 2 --time-limit 3 --rate 100 --nemesis partition
+//! ```
+
+use anyhow::Result;
+use gossip_glomers::logic::main_loop;
+use gossip_glomers::message::{
+    ErrorCode, ErrorPayload, KvPayload, Message, MicroOp, TxnNodePayload, TxnPayload, LIN_KV,
+    LWW_KV,
+};
+use gossip_glomers::node::Node;
+use serde_json::Value;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::StdoutLock;
+use std::time::{Duration, Instant};
+
+/// The key of the root pointer in `lin-kv`.
+const ROOT_KEY: &str = "root";
+
+/// How often the node retries reading thunks that weren't visible yet, and looks for stale transactions.
+const TICK_INTERVAL: Duration = Duration::from_millis(20);
+
+/// After how long a transaction is given up on; its outcome is then unknown to us.
+const TXN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The phases of a transaction's execution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for the root pointer from `lin-kv`.
+    ReadingRoot,
+    /// Waiting for the map thunk and the list thunks.
+    ReadingThunks,
+    /// Waiting for the new thunks to be written.
+    Writing,
+    /// Waiting for the root pointer to be swung to the new map.
+    Committing,
+}
+
+/// What a request to a key-value service was about.
+#[derive(Clone, Debug)]
+enum Pending {
+    Root,
+    Thunk(String),
+    Write,
+    Cas,
+}
+
+/// A transaction in progress.
+#[derive(Debug)]
+struct Txn {
+    /// The client that requested the transaction.
+    client: String,
+    /// The `msg_id` of the request.
+    msg_id: Option<usize>,
+    /// The micro-operations; once executed, with the values of the reads filled in.
+    ops: Vec<MicroOp>,
+    phase: Phase,
+    /// The root pointer that the transaction read, if there was one.
+    root: Option<String>,
+    /// The map from keys to list thunks, once it's known.
+    map: Option<HashMap<usize, String>>,
+    /// The number of requests to the key-value services that we are still waiting for.
+    outstanding: usize,
+    /// The key of the new map thunk, when the transaction appends anything.
+    new_root: Option<String>,
+    started: Instant,
+}
+
+/// # The Transactional List-Append Node (Server)
+///
+/// Provides strict serializability with a root pointer in `lin-kv` and copy-on-write thunks in `lww-kv`.
+#[derive(Default, Debug)]
+struct TxnListAppendNode {
+    /// A unique node name. Maelstrom sets the node ID for our node(s), during the initialization phase.
+    pub node_id: Option<String>,
+    /// A locally-unique integer identifier for a message from a node. It isn't globally-unique.
+    pub msg_id: usize,
+    /// A counter for locally-unique IDs of transactions and thunks.
+    pub next_id: usize,
+    /// The thunks that we have read or written, by their keys; they never change.
+    pub thunks: HashMap<String, Value>,
+    /// The transactions in progress, by their local IDs.
+    txns: HashMap<usize, Txn>,
+    /// Requests to the key-value services that we are waiting for, by their `msg_id`s,
+    /// mapped to the transactions they belong to.
+    pending: HashMap<usize, (usize, Pending)>,
+    /// Thunks that weren't visible in `lww-kv` yet, to read again on the next tick, per transaction.
+    retries: Vec<(usize, String)>,
+}
+
+impl TxnListAppendNode {
+    fn new_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// A fresh, globally-unique key for a new thunk.
+    fn new_thunk_key(&mut self) -> String {
+        let node_id = self.node_id.clone().expect("expected some self.node_id");
+        format!("{node_id}-{}", self.new_id())
+    }
+
+    /// Sends a request to a key-value service on behalf of the transaction.
+    fn kv_request(
+        &mut self,
+        txn_id: usize,
+        service: &str,
+        what: Pending,
+        payload: KvPayload,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        self.pending.insert(self.msg_id, (txn_id, what));
+        if let Some(txn) = self.txns.get_mut(&txn_id) {
+            txn.outstanding += 1;
+        }
+        self.request(
+            service.to_string(),
+            TxnNodePayload::Kv(payload),
+            output_lock,
+            "kv",
+        )
+    }
+
+    /// Makes sure that the thunk is (or will be) in the cache.
+    fn fetch_thunk(
+        &mut self,
+        txn_id: usize,
+        key: String,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        if self.thunks.contains_key(&key) {
+            return Ok(());
+        }
+
+        let payload = KvPayload::Read {
+            key: Value::from(key.clone()),
+        };
+        self.kv_request(txn_id, LWW_KV, Pending::Thunk(key), payload, output_lock)
+    }
+
+    /// Moves the transaction forward as far as it can go with what we know so far.
+    fn advance(&mut self, txn_id: usize, output_lock: &mut StdoutLock) -> Result<()> {
+        let Some(txn) = self.txns.get(&txn_id) else {
+            return Ok(());
+        };
+        if txn.outstanding > 0 || self.retries.iter().any(|(id, _)| *id == txn_id) {
+            return Ok(());
+        }
+
+        match txn.phase {
+            Phase::ReadingRoot => Ok(()),
+            Phase::ReadingThunks => {
+                // First the map, then the lists that the transaction touches.
+                let map = match (&txn.map, &txn.root) {
+                    (Some(map), _) => map.clone(),
+                    (None, None) => HashMap::new(),
+                    (None, Some(root)) => match self.thunks.get(root) {
+                        Some(map) => serde_json::from_value(map.clone())?,
+                        None => return self.fetch_thunk(txn_id, root.clone(), output_lock),
+                    },
+                };
+
+                let missing: Vec<String> = txn
+                    .ops
+                    .iter()
+                    .filter_map(|op| map.get(&op.key()))
+                    .filter(|key| !self.thunks.contains_key(*key))
+                    .cloned()
+                    .collect();
+                self.txns.get_mut(&txn_id).expect("txn exists").map = Some(map);
+
+                if missing.is_empty() {
+                    self.execute(txn_id, output_lock)
+                } else {
+                    for key in missing {
+                        self.fetch_thunk(txn_id, key, output_lock)?;
+                    }
+                    Ok(())
+                }
+            }
+            Phase::Writing => {
+                let txn = self.txns.get_mut(&txn_id).expect("txn exists");
+                txn.phase = Phase::Committing;
+                let payload = KvPayload::Cas {
+                    key: Value::from(ROOT_KEY),
+                    from: Value::from(txn.root.clone()),
+                    to: Value::from(txn.new_root.clone()),
+                    create_if_not_exists: true,
+                };
+                self.kv_request(txn_id, LIN_KV, Pending::Cas, payload, output_lock)
+            }
+            Phase::Committing => Ok(()),
+        }
+    }
+
+    /// Executes the transaction locally, on the lists that it read.
+    ///
+    /// Read-only transactions are done right away; the others write their new thunks.
+    fn execute(&mut self, txn_id: usize, output_lock: &mut StdoutLock) -> Result<()> {
+        let mut txn = self.txns.remove(&txn_id).expect("txn exists");
+        let mut map = txn.map.take().unwrap_or_default();
+
+        // The current state of every list that the transaction touches, and the keys of the appended lists.
+        let mut lists: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut appended = Vec::new();
+        for op in &mut txn.ops {
+            let key = op.key();
+            if let (Entry::Vacant(entry), Some(thunk)) = (lists.entry(key), map.get(&key)) {
+                entry.insert(serde_json::from_value(self.thunks[thunk].clone())?);
+            }
+
+            match op {
+                MicroOp::Read { value, .. } => *value = lists.get(&key).cloned(),
+                MicroOp::Append { element, .. } => {
+                    lists.entry(key).or_default().push(*element);
+                    if !appended.contains(&key) {
+                        appended.push(key);
+                    }
+                }
+            }
+        }
+
+        if appended.is_empty() {
+            let payload = TxnNodePayload::Txn(TxnPayload::TxnOk { txn: txn.ops });
+            return self.respond(txn.client, txn.msg_id, payload, output_lock, "txn_ok");
+        }
+
+        // Copy-on-write: new thunks for the appended lists, and for the map that points to them.
+        let mut writes = Vec::new();
+        for key in appended {
+            let thunk = self.new_thunk_key();
+            let list = Value::from(lists.remove(&key).unwrap_or_default());
+            map.insert(key, thunk.clone());
+            writes.push((thunk, list));
+        }
+        let new_root = self.new_thunk_key();
+        writes.push((new_root.clone(), serde_json::to_value(&map)?));
+
+        txn.phase = Phase::Writing;
+        txn.map = Some(map);
+        txn.new_root = Some(new_root);
+        self.txns.insert(txn_id, txn);
+
+        for (key, value) in writes {
+            self.thunks.insert(key.clone(), value.clone());
+            let payload = KvPayload::Write {
+                key: Value::from(key),
+                value,
+            };
+            self.kv_request(txn_id, LWW_KV, Pending::Write, payload, output_lock)?;
+        }
+
+        Ok(())
+    }
+
+    /// Ends the transaction with an error.
+    fn fail(
+        &mut self,
+        txn_id: usize,
+        code: ErrorCode,
+        text: String,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        if let Some(txn) = self.txns.remove(&txn_id) {
+            self.pending.retain(|_, (id, _)| *id != txn_id);
+            self.retries.retain(|(id, _)| *id != txn_id);
+            let payload = TxnNodePayload::Error(ErrorPayload {
+                code,
+                text: Some(text),
+            });
+            self.respond(txn.client, txn.msg_id, payload, output_lock, "error")?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles a response from a key-value service.
+    fn on_kv_response(
+        &mut self,
+        txn_id: usize,
+        what: Pending,
+        payload: TxnNodePayload,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        let Some(txn) = self.txns.get_mut(&txn_id) else {
+            return Ok(());
+        };
+        txn.outstanding -= 1;
+
+        match (what, payload) {
+            (Pending::Root, TxnNodePayload::Kv(KvPayload::ReadOk { value })) => {
+                txn.root = value.as_str().map(str::to_string);
+                txn.phase = Phase::ReadingThunks;
+            }
+            (Pending::Root, TxnNodePayload::Error(error))
+                if error.code == ErrorCode::KeyDoesNotExist =>
+            {
+                // The very first transaction: the database is empty.
+                txn.phase = Phase::ReadingThunks;
+            }
+            (Pending::Thunk(key), TxnNodePayload::Kv(KvPayload::ReadOk { value })) => {
+                self.thunks.insert(key, value);
+            }
+            (Pending::Thunk(key), TxnNodePayload::Error(error))
+                if error.code == ErrorCode::KeyDoesNotExist =>
+            {
+                // Not replicated to the replica we asked yet; it will be.
+                self.retries.push((txn_id, key));
+            }
+            (Pending::Write, TxnNodePayload::Kv(KvPayload::WriteOk)) => {}
+            (Pending::Cas, TxnNodePayload::Kv(KvPayload::CasOk)) => {
+                let txn = self.txns.remove(&txn_id).expect("txn exists");
+                let payload = TxnNodePayload::Txn(TxnPayload::TxnOk { txn: txn.ops });
+                return self.respond(txn.client, txn.msg_id, payload, output_lock, "txn_ok");
+            }
+            (Pending::Cas, TxnNodePayload::Error(error))
+                if error.code == ErrorCode::PreconditionFailed =>
+            {
+                return self.fail(
+                    txn_id,
+                    ErrorCode::TxnConflict,
+                    "the database was changed by a concurrent transaction".to_string(),
+                    output_lock,
+                );
+            }
+            (what, payload) => {
+                return self.fail(
+                    txn_id,
+                    ErrorCode::Crash,
+                    format!("unexpected response to {what:?}: {payload:?}"),
+                    output_lock,
+                );
+            }
+        }
+
+        self.advance(txn_id, output_lock)
+    }
+}
+
+impl Node for TxnListAppendNode {
+    type Payload = TxnNodePayload;
+
+    fn new() -> Self {
+        Self {
+            node_id: None,
+            msg_id: 0,
+            next_id: 0,
+            thunks: HashMap::new(),
+            txns: HashMap::new(),
+            pending: HashMap::new(),
+            retries: Vec::new(),
+        }
+    }
+
+    fn get_msg_id(&self) -> usize {
+        self.msg_id
+    }
+
+    fn incr_msg_id(&mut self) {
+        self.msg_id += 1;
+    }
+
+    fn get_node_id(&self) -> Option<String> {
+        self.node_id.clone()
+    }
+
+    fn set_node_id(&mut self, value: Option<String>) {
+        self.node_id = value;
+    }
+
+    fn step(
+        &mut self,
+        request: Message<Self::Payload>,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        match request.body.payload {
+            TxnNodePayload::Txn(TxnPayload::Txn { txn }) => {
+                let txn_id = self.new_id();
+                self.txns.insert(
+                    txn_id,
+                    Txn {
+                        client: request.src,
+                        msg_id: request.body.msg_id,
+                        ops: txn,
+                        phase: Phase::ReadingRoot,
+                        root: None,
+                        map: None,
+                        outstanding: 0,
+                        new_root: None,
+                        started: Instant::now(),
+                    },
+                );
+
+                let payload = KvPayload::Read {
+                    key: Value::from(ROOT_KEY),
+                };
+                self.kv_request(txn_id, LIN_KV, Pending::Root, payload, output_lock)?;
+            }
+            TxnNodePayload::Txn(TxnPayload::TxnOk { .. }) => {}
+            payload @ (TxnNodePayload::Kv(_) | TxnNodePayload::Error(_)) => {
+                let pending = request
+                    .body
+                    .in_reply_to
+                    .and_then(|in_reply_to| self.pending.remove(&in_reply_to));
+                if let Some((txn_id, what)) = pending {
+                    self.on_kv_response(txn_id, what, payload, output_lock)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(TICK_INTERVAL)
+    }
+
+    fn tick(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        let stale: Vec<usize> = self
+            .txns
+            .iter()
+            .filter(|(_, txn)| txn.started.elapsed() >= TXN_TIMEOUT)
+            .map(|(&txn_id, _)| txn_id)
+            .collect();
+        for txn_id in stale {
+            // We can't tell whether the root pointer was swung or not, so the outcome is indefinite.
+            self.fail(
+                txn_id,
+                ErrorCode::Timeout,
+                "the transaction timed out".to_string(),
+                output_lock,
+            )?;
+        }
+
+        for (txn_id, key) in std::mem::take(&mut self.retries) {
+            self.fetch_thunk(txn_id, key, output_lock)?;
+            // Another transaction may have fetched it in the meantime.
+            self.advance(txn_id, output_lock)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    main_loop::<TxnListAppendNode>()
+}
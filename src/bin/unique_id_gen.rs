@@ -29,14 +29,72 @@
 //! It will also verify that all IDs are unique.
 //!
 //! Everything looks good! ヽ(‘ー`)ノ
+//!
+//! The ID scheme is selected with the `ID_SCHEME` environment variable:
+//! - `node-msg-id` (the default): `"{node_id}-{counter}"` strings;
+//! - `snowflake`: time-sortable 64-bit integers, made of a millisecond timestamp, the node's index
+//!   in the cluster and a per-millisecond sequence number;
+//! - `uuid-v4`: random UUIDs;
+//! - `uuid-v7`: time-sortable UUIDs;
+//! - `ulid`: time-sortable, lexicographically-sortable strings;
+//! - `block-lease`: integers from blocks of IDs that are leased by each node;
+//! - `lin-kv-lease`: dense, small integers from blocks that are leased from a shared counter
+//!   in Maelstrom's `lin-kv` service.
+//!
+//! ```
+//! ID_SCHEME=snowflake ~/maelstrom/maelstrom test -w unique-ids --bin target/debug/unique_id_gen --time-limit 30 --rate 1000 --node-count 3 --availability total --nemesis partition
+//! ```
+//!
+//! With `lin-kv-lease`, a node leases a block of [`LEASE_BLOCK_SIZE`] IDs by compare-and-setting
+//! the shared counter from `n` to `n + LEASE_BLOCK_SIZE`, which grants it the IDs `n..n + LEASE_BLOCK_SIZE`.
+//! As soon as it starts handing out IDs from a block, it leases the next one in the background,
+//! so that it doesn't have to wait for `lin-kv` when the current block runs out.
+//!
+//! If it runs out nevertheless, because `lin-kv` is unreachable or slow (e.g., during a partition),
+//! the node stays available: it falls back to `"{node_id}-{counter}"` string IDs, which are still
+//! globally unique, and can't clash with the integers, but aren't dense.
+//! It goes back to leased integers as soon as it gets a new block.
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use gossip_glomers::id_gen::{BlockLease, IdGenerator, IdScheme};
 use gossip_glomers::logic::main_loop;
-use gossip_glomers::message::{GeneratePayload, Message, Payload};
+use gossip_glomers::message::{ErrorCode, GeneratePayload, KvPayload, Message, Payload, LIN_KV};
 use gossip_glomers::node::Node;
 use gossip_glomers::IdType;
+use serde_json::Value;
 use std::fmt::Debug;
 use std::io::StdoutLock;
+use std::time::{Duration, Instant};
+
+/// The key of the shared ID counter in `lin-kv`: the start of the next block to lease.
+const ID_COUNTER_KEY: &str = "id-counter";
+
+/// The number of IDs in a block leased from `lin-kv`.
+const LEASE_BLOCK_SIZE: u64 = 100;
+
+/// How often the node checks whether it should lease a new block.
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// After how long an unanswered lease request is given up on and retried.
+const LEASE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Where a lease request currently stands.
+#[derive(Clone, Copy, Debug)]
+enum LeaseStep {
+    /// Waiting for the current value of the shared counter.
+    Reading,
+    /// Waiting for the shared counter to be compare-and-set from `from` to the end of our new block.
+    Swapping { from: u64 },
+}
+
+/// A lease request in progress.
+#[derive(Clone, Debug)]
+struct LeaseRequest {
+    /// The `msg_id` of the request to `lin-kv` that we are waiting for.
+    msg_id: usize,
+    step: LeaseStep,
+    sent: Instant,
+}
 
 /// # The Unique ID Generator Node (Server)
 ///
@@ -53,14 +111,104 @@ pub struct UniqueIDGeneratorNode {
     /// A generated globally-unique ID.
     /// It may be of any type: strings, booleans, integers, floats, compound JSON values, etc.
     pub guid: IdType,
+    /// The ID scheme, selected at startup.
+    pub id_scheme: IdScheme,
+    /// The generator of the selected scheme; it's created during the initialization phase.
+    ///
+    /// With `lin-kv-lease`, it's the fallback generator, for when no leased IDs are left.
+    pub generator: Option<Box<dyn IdGenerator>>,
+    /// The blocks leased from `lin-kv`, with `lin-kv-lease`.
+    pub leased: Option<BlockLease>,
+    /// The lease request in progress, if any.
+    lease: Option<LeaseRequest>,
+}
+
+impl UniqueIDGeneratorNode {
+    /// Asks `lin-kv` for the current value of the shared counter, which starts a new lease.
+    fn read_counter(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        self.lease = Some(LeaseRequest {
+            msg_id: self.msg_id,
+            step: LeaseStep::Reading,
+            sent: Instant::now(),
+        });
+        let payload = Payload::Kv(KvPayload::Read {
+            key: Value::from(ID_COUNTER_KEY),
+        });
+        self.request(LIN_KV.to_string(), payload, output_lock, "read")
+    }
+
+    /// Tries to move the shared counter past the block that starts at `from`.
+    fn swap_counter(&mut self, from: u64, output_lock: &mut StdoutLock) -> Result<()> {
+        self.lease = Some(LeaseRequest {
+            msg_id: self.msg_id,
+            step: LeaseStep::Swapping { from },
+            sent: Instant::now(),
+        });
+        let payload = Payload::Kv(KvPayload::Cas {
+            key: Value::from(ID_COUNTER_KEY),
+            from: Value::from(from),
+            to: Value::from(from + LEASE_BLOCK_SIZE),
+            // The first lease ever creates the counter.
+            create_if_not_exists: from == 0,
+        });
+        self.request(LIN_KV.to_string(), payload, output_lock, "cas")
+    }
+
+    /// Handles a response from `lin-kv` to a lease request.
+    fn on_lease_response(
+        &mut self,
+        in_reply_to: Option<usize>,
+        payload: Payload,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        let step = match &self.lease {
+            Some(lease) if Some(lease.msg_id) == in_reply_to => lease.step,
+            // A response to a lease request that we have given up on.
+            _ => return Ok(()),
+        };
+        self.lease = None;
+
+        match (step, payload) {
+            (LeaseStep::Reading, Payload::Kv(KvPayload::ReadOk { value })) => {
+                let from = value.as_u64().context("expected an integer ID counter")?;
+                self.swap_counter(from, output_lock)?;
+            }
+            (LeaseStep::Reading, Payload::Error(error))
+                if error.code == ErrorCode::KeyDoesNotExist =>
+            {
+                self.swap_counter(0, output_lock)?;
+            }
+            (LeaseStep::Swapping { from }, Payload::Kv(KvPayload::CasOk)) => {
+                if let Some(leased) = &mut self.leased {
+                    leased.lease(from..from + LEASE_BLOCK_SIZE);
+                }
+            }
+            (LeaseStep::Swapping { .. }, Payload::Error(error))
+                if error.code == ErrorCode::PreconditionFailed =>
+            {
+                // Another node leased that block first; try again with the new value of the counter.
+                self.read_counter(output_lock)?;
+            }
+            // Anything else is retried on a later tick.
+            _ => {}
+        }
+
+        Ok(())
+    }
 }
 
 impl Node for UniqueIDGeneratorNode {
+    type Payload = Payload;
+
     fn new() -> Self {
         Self {
             node_id: None,
             msg_id: 0,
             guid: IdType::new(),
+            id_scheme: IdScheme::from_env().expect("expected a valid ID scheme"),
+            generator: None,
+            leased: None,
+            lease: None,
         }
     }
 
@@ -80,14 +228,41 @@ impl Node for UniqueIDGeneratorNode {
         self.node_id = value;
     }
 
-    fn step(&mut self, request: Message<Payload>, output_lock: &mut StdoutLock) -> Result<()> {
+    fn set_node_ids(&mut self, node_ids: Vec<String>) {
+        let node_id = self.node_id.clone().expect("expected some self.node_id");
+        let id_scheme = match self.id_scheme {
+            IdScheme::LinKvLease => {
+                self.leased = Some(BlockLease::new(LEASE_BLOCK_SIZE));
+                IdScheme::NodeMsgId
+            }
+            id_scheme => id_scheme,
+        };
+        self.generator = Some(
+            id_scheme
+                .generator(&node_id, &node_ids)
+                .expect("expected a generator for the node"),
+        );
+    }
+
+    fn step(
+        &mut self,
+        request: Message<Self::Payload>,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
         match request.body.payload {
             Payload::UniqueIdGen(generate_payload) => match generate_payload {
                 GeneratePayload::Generate => {
-                    let node_id = self.node_id.clone().expect("expected some self.node_id");
-                    let msg_id = self.msg_id;
-                    self.guid = format!("{node_id}-{msg_id}");
-
+                    let generator = self
+                        .generator
+                        .as_mut()
+                        .expect("expected some self.generator");
+                    // With `lin-kv-lease`, the fallback generator takes over when no leased IDs are left,
+                    // and none of the generators ever runs out on its own, so there always is an ID.
+                    let guid = match &mut self.leased {
+                        Some(leased) => leased.next_id().or_else(|| generator.next_id()),
+                        None => generator.next_id(),
+                    };
+                    self.guid = guid.context("expected the ID generator to have an ID")?;
                     let payload = Payload::UniqueIdGen(GeneratePayload::GenerateOk {
                         id: self.guid.clone(),
                     });
@@ -101,11 +276,31 @@ impl Node for UniqueIDGeneratorNode {
                 }
                 GeneratePayload::GenerateOk { .. } => {}
             },
+            payload @ (Payload::Kv(_) | Payload::Error(_)) => {
+                self.on_lease_response(request.body.in_reply_to, payload, output_lock)?;
+            }
             other => bail!("received unexpected request message type: {other:?}"),
         }
 
         Ok(())
     }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        self.leased.as_ref().map(|_| TICK_INTERVAL)
+    }
+
+    fn tick(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        let wants_block = self.leased.as_ref().is_some_and(BlockLease::wants_block);
+        let idle = self
+            .lease
+            .as_ref()
+            .is_none_or(|lease| lease.sent.elapsed() >= LEASE_TIMEOUT);
+        if wants_block && idle {
+            self.read_counter(output_lock)?;
+        }
+
+        Ok(())
+    }
 }
 
 fn main() -> Result<()> {
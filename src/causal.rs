@@ -0,0 +1,224 @@
+//! # Causal Broadcast
+//!
+//! Delivers broadcast values in causal order: if a node had delivered a value before it broadcast another,
+//! then every node delivers the former before the latter. Concurrent values may be delivered in any order.
+//!
+//! Every value carries a vector clock of its causal dependencies; see [`CausalMessage`].
+//! A value that arrives before its dependencies is buffered until they are delivered.
+//!
+//! [Lightweight Causal and Atomic Group Multicast](https://doi.org/10.1145/128738.128742)
+//!
+//! This is only the delivery layer. It doesn't spread the values on its own; any reliable dissemination
+//! will do, in any order, as long as it carries the [`CausalMessage`]s as they are.
+
+use crate::clock::VectorClock;
+use crate::message::CausalMessage;
+use std::collections::HashMap;
+
+/// # A Causal Broadcast Delivery Layer
+#[derive(Debug, Default)]
+pub struct CausalBroadcast {
+    /// Our own node ID.
+    node_id: String,
+    /// For every node, the number of its values that we have delivered.
+    delivered: VectorClock,
+    /// The delivered values, in the order of delivery.
+    order: Vec<usize>,
+    /// All the values that we have received, delivered or not, with their causal dependencies.
+    messages: HashMap<usize, CausalMessage>,
+    /// The values that have been received, but whose dependencies haven't all been delivered yet.
+    pending: Vec<usize>,
+}
+
+impl CausalBroadcast {
+    /// Creates a new causal broadcast layer for the node `node_id`.
+    pub fn new(node_id: String) -> Self {
+        Self {
+            node_id,
+            ..Self::default()
+        }
+    }
+
+    /// The delivered values, in causal order.
+    pub fn delivered(&self) -> &[usize] {
+        &self.order
+    }
+
+    /// The number of received values that are waiting for their dependencies.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The received value, with its causal dependencies, for spreading it further.
+    pub fn message(&self, value: usize) -> Option<&CausalMessage> {
+        self.messages.get(&value)
+    }
+
+    /// Broadcasts a value from this node, which is delivered here at once; returns it with its dependencies,
+    /// for spreading it.
+    ///
+    /// Returns `None` if the value has already been received.
+    pub fn broadcast(&mut self, value: usize) -> Option<CausalMessage> {
+        if self.messages.contains_key(&value) {
+            return None;
+        }
+
+        let mut clock = self.delivered.clone();
+        clock.increment(&self.node_id);
+        let message = CausalMessage {
+            origin: self.node_id.clone(),
+            clock,
+            value,
+        };
+        self.messages.insert(value, message.clone());
+        self.deliver(value);
+        Some(message)
+    }
+
+    /// Receives a value from another node; returns the values that have become deliverable, in causal order.
+    pub fn receive(&mut self, message: CausalMessage) -> Vec<usize> {
+        if self.messages.contains_key(&message.value) {
+            return Vec::new();
+        }
+        self.pending.push(message.value);
+        self.messages.insert(message.value, message);
+
+        let mut delivered = Vec::new();
+        while let Some(position) = self
+            .pending
+            .iter()
+            .position(|value| self.is_deliverable(&self.messages[value]))
+        {
+            let value = self.pending.swap_remove(position);
+            self.deliver(value);
+            delivered.push(value);
+        }
+        delivered
+    }
+
+    /// Whether the message is next from its origin, and all its other dependencies have been delivered.
+    fn is_deliverable(&self, message: &CausalMessage) -> bool {
+        message.clock.iter().all(|(node_id, &time)| {
+            let delivered = self.delivered.get(node_id);
+            if *node_id == message.origin {
+                time == delivered + 1
+            } else {
+                time <= delivered
+            }
+        })
+    }
+
+    fn deliver(&mut self, value: usize) {
+        let origin = &self.messages[&value].origin;
+        self.delivered.increment(origin);
+        self.order.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    #[test]
+    fn values_from_one_node_wait_for_the_earlier_ones() {
+        let mut n0 = CausalBroadcast::new("n0".to_string());
+        let mut n1 = CausalBroadcast::new("n1".to_string());
+        let messages: Vec<CausalMessage> =
+            (1..=3).map(|value| n0.broadcast(value).unwrap()).collect();
+
+        assert!(n1.receive(messages[2].clone()).is_empty());
+        assert!(n1.receive(messages[1].clone()).is_empty());
+        assert_eq!(n1.pending(), 2);
+        assert_eq!(n1.receive(messages[0].clone()), [1, 2, 3]);
+        assert_eq!(n1.pending(), 0);
+        assert_eq!(n1.delivered(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn values_wait_for_what_their_origin_had_delivered() {
+        let mut n0 = CausalBroadcast::new("n0".to_string());
+        let mut n1 = CausalBroadcast::new("n1".to_string());
+        let mut n2 = CausalBroadcast::new("n2".to_string());
+
+        // n1 replies to n0's value, and the reply overtakes it on the way to n2.
+        let question = n0.broadcast(1).unwrap();
+        assert_eq!(n1.receive(question.clone()), [1]);
+        let reply = n1.broadcast(2).unwrap();
+
+        assert!(n2.receive(reply.clone()).is_empty());
+        assert_eq!(n2.receive(question.clone()), [1, 2]);
+
+        // Duplicates, and our own values coming back, are ignored.
+        assert!(n2.receive(reply).is_empty());
+        assert!(n0.receive(question).is_empty());
+        assert_eq!(n2.delivered(), [1, 2]);
+    }
+
+    #[test]
+    fn concurrent_values_dont_wait_for_each_other() {
+        let mut n0 = CausalBroadcast::new("n0".to_string());
+        let mut n1 = CausalBroadcast::new("n1".to_string());
+        let mut n2 = CausalBroadcast::new("n2".to_string());
+        let a = n0.broadcast(1).unwrap();
+        let b = n1.broadcast(2).unwrap();
+
+        assert_eq!(n2.receive(b), [2]);
+        assert_eq!(n2.receive(a), [1]);
+        assert_eq!(n2.delivered(), [2, 1]);
+    }
+
+    #[test]
+    fn every_node_delivers_in_causal_order_whatever_the_order_of_arrival() {
+        let node_ids = ["n0", "n1", "n2", "n3"];
+        let mut nodes: Vec<CausalBroadcast> = node_ids
+            .iter()
+            .map(|node_id| CausalBroadcast::new(node_id.to_string()))
+            .collect();
+        let mut rng = Rng::with_seed(41);
+
+        // Values in flight, to every node, which arrive in a random order.
+        let mut in_flight: Vec<(usize, CausalMessage)> = Vec::new();
+        let mut next_value = 0;
+        for _ in 0..300 {
+            if in_flight.is_empty() || rng.gen_range(0..3) == 0 {
+                let origin = rng.gen_range(0..nodes.len() as u64) as usize;
+                next_value += 1;
+                let message = nodes[origin].broadcast(next_value).unwrap();
+                in_flight.extend(
+                    (0..nodes.len())
+                        .filter(|dest| *dest != origin)
+                        .map(|dest| (dest, message.clone())),
+                );
+            } else {
+                let index = rng.gen_range(0..in_flight.len() as u64) as usize;
+                let (dest, message) = in_flight.swap_remove(index);
+                nodes[dest].receive(message);
+            }
+        }
+        for (dest, message) in in_flight {
+            nodes[dest].receive(message);
+        }
+
+        for node in &nodes {
+            assert_eq!(node.pending(), 0);
+            assert_eq!(node.delivered().len(), next_value);
+            // A value is delivered after everything that its origin had delivered before broadcasting it.
+            let position: HashMap<usize, usize> = node
+                .delivered()
+                .iter()
+                .enumerate()
+                .map(|(position, value)| (*value, position))
+                .collect();
+            for value in node.delivered() {
+                let clock = &node.message(*value).unwrap().clock;
+                for earlier in node.delivered() {
+                    let earlier_clock = &node.message(*earlier).unwrap().clock;
+                    if earlier_clock.happened_before(clock) {
+                        assert!(position[earlier] < position[value]);
+                    }
+                }
+            }
+        }
+    }
+}
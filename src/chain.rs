@@ -0,0 +1,782 @@
+//! # Chain Replication
+//!
+//! The nodes are ordered into a chain. Writes go to the head, which applies them and passes them down the chain;
+//! every node applies them in the same order and passes them on, and the tail responds to the client.
+//! Reads go to the tail, which has only applied writes that the whole chain has applied,
+//! so they are linearizable without any messages between the nodes.
+//!
+//! [Chain Replication for Supporting High Throughput and Availability](https://www.usenix.org/legacy/event/osdi04/tech/full_papers/renesse/renesse.pdf)
+//!
+//! Every node keeps the writes that it has passed on, until the tail acknowledges them, so that it can pass them on
+//! again to a new successor when the one in between fails. A successor that is too far behind for that,
+//! e.g., one that rejoins the chain as its new tail, gets a snapshot of the whole store instead.
+//!
+//! The configuration, i.e., the chain and its `epoch`, is kept by a configuration master outside the chain:
+//! Maelstrom's `lin-kv` service. Nodes compare-and-set it to take out the chain members that they suspect,
+//! or to add themselves back at the tail, and they poll it to learn about the changes.
+//!
+//! A node only acts as the head or the tail under a configuration that it has read within [`CONFIG_LEASE`],
+//! and no sooner than [`CONFIG_LEASE`] after it learned about it. So a node that is taken out of the chain,
+//! without knowing it yet, stops serving reads before its successors start serving writes.
+
+use crate::message::{ChainEntry, ChainPayload, ClientCommand, ErrorPayload, KvPayload};
+use crate::rsm::{KvStore, StateMachine};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The key of the chain's configuration in `lin-kv`.
+pub const CONFIG_KEY: &str = "chain-config";
+
+/// How long a configuration that has been read from `lin-kv` may be acted on,
+/// and how long a new one has to be known before it's acted on.
+pub const CONFIG_LEASE: Duration = Duration::from_secs(1);
+
+/// After how long without progress the writes that the successor is missing are passed on again.
+pub const RESEND_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often the tail acknowledges the writes that it has applied.
+pub const ACK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The maximum number of entries in a single `update` message.
+pub const MAX_ENTRIES_PER_UPDATE: usize = 128;
+
+/// Messages to send: pairs of destination node IDs and payloads.
+pub type Outbox = Vec<(String, ChainPayload)>;
+
+/// Responses to send: the client, the `msg_id` of its request, and the response.
+pub type Replies = Vec<(String, Option<usize>, Result<KvPayload, ErrorPayload>)>;
+
+/// # A Chain Configuration
+///
+/// As stored in `lin-kv`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainConfig {
+    /// Increases with every change of the chain.
+    pub epoch: usize,
+    /// The chain, from the head to the tail.
+    pub nodes: Vec<String>,
+}
+
+impl ChainConfig {
+    /// The initial configuration, before any changes: all the nodes, in the order of their IDs.
+    pub fn initial(node_ids: &[String]) -> Self {
+        let mut nodes = node_ids.to_vec();
+        nodes.sort();
+        Self { epoch: 0, nodes }
+    }
+
+    pub fn head(&self) -> Option<&str> {
+        self.nodes.first().map(String::as_str)
+    }
+
+    pub fn tail(&self) -> Option<&str> {
+        self.nodes.last().map(String::as_str)
+    }
+
+    pub fn contains(&self, node_id: &str) -> bool {
+        self.nodes.iter().any(|node| node == node_id)
+    }
+
+    /// The node before `node_id` in the chain, if any.
+    pub fn predecessor(&self, node_id: &str) -> Option<&str> {
+        let position = self.nodes.iter().position(|node| node == node_id)?;
+        position
+            .checked_sub(1)
+            .map(|position| self.nodes[position].as_str())
+    }
+
+    /// The node after `node_id` in the chain, if any.
+    pub fn successor(&self, node_id: &str) -> Option<&str> {
+        let position = self.nodes.iter().position(|node| node == node_id)?;
+        self.nodes.get(position + 1).map(String::as_str)
+    }
+
+    /// The next configuration, without the `removed` nodes.
+    pub fn without(&self, removed: &[String]) -> Self {
+        Self {
+            epoch: self.epoch + 1,
+            nodes: self
+                .nodes
+                .iter()
+                .filter(|node| !removed.contains(node))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// The next configuration, with `node_id` added as the new tail.
+    pub fn with_tail(&self, node_id: String) -> Self {
+        let mut nodes = self.nodes.clone();
+        nodes.push(node_id);
+        Self {
+            epoch: self.epoch + 1,
+            nodes,
+        }
+    }
+}
+
+/// # A Chain Replication Module
+///
+/// Replicates a [`KvStore`] along the chain of the current configuration.
+#[derive(Debug)]
+pub struct ChainReplication {
+    /// Our own node ID.
+    node_id: String,
+    config: ChainConfig,
+    /// When we last sent a read of the configuration that confirmed it; `None` if we never have.
+    config_read_at: Option<Instant>,
+    /// When we learned about the current configuration.
+    config_learned_at: Instant,
+    store: KvStore,
+    /// The `seq` of the last write that we have applied.
+    applied: usize,
+    /// The writes that we have applied and passed on, but that the tail hasn't acknowledged yet, in order.
+    pending: VecDeque<ChainEntry>,
+    /// The last applied write of our successor, as far as we know, in the current configuration.
+    successor_applied: Option<usize>,
+    /// When we last passed writes on, or learned that the successor made progress.
+    last_progress: Instant,
+    /// When we last sent the successor an `update` or a `snapshot` on a tick.
+    last_resend: Instant,
+    /// Whether we have caught up with our predecessor in the current configuration; the head always has.
+    synced: bool,
+    /// As the tail, the last write that we have acknowledged, and when.
+    acked: usize,
+    last_ack: Instant,
+}
+
+impl ChainReplication {
+    /// Creates a new chain replication module for the node `node_id`, in a cluster of `node_ids`,
+    /// which includes this node, under the initial configuration.
+    pub fn new(node_id: String, node_ids: &[String]) -> Self {
+        Self {
+            node_id,
+            config: ChainConfig::initial(node_ids),
+            config_read_at: None,
+            config_learned_at: Instant::now(),
+            store: KvStore::default(),
+            applied: 0,
+            pending: VecDeque::new(),
+            successor_applied: None,
+            last_progress: Instant::now(),
+            last_resend: Instant::now(),
+            // Everybody starts out with the same, empty, store.
+            synced: true,
+            acked: 0,
+            last_ack: Instant::now(),
+        }
+    }
+
+    pub fn config(&self) -> &ChainConfig {
+        &self.config
+    }
+
+    pub fn is_head(&self) -> bool {
+        self.config.head() == Some(self.node_id.as_str())
+    }
+
+    pub fn is_tail(&self) -> bool {
+        self.config.tail() == Some(self.node_id.as_str())
+    }
+
+    /// The `seq` of the last write that we have applied.
+    pub fn applied(&self) -> usize {
+        self.applied
+    }
+
+    /// Notes a configuration that was read from `lin-kv` by a request sent at `read_at`, and adopts it if it's newer.
+    pub fn on_config(&mut self, config: ChainConfig, read_at: Instant) {
+        if config.epoch < self.config.epoch {
+            return;
+        }
+        if config.epoch > self.config.epoch {
+            self.adopt(config);
+        }
+        self.config_read_at = Some(read_at);
+    }
+
+    /// Whether the current configuration may be acted on: it has been read recently,
+    /// and it has been known for long enough for the leases of the nodes that it took out to expire.
+    fn is_leased(&self) -> bool {
+        let read_recently = self
+            .config_read_at
+            .is_some_and(|read_at| read_at.elapsed() < CONFIG_LEASE);
+        let settled = self.config.epoch == 0 || self.config_learned_at.elapsed() >= CONFIG_LEASE;
+        read_recently && settled
+    }
+
+    /// Whether we may serve reads now: as the tail, caught up, under a leased configuration.
+    pub fn can_read(&self) -> bool {
+        self.is_tail() && self.synced && self.is_leased()
+    }
+
+    /// Whether we may take writes now: as the head, under a leased configuration.
+    pub fn can_write(&self) -> bool {
+        self.is_head() && self.is_leased()
+    }
+
+    /// Serves a read, as the tail; see [`ChainReplication::can_read()`].
+    pub fn read(&mut self, op: KvPayload) -> Result<KvPayload, ErrorPayload> {
+        self.store.apply(op)
+    }
+
+    /// Takes a write, as the head: applies it, and passes it down the chain; see [`ChainReplication::can_write()`].
+    ///
+    /// If we are the tail as well, the response is added to `replies` at once.
+    pub fn write(&mut self, command: ClientCommand<KvPayload>, replies: &mut Replies) -> Outbox {
+        let entry = ChainEntry {
+            seq: self.applied + 1,
+            command,
+        };
+        self.apply(vec![entry], replies)
+    }
+
+    /// Handles a chain message from the node `src`; the responses to the clients, if any, are added to `replies`.
+    pub fn handle(&mut self, src: String, payload: ChainPayload, replies: &mut Replies) -> Outbox {
+        match payload {
+            ChainPayload::Update {
+                epoch,
+                applied,
+                entries,
+            } => {
+                if !self.is_from_predecessor(epoch, &src) {
+                    return Vec::new();
+                }
+
+                let probe = entries.is_empty();
+                let gap = entries
+                    .first()
+                    .is_some_and(|entry| entry.seq > self.applied + 1);
+                let mut outbox = self.apply(entries, replies);
+                if self.applied >= applied {
+                    self.synced = true;
+                }
+                if probe || gap {
+                    outbox.push((
+                        src,
+                        ChainPayload::UpdateOk {
+                            epoch,
+                            applied: self.applied,
+                        },
+                    ));
+                }
+                outbox
+            }
+            ChainPayload::Snapshot {
+                epoch,
+                applied,
+                store,
+            } => {
+                if !self.is_from_predecessor(epoch, &src) {
+                    return Vec::new();
+                }
+
+                // A delayed snapshot that we've already gone past would roll us back.
+                if applied > self.applied {
+                    self.store.restore(store);
+                    self.applied = applied;
+                    self.pending.clear();
+                    self.synced = true;
+                }
+                vec![(
+                    src,
+                    ChainPayload::UpdateOk {
+                        epoch,
+                        applied: self.applied,
+                    },
+                )]
+            }
+            ChainPayload::UpdateOk { epoch, applied } => {
+                if self.is_from_successor(epoch, &src) {
+                    self.successor_applied = Some(applied);
+                    self.last_progress = Instant::now();
+                }
+                Vec::new()
+            }
+            ChainPayload::Ack { epoch, applied } => {
+                if !self.is_from_successor(epoch, &src) {
+                    return Vec::new();
+                }
+
+                while self
+                    .pending
+                    .front()
+                    .is_some_and(|entry| entry.seq <= applied)
+                {
+                    self.pending.pop_front();
+                }
+                if self.successor_applied.is_none_or(|known| known < applied) {
+                    self.successor_applied = Some(applied);
+                    self.last_progress = Instant::now();
+                }
+
+                match self.config.predecessor(&self.node_id) {
+                    Some(predecessor) => vec![(
+                        predecessor.to_string(),
+                        ChainPayload::Ack { epoch, applied },
+                    )],
+                    None => Vec::new(),
+                }
+            }
+        }
+    }
+
+    /// Acknowledges the applied writes as the tail, and catches up a successor that has fallen behind.
+    pub fn tick(&mut self) -> Outbox {
+        let mut outbox = Vec::new();
+        let epoch = self.config.epoch;
+
+        if self.is_tail() && self.applied > self.acked && self.last_ack.elapsed() >= ACK_INTERVAL {
+            self.acked = self.applied;
+            self.last_ack = Instant::now();
+            if let Some(predecessor) = self.config.predecessor(&self.node_id) {
+                outbox.push((
+                    predecessor.to_string(),
+                    ChainPayload::Ack {
+                        epoch,
+                        applied: self.applied,
+                    },
+                ));
+            }
+        }
+
+        let Some(successor) = self.config.successor(&self.node_id).map(str::to_string) else {
+            return outbox;
+        };
+        if self.last_resend.elapsed() < RESEND_INTERVAL {
+            return outbox;
+        }
+        let payload = match self.successor_applied {
+            // A new successor; find out how far it is.
+            None => ChainPayload::Update {
+                epoch,
+                applied: self.applied,
+                entries: Vec::new(),
+            },
+            Some(applied)
+                if applied < self.applied && self.last_progress.elapsed() >= RESEND_INTERVAL =>
+            {
+                let covered = self
+                    .pending
+                    .front()
+                    .is_some_and(|entry| entry.seq <= applied + 1);
+                if covered {
+                    ChainPayload::Update {
+                        epoch,
+                        applied: self.applied,
+                        entries: self
+                            .pending
+                            .iter()
+                            .filter(|entry| entry.seq > applied)
+                            .take(MAX_ENTRIES_PER_UPDATE)
+                            .cloned()
+                            .collect(),
+                    }
+                } else {
+                    ChainPayload::Snapshot {
+                        epoch,
+                        applied: self.applied,
+                        store: self.store.snapshot(),
+                    }
+                }
+            }
+            // It's caught up, or it may still catch up on its own.
+            Some(_) => return outbox,
+        };
+        self.last_resend = Instant::now();
+        outbox.push((successor, payload));
+        outbox
+    }
+
+    fn is_from_predecessor(&self, epoch: usize, src: &str) -> bool {
+        epoch == self.config.epoch && self.config.predecessor(&self.node_id) == Some(src)
+    }
+
+    fn is_from_successor(&self, epoch: usize, src: &str) -> bool {
+        epoch == self.config.epoch && self.config.successor(&self.node_id) == Some(src)
+    }
+
+    /// Switches to a newer configuration.
+    ///
+    /// A node that has been taken out of the chain forgets its store: when it rejoins, it's brought up to date
+    /// from scratch. The writes that the old tail hadn't acknowledged yet stay unacknowledged.
+    fn adopt(&mut self, config: ChainConfig) {
+        self.config = config;
+        self.config_learned_at = Instant::now();
+        self.successor_applied = None;
+        self.synced = self.is_head();
+        self.acked = 0;
+
+        if !self.config.contains(&self.node_id) {
+            self.store = KvStore::default();
+            self.applied = 0;
+            self.pending.clear();
+        } else if self.is_tail() {
+            self.pending.clear();
+        }
+    }
+
+    /// Applies the entries that follow on our last applied write, in order, and passes them on,
+    /// or responds to their clients as the tail.
+    fn apply(&mut self, entries: Vec<ChainEntry>, replies: &mut Replies) -> Outbox {
+        let mut passed_on = Vec::new();
+        for entry in entries {
+            if entry.seq != self.applied + 1 {
+                continue;
+            }
+
+            let output = self.store.apply(entry.command.op.clone());
+            self.applied = entry.seq;
+            if self.is_tail() {
+                replies.push((entry.command.client, entry.command.msg_id, output));
+            } else {
+                self.pending.push_back(entry.clone());
+                passed_on.push(entry);
+            }
+        }
+
+        match self.config.successor(&self.node_id) {
+            Some(successor) if !passed_on.is_empty() => {
+                self.last_progress = Instant::now();
+                vec![(
+                    successor.to_string(),
+                    ChainPayload::Update {
+                        epoch: self.config.epoch,
+                        applied: self.applied,
+                        entries: passed_on,
+                    },
+                )]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::collections::HashMap;
+
+    fn write(seq: usize, value: usize) -> ChainEntry {
+        ChainEntry {
+            seq,
+            command: ClientCommand {
+                client: "c1".to_string(),
+                msg_id: Some(seq),
+                op: KvPayload::Write {
+                    key: Value::from(0),
+                    value: Value::from(value),
+                },
+            },
+        }
+    }
+
+    fn node_ids() -> Vec<String> {
+        (0..3).map(|i| format!("n{i}")).collect()
+    }
+
+    /// The nodes `n0`, `n1` and `n2`, in a chain in that order, which they have just read.
+    fn chain() -> Vec<ChainReplication> {
+        let node_ids = node_ids();
+        node_ids
+            .iter()
+            .map(|node_id| {
+                let mut node = ChainReplication::new(node_id.clone(), &node_ids);
+                node.on_config(ChainConfig::initial(&node_ids), Instant::now());
+                node
+            })
+            .collect()
+    }
+
+    /// Delivers the messages from `src`, and the ones that they lead to, in order;
+    /// returns the responses to the clients.
+    fn deliver(nodes: &mut [ChainReplication], src: &str, outbox: Outbox) -> Replies {
+        let mut messages: VecDeque<(String, String, ChainPayload)> = outbox
+            .into_iter()
+            .map(|(dest, payload)| (src.to_string(), dest, payload))
+            .collect();
+        let mut replies = Replies::new();
+        while let Some((src, dest, payload)) = messages.pop_front() {
+            let index: usize = dest[1..].parse().unwrap();
+            let outbox = nodes[index].handle(src, payload, &mut replies);
+            messages.extend(
+                outbox
+                    .into_iter()
+                    .map(|(next, payload)| (dest.clone(), next, payload)),
+            );
+        }
+        replies
+    }
+
+    /// Writes `value` through the head, and passes it down the whole chain.
+    fn write_through(nodes: &mut [ChainReplication], seq: usize, value: usize) -> Replies {
+        let mut replies = Replies::new();
+        let outbox = nodes[0].write(write(seq, value).command, &mut replies);
+        replies.extend(deliver(nodes, "n0", outbox));
+        replies
+    }
+
+    fn read(node: &mut ChainReplication) -> Value {
+        match node.read(KvPayload::Read {
+            key: Value::from(0),
+        }) {
+            Ok(KvPayload::ReadOk { value }) => value,
+            other => panic!("expected a value, got {other:?}"),
+        }
+    }
+
+    /// Makes the node's resends and acknowledgments due.
+    fn age(node: &mut ChainReplication, by: Duration) {
+        node.last_progress -= by;
+        node.last_resend -= by;
+        node.last_ack -= by;
+    }
+
+    #[test]
+    fn writes_go_from_the_head_to_the_tail_which_responds() {
+        let mut nodes = chain();
+        assert!(nodes[0].can_write() && !nodes[1].can_write() && !nodes[2].can_write());
+        assert!(!nodes[0].can_read() && !nodes[1].can_read() && nodes[2].can_read());
+
+        let mut replies = Replies::new();
+        let outbox = nodes[0].write(write(1, 10).command, &mut replies);
+        assert!(replies.is_empty());
+        assert!(matches!(
+            &outbox[..],
+            [(dest, ChainPayload::Update { epoch: 0, applied: 1, entries })] if dest == "n1" && entries.len() == 1
+        ));
+
+        let replies = deliver(&mut nodes, "n0", outbox);
+        assert!(matches!(
+            &replies[..],
+            [(client, Some(1), Ok(KvPayload::WriteOk))] if client == "c1"
+        ));
+        for node in &nodes {
+            assert_eq!(node.applied(), 1);
+        }
+        assert_eq!(read(&mut nodes[2]), 10);
+
+        // Until the tail acknowledges it, everybody before it keeps it.
+        assert_eq!(nodes[0].pending.len(), 1);
+        assert_eq!(nodes[1].pending.len(), 1);
+        assert!(nodes[2].pending.is_empty());
+    }
+
+    #[test]
+    fn acks_trim_the_pending_writes_and_go_up_the_chain() {
+        let mut nodes = chain();
+        for seq in 1..=2 {
+            write_through(&mut nodes, seq, seq);
+        }
+
+        // The tail acknowledges at most every ACK_INTERVAL.
+        assert!(nodes[2].tick().is_empty());
+        age(&mut nodes[2], ACK_INTERVAL);
+        let tail_ack = nodes[2].tick();
+        assert!(matches!(
+            &tail_ack[..],
+            [(dest, ChainPayload::Ack { epoch: 0, applied: 2 })] if dest == "n1"
+        ));
+        age(&mut nodes[2], ACK_INTERVAL);
+        assert!(nodes[2].tick().is_empty());
+
+        // An acknowledgment trims the writes up to it, and is passed up to the head.
+        let mut replies = Replies::new();
+        let ack = |applied| ChainPayload::Ack { epoch: 0, applied };
+        let outbox = nodes[1].handle("n2".to_string(), ack(1), &mut replies);
+        assert!(matches!(
+            &outbox[..],
+            [(dest, ChainPayload::Ack { epoch: 0, applied: 1 })] if dest == "n0"
+        ));
+        assert_eq!(nodes[1].pending.len(), 1);
+        deliver(&mut nodes, "n1", outbox);
+        assert_eq!(nodes[0].pending.len(), 1);
+
+        deliver(&mut nodes, "n2", tail_ack);
+        assert!(nodes[1].pending.is_empty());
+        assert!(nodes[0].pending.is_empty());
+
+        // Only from our successor, in the current configuration.
+        write_through(&mut nodes, 3, 3);
+        assert!(nodes[1]
+            .handle("n0".to_string(), ack(3), &mut replies)
+            .is_empty());
+        let stale = ChainPayload::Ack {
+            epoch: 1,
+            applied: 3,
+        };
+        assert!(nodes[1]
+            .handle("n2".to_string(), stale, &mut replies)
+            .is_empty());
+        assert_eq!(nodes[1].pending.len(), 1);
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn a_successor_that_fell_behind_gets_the_pending_writes_again() {
+        let mut nodes = chain();
+        let mut replies = Replies::new();
+        for seq in 1..=3 {
+            // The updates are lost.
+            nodes[0].write(write(seq, seq).command, &mut replies);
+        }
+        assert_eq!(nodes[0].pending.len(), 3);
+        assert!(nodes[0].tick().is_empty());
+
+        // First, it finds out how far its successor is.
+        age(&mut nodes[0], RESEND_INTERVAL);
+        let outbox = nodes[0].tick();
+        assert!(matches!(
+            &outbox[..],
+            [(dest, ChainPayload::Update { applied: 3, entries, .. })] if dest == "n1" && entries.is_empty()
+        ));
+        assert!(deliver(&mut nodes, "n0", outbox).is_empty());
+        assert_eq!(nodes[0].successor_applied, Some(0));
+        assert!(nodes[0].tick().is_empty());
+
+        age(&mut nodes[0], RESEND_INTERVAL);
+        let outbox = nodes[0].tick();
+        let [(_, ChainPayload::Update { entries, .. })] = &outbox[..] else {
+            panic!("expected an update, got {outbox:?}");
+        };
+        let seqs: Vec<usize> = entries.iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, [1, 2, 3]);
+
+        let replies = deliver(&mut nodes, "n0", outbox);
+        assert_eq!(replies.len(), 3);
+        assert_eq!(nodes[2].applied(), 3);
+        assert_eq!(read(&mut nodes[2]), 3);
+    }
+
+    #[test]
+    fn a_successor_that_rejoins_gets_a_snapshot() {
+        let mut nodes = chain();
+        for seq in 1..=2 {
+            write_through(&mut nodes, seq, seq);
+        }
+
+        // `n2` is taken out of the chain, and forgets its store.
+        let without = nodes[0].config().without(&["n2".to_string()]);
+        for node in &mut nodes {
+            node.on_config(without.clone(), Instant::now());
+        }
+        assert_eq!(nodes[2].applied(), 0);
+        let replies = write_through(&mut nodes, 3, 30);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(nodes[1].applied(), 3);
+
+        // It rejoins as the tail; its predecessor doesn't keep the writes that are older than that.
+        let with = without.with_tail("n2".to_string());
+        for node in &mut nodes {
+            node.on_config(with.clone(), Instant::now());
+        }
+        assert!(nodes[1].pending.is_empty());
+        assert!(!nodes[2].can_read());
+
+        age(&mut nodes[1], RESEND_INTERVAL);
+        let outbox = nodes[1].tick();
+        deliver(&mut nodes, "n1", outbox);
+        assert_eq!(nodes[1].successor_applied, Some(0));
+
+        age(&mut nodes[1], RESEND_INTERVAL);
+        let outbox = nodes[1].tick();
+        assert!(matches!(
+            &outbox[..],
+            [(dest, ChainPayload::Snapshot { epoch: 2, applied: 3, .. })] if dest == "n2"
+        ));
+        assert!(deliver(&mut nodes, "n1", outbox).is_empty());
+        assert_eq!(nodes[1].successor_applied, Some(3));
+        assert_eq!(nodes[2].applied(), 3);
+        assert_eq!(read(&mut nodes[2]), 30);
+
+        // It serves reads once the configuration is settled.
+        assert!(!nodes[2].can_read());
+        nodes[2].config_learned_at -= CONFIG_LEASE;
+        assert!(nodes[2].can_read());
+    }
+
+    #[test]
+    fn a_new_configuration_is_only_acted_on_after_the_lease() {
+        let node_ids = node_ids();
+        let mut head = ChainReplication::new("n0".to_string(), &node_ids);
+        let mut tail = ChainReplication::new("n2".to_string(), &node_ids);
+
+        // Not before the configuration has been read.
+        assert!(!head.can_write());
+        assert!(!tail.can_read());
+        for node in [&mut head, &mut tail] {
+            node.on_config(ChainConfig::initial(&node_ids), Instant::now());
+        }
+        assert!(head.can_write());
+        assert!(tail.can_read());
+
+        // Not after the read has expired.
+        head.config_read_at = Some(Instant::now() - CONFIG_LEASE);
+        assert!(!head.can_write());
+        head.on_config(ChainConfig::initial(&node_ids), Instant::now());
+        assert!(head.can_write());
+
+        // Not until CONFIG_LEASE after adopting a new configuration, however recently it's been read.
+        let config = head.config().without(&["n1".to_string()]);
+        for node in [&mut head, &mut tail] {
+            node.on_config(config.clone(), Instant::now());
+            node.on_config(config.clone(), Instant::now());
+        }
+        assert!(!head.can_write());
+        assert!(!tail.can_read());
+        for node in [&mut head, &mut tail] {
+            node.config_learned_at -= CONFIG_LEASE;
+        }
+        assert!(head.can_write());
+
+        // The tail also has to catch up with its new predecessor.
+        assert!(!tail.can_read());
+        let mut replies = Replies::new();
+        let outbox = head.write(write(1, 10).command, &mut replies);
+        let [(dest, update)] = &outbox[..] else {
+            panic!("expected an update, got {outbox:?}");
+        };
+        assert_eq!(dest, "n2");
+        tail.handle("n0".to_string(), update.clone(), &mut replies);
+        assert!(tail.can_read());
+        assert_eq!(read(&mut tail), 10);
+
+        // An older configuration is ignored.
+        head.on_config(ChainConfig::initial(&node_ids), Instant::now());
+        assert_eq!(head.config(), &config);
+        assert!(head.can_write());
+    }
+
+    #[test]
+    fn stale_snapshot_does_not_roll_back() {
+        let node_ids = vec!["n0".to_string(), "n1".to_string(), "n2".to_string()];
+        let mut chain = ChainReplication::new("n1".to_string(), &node_ids);
+        let mut replies = Replies::new();
+
+        let update = ChainPayload::Update {
+            epoch: 0,
+            applied: 2,
+            entries: vec![write(1, 10), write(2, 20)],
+        };
+        chain.handle("n0".to_string(), update, &mut replies);
+        assert_eq!(chain.applied(), 2);
+
+        let snapshot = ChainPayload::Snapshot {
+            epoch: 0,
+            applied: 1,
+            store: HashMap::from([(Value::from(0).to_string(), Value::from(10))]),
+        };
+        let outbox = chain.handle("n0".to_string(), snapshot, &mut replies);
+        assert!(matches!(
+            outbox[..],
+            [(_, ChainPayload::UpdateOk { applied: 2, .. })]
+        ));
+        assert_eq!(chain.applied(), 2);
+        let read = chain.read(KvPayload::Read {
+            key: Value::from(0),
+        });
+        assert!(matches!(read, Ok(KvPayload::ReadOk { value }) if value == 20));
+    }
+}
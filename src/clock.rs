@@ -0,0 +1,301 @@
+//! # Logical Clocks
+//!
+//! Lamport clocks and vector clocks, which order events across nodes without synchronized physical clocks.
+//!
+//! - A Lamport clock is a single counter. If an event happened before another, its Lamport time is smaller,
+//!   but not the other way around. It gives a total order that is consistent with causality.
+//! - A vector clock has a counter per node. An event happened before another exactly if its vector time is
+//!   smaller, so it also tells which events are concurrent.
+//!
+//! [Time, Clocks, and the Ordering of Events in a Distributed System](https://lamport.azurewebsites.net/pubs/time-clocks.pdf)
+//!
+//! A node that keeps [`Clocks`] gets them ticked on every message that it sends and receives,
+//! and its messages to other nodes carry them, in the `clock` field of their bodies; see [`crate::node::Node::clocks()`].
+//! That field is our own extension to Maelstrom's protocol, so it's never sent to clients or to Maelstrom's services.
+//! In the recorded transcripts of a test, it shows how the messages between nodes are ordered.
+//!
+//! The clocks are selected at startup, through the [`CLOCKS_VAR`] environment variable.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+
+/// The environment variable that selects the clocks: `lamport`, `vector`, or both, separated by a comma;
+/// without it, there are none.
+pub const CLOCKS_VAR: &str = "CLOCKS";
+
+/// # A Lamport Clock
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct LamportClock(u64);
+
+impl LamportClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    /// Advances the clock for a local event; returns the new time.
+    pub fn tick(&mut self) -> u64 {
+        self.0 += 1;
+        self.0
+    }
+
+    /// Catches up with a clock that we have received.
+    pub fn merge(&mut self, other: &Self) {
+        self.0 = self.0.max(other.0);
+    }
+}
+
+/// # A Vector Clock
+///
+/// Nodes that are missing from it are at `0`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VectorClock(BTreeMap<String, u64>);
+
+impl VectorClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The time of the node `node_id`.
+    pub fn get(&self, node_id: &str) -> u64 {
+        self.0.get(node_id).copied().unwrap_or(0)
+    }
+
+    /// Advances the time of the node `node_id`, for a local event on it; returns the new time.
+    pub fn increment(&mut self, node_id: &str) -> u64 {
+        let time = self.0.entry(node_id.to_string()).or_default();
+        *time += 1;
+        *time
+    }
+
+    /// Catches up with a clock that we have received: takes the later time of every node.
+    pub fn merge(&mut self, other: &Self) {
+        for (node_id, &time) in &other.0 {
+            let ours = self.0.entry(node_id.clone()).or_default();
+            *ours = (*ours).max(time);
+        }
+    }
+
+    /// The nodes and their times.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &u64)> {
+        self.0.iter()
+    }
+
+    /// Whether the event at this time happened before the event at the `other` time.
+    pub fn happened_before(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Less)
+    }
+
+    /// Whether neither of the events happened before the other.
+    pub fn concurrent_with(&self, other: &Self) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+}
+
+impl PartialOrd for VectorClock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let (mut less, mut greater) = (false, false);
+        for node_id in self.0.keys().chain(other.0.keys()) {
+            match self.get(node_id).cmp(&other.get(node_id)) {
+                Ordering::Less => less = true,
+                Ordering::Greater => greater = true,
+                Ordering::Equal => {}
+            }
+        }
+        match (less, greater) {
+            (false, false) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (true, true) => None,
+        }
+    }
+}
+
+/// The clocks that a message carries: the sender's, as of sending it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timestamp {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lamport: Option<LamportClock>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector: Option<VectorClock>,
+}
+
+/// # A Node's Logical Clocks
+///
+/// Sending and receiving messages are events that tick them.
+#[derive(Clone, Debug)]
+pub struct Clocks {
+    /// Our own node ID.
+    node_id: String,
+    /// All nodes in the cluster; only messages to them carry the clocks.
+    node_ids: HashSet<String>,
+    lamport: Option<LamportClock>,
+    vector: Option<VectorClock>,
+}
+
+impl Clocks {
+    /// Creates the selected clocks for the node `node_id`, in a cluster of `node_ids`.
+    pub fn new(node_id: String, node_ids: Vec<String>, lamport: bool, vector: bool) -> Self {
+        Self {
+            node_id,
+            node_ids: node_ids.into_iter().collect(),
+            lamport: lamport.then(LamportClock::new),
+            vector: vector.then(VectorClock::new),
+        }
+    }
+
+    /// Reads the clocks from the [`CLOCKS_VAR`] environment variable; `None` if it isn't set.
+    pub fn from_env(node_id: String, node_ids: Vec<String>) -> Result<Option<Self>> {
+        let Ok(clocks) = std::env::var(CLOCKS_VAR) else {
+            return Ok(None);
+        };
+
+        let (mut lamport, mut vector) = (false, false);
+        for clock in clocks.split(',').map(str::trim) {
+            match clock {
+                "lamport" => lamport = true,
+                "vector" => vector = true,
+                other => bail!("unknown clock: {other}"),
+            }
+        }
+        Ok(Some(Self::new(node_id, node_ids, lamport, vector)))
+    }
+
+    pub fn lamport(&self) -> Option<&LamportClock> {
+        self.lamport.as_ref()
+    }
+
+    pub fn vector(&self) -> Option<&VectorClock> {
+        self.vector.as_ref()
+    }
+
+    /// Ticks the clocks for a local event, other than sending or receiving a message.
+    pub fn tick(&mut self) {
+        if let Some(lamport) = &mut self.lamport {
+            lamport.tick();
+        }
+        if let Some(vector) = &mut self.vector {
+            vector.increment(&self.node_id);
+        }
+    }
+
+    /// Ticks the clocks for sending a message to `dest`; returns the timestamp that it should carry, if any.
+    pub fn on_send(&mut self, dest: &str) -> Option<Timestamp> {
+        self.tick();
+        self.node_ids.contains(dest).then(|| Timestamp {
+            lamport: self.lamport,
+            vector: self.vector.clone(),
+        })
+    }
+
+    /// Ticks the clocks for receiving a message, after catching up with its timestamp, if any.
+    pub fn on_receive(&mut self, timestamp: Option<&Timestamp>) {
+        if let Some(timestamp) = timestamp {
+            if let (Some(lamport), Some(theirs)) = (&mut self.lamport, &timestamp.lamport) {
+                lamport.merge(theirs);
+            }
+            if let (Some(vector), Some(theirs)) = (&mut self.vector, &timestamp.vector) {
+                vector.merge(theirs);
+            }
+        }
+        self.tick();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(times: &[(&str, u64)]) -> VectorClock {
+        VectorClock(
+            times
+                .iter()
+                .map(|(node_id, time)| (node_id.to_string(), *time))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn vector_clocks_are_partially_ordered() {
+        let a = vector(&[("n0", 1), ("n1", 2)]);
+        let b = vector(&[("n0", 2), ("n1", 2)]);
+        let c = vector(&[("n0", 0), ("n1", 3)]);
+
+        assert_eq!(a.partial_cmp(&a), Some(Ordering::Equal));
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Less));
+        assert_eq!(b.partial_cmp(&a), Some(Ordering::Greater));
+        assert!(a.happened_before(&b));
+        assert!(!b.happened_before(&a));
+        assert!(!a.happened_before(&a));
+
+        assert_eq!(b.partial_cmp(&c), None);
+        assert!(b.concurrent_with(&c) && c.concurrent_with(&b));
+        assert!(!a.concurrent_with(&b) && !a.concurrent_with(&a));
+    }
+
+    #[test]
+    fn missing_nodes_are_at_zero() {
+        let a = vector(&[("n0", 1)]);
+        let b = vector(&[("n0", 1), ("n1", 0)]);
+        let c = vector(&[("n1", 1)]);
+
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Equal));
+        assert!(VectorClock::new().happened_before(&a));
+        assert!(a.concurrent_with(&c));
+        assert_eq!(a.get("n1"), 0);
+    }
+
+    #[test]
+    fn merging_takes_the_later_time_of_every_node() {
+        let mut a = vector(&[("n0", 3), ("n1", 1)]);
+        let b = vector(&[("n1", 2), ("n2", 5)]);
+        a.merge(&b);
+
+        assert_eq!(a, vector(&[("n0", 3), ("n1", 2), ("n2", 5)]));
+        assert!(!a.happened_before(&b) && b.happened_before(&a));
+    }
+
+    #[test]
+    fn messages_order_events_across_nodes() {
+        let node_ids = vec!["n0".to_string(), "n1".to_string(), "n2".to_string()];
+        let [mut n0, mut n1, mut n2] = ["n0", "n1", "n2"]
+            .map(|node_id| Clocks::new(node_id.to_string(), node_ids.clone(), true, true));
+
+        // n0 sends to n1, which then sends to n2; n2 has done something on its own meanwhile.
+        n2.tick();
+        let independent = n2.vector().unwrap().clone();
+        let sent = n0.on_send("n1").unwrap();
+        n1.on_receive(Some(&sent));
+        let forwarded = n1.on_send("n2").unwrap();
+        n2.on_receive(Some(&forwarded));
+        let received = n2.vector().unwrap().clone();
+
+        let sent_vector = sent.vector.unwrap();
+        assert!(sent_vector.happened_before(forwarded.vector.as_ref().unwrap()));
+        assert!(sent_vector.happened_before(&received));
+        assert!(independent.happened_before(&received));
+        assert!(sent_vector.concurrent_with(&independent));
+        assert!(sent.lamport.unwrap() < forwarded.lamport.unwrap());
+        assert!(forwarded.lamport.unwrap() < *n2.lamport().unwrap());
+    }
+
+    #[test]
+    fn only_messages_to_nodes_carry_the_clocks() {
+        let mut n0 = Clocks::new("n0".to_string(), vec!["n0".to_string()], true, false);
+
+        assert_eq!(n0.on_send("c1"), None);
+        assert_eq!(n0.lamport().unwrap().value(), 1);
+        let timestamp = n0.on_send("n0").unwrap();
+        assert_eq!(timestamp.lamport.unwrap().value(), 2);
+        assert_eq!(timestamp.vector, None);
+    }
+}
@@ -0,0 +1,305 @@
+//! # Conflict-Free Replicated Data Types
+//!
+//! State-based CRDTs (CvRDTs): every replica can be updated locally, without coordination,
+//! and replicas converge by exchanging their states (or deltas of them) and merging them.
+//!
+//! Merging must be commutative, associative and idempotent, so that states can be sent
+//! any number of times, in any order, and through any path.
+//!
+//! [A comprehensive study of Convergent and Commutative Replicated Data Types](https://inria.hal.science/inria-00555588)
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+/// A state-based CRDT.
+pub trait Crdt {
+    /// Merges the other replica's state into this one, which then reflects all updates seen by either.
+    fn merge(&mut self, other: &Self);
+}
+
+/// # A Grow-Only Set
+///
+/// Elements can only be added, never removed. Merging is the set union.
+///
+/// Serialized as a plain list of elements.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+#[serde(bound(
+    serialize = "T: Serialize",
+    deserialize = "T: Deserialize<'de> + Eq + Hash"
+))]
+pub struct GSet<T: Eq + Hash> {
+    elements: HashSet<T>,
+}
+
+impl<T: Eq + Hash> GSet<T> {
+    /// Creates a new, empty set.
+    pub fn new() -> Self {
+        Self {
+            elements: HashSet::new(),
+        }
+    }
+
+    /// Adds an element; returns whether it was new.
+    pub fn insert(&mut self, element: T) -> bool {
+        self.elements.insert(element)
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.elements.contains(element)
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.elements.iter()
+    }
+
+    /// Removes all elements and returns them as a new set.
+    ///
+    /// That is *not* a CRDT operation; it's meant for buffers of deltas that are being shipped out.
+    pub fn take(&mut self) -> Self {
+        Self {
+            elements: std::mem::take(&mut self.elements),
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> Crdt for GSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.elements.extend(other.elements.iter().cloned());
+    }
+}
+
+impl<T: Eq + Hash> Default for GSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Eq + Hash> FromIterator<T> for GSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self {
+            elements: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<T: Eq + Hash> IntoIterator for GSet<T> {
+    type Item = T;
+    type IntoIter = std::collections::hash_set::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.elements.into_iter()
+    }
+}
+
+/// # A Grow-Only Counter
+///
+/// Every node increments only its own entry, and the value of the counter is the sum of all entries.
+/// Merging takes the entry-wise maximum.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GCounter {
+    counts: HashMap<String, u64>,
+}
+
+impl GCounter {
+    /// Creates a new counter, with the value of zero.
+    pub fn new() -> Self {
+        Self {
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Increments the entry of the node `node_id` by `amount`.
+    pub fn increment(&mut self, node_id: &str, amount: u64) {
+        *self.counts.entry(node_id.to_string()).or_default() += amount;
+    }
+
+    /// The value of the counter: the sum of all nodes' entries.
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge(&mut self, other: &Self) {
+        for (node_id, &count) in &other.counts {
+            let entry = self.counts.entry(node_id.clone()).or_default();
+            *entry = (*entry).max(count);
+        }
+    }
+}
+
+/// # A Positive-Negative Counter
+///
+/// A counter that can be both incremented and decremented: a pair of grow-only counters,
+/// one for the increments and one for the decrements, each with an entry per node.
+/// The value of the counter is their difference.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PNCounter {
+    increments: GCounter,
+    decrements: GCounter,
+}
+
+impl PNCounter {
+    /// Creates a new counter, with the value of zero.
+    pub fn new() -> Self {
+        Self {
+            increments: GCounter::new(),
+            decrements: GCounter::new(),
+        }
+    }
+
+    /// Adds `delta`, which may be negative, on behalf of the node `node_id`.
+    pub fn add(&mut self, node_id: &str, delta: i64) {
+        if delta >= 0 {
+            self.increments.increment(node_id, delta.unsigned_abs());
+        } else {
+            self.decrements.increment(node_id, delta.unsigned_abs());
+        }
+    }
+
+    /// The value of the counter: all increments minus all decrements.
+    pub fn value(&self) -> i64 {
+        self.increments.value() as i64 - self.decrements.value() as i64
+    }
+}
+
+impl Crdt for PNCounter {
+    fn merge(&mut self, other: &Self) {
+        self.increments.merge(&other.increments);
+        self.decrements.merge(&other.decrements);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+    use std::fmt::Debug;
+
+    const NODES: [&str; 3] = ["n0", "n1", "n2"];
+
+    /// How many random replica states every law is checked on, for all pairs and triples of them.
+    const STATES: usize = 12;
+
+    fn merged<C: Crdt + Clone>(a: &C, b: &C) -> C {
+        let mut merged = a.clone();
+        merged.merge(b);
+        merged
+    }
+
+    /// Checks that merging the states is commutative, associative and idempotent.
+    fn check_laws<C: Crdt + Clone + PartialEq + Debug>(states: &[C]) {
+        for a in states {
+            assert_eq!(merged(a, a), *a, "idempotence");
+            for b in states {
+                assert_eq!(merged(a, b), merged(b, a), "commutativity");
+                assert_eq!(merged(&merged(a, b), b), merged(a, b), "idempotence");
+                for c in states {
+                    assert_eq!(
+                        merged(&merged(a, b), c),
+                        merged(a, &merged(b, c)),
+                        "associativity"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Random states of replicas: every one is a random number of random updates, on a random node,
+    /// on top of a merge of some earlier states, so that states overlap as they do between replicas.
+    fn states<C: Crdt + Clone + Default>(update: impl Fn(&mut C, &str, &mut Rng)) -> Vec<C> {
+        let mut rng = Rng::with_seed(27);
+        let mut states: Vec<C> = vec![C::default()];
+        while states.len() < STATES {
+            let base = rng.gen_range(0..states.len() as u64) as usize;
+            let mut state = states[base].clone();
+            if rng.gen_range(0..2) == 0 {
+                let other = rng.gen_range(0..states.len() as u64) as usize;
+                state.merge(&states[other]);
+            }
+            let node_id = NODES[rng.gen_range(0..NODES.len() as u64) as usize];
+            for _ in 0..rng.gen_range(0..4) {
+                update(&mut state, node_id, &mut rng);
+            }
+            states.push(state);
+        }
+        states
+    }
+
+    #[test]
+    fn g_set_merges_lawfully() {
+        let states = states(|set: &mut GSet<u64>, _, rng| {
+            set.insert(rng.gen_range(0..20));
+        });
+        check_laws(&states);
+
+        let union = states
+            .iter()
+            .fold(GSet::new(), |union, set| merged(&union, set));
+        for set in &states {
+            assert!(set.iter().all(|element| union.contains(element)));
+        }
+    }
+
+    #[test]
+    fn g_counter_merges_lawfully() {
+        let states = states(|counter: &mut GCounter, node_id, rng| {
+            counter.increment(node_id, rng.gen_range(1..10));
+        });
+        check_laws(&states);
+
+        let all = states
+            .iter()
+            .fold(GCounter::new(), |all, counter| merged(&all, counter));
+        assert!(states.iter().all(|counter| counter.value() <= all.value()));
+    }
+
+    #[test]
+    fn pn_counter_merges_lawfully() {
+        let states = states(|counter: &mut PNCounter, node_id, rng| {
+            counter.add(node_id, rng.gen_range(0..20) as i64 - 10);
+        });
+        check_laws(&states);
+    }
+
+    #[test]
+    fn counters_converge_on_the_sum_of_all_updates() {
+        let mut replicas: Vec<PNCounter> = NODES.iter().map(|_| PNCounter::new()).collect();
+        let mut rng = Rng::with_seed(27);
+        let mut sum = 0;
+        for _ in 0..100 {
+            let index = rng.gen_range(0..NODES.len() as u64) as usize;
+            let delta = rng.gen_range(0..20) as i64 - 10;
+            replicas[index].add(NODES[index], delta);
+            sum += delta;
+
+            // Gossip, in some random direction.
+            let nodes = NODES.len() as u64;
+            let (from, to) = (
+                rng.gen_range(0..nodes) as usize,
+                rng.gen_range(0..nodes) as usize,
+            );
+            let state = replicas[from].clone();
+            replicas[to].merge(&state);
+        }
+
+        for index in 0..replicas.len() {
+            for other in 0..replicas.len() {
+                let state = replicas[other].clone();
+                replicas[index].merge(&state);
+            }
+        }
+        assert!(replicas.iter().all(|replica| replica.value() == sum));
+    }
+}
@@ -0,0 +1,402 @@
+//! # Failure Detector
+//!
+//! Tells which peers are probably down, or partitioned away, from the heartbeats that they send.
+//!
+//! Every node sends heartbeats to the peers that it monitors, and any other message from a peer shows
+//! that it's alive, too. A peer that hasn't been heard from for too long is suspected. There are two ways
+//! to decide how long is too long:
+//! - A fixed timeout.
+//! - Phi accrual: the intervals between heartbeats are sampled, and the suspicion level `phi` grows with
+//!   the time since the peer was last heard from, relative to those intervals. A peer is suspected when `phi`
+//!   exceeds a threshold; `phi = 8`, e.g., means that the chance that it's still alive is about `10^-8`.
+//!   This adapts to the network's latency, and to its jitter.
+//!
+//! [The φ Accrual Failure Detector](https://doi.org/10.1109/RELDIS.2004.1353004)
+//!
+//! A suspected peer is trusted again as soon as it's heard from.
+//!
+//! Status changes are reported to the callbacks registered with [`FailureDetector::on_status_change()`].
+//!
+//! The failure detector is selected at startup, through the [`FAILURE_DETECTOR_VAR`] environment variable.
+
+use crate::message::HeartbeatPayload;
+use anyhow::{bail, Result};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Debug, Formatter};
+use std::time::{Duration, Instant};
+
+/// The environment variable that selects the failure detector: `phi-accrual` or `timeout`;
+/// without it, there is none.
+pub const FAILURE_DETECTOR_VAR: &str = "FAILURE_DETECTOR";
+
+/// How often heartbeats are sent to every monitored peer.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The default suspicion level above which a peer is suspected, with phi accrual.
+pub const DEFAULT_PHI_THRESHOLD: f64 = 8.0;
+
+/// The default time without a heartbeat after which a peer is suspected, with a fixed timeout.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The number of the most recent intervals between heartbeats that are sampled, with phi accrual.
+pub const SAMPLE_WINDOW: usize = 100;
+
+/// The least standard deviation of the intervals between heartbeats, in milliseconds,
+/// so that a very regular peer isn't suspected after the slightest delay.
+pub const MIN_STD_DEV_MS: f64 = 50.0;
+
+/// Messages to send: pairs of destination node IDs and payloads.
+pub type Outbox = Vec<(String, HeartbeatPayload)>;
+
+/// A callback that is called with a peer and its new status, whenever that changes.
+pub type StatusCallback = Box<dyn FnMut(&str, Status)>;
+
+/// How a peer is doing, as far as the failure detector can tell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Alive,
+    Suspected,
+}
+
+/// How the failure detector decides that a peer is suspected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Detection {
+    /// When its suspicion level exceeds the threshold.
+    PhiAccrual { threshold: f64 },
+    /// When it hasn't been heard from for the timeout.
+    Timeout(Duration),
+}
+
+impl Detection {
+    /// Reads the failure detector from the [`FAILURE_DETECTOR_VAR`] environment variable; `None` if it isn't set.
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var(FAILURE_DETECTOR_VAR).as_deref() {
+            Err(_) => Ok(None),
+            Ok("phi-accrual") => Ok(Some(Self::PhiAccrual {
+                threshold: DEFAULT_PHI_THRESHOLD,
+            })),
+            Ok("timeout") => Ok(Some(Self::Timeout(DEFAULT_TIMEOUT))),
+            Ok(other) => bail!("unknown failure detector: {other}"),
+        }
+    }
+}
+
+/// What we know about a monitored peer.
+#[derive(Clone, Debug)]
+struct Peer {
+    /// When we last heard from it; when we started monitoring it, if we haven't yet.
+    last_heard: Instant,
+    /// When we last got a heartbeat from it; when we started monitoring it, if we haven't yet.
+    last_heartbeat: Instant,
+    /// The most recent intervals between heartbeats, in milliseconds.
+    intervals: VecDeque<f64>,
+    status: Status,
+}
+
+impl Peer {
+    fn new() -> Self {
+        Self {
+            last_heard: Instant::now(),
+            last_heartbeat: Instant::now(),
+            // Until real intervals come in, heartbeats are expected on schedule.
+            intervals: VecDeque::from([HEARTBEAT_INTERVAL.as_secs_f64() * 1000.0]),
+            status: Status::Alive,
+        }
+    }
+
+    /// The suspicion level at `now`.
+    fn phi(&self, now: Instant) -> f64 {
+        let count = self.intervals.len() as f64;
+        let mean = self.intervals.iter().sum::<f64>() / count;
+        let variance = self
+            .intervals
+            .iter()
+            .map(|interval| (interval - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        let std_dev = variance.sqrt().max(MIN_STD_DEV_MS);
+
+        // A logistic approximation of the normal distribution's cumulative distribution function.
+        let elapsed = (now - self.last_heard).as_secs_f64() * 1000.0;
+        let y = (elapsed - mean) / std_dev;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        if elapsed > mean {
+            -(e / (1.0 + e)).log10()
+        } else {
+            -(1.0 - 1.0 / (1.0 + e)).log10()
+        }
+    }
+}
+
+/// # A Failure Detector
+///
+/// Monitors a set of peers, which can change over time.
+pub struct FailureDetector {
+    detection: Detection,
+    peers: HashMap<String, Peer>,
+    /// When we last sent heartbeats.
+    last_heartbeat: Option<Instant>,
+    callbacks: Vec<StatusCallback>,
+}
+
+impl Debug for FailureDetector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FailureDetector")
+            .field("detection", &self.detection)
+            .field("peers", &self.peers)
+            .field("last_heartbeat", &self.last_heartbeat)
+            .field("callbacks", &self.callbacks.len())
+            .finish()
+    }
+}
+
+impl FailureDetector {
+    /// Creates a new failure detector, which doesn't monitor any peers yet.
+    pub fn new(detection: Detection) -> Self {
+        Self {
+            detection,
+            peers: HashMap::new(),
+            last_heartbeat: None,
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// Registers a callback for status changes.
+    pub fn on_status_change(&mut self, callback: impl FnMut(&str, Status) + 'static) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Sets the peers to monitor; the new ones start out alive, and the ones that are gone are forgotten.
+    pub fn set_peers(&mut self, peers: impl IntoIterator<Item = String>) {
+        let mut monitored = HashMap::new();
+        for peer in peers {
+            let state = self.peers.remove(&peer).unwrap_or_else(Peer::new);
+            monitored.insert(peer, state);
+        }
+        self.peers = monitored;
+    }
+
+    /// Notes that we have heard from `peer`, by a heartbeat or by any other message.
+    pub fn heard_from(&mut self, peer: &str) {
+        let Some(state) = self.peers.get_mut(peer) else {
+            return;
+        };
+
+        state.last_heard = Instant::now();
+        if state.status == Status::Suspected {
+            state.status = Status::Alive;
+            self.notify(peer, Status::Alive);
+        }
+    }
+
+    /// Handles a heartbeat from the node `src`.
+    ///
+    /// Only the intervals between heartbeats are sampled, because other messages come irregularly.
+    pub fn handle(&mut self, src: String, payload: HeartbeatPayload) {
+        match payload {
+            HeartbeatPayload::Heartbeat => {
+                if let Some(state) = self.peers.get_mut(&src) {
+                    let now = Instant::now();
+                    let interval = (now - state.last_heartbeat).as_secs_f64() * 1000.0;
+                    state.last_heartbeat = now;
+                    state.intervals.push_back(interval);
+                    if state.intervals.len() > SAMPLE_WINDOW {
+                        state.intervals.pop_front();
+                    }
+                }
+                self.heard_from(&src);
+            }
+        }
+    }
+
+    /// The suspicion level of `peer`; `None` if it isn't monitored.
+    ///
+    /// With a fixed timeout, it's `0` for an alive peer, and infinite for a suspected one.
+    pub fn phi(&self, peer: &str) -> Option<f64> {
+        let state = self.peers.get(peer)?;
+        Some(match self.detection {
+            Detection::PhiAccrual { .. } => state.phi(Instant::now()),
+            Detection::Timeout(timeout) if state.last_heard.elapsed() >= timeout => f64::INFINITY,
+            Detection::Timeout(_) => 0.0,
+        })
+    }
+
+    /// The status of `peer`, as of the last tick; `None` if it isn't monitored.
+    pub fn status(&self, peer: &str) -> Option<Status> {
+        self.peers.get(peer).map(|state| state.status)
+    }
+
+    /// Whether `peer` is suspected, as of the last tick; peers that aren't monitored aren't.
+    pub fn is_suspected(&self, peer: &str) -> bool {
+        self.status(peer) == Some(Status::Suspected)
+    }
+
+    /// Updates the statuses of the peers, and sends heartbeats when they are due.
+    pub fn tick(&mut self) -> Outbox {
+        let now = Instant::now();
+
+        let mut suspected = Vec::new();
+        for (peer, state) in &mut self.peers {
+            let suspicious = match self.detection {
+                Detection::PhiAccrual { threshold } => state.phi(now) > threshold,
+                Detection::Timeout(timeout) => now - state.last_heard >= timeout,
+            };
+            if suspicious && state.status == Status::Alive {
+                state.status = Status::Suspected;
+                suspected.push(peer.clone());
+            }
+        }
+        for peer in suspected {
+            self.notify(&peer, Status::Suspected);
+        }
+
+        if self
+            .last_heartbeat
+            .is_some_and(|sent| now - sent < HEARTBEAT_INTERVAL)
+        {
+            return Outbox::new();
+        }
+        self.last_heartbeat = Some(now);
+        self.peers
+            .keys()
+            .map(|peer| (peer.clone(), HeartbeatPayload::Heartbeat))
+            .collect()
+    }
+
+    fn notify(&mut self, peer: &str, status: Status) {
+        for callback in &mut self.callbacks {
+            callback(peer, status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// The status changes that a failure detector has reported, in order.
+    type Changes = Rc<RefCell<Vec<(String, Status)>>>;
+
+    /// A failure detector that monitors `peers`, with the status changes that it has reported.
+    fn detector(detection: Detection, peers: &[&str]) -> (FailureDetector, Changes) {
+        let mut detector = FailureDetector::new(detection);
+        detector.set_peers(peers.iter().map(|peer| peer.to_string()));
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&changes);
+        detector.on_status_change(move |peer, status| {
+            recorded.borrow_mut().push((peer.to_string(), status));
+        });
+        (detector, changes)
+    }
+
+    /// Lets `by` pass without hearing from `peer`.
+    fn age(detector: &mut FailureDetector, peer: &str, by: Duration) {
+        let state = detector.peers.get_mut(peer).unwrap();
+        state.last_heard = state.last_heard.checked_sub(by).unwrap();
+        state.last_heartbeat = state.last_heartbeat.checked_sub(by).unwrap();
+    }
+
+    /// Delivers `count` heartbeats from `peer`, every `interval`.
+    fn heartbeats(detector: &mut FailureDetector, peer: &str, count: usize, interval: Duration) {
+        for _ in 0..count {
+            age(detector, peer, interval);
+            detector.handle(peer.to_string(), HeartbeatPayload::Heartbeat);
+        }
+    }
+
+    #[test]
+    fn a_silent_peer_is_suspected_after_the_timeout_and_trusted_once_heard_from() {
+        let (mut detector, changes) = detector(Detection::Timeout(DEFAULT_TIMEOUT), &["n1", "n2"]);
+
+        detector.tick();
+        assert!(!detector.is_suspected("n1"));
+        age(&mut detector, "n1", DEFAULT_TIMEOUT);
+        detector.tick();
+        detector.tick();
+        assert!(detector.is_suspected("n1"));
+        assert!(!detector.is_suspected("n2"));
+        assert_eq!(detector.phi("n1"), Some(f64::INFINITY));
+
+        detector.heard_from("n1");
+        detector.heard_from("n1");
+        detector.tick();
+        assert_eq!(detector.status("n1"), Some(Status::Alive));
+        assert_eq!(
+            *changes.borrow(),
+            [
+                ("n1".to_string(), Status::Suspected),
+                ("n1".to_string(), Status::Alive)
+            ]
+        );
+    }
+
+    #[test]
+    fn phi_rises_with_the_time_since_the_last_heartbeat() {
+        let threshold = DEFAULT_PHI_THRESHOLD;
+        let (mut detector, changes) = detector(Detection::PhiAccrual { threshold }, &["n1"]);
+        heartbeats(&mut detector, "n1", 20, HEARTBEAT_INTERVAL);
+
+        let mut phis = Vec::new();
+        for _ in 0..4 {
+            phis.push(detector.phi("n1").unwrap());
+            detector.tick();
+            age(&mut detector, "n1", HEARTBEAT_INTERVAL / 2);
+        }
+        assert!(phis.is_sorted_by(|a, b| a < b), "{phis:?}");
+        assert!(phis[2] < 1.0, "{phis:?}");
+        assert!(!detector.is_suspected("n1"));
+
+        age(&mut detector, "n1", HEARTBEAT_INTERVAL);
+        assert!(detector.phi("n1").unwrap() > threshold);
+        detector.tick();
+        assert!(detector.is_suspected("n1"));
+        assert_eq!(*changes.borrow(), [("n1".to_string(), Status::Suspected)]);
+    }
+
+    #[test]
+    fn phi_adapts_to_slower_heartbeats() {
+        let threshold = DEFAULT_PHI_THRESHOLD;
+        let (mut fast, _) = detector(Detection::PhiAccrual { threshold }, &["n1"]);
+        let (mut slow, _) = detector(Detection::PhiAccrual { threshold }, &["n1"]);
+        heartbeats(&mut fast, "n1", SAMPLE_WINDOW, HEARTBEAT_INTERVAL);
+        heartbeats(&mut slow, "n1", SAMPLE_WINDOW, HEARTBEAT_INTERVAL * 4);
+
+        age(&mut fast, "n1", HEARTBEAT_INTERVAL * 3);
+        age(&mut slow, "n1", HEARTBEAT_INTERVAL * 3);
+        assert!(fast.phi("n1").unwrap() > threshold);
+        assert!(slow.phi("n1").unwrap() < 1.0);
+    }
+
+    #[test]
+    fn retained_peers_keep_their_state_and_removed_ones_are_forgotten() {
+        let (mut detector, changes) = detector(Detection::Timeout(DEFAULT_TIMEOUT), &["n1", "n2"]);
+        heartbeats(&mut detector, "n1", 3, HEARTBEAT_INTERVAL);
+        age(&mut detector, "n1", DEFAULT_TIMEOUT);
+        age(&mut detector, "n2", DEFAULT_TIMEOUT);
+        detector.tick();
+        assert!(detector.is_suspected("n1") && detector.is_suspected("n2"));
+
+        detector.set_peers(["n1".to_string(), "n3".to_string()]);
+        assert!(detector.is_suspected("n1"));
+        assert_eq!(detector.peers["n1"].intervals.len(), 4);
+        assert_eq!(detector.status("n2"), None);
+        assert_eq!(detector.phi("n2"), None);
+        assert!(!detector.is_suspected("n2"));
+        assert_eq!(detector.status("n3"), Some(Status::Alive));
+
+        // A peer that's forgotten isn't reported on anymore.
+        detector.heard_from("n2");
+        assert_eq!(changes.borrow().len(), 2);
+
+        // Heartbeats are due again, for the monitored peers only.
+        detector.last_heartbeat = detector
+            .last_heartbeat
+            .map(|sent| sent - HEARTBEAT_INTERVAL);
+        let mut heartbeats: Vec<String> =
+            detector.tick().into_iter().map(|(dest, _)| dest).collect();
+        heartbeats.sort();
+        assert_eq!(heartbeats, ["n1", "n3"]);
+    }
+}
@@ -0,0 +1,564 @@
+//! # HyParView
+//!
+//! A membership protocol that keeps every node connected to a few random peers, and repairs those
+//! connections when peers fail, so that gossip can run on top of it without a fixed topology.
+//!
+//! [HyParView: a membership protocol for reliable gossip-based broadcast](https://asc.di.fct.unl.pt/~jleitao/pdf/dsn07-leitao.pdf)
+//!
+//! Every node has two partial views of the cluster:
+//! - A small, symmetric active view: its neighbors, which gossip goes to. Links are kept alive with keepalives,
+//!   and a neighbor that goes silent is considered failed.
+//! - A larger passive view: a reserve of peers, which replace failed neighbors.
+//!
+//! A node joins through a contact node, which spreads it to other nodes' views by random walks (`forward_join`).
+//! Nodes periodically exchange samples of their views with random walks (`shuffle`), which keeps
+//! the passive views fresh.
+//!
+//! Maelstrom tells every node about all the nodes in the cluster, so the passive views start out
+//! with a random sample of them; the first node in the cluster is the contact node for everyone else.
+
+use crate::message::HyParViewPayload;
+use crate::rng::Rng;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The maximum size of the active view.
+pub const ACTIVE_VIEW_SIZE: usize = 5;
+
+/// The maximum size of the passive view.
+pub const PASSIVE_VIEW_SIZE: usize = 30;
+
+/// The length of the random walks of `forward_join` and `shuffle` messages.
+pub const ACTIVE_RANDOM_WALK_LENGTH: usize = 6;
+
+/// The step of a `forward_join` random walk at which the new node is added to a passive view.
+pub const PASSIVE_RANDOM_WALK_LENGTH: usize = 3;
+
+/// How many nodes from the active view and from the passive view a `shuffle` carries.
+pub const SHUFFLE_ACTIVE: usize = 3;
+pub const SHUFFLE_PASSIVE: usize = 4;
+
+/// How often a node shuffles its views with a random peer.
+pub const SHUFFLE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often a node sends keepalives to its neighbors.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// After how long a silent neighbor, or an unanswered `join` or `neighbor` request, is given up on.
+pub const PEER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Messages to send: pairs of destination node IDs and payloads.
+pub type Outbox = Vec<(String, HyParViewPayload)>;
+
+/// # A HyParView Membership Module
+#[derive(Debug)]
+pub struct HyParView {
+    /// Our own node ID.
+    node_id: String,
+    /// All other nodes in the cluster; the passive view is refilled from them when both views run dry.
+    others: Vec<String>,
+    /// The node to join through, unless it's us.
+    contact: Option<String>,
+    /// Our neighbors.
+    active: Vec<String>,
+    /// The peers that replace failed neighbors.
+    passive: Vec<String>,
+    /// When we last heard from each neighbor.
+    last_heard: HashMap<String, Instant>,
+    /// The peers that we have sent `neighbor` requests to, and when.
+    pending: HashMap<String, Instant>,
+    /// Whether we have ever had a neighbor.
+    joined: bool,
+    /// When we last sent `join` to the contact node.
+    join_sent: Option<Instant>,
+    /// How many times we have sent `join` to the contact node.
+    join_attempts: usize,
+    last_keepalive: Instant,
+    last_shuffle: Instant,
+    rng: Rng,
+}
+
+impl HyParView {
+    /// Creates a new HyParView module for the node `node_id`, in a cluster of `node_ids`, which includes this node.
+    pub fn new(node_id: String, node_ids: Vec<String>) -> Self {
+        let contact = node_ids.first().filter(|&id| *id != node_id).cloned();
+        let others: Vec<String> = node_ids.into_iter().filter(|id| *id != node_id).collect();
+        let mut hyparview = Self {
+            node_id,
+            others,
+            contact,
+            active: Vec::new(),
+            passive: Vec::new(),
+            last_heard: HashMap::new(),
+            pending: HashMap::new(),
+            joined: false,
+            join_sent: None,
+            join_attempts: 0,
+            last_keepalive: Instant::now(),
+            last_shuffle: Instant::now(),
+            rng: Rng::new(),
+        };
+        hyparview.reseed_passive();
+        hyparview
+    }
+
+    /// The active view: the node's neighbors, for gossip.
+    pub fn active_view(&self) -> &[String] {
+        &self.active
+    }
+
+    /// The passive view.
+    pub fn passive_view(&self) -> &[String] {
+        &self.passive
+    }
+
+    /// Notes that we have heard from `peer`, by any message, which shows that it's alive.
+    pub fn heard_from(&mut self, peer: &str) {
+        if let Some(last_heard) = self.last_heard.get_mut(peer) {
+            *last_heard = Instant::now();
+        }
+    }
+
+    /// Handles a message from the node `src`.
+    pub fn handle(&mut self, src: String, payload: HyParViewPayload) -> Outbox {
+        self.heard_from(&src);
+        let mut outbox = Outbox::new();
+
+        match payload {
+            HyParViewPayload::Join => {
+                for peer in &self.active {
+                    if *peer != src {
+                        let payload = HyParViewPayload::ForwardJoin {
+                            new_node: src.clone(),
+                            ttl: ACTIVE_RANDOM_WALK_LENGTH,
+                        };
+                        outbox.push((peer.clone(), payload));
+                    }
+                }
+                self.connect(src, &mut outbox);
+            }
+            HyParViewPayload::ForwardJoin { new_node, ttl } => {
+                if new_node == self.node_id || self.active.contains(&new_node) {
+                    return outbox;
+                }
+                if ttl == PASSIVE_RANDOM_WALK_LENGTH {
+                    self.add_passive(new_node.clone());
+                }
+                match self.random_active_except(&[&src, &new_node]) {
+                    Some(peer) if ttl > 0 && self.active.len() > 1 => {
+                        let payload = HyParViewPayload::ForwardJoin {
+                            new_node,
+                            ttl: ttl - 1,
+                        };
+                        outbox.push((peer, payload));
+                    }
+                    _ => self.connect(new_node, &mut outbox),
+                }
+            }
+            HyParViewPayload::Neighbor { high_priority } => {
+                let accepted = high_priority
+                    || self.active.contains(&src)
+                    || self.active.len() < ACTIVE_VIEW_SIZE;
+                if accepted {
+                    self.add_active(src.clone(), &mut outbox);
+                }
+                outbox.push((src, HyParViewPayload::NeighborOk { accepted }));
+            }
+            HyParViewPayload::NeighborOk { accepted } => {
+                self.pending.remove(&src);
+                if accepted {
+                    self.add_active(src, &mut outbox);
+                }
+            }
+            HyParViewPayload::Disconnect => {
+                if self.remove_active(&src) {
+                    self.add_passive(src);
+                }
+            }
+            HyParViewPayload::Shuffle { origin, ttl, nodes } => {
+                if origin == self.node_id {
+                    return outbox;
+                }
+                match self.random_active_except(&[&src, &origin]) {
+                    Some(peer) if ttl > 0 && self.active.len() > 1 => {
+                        let payload = HyParViewPayload::Shuffle {
+                            origin,
+                            ttl: ttl - 1,
+                            nodes,
+                        };
+                        outbox.push((peer, payload));
+                    }
+                    _ => {
+                        let sample = self.sample(&self.passive.clone(), nodes.len());
+                        outbox.push((origin, HyParViewPayload::ShuffleOk { nodes: sample }));
+                        self.integrate(nodes);
+                    }
+                }
+            }
+            HyParViewPayload::ShuffleOk { nodes } => self.integrate(nodes),
+            HyParViewPayload::Keepalive => {
+                // The sender thinks that we are neighbors, but we don't.
+                if !self.active.contains(&src) {
+                    outbox.push((src, HyParViewPayload::Disconnect));
+                }
+            }
+        }
+
+        outbox
+    }
+
+    /// Detects failed neighbors, repairs the active view, and sends keepalives and shuffles when they are due.
+    pub fn tick(&mut self) -> Outbox {
+        let now = Instant::now();
+        let mut outbox = Outbox::new();
+
+        let failed: Vec<String> = self
+            .active
+            .iter()
+            .filter(|&peer| {
+                self.last_heard
+                    .get(peer)
+                    .is_none_or(|&last_heard| now - last_heard >= PEER_TIMEOUT)
+            })
+            .cloned()
+            .collect();
+        for peer in failed {
+            self.remove_active(&peer);
+        }
+
+        let timed_out: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|&(_, &sent)| now - sent >= PEER_TIMEOUT)
+            .map(|(peer, _)| peer.clone())
+            .collect();
+        for peer in timed_out {
+            self.pending.remove(&peer);
+            self.passive.retain(|id| *id != peer);
+        }
+
+        self.joined |= !self.active.is_empty();
+        let join_due = self.join_sent.is_none_or(|sent| now - sent >= PEER_TIMEOUT);
+        if let (false, Some(contact), true) = (self.joined, &self.contact, join_due) {
+            outbox.push((contact.clone(), HyParViewPayload::Join));
+            self.join_sent = Some(now);
+            self.join_attempts += 1;
+        }
+
+        // Until a node's first join times out, it doesn't look for neighbors on its own.
+        let may_repair = self.joined || self.contact.is_none() || self.join_attempts > 1;
+        if may_repair && self.active.len() < ACTIVE_VIEW_SIZE && self.pending.is_empty() {
+            self.repair(&mut outbox);
+        }
+
+        if now - self.last_keepalive >= KEEPALIVE_INTERVAL {
+            self.last_keepalive = now;
+            for peer in &self.active {
+                outbox.push((peer.clone(), HyParViewPayload::Keepalive));
+            }
+        }
+
+        if now - self.last_shuffle >= SHUFFLE_INTERVAL {
+            self.last_shuffle = now;
+            if let Some(peer) = self.random_active_except(&[]) {
+                let mut nodes = vec![self.node_id.clone()];
+                nodes.extend(self.sample(&self.active.clone(), SHUFFLE_ACTIVE));
+                nodes.extend(self.sample(&self.passive.clone(), SHUFFLE_PASSIVE));
+                let payload = HyParViewPayload::Shuffle {
+                    origin: self.node_id.clone(),
+                    ttl: ACTIVE_RANDOM_WALK_LENGTH,
+                    nodes,
+                };
+                outbox.push((peer, payload));
+            }
+        }
+
+        outbox
+    }
+
+    /// Asks a random peer from the passive view to become our neighbor.
+    fn repair(&mut self, outbox: &mut Outbox) {
+        if self.passive.is_empty() && self.active.is_empty() {
+            self.reseed_passive();
+        }
+        if self.passive.is_empty() {
+            return;
+        }
+
+        let peer = self.passive[self.rng.gen_range(0..self.passive.len() as u64) as usize].clone();
+        self.pending.insert(peer.clone(), Instant::now());
+        let payload = HyParViewPayload::Neighbor {
+            high_priority: self.active.is_empty(),
+        };
+        outbox.push((peer, payload));
+    }
+
+    /// Adds `peer` to the active view, and tells it to do the same.
+    fn connect(&mut self, peer: String, outbox: &mut Outbox) {
+        self.add_active(peer.clone(), outbox);
+        let payload = HyParViewPayload::Neighbor {
+            high_priority: true,
+        };
+        outbox.push((peer, payload));
+    }
+
+    /// Adds `peer` to the active view; if it's full, a random neighbor is moved to the passive view.
+    fn add_active(&mut self, peer: String, outbox: &mut Outbox) {
+        if peer == self.node_id || self.active.contains(&peer) {
+            return;
+        }
+
+        self.passive.retain(|id| *id != peer);
+        if self.active.len() >= ACTIVE_VIEW_SIZE {
+            let dropped = self
+                .active
+                .swap_remove(self.rng.gen_range(0..self.active.len() as u64) as usize);
+            self.last_heard.remove(&dropped);
+            outbox.push((dropped.clone(), HyParViewPayload::Disconnect));
+            self.add_passive(dropped);
+        }
+        self.last_heard.insert(peer.clone(), Instant::now());
+        self.active.push(peer);
+    }
+
+    /// Removes `peer` from the active view; returns whether it was there.
+    fn remove_active(&mut self, peer: &str) -> bool {
+        self.last_heard.remove(peer);
+        let len = self.active.len();
+        self.active.retain(|id| id != peer);
+        self.active.len() < len
+    }
+
+    /// Adds `peer` to the passive view; if it's full, a random peer is removed from it.
+    fn add_passive(&mut self, peer: String) {
+        if peer == self.node_id || self.active.contains(&peer) || self.passive.contains(&peer) {
+            return;
+        }
+
+        if self.passive.len() >= PASSIVE_VIEW_SIZE {
+            self.passive
+                .swap_remove(self.rng.gen_range(0..self.passive.len() as u64) as usize);
+        }
+        self.passive.push(peer);
+    }
+
+    /// Adds the nodes from a shuffle to the passive view.
+    fn integrate(&mut self, nodes: Vec<String>) {
+        for node in nodes {
+            self.add_passive(node);
+        }
+    }
+
+    /// Fills the passive view with a random sample of all other nodes.
+    fn reseed_passive(&mut self) {
+        let others = self.others.clone();
+        for node in self.sample(&others, PASSIVE_VIEW_SIZE) {
+            self.add_passive(node);
+        }
+    }
+
+    /// A random neighbor, other than the given nodes.
+    fn random_active_except(&mut self, except: &[&String]) -> Option<String> {
+        let candidates: Vec<&String> = self
+            .active
+            .iter()
+            .filter(|peer| !except.contains(peer))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let index = self.rng.gen_range(0..candidates.len() as u64) as usize;
+        Some(candidates[index].clone())
+    }
+
+    /// A random sample of at most `count` of the nodes.
+    fn sample(&mut self, nodes: &[String], count: usize) -> Vec<String> {
+        let mut nodes = nodes.to_vec();
+        self.rng.shuffle(&mut nodes);
+        nodes.truncate(count);
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The node `n0` of a cluster of `n0` to `n49`: its own contact node.
+    fn hyparview() -> HyParView {
+        let node_ids = (0..50).map(|i| format!("n{i}")).collect();
+        let mut hyparview = HyParView::new("n0".to_string(), node_ids);
+        hyparview.rng = Rng::with_seed(1);
+        hyparview
+    }
+
+    fn assert_views_are_valid(hyparview: &HyParView) {
+        assert!(hyparview.active.len() <= ACTIVE_VIEW_SIZE);
+        assert!(hyparview.passive.len() <= PASSIVE_VIEW_SIZE);
+        for peer in &hyparview.active {
+            assert_ne!(peer, "n0");
+            assert!(!hyparview.passive.contains(peer), "{peer} is in both views");
+        }
+        assert!(!hyparview.passive.iter().any(|peer| peer == "n0"));
+    }
+
+    /// The peers that are sent the given kind of message.
+    fn sent(outbox: &Outbox, kind: fn(&HyParViewPayload) -> bool) -> Vec<&str> {
+        outbox
+            .iter()
+            .filter(|(_, payload)| kind(payload))
+            .map(|(peer, _)| peer.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn the_active_view_stays_within_its_size_on_joins() {
+        let mut hyparview = hyparview();
+        for i in 1..=ACTIVE_VIEW_SIZE + 3 {
+            let outbox = hyparview.handle(format!("n{i}"), HyParViewPayload::Join);
+            assert_views_are_valid(&hyparview);
+            assert_eq!(hyparview.active.last().unwrap(), &format!("n{i}"));
+
+            // The join is spread to the other neighbors, and a neighbor is dropped once the view is full.
+            let forwarded = sent(&outbox, |payload| {
+                matches!(payload, HyParViewPayload::ForwardJoin { .. })
+            });
+            assert_eq!(forwarded.len(), (i - 1).min(ACTIVE_VIEW_SIZE));
+            let dropped = sent(&outbox, |payload| {
+                matches!(payload, HyParViewPayload::Disconnect)
+            });
+            assert_eq!(dropped.len(), usize::from(i > ACTIVE_VIEW_SIZE));
+            for peer in dropped {
+                assert!(hyparview.passive.iter().any(|id| id == peer));
+            }
+        }
+        assert_eq!(hyparview.active.len(), ACTIVE_VIEW_SIZE);
+    }
+
+    #[test]
+    fn the_active_view_stays_within_its_size_on_forward_joins() {
+        let mut hyparview = hyparview();
+        for i in 1..=ACTIVE_VIEW_SIZE {
+            hyparview.handle(format!("n{i}"), HyParViewPayload::Join);
+        }
+
+        // At the end of its walk, the new node becomes a neighbor.
+        for i in 10..20 {
+            let payload = HyParViewPayload::ForwardJoin {
+                new_node: format!("n{i}"),
+                ttl: 0,
+            };
+            let outbox = hyparview.handle("n1".to_string(), payload);
+            assert_views_are_valid(&hyparview);
+            assert!(hyparview.active.contains(&format!("n{i}")));
+            let dropped = sent(&outbox, |payload| {
+                matches!(payload, HyParViewPayload::Disconnect)
+            });
+            assert_eq!(dropped.len(), 1);
+        }
+
+        // Along the way, it's forwarded, and added to the passive view at the given step.
+        let payload = HyParViewPayload::ForwardJoin {
+            new_node: "n40".to_string(),
+            ttl: PASSIVE_RANDOM_WALK_LENGTH,
+        };
+        hyparview.passive.retain(|peer| peer != "n40");
+        let outbox = hyparview.handle("n1".to_string(), payload);
+        assert_views_are_valid(&hyparview);
+        assert!(matches!(
+            &outbox[..],
+            [(peer, HyParViewPayload::ForwardJoin { new_node, ttl })]
+                if peer != "n1" && new_node == "n40" && *ttl == PASSIVE_RANDOM_WALK_LENGTH - 1
+        ));
+        assert!(hyparview.passive.iter().any(|peer| peer == "n40"));
+        assert!(!hyparview.active.iter().any(|peer| peer == "n40"));
+    }
+
+    #[test]
+    fn a_disconnect_promotes_a_node_from_the_passive_view() {
+        let mut hyparview = hyparview();
+        for i in 1..=ACTIVE_VIEW_SIZE {
+            hyparview.handle(format!("n{i}"), HyParViewPayload::Join);
+        }
+
+        let outbox = hyparview.handle("n3".to_string(), HyParViewPayload::Disconnect);
+        assert!(outbox.is_empty());
+        assert_eq!(hyparview.active.len(), ACTIVE_VIEW_SIZE - 1);
+        assert!(hyparview.passive.iter().any(|peer| peer == "n3"));
+
+        let outbox = hyparview.tick();
+        let [(peer, HyParViewPayload::Neighbor { high_priority })] = &outbox[..] else {
+            panic!("expected a neighbor request, got {outbox:?}");
+        };
+        assert!(!high_priority);
+        assert!(hyparview.passive.contains(peer));
+
+        // No other request is sent while it's pending.
+        assert!(hyparview.tick().is_empty());
+
+        let peer = peer.clone();
+        let payload = HyParViewPayload::NeighborOk { accepted: true };
+        assert!(hyparview.handle(peer.clone(), payload).is_empty());
+        assert_views_are_valid(&hyparview);
+        assert_eq!(hyparview.active.len(), ACTIVE_VIEW_SIZE);
+        assert!(hyparview.active.contains(&peer));
+        assert!(hyparview.tick().is_empty());
+    }
+
+    #[test]
+    fn shuffles_keep_the_passive_view_bounded_and_without_us() {
+        let mut hyparview = hyparview();
+        hyparview.handle("n1".to_string(), HyParViewPayload::Join);
+
+        // A shuffle that reaches the end of its walk is answered with a sample of the passive view.
+        let nodes: Vec<String> = ["n0", "n1"]
+            .into_iter()
+            .map(String::from)
+            .chain((0..20).map(|i| format!("x{i}")))
+            .collect();
+        let payload = HyParViewPayload::Shuffle {
+            origin: "n2".to_string(),
+            ttl: ACTIVE_RANDOM_WALK_LENGTH,
+            nodes: nodes.clone(),
+        };
+        let outbox = hyparview.handle("n1".to_string(), payload);
+        let [(origin, HyParViewPayload::ShuffleOk { nodes: sample })] = &outbox[..] else {
+            panic!("expected a shuffle reply, got {outbox:?}");
+        };
+        assert_eq!(origin, "n2");
+        assert_eq!(sample.len(), nodes.len());
+        assert!(!sample.iter().any(|peer| peer == "n0"));
+        assert_views_are_valid(&hyparview);
+
+        let nodes = ["n0".to_string()]
+            .into_iter()
+            .chain((20..60).map(|i| format!("x{i}")))
+            .collect();
+        hyparview.handle("n2".to_string(), HyParViewPayload::ShuffleOk { nodes });
+        assert_views_are_valid(&hyparview);
+        assert_eq!(hyparview.passive.len(), PASSIVE_VIEW_SIZE);
+
+        // Our own shuffles carry us, and a sample of both views.
+        hyparview.last_shuffle -= SHUFFLE_INTERVAL;
+        let outbox = hyparview.tick();
+        let shuffle = outbox.iter().find_map(|(peer, payload)| match payload {
+            HyParViewPayload::Shuffle { origin, nodes, .. } => Some((peer, origin, nodes)),
+            _ => None,
+        });
+        let Some((peer, origin, nodes)) = shuffle else {
+            panic!("expected a shuffle, got {outbox:?}");
+        };
+        assert_eq!(peer, "n1");
+        assert_eq!(origin, "n0");
+        assert_eq!(nodes[0], "n0");
+        assert_eq!(nodes.len(), 1 + 1 + SHUFFLE_PASSIVE);
+
+        // It's dropped if it comes back to us.
+        let payload = HyParViewPayload::Shuffle {
+            origin: "n0".to_string(),
+            ttl: 1,
+            nodes: nodes.clone(),
+        };
+        assert!(hyparview.handle("n1".to_string(), payload).is_empty());
+    }
+}
@@ -0,0 +1,550 @@
+//! # ID Generation
+//!
+//! Schemes for generating globally-unique IDs, behind the [`IdGenerator`] trait.
+//!
+//! The scheme is selected at startup, through the [`ID_SCHEME_VAR`] environment variable,
+//! because Maelstrom doesn't pass any command-line arguments to our nodes.
+
+use crate::rng::Rng;
+use crate::IdType;
+use anyhow::{bail, Result};
+use std::fmt::Debug;
+use std::ops::Range;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The environment variable that selects the ID scheme.
+pub const ID_SCHEME_VAR: &str = "ID_SCHEME";
+
+/// A generator of globally-unique IDs.
+pub trait IdGenerator: Debug {
+    /// Generates the next ID.
+    ///
+    /// Returns `None` if the generator can't generate one right now, e.g., while it waits for a new block of IDs.
+    fn next_id(&mut self) -> Option<IdType>;
+}
+
+/// The available ID schemes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdScheme {
+    /// `"{node_id}-{counter}"` strings; see [`NodeMsgIdGenerator`].
+    #[default]
+    NodeMsgId,
+    /// Time-sortable 64-bit integers; see [`Snowflake`].
+    Snowflake,
+    /// Random UUIDs; see [`UuidV4`].
+    UuidV4,
+    /// Time-sortable UUIDs; see [`UuidV7`].
+    UuidV7,
+    /// Time-sortable, lexicographically-sortable strings; see [`Ulid`].
+    Ulid,
+    /// Integers from blocks leased by each node; see [`BlockLease`].
+    BlockLease,
+    /// Dense integers from blocks leased from a shared counter in Maelstrom's `lin-kv` service.
+    ///
+    /// The generator is a [`BlockLease`] without blocks of its own; the node has to lease them.
+    LinKvLease,
+}
+
+impl IdScheme {
+    /// Reads the scheme from the [`ID_SCHEME_VAR`] environment variable; the default is [`IdScheme::NodeMsgId`].
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(ID_SCHEME_VAR) {
+            Ok(scheme) => scheme.parse(),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Creates a generator of this scheme for the node `node_id`, in a cluster of `node_ids`.
+    pub fn generator(&self, node_id: &str, node_ids: &[String]) -> Result<Box<dyn IdGenerator>> {
+        Ok(match self {
+            IdScheme::NodeMsgId => Box::new(NodeMsgIdGenerator::new(node_id.to_string())),
+            IdScheme::Snowflake => Box::new(Snowflake::for_node(node_id, node_ids)?),
+            IdScheme::UuidV4 => Box::new(UuidV4::new()),
+            IdScheme::UuidV7 => Box::new(UuidV7::new()),
+            IdScheme::Ulid => Box::new(Ulid::new()),
+            IdScheme::BlockLease => Box::new(BlockLease::striped(
+                node_index(node_id, node_ids)?,
+                node_ids.len() as u64,
+                DEFAULT_BLOCK_SIZE,
+            )),
+            IdScheme::LinKvLease => Box::new(BlockLease::new(DEFAULT_BLOCK_SIZE)),
+        })
+    }
+}
+
+impl std::str::FromStr for IdScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "node-msg-id" => Ok(Self::NodeMsgId),
+            "snowflake" => Ok(Self::Snowflake),
+            "uuid-v4" => Ok(Self::UuidV4),
+            "uuid-v7" => Ok(Self::UuidV7),
+            "ulid" => Ok(Self::Ulid),
+            "block-lease" => Ok(Self::BlockLease),
+            "lin-kv-lease" => Ok(Self::LinKvLease),
+            other => bail!("unknown ID scheme: {other}"),
+        }
+    }
+}
+
+/// The position of the node `node_id` in `node_ids`, which all nodes receive identically.
+fn node_index(node_id: &str, node_ids: &[String]) -> Result<u64> {
+    match node_ids.iter().position(|id| id == node_id) {
+        Some(node_index) => Ok(node_index as u64),
+        None => bail!("node {node_id} is not in the cluster {node_ids:?}"),
+    }
+}
+
+/// The current wall-clock time in milliseconds since the Unix epoch.
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// # The Node-Message-ID Generator
+///
+/// Generates `"{node_id}-{counter}"` strings. Node IDs are unique in the cluster,
+/// and the counter is unique on the node, just like the node's `msg_id`.
+///
+/// That leaks the topology, isn't sortable by time, and is larger than needed, but it's simple.
+#[derive(Clone, Debug)]
+pub struct NodeMsgIdGenerator {
+    node_id: String,
+    counter: usize,
+}
+
+impl NodeMsgIdGenerator {
+    pub fn new(node_id: String) -> Self {
+        Self {
+            node_id,
+            counter: 0,
+        }
+    }
+}
+
+impl IdGenerator for NodeMsgIdGenerator {
+    fn next_id(&mut self) -> Option<IdType> {
+        self.counter += 1;
+        Some(IdType::String(format!("{}-{}", self.node_id, self.counter)))
+    }
+}
+
+/// The start of our Snowflake time: 2024-01-01T00:00:00Z, in milliseconds since the Unix epoch.
+pub const SNOWFLAKE_EPOCH_MS: u64 = 1_704_067_200_000;
+
+/// The number of bits for the node index.
+pub const NODE_INDEX_BITS: u32 = 10;
+
+/// The number of bits for the per-millisecond sequence number.
+pub const SEQUENCE_BITS: u32 = 12;
+
+/// The largest node index that fits in a Snowflake ID.
+pub const MAX_NODE_INDEX: u64 = (1 << NODE_INDEX_BITS) - 1;
+
+/// The largest sequence number that fits in a Snowflake ID.
+pub const MAX_SEQUENCE: u64 = (1 << SEQUENCE_BITS) - 1;
+
+/// # A Snowflake ID Generator
+///
+/// Generates 64-bit integers which are unique across the cluster and roughly sortable by time:
+///
+/// ```text
+/// | 0 | 41 bits: milliseconds since SNOWFLAKE_EPOCH_MS | 10 bits: node index | 12 bits: sequence |
+/// ```
+///
+/// The node index is the node's position in the list of all nodes, which all nodes receive identically,
+/// so the nodes never have to coordinate. Up to 4096 IDs can be generated per millisecond per node.
+///
+/// The timestamp never goes backwards: if the clock regresses, or if the sequence is exhausted within
+/// a millisecond, the generator keeps going on a logical timestamp ahead of the wall clock,
+/// borrowing milliseconds from the future instead of blocking, which keeps the node totally available.
+/// The wall clock catches up eventually.
+#[derive(Clone, Debug)]
+pub struct Snowflake {
+    node_index: u64,
+    /// The timestamp of the last generated ID.
+    last_timestamp: u64,
+    /// The sequence number of the last generated ID, within `last_timestamp`.
+    sequence: u64,
+}
+
+impl Snowflake {
+    /// Creates a generator for the node at `node_index`, which must fit in [`NODE_INDEX_BITS`].
+    pub fn new(node_index: u64) -> Result<Self> {
+        if node_index > MAX_NODE_INDEX {
+            bail!("node index {node_index} doesn't fit in {NODE_INDEX_BITS} bits");
+        }
+
+        Ok(Self {
+            node_index,
+            last_timestamp: 0,
+            sequence: 0,
+        })
+    }
+
+    /// Creates a generator for the node `node_id`, indexed by its position in `node_ids`.
+    pub fn for_node(node_id: &str, node_ids: &[String]) -> Result<Self> {
+        Self::new(node_index(node_id, node_ids)?)
+    }
+
+    /// Generates the next ID, given the current wall-clock time in milliseconds since [`SNOWFLAKE_EPOCH_MS`].
+    pub fn next_id_at(&mut self, now: u64) -> u64 {
+        if now > self.last_timestamp {
+            self.last_timestamp = now;
+            self.sequence = 0;
+        } else if self.sequence < MAX_SEQUENCE {
+            // The same millisecond, or the clock went backwards: keep counting in the last one.
+            self.sequence += 1;
+        } else {
+            // The sequence is exhausted: move on to the next millisecond, ahead of the clock.
+            self.last_timestamp += 1;
+            self.sequence = 0;
+        }
+
+        (self.last_timestamp << (NODE_INDEX_BITS + SEQUENCE_BITS))
+            | (self.node_index << SEQUENCE_BITS)
+            | self.sequence
+    }
+}
+
+impl IdGenerator for Snowflake {
+    fn next_id(&mut self) -> Option<IdType> {
+        let now = unix_ms().saturating_sub(SNOWFLAKE_EPOCH_MS);
+        Some(IdType::Integer(self.next_id_at(now)))
+    }
+}
+
+/// Formats 128 bits as a UUID, with the version and the (RFC 9562) variant bits set.
+fn format_uuid(bits: u128, version: u8) -> String {
+    let bits = (bits & !(0xF << 76)) | ((version as u128) << 76);
+    let bits = (bits & !(0b11 << 62)) | (0b10 << 62);
+    let hex = format!("{bits:032x}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// # A UUIDv4 Generator
+///
+/// Generates UUIDs with 122 random bits, so collisions are practically impossible without any coordination.
+///
+/// [RFC 9562: UUID Version 4](https://www.rfc-editor.org/rfc/rfc9562#name-uuid-version-4)
+#[derive(Clone, Debug, Default)]
+pub struct UuidV4 {
+    rng: Rng,
+}
+
+impl UuidV4 {
+    pub fn new() -> Self {
+        Self { rng: Rng::new() }
+    }
+}
+
+impl IdGenerator for UuidV4 {
+    fn next_id(&mut self) -> Option<IdType> {
+        let bits = ((self.rng.next_u64() as u128) << 64) | self.rng.next_u64() as u128;
+        Some(IdType::String(format_uuid(bits, 4)))
+    }
+}
+
+/// # A UUIDv7 Generator
+///
+/// Generates UUIDs that start with a 48-bit Unix timestamp in milliseconds, followed by 74 random bits,
+/// which makes them sortable by time.
+///
+/// [RFC 9562: UUID Version 7](https://www.rfc-editor.org/rfc/rfc9562#name-uuid-version-7)
+#[derive(Clone, Debug, Default)]
+pub struct UuidV7 {
+    rng: Rng,
+}
+
+impl UuidV7 {
+    pub fn new() -> Self {
+        Self { rng: Rng::new() }
+    }
+}
+
+impl IdGenerator for UuidV7 {
+    fn next_id(&mut self) -> Option<IdType> {
+        let timestamp = (unix_ms() as u128 & ((1 << 48) - 1)) << 80;
+        let random =
+            ((self.rng.next_u64() as u128) << 64 | self.rng.next_u64() as u128) & ((1 << 80) - 1);
+        Some(IdType::String(format_uuid(timestamp | random, 7)))
+    }
+}
+
+/// Crockford's Base32 alphabet, which ULIDs are encoded in.
+const CROCKFORD_BASE32: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// # A ULID Generator
+///
+/// Generates Universally Unique Lexicographically Sortable Identifiers: a 48-bit Unix timestamp
+/// in milliseconds, followed by 80 random bits, encoded as 26 characters of Crockford's Base32.
+///
+/// [ULID Specification](https://github.com/ulid/spec)
+#[derive(Clone, Debug, Default)]
+pub struct Ulid {
+    rng: Rng,
+}
+
+impl Ulid {
+    pub fn new() -> Self {
+        Self { rng: Rng::new() }
+    }
+}
+
+impl IdGenerator for Ulid {
+    fn next_id(&mut self) -> Option<IdType> {
+        let timestamp = (unix_ms() as u128 & ((1 << 48) - 1)) << 80;
+        let random =
+            ((self.rng.next_u64() as u128) << 64 | self.rng.next_u64() as u128) & ((1 << 80) - 1);
+        let bits = timestamp | random;
+
+        // 26 characters of 5 bits each hold 130 bits; the top two are always zero.
+        let ulid = (0..26)
+            .rev()
+            .map(|i| CROCKFORD_BASE32[((bits >> (i * 5)) & 0x1F) as usize] as char)
+            .collect();
+        Some(IdType::String(ulid))
+    }
+}
+
+/// The default number of IDs in a leased block.
+pub const DEFAULT_BLOCK_SIZE: u64 = 1000;
+
+/// # A Block-Leasing ID Generator
+///
+/// Hands out consecutive integers from a block (range) of IDs that the node has leased exclusively.
+/// It holds the current block and, ideally, the next one, so that it never runs dry.
+///
+/// When it's [`BlockLease::striped()`], it leases blocks on its own, without any coordination:
+/// the `i`-th node of `n` gets blocks `i`, `i + n`, `i + 2n`, and so on.
+/// Otherwise, blocks come from the outside, through [`BlockLease::lease()`].
+#[derive(Clone, Debug)]
+pub struct BlockLease {
+    /// The block that IDs are currently handed out from.
+    current: Range<u64>,
+    /// The block to continue with, once the current one is exhausted.
+    next: Option<Range<u64>>,
+    block_size: u64,
+    /// When leasing on its own: the index of the next block, and the stride between this node's blocks.
+    stripe: Option<(u64, u64)>,
+}
+
+impl BlockLease {
+    /// Creates a generator without any blocks, which have to be leased from the outside.
+    pub fn new(block_size: u64) -> Self {
+        Self {
+            current: 0..0,
+            next: None,
+            block_size,
+            stripe: None,
+        }
+    }
+
+    /// Creates a generator that leases every `node_count`-th block, starting with block `node_index`.
+    pub fn striped(node_index: u64, node_count: u64, block_size: u64) -> Self {
+        Self {
+            stripe: Some((node_index, node_count)),
+            ..Self::new(block_size)
+        }
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Whether the generator has room for another block, which it will need sooner or later.
+    pub fn wants_block(&self) -> bool {
+        self.next.is_none()
+    }
+
+    /// Adds a leased block, to continue with once the current one is exhausted.
+    pub fn lease(&mut self, block: Range<u64>) {
+        if self.current.is_empty() {
+            self.current = block;
+        } else {
+            self.next = Some(block);
+        }
+    }
+
+    /// The number of IDs left, including the next block.
+    pub fn remaining(&self) -> u64 {
+        let next = self.next.as_ref().map_or(0, |next| next.end - next.start);
+        self.current.end - self.current.start + next
+    }
+}
+
+impl IdGenerator for BlockLease {
+    fn next_id(&mut self) -> Option<IdType> {
+        if self.current.is_empty() {
+            self.current = self.next.take().unwrap_or(0..0);
+        }
+        if let (true, Some((block, stride))) = (self.current.is_empty(), &mut self.stripe) {
+            self.current = *block * self.block_size..(*block + 1) * self.block_size;
+            *block += *stride;
+        }
+
+        self.current.next().map(IdType::Integer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const NODES: usize = 5;
+    const IDS_PER_NODE: usize = 10_000;
+
+    fn node_ids() -> Vec<String> {
+        (0..NODES).map(|node| format!("n{node}")).collect()
+    }
+
+    /// Draws IDs of the `scheme` from every node, in the order in which each node generated them.
+    fn draw(scheme: IdScheme) -> Vec<Vec<IdType>> {
+        let node_ids = node_ids();
+        node_ids
+            .iter()
+            .map(|node_id| {
+                let mut generator = scheme.generator(node_id, &node_ids).unwrap();
+                (0..IDS_PER_NODE)
+                    .map(|_| generator.next_id().expect("expected an ID"))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn assert_distinct(ids: &[Vec<IdType>]) {
+        let all: HashSet<&IdType> = ids.iter().flatten().collect();
+        assert_eq!(all.len(), NODES * IDS_PER_NODE);
+    }
+
+    fn integer(id: &IdType) -> u64 {
+        match id {
+            IdType::Integer(id) => *id,
+            IdType::String(id) => panic!("expected an integer ID, not {id}"),
+        }
+    }
+
+    fn string(id: &IdType) -> &str {
+        match id {
+            IdType::String(id) => id,
+            IdType::Integer(id) => panic!("expected a string ID, not {id}"),
+        }
+    }
+
+    #[test]
+    fn node_msg_ids_are_distinct_and_increasing_per_node() {
+        let ids = draw(IdScheme::NodeMsgId);
+        assert_distinct(&ids);
+        for node in &ids {
+            let counters: Vec<u64> = node
+                .iter()
+                .map(|id| string(id).rsplit_once('-').unwrap().1.parse().unwrap())
+                .collect();
+            assert!(counters.is_sorted_by(|a, b| a < b));
+        }
+    }
+
+    #[test]
+    fn snowflakes_are_distinct_and_increasing_per_node() {
+        let ids = draw(IdScheme::Snowflake);
+        assert_distinct(&ids);
+        for node in &ids {
+            assert!(node.iter().map(integer).is_sorted_by(|a, b| a < b));
+        }
+    }
+
+    #[test]
+    fn snowflakes_survive_clock_regression_and_sequence_exhaustion() {
+        // Forwards, a regression, a long stall on one millisecond (more IDs than fit in one), and forwards again.
+        let clock: Vec<u64> = (100..200)
+            .chain(50..150)
+            .chain(std::iter::repeat_n(300, 3 * (MAX_SEQUENCE as usize + 1)))
+            .chain(301..400)
+            .collect();
+
+        let mut all = HashSet::new();
+        for node_index in [0, 1, 7, MAX_NODE_INDEX] {
+            let mut snowflake = Snowflake::new(node_index).unwrap();
+            let ids: Vec<u64> = clock.iter().map(|&now| snowflake.next_id_at(now)).collect();
+            assert!(ids.is_sorted_by(|a, b| a < b));
+            assert!(ids
+                .iter()
+                .all(|id| (id >> SEQUENCE_BITS) & MAX_NODE_INDEX == node_index));
+            all.extend(ids);
+        }
+        assert_eq!(all.len(), 4 * clock.len());
+
+        assert!(Snowflake::new(MAX_NODE_INDEX + 1).is_err());
+    }
+
+    #[test]
+    fn uuid_v4s_are_distinct() {
+        let ids = draw(IdScheme::UuidV4);
+        assert_distinct(&ids);
+        assert!(ids
+            .iter()
+            .flatten()
+            .all(|id| string(id).len() == 36 && &string(id)[14..15] == "4"));
+    }
+
+    #[test]
+    fn uuid_v7s_are_distinct_and_sorted_by_time_per_node() {
+        let ids = draw(IdScheme::UuidV7);
+        assert_distinct(&ids);
+        for node in &ids {
+            assert!(node.iter().all(|id| &string(id)[14..15] == "7"));
+            // The timestamp is the first 48 bits: the first 13 characters, with the hyphen.
+            assert!(node.iter().map(|id| &string(id)[..13]).is_sorted());
+        }
+    }
+
+    #[test]
+    fn ulids_are_distinct_and_sorted_by_time_per_node() {
+        let ids = draw(IdScheme::Ulid);
+        assert_distinct(&ids);
+        for node in &ids {
+            assert!(node.iter().all(|id| string(id).len() == 26));
+            // The timestamp is the first 10 characters.
+            assert!(node.iter().map(|id| &string(id)[..10]).is_sorted());
+        }
+    }
+
+    #[test]
+    fn striped_block_leases_are_distinct_and_increasing_per_node() {
+        let ids = draw(IdScheme::BlockLease);
+        assert_distinct(&ids);
+        for node in &ids {
+            assert!(node.iter().map(integer).is_sorted_by(|a, b| a < b));
+        }
+    }
+
+    #[test]
+    fn block_leases_only_hand_out_leased_blocks() {
+        let mut lease = BlockLease::new(10);
+        assert!(lease.next_id().is_none());
+        assert!(lease.wants_block());
+
+        lease.lease(20..30);
+        lease.lease(50..60);
+        assert!(!lease.wants_block());
+        assert_eq!(lease.remaining(), 20);
+
+        let ids: Vec<u64> = std::iter::from_fn(|| lease.next_id())
+            .map(|id| integer(&id))
+            .collect();
+        assert_eq!(ids, (20..30).chain(50..60).collect::<Vec<_>>());
+        assert!(lease.wants_block());
+    }
+}
@@ -0,0 +1,530 @@
+//! # Leader Election
+//!
+//! Elects a single leader among the nodes with a lease in Maelstrom's `lin-kv` service.
+//!
+//! The lease is a record under a key of the caller's choice: the leader, a fencing token, and a renewal counter.
+//! - The leader renews the lease every [`RENEW_INTERVAL`], by compare-and-setting the record to one with
+//!   the next renewal count. It's the leader for [`LEASE_DURATION`] minus [`CLOCK_MARGIN`] after it sent
+//!   its last successful renewal, and it learns that it's lost the lease when a renewal fails,
+//!   and it reads somebody else's lease instead.
+//! - The other nodes poll the record every [`POLL_INTERVAL`]. A node that has seen the same record for
+//!   [`LEASE_DURATION`], measured from when it first read it, takes the lease over, by compare-and-setting
+//!   the record to one with itself as the leader and the next fencing token.
+//!
+//! A record that has been read was written before it was read, so the old leader's lease has run out
+//! by the time another node takes it over, even if the old leader can't reach `lin-kv`, or it's partitioned
+//! away from everybody: there is at most one leader at a time, as long as the nodes' clocks run at about
+//! the same rate. Nothing depends on the clocks showing the same time, though, because every node only
+//! measures how much time has passed since its own events.
+//!
+//! The fencing token increases with every new leader. A leader passes it along with whatever it does as the leader,
+//! so that a resource can reject a deposed leader that doesn't know it yet: one with a lower token than it's seen.
+//!
+//! [How to do distributed locking](https://martin.kleppmann.com/2016/02/08/how-to-do-distributed-locking.html)
+//!
+//! Its requests go to `lin-kv`, and the node hands it the responses. Changes of leadership are reported
+//! to the callbacks registered with [`LeaderElection::on_leadership_change()`].
+
+use crate::message::{ErrorCode, ErrorPayload, KvPayload};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt::{self, Debug, Formatter};
+use std::time::{Duration, Instant};
+
+/// How long a lease lasts without a renewal, as far as the other nodes can tell.
+pub const LEASE_DURATION: Duration = Duration::from_secs(1);
+
+/// How much sooner than the other nodes the leader considers its lease expired,
+/// to make up for the clocks not running at exactly the same rate.
+pub const CLOCK_MARGIN: Duration = Duration::from_millis(200);
+
+/// How often the leader renews its lease.
+pub const RENEW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often the other nodes read the lease.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// After how long an unanswered request to `lin-kv` is given up on.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// # A Lease
+///
+/// As stored in `lin-kv`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    pub leader: String,
+    /// The fencing token: increases with every new leader.
+    pub token: u64,
+    /// Increases with every renewal by the same leader, so that the other nodes see it's alive.
+    pub renewal: u64,
+}
+
+/// A change of leadership, for this node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Leadership {
+    /// We have become the leader, with the fencing token `token`.
+    Gained { token: u64 },
+    /// We aren't the leader anymore.
+    Lost,
+}
+
+pub type LeadershipCallback = Box<dyn FnMut(Leadership)>;
+
+/// What a request to `lin-kv` is about.
+#[derive(Clone, Debug)]
+enum Pending {
+    Read,
+    /// Taking the lease over, or creating it; the new lease.
+    Claim(Lease),
+    /// Renewing our lease; the renewed lease.
+    Renew(Lease),
+}
+
+/// # A Leader Election
+pub struct LeaderElection {
+    /// Our own node ID.
+    node_id: String,
+    /// The key of the lease in `lin-kv`.
+    key: String,
+    /// The request to `lin-kv` that we are waiting for, and when we sent it.
+    pending: Option<(Pending, Instant)>,
+    /// The lease as we last read it (`None` if there was none), and when we first read it like that.
+    observed: Option<(Option<Lease>, Instant)>,
+    /// Our lease, and until when we are the leader.
+    held: Option<(Lease, Instant)>,
+    /// When we last sent a request that wasn't answered with a follow-up.
+    last_request: Option<Instant>,
+    callbacks: Vec<LeadershipCallback>,
+}
+
+impl Debug for LeaderElection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LeaderElection")
+            .field("node_id", &self.node_id)
+            .field("key", &self.key)
+            .field("pending", &self.pending)
+            .field("observed", &self.observed)
+            .field("held", &self.held)
+            .field("last_request", &self.last_request)
+            .field("callbacks", &self.callbacks.len())
+            .finish()
+    }
+}
+
+impl LeaderElection {
+    /// Creates a new election for the node `node_id`, with the lease under `key` in `lin-kv`.
+    pub fn new(node_id: String, key: String) -> Self {
+        Self {
+            node_id,
+            key,
+            pending: None,
+            observed: None,
+            held: None,
+            last_request: None,
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// Registers a callback for changes of leadership.
+    pub fn on_leadership_change(&mut self, callback: impl FnMut(Leadership) + 'static) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Whether we are the leader now.
+    pub fn is_leader(&self) -> bool {
+        self.held
+            .as_ref()
+            .is_some_and(|(_, until)| Instant::now() < *until)
+    }
+
+    /// Our fencing token, while we are the leader.
+    pub fn token(&self) -> Option<u64> {
+        self.held
+            .as_ref()
+            .filter(|_| self.is_leader())
+            .map(|(lease, _)| lease.token)
+    }
+
+    /// The leader, as far as we know: ourselves, or the one in the lease that we last read;
+    /// `None` if we don't know of any.
+    pub fn leader(&self) -> Option<&str> {
+        if self.is_leader() {
+            return Some(&self.node_id);
+        }
+        self.observed
+            .as_ref()
+            .and_then(|(lease, _)| lease.as_ref())
+            .map(|lease| lease.leader.as_str())
+            .filter(|leader| *leader != self.node_id)
+    }
+
+    /// Expires our lease, if it's run out, and returns the next request to send to `lin-kv`, if one is due.
+    pub fn tick(&mut self) -> Option<KvPayload> {
+        if self.held.is_some() && !self.is_leader() {
+            self.lose();
+        }
+
+        match &self.pending {
+            Some((_, sent)) if sent.elapsed() < REQUEST_TIMEOUT => return None,
+            // The outcome is unknown; the next read tells.
+            Some(_) => self.pending = None,
+            None => {}
+        }
+
+        let interval = if self.held.is_some() {
+            RENEW_INTERVAL
+        } else {
+            POLL_INTERVAL
+        };
+        if self
+            .last_request
+            .is_some_and(|sent| sent.elapsed() < interval)
+        {
+            return None;
+        }
+        self.last_request = Some(Instant::now());
+
+        match &self.held {
+            Some((lease, _)) => {
+                let renewed = Lease {
+                    renewal: lease.renewal + 1,
+                    ..lease.clone()
+                };
+                Some(self.cas(Some(lease.clone()), Pending::Renew(renewed)))
+            }
+            None => Some(self.read()),
+        }
+    }
+
+    /// Handles the response from `lin-kv` to our last request, and returns a follow-up request, if there is one.
+    pub fn on_response(&mut self, response: Result<KvPayload, ErrorPayload>) -> Option<KvPayload> {
+        let (pending, sent) = self.pending.take()?;
+
+        match (pending, response) {
+            (Pending::Read, Ok(KvPayload::ReadOk { value })) => {
+                let Ok(lease) = serde_json::from_value::<Lease>(value) else {
+                    return None;
+                };
+                self.observe(Some(lease.clone()));
+                if lease.leader != self.node_id && self.held.is_some() {
+                    self.lose();
+                }
+
+                let first_seen = self.observed.as_ref().map(|(_, first_seen)| *first_seen);
+                if lease.leader == self.node_id {
+                    // Ours, from a claim or a renewal whose response we missed; when it was written is unknown,
+                    // so it's only ours once we renew it.
+                    let renewed = Lease {
+                        renewal: lease.renewal + 1,
+                        ..lease.clone()
+                    };
+                    Some(self.cas(Some(lease), Pending::Renew(renewed)))
+                } else if first_seen
+                    .is_some_and(|first_seen| first_seen.elapsed() >= LEASE_DURATION)
+                {
+                    let claimed = Lease {
+                        leader: self.node_id.clone(),
+                        token: lease.token + 1,
+                        renewal: 0,
+                    };
+                    Some(self.cas(Some(lease), Pending::Claim(claimed)))
+                } else {
+                    None
+                }
+            }
+            (Pending::Read, Err(error)) if error.code == ErrorCode::KeyDoesNotExist => {
+                // Nobody has ever been the leader.
+                self.observe(None);
+                let claimed = Lease {
+                    leader: self.node_id.clone(),
+                    token: 1,
+                    renewal: 0,
+                };
+                Some(self.cas(None, Pending::Claim(claimed)))
+            }
+            (Pending::Claim(lease) | Pending::Renew(lease), Ok(KvPayload::CasOk)) => {
+                let gained = self.held.is_none();
+                self.observe(Some(lease.clone()));
+                // The lease is ours from when we sent the request, which is before the others can read it.
+                self.held = Some((lease.clone(), sent + LEASE_DURATION - CLOCK_MARGIN));
+                if gained {
+                    self.notify(Leadership::Gained { token: lease.token });
+                }
+                None
+            }
+            (Pending::Claim(_) | Pending::Renew(_), Err(error))
+                if error.code == ErrorCode::PreconditionFailed =>
+            {
+                // Somebody else has the lease now, or a renewal whose response we missed has changed it;
+                // the read tells.
+                Some(self.read())
+            }
+            // Unknown; the next request tells.
+            _ => None,
+        }
+    }
+
+    fn read(&mut self) -> KvPayload {
+        self.pending = Some((Pending::Read, Instant::now()));
+        KvPayload::Read {
+            key: Value::from(self.key.clone()),
+        }
+    }
+
+    /// A compare-and-set of the lease from `from` (`None` if there's none yet) to the claimed or renewed lease.
+    fn cas(&mut self, from: Option<Lease>, pending: Pending) -> KvPayload {
+        let to = match &pending {
+            Pending::Claim(lease) | Pending::Renew(lease) => lease.clone(),
+            Pending::Read => unreachable!("expected a claim or a renewal"),
+        };
+        self.pending = Some((pending, Instant::now()));
+        KvPayload::Cas {
+            key: Value::from(self.key.clone()),
+            from: serde_json::to_value(&from).expect("expected a serializable lease"),
+            to: serde_json::to_value(to).expect("expected a serializable lease"),
+            create_if_not_exists: from.is_none(),
+        }
+    }
+
+    /// Notes the lease as read or written now.
+    fn observe(&mut self, lease: Option<Lease>) {
+        let unchanged = self
+            .observed
+            .as_ref()
+            .is_some_and(|(observed, _)| *observed == lease);
+        if !unchanged {
+            self.observed = Some((lease, Instant::now()));
+        }
+    }
+
+    fn lose(&mut self) {
+        self.held = None;
+        self.notify(Leadership::Lost);
+    }
+
+    fn notify(&mut self, leadership: Leadership) {
+        for callback in &mut self.callbacks {
+            callback(leadership);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rsm::{KvStore, StateMachine};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A node's election, with the changes of leadership that it has reported.
+    fn election(node_id: &str) -> (LeaderElection, Rc<RefCell<Vec<Leadership>>>) {
+        let mut election = LeaderElection::new(node_id.to_string(), "leader".to_string());
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&changes);
+        election.on_leadership_change(move |change| recorded.borrow_mut().push(change));
+        (election, changes)
+    }
+
+    /// Lets `by` pass, for the election: moves all of its points in time back.
+    fn age(election: &mut LeaderElection, by: Duration) {
+        let back = |instant: &mut Instant| *instant = instant.checked_sub(by).unwrap();
+        if let Some((_, sent)) = &mut election.pending {
+            back(sent);
+        }
+        if let Some((_, first_seen)) = &mut election.observed {
+            back(first_seen);
+        }
+        if let Some((_, until)) = &mut election.held {
+            back(until);
+        }
+        if let Some(sent) = &mut election.last_request {
+            back(sent);
+        }
+    }
+
+    /// Runs a request through `lin-kv`, and its follow-ups, until there are none.
+    fn exchange(election: &mut LeaderElection, kv: &mut KvStore, request: Option<KvPayload>) {
+        let mut request = request;
+        while let Some(payload) = request {
+            request = election.on_response(kv.apply(payload));
+        }
+    }
+
+    fn lease(kv: &mut KvStore) -> Lease {
+        let Ok(KvPayload::ReadOk { value }) = kv.apply(KvPayload::Read {
+            key: Value::from("leader"),
+        }) else {
+            panic!("expected a lease");
+        };
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn the_first_node_creates_the_lease() {
+        let mut kv = KvStore::default();
+        let (mut n0, changes) = election("n0");
+
+        let request = n0.tick();
+        exchange(&mut n0, &mut kv, request);
+
+        assert!(n0.is_leader());
+        assert_eq!(n0.token(), Some(1));
+        assert_eq!(*changes.borrow(), [Leadership::Gained { token: 1 }]);
+        assert_eq!(lease(&mut kv).leader, "n0");
+    }
+
+    #[test]
+    fn a_lease_is_claimed_only_after_it_has_been_unchanged_for_its_duration() {
+        let mut kv = KvStore::default();
+        let (mut n0, _) = election("n0");
+        let request = n0.tick();
+        exchange(&mut n0, &mut kv, request);
+
+        let (mut n1, changes) = election("n1");
+        let request = n1.tick();
+        exchange(&mut n1, &mut kv, request);
+        assert!(!n1.is_leader());
+        assert_eq!(n1.leader(), Some("n0"));
+
+        // Not yet: the lease has only been seen unchanged for part of its duration.
+        age(&mut n1, LEASE_DURATION / 2);
+        let request = n1.tick();
+        exchange(&mut n1, &mut kv, request);
+        assert!(!n1.is_leader());
+
+        // `n0` has stopped renewing it, e.g., because it's partitioned away.
+        age(&mut n0, LEASE_DURATION);
+        assert!(!n0.is_leader());
+        age(&mut n1, LEASE_DURATION / 2);
+        let request = n1.tick();
+        exchange(&mut n1, &mut kv, request);
+        assert!(n1.is_leader());
+        assert_eq!(*changes.borrow(), [Leadership::Gained { token: 2 }]);
+        assert_eq!(lease(&mut kv).token, 2);
+    }
+
+    #[test]
+    fn a_failed_renewal_loses_the_leadership() {
+        let mut kv = KvStore::default();
+        let (mut n0, changes) = election("n0");
+        let request = n0.tick();
+        exchange(&mut n0, &mut kv, request);
+
+        // Somebody else has taken the lease over meanwhile.
+        let taken = Lease {
+            leader: "n1".to_string(),
+            token: 2,
+            renewal: 0,
+        };
+        kv.apply(KvPayload::Write {
+            key: Value::from("leader"),
+            value: serde_json::to_value(&taken).unwrap(),
+        })
+        .unwrap();
+
+        age(&mut n0, RENEW_INTERVAL);
+        let request = n0.tick();
+        assert!(matches!(request, Some(KvPayload::Cas { .. })));
+        exchange(&mut n0, &mut kv, request);
+
+        assert!(!n0.is_leader());
+        assert_eq!(n0.leader(), Some("n1"));
+        assert_eq!(
+            *changes.borrow(),
+            [Leadership::Gained { token: 1 }, Leadership::Lost]
+        );
+    }
+
+    #[test]
+    fn a_missed_renewal_response_keeps_the_leadership() {
+        let mut kv = KvStore::default();
+        let (mut n0, changes) = election("n0");
+        let request = n0.tick();
+        exchange(&mut n0, &mut kv, request);
+
+        // The renewal goes through, but its response is lost.
+        age(&mut n0, RENEW_INTERVAL);
+        let request = n0.tick().expect("expected a renewal");
+        kv.apply(request).unwrap();
+        assert_eq!(lease(&mut kv).renewal, 1);
+
+        // It's given up on, and the next renewal's precondition fails, so the lease is read, found ours, and renewed.
+        age(&mut n0, REQUEST_TIMEOUT);
+        assert!(n0.is_leader());
+        let request = n0.tick();
+        exchange(&mut n0, &mut kv, request);
+
+        assert!(n0.is_leader());
+        assert_eq!(n0.token(), Some(1));
+        assert_eq!(lease(&mut kv).renewal, 2);
+        assert_eq!(*changes.borrow(), [Leadership::Gained { token: 1 }]);
+    }
+
+    #[test]
+    fn a_leader_that_cant_renew_steps_down_before_anybody_takes_over() {
+        let mut kv = KvStore::default();
+        let (mut n0, changes) = election("n0");
+        let request = n0.tick();
+        exchange(&mut n0, &mut kv, request);
+
+        // No renewal gets through, e.g., because `n0` is partitioned away from `lin-kv`.
+        age(&mut n0, LEASE_DURATION - CLOCK_MARGIN);
+        assert!(!n0.is_leader());
+        assert!(matches!(n0.tick(), Some(KvPayload::Read { .. })));
+        assert_eq!(
+            *changes.borrow(),
+            [Leadership::Gained { token: 1 }, Leadership::Lost]
+        );
+    }
+
+    /// The elections of the nodes other than the leader.
+    fn others(
+        elections: &mut [LeaderElection],
+        leader: usize,
+    ) -> impl Iterator<Item = &mut LeaderElection> {
+        elections
+            .iter_mut()
+            .enumerate()
+            .filter(move |(index, _)| *index != leader)
+            .map(|(_, election)| election)
+    }
+
+    #[test]
+    fn every_new_leader_has_a_greater_token() {
+        let mut kv = KvStore::default();
+        let mut elections: Vec<LeaderElection> = ["n0", "n1", "n2"]
+            .into_iter()
+            .map(|node| election(node).0)
+            .collect();
+        let request = elections[0].tick();
+        exchange(&mut elections[0], &mut kv, request);
+
+        let mut leader = 0;
+        let mut tokens = vec![elections[leader].token().unwrap()];
+        for _ in 0..6 {
+            // The others read the lease, and then the leader is cut off, and its lease runs out.
+            for election in others(&mut elections, leader) {
+                age(election, POLL_INTERVAL);
+                let request = election.tick();
+                exchange(election, &mut kv, request);
+            }
+            for election in &mut elections {
+                age(election, LEASE_DURATION);
+            }
+            for election in others(&mut elections, leader) {
+                let request = election.tick();
+                exchange(election, &mut kv, request);
+            }
+
+            let leaders: Vec<usize> = (0..elections.len())
+                .filter(|index| elections[*index].is_leader())
+                .collect();
+            assert_eq!(leaders.len(), 1, "{elections:?}");
+            leader = leaders[0];
+            tokens.push(elections[leader].token().unwrap());
+            assert_eq!(lease(&mut kv).token, *tokens.last().unwrap());
+        }
+
+        assert!(tokens.is_sorted_by(|a, b| a < b), "{tokens:?}");
+    }
+}
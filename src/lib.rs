@@ -1,9 +1,65 @@
 //! # The Gossip Glomers Library
+//!
+//! The nodes, in `src/bin`, are built from the modules here. The protocol modules, e.g., [`raft`], [`paxos`],
+//! [`chain`], [`quorum`], [`two_phase_commit`], [`plumtree`] or [`failure_detector`], don't do any I/O on their own:
+//! their methods return the messages that the node should send, the node hands them the messages that it receives,
+//! and it ticks them, for their timeouts. So a test can drive them just as well as a node.
 
+pub mod anti_entropy;
+pub mod causal;
+pub mod chain;
+pub mod clock;
+pub mod crdt;
+pub mod failure_detector;
+pub mod hyparview;
+pub mod id_gen;
+pub mod leader_election;
+pub mod lock;
 pub mod logic;
 pub mod message;
 pub mod node;
+pub mod paxos;
+pub mod plumtree;
+pub mod quorum;
+pub mod raft;
+pub mod ring;
+pub mod rng;
+pub mod rsm;
+pub mod total_order;
+pub mod two_phase_commit;
+
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
 
 /// The type of the generated globally-unique ID.
 /// It may be any type: strings, booleans, integers, floats, compound JSON values, etc.
-pub type IdType = String;
+///
+/// We generate either strings or 64-bit integers, depending on the ID scheme.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum IdType {
+    Integer(u64),
+    String(String),
+}
+
+impl IdType {
+    /// Creates a new, empty string ID.
+    pub fn new() -> Self {
+        Self::String(String::new())
+    }
+}
+
+impl Default for IdType {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for IdType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            IdType::Integer(id) => write!(f, "{id}"),
+            IdType::String(id) => write!(f, "{id}"),
+        }
+    }
+}
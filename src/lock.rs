@@ -0,0 +1,574 @@
+//! # A Lock Service
+//!
+//! Named locks, granted as leases: a client acquires a lock for a time-to-live, renews the lease before it expires,
+//! and releases it when it's done. A lease that isn't renewed in time expires, so a crashed or partitioned client
+//! can't keep a lock forever.
+//!
+//! Every grant comes with a fencing token, which is greater than the tokens of all earlier grants.
+//! A client passes it along with whatever it does under the lock, so that a resource can reject a client whose lease
+//! has expired without it noticing: one with a lower token than it's seen.
+//!
+//! [How to do distributed locking](https://martin.kleppmann.com/2016/02/08/how-to-do-distributed-locking.html)
+//!
+//! [`LockTable`] is a [`StateMachine`], replicated by [`crate::rsm::Replica`]. Expiry depends on the time,
+//! which `apply()` mustn't look up, so the leader stamps every request with its clock when it puts it into the log,
+//! and the table's clock only ever moves forward, to the latest of those times. A lease expires on every replica at
+//! the same point in the log, then, and a new leader whose clock is behind only makes the leases last longer.
+//!
+//! A client measures its lease from when it sent the request, which is before the leader stamped it, so the client
+//! considers its lease expired no later than the service does, as long as their clocks run at about the same rate.
+//!
+//! [`check_locks()`] checks a history of lock operations, as the clients saw them, for mutual exclusion
+//! and for the order of the fencing tokens.
+
+use crate::message::{ErrorCode, ErrorPayload, LockPayload};
+use crate::rsm::StateMachine;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The current time in milliseconds since the Unix epoch.
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+fn error(code: ErrorCode, text: String) -> ErrorPayload {
+    ErrorPayload {
+        code,
+        text: Some(text),
+    }
+}
+
+/// A granted lease.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Lease {
+    token: u64,
+    /// When it expires, on the table's clock.
+    expires: u64,
+}
+
+/// # A Lock Table
+///
+/// Applies the `acquire`, `release` and `renew` operations of the lock service, and returns their responses.
+#[derive(Clone, Debug, Default)]
+pub struct LockTable {
+    /// The leases, by lock; expired leases linger until they are taken over, released or renewed.
+    leases: HashMap<String, Lease>,
+    /// The last fencing token that was handed out.
+    last_token: u64,
+    /// The latest time stamped on a command, in milliseconds since the Unix epoch.
+    now: u64,
+}
+
+impl LockTable {
+    /// Moves the clock forward to `time`, if it's later.
+    fn advance(&mut self, time: Option<u64>) {
+        self.now = self.now.max(time.unwrap_or(0));
+    }
+
+    /// The lease on `lock` with the fencing token `token`, if it hasn't expired.
+    fn live_lease(&mut self, lock: &str, token: u64) -> Result<&mut Lease, ErrorPayload> {
+        let now = self.now;
+        match self.leases.get_mut(lock) {
+            Some(lease) if lease.token == token && now < lease.expires => Ok(lease),
+            Some(lease) if lease.token == token => Err(error(
+                ErrorCode::PreconditionFailed,
+                format!("the lease {token} on {lock} has expired"),
+            )),
+            _ => Err(error(
+                ErrorCode::PreconditionFailed,
+                format!("{lock} isn't held with the lease {token}"),
+            )),
+        }
+    }
+}
+
+impl StateMachine for LockTable {
+    type Command = LockPayload;
+    type Output = Result<LockPayload, ErrorPayload>;
+    type Snapshot = LockTable;
+
+    fn prepare(command: LockPayload) -> LockPayload {
+        match command {
+            LockPayload::Acquire { lock, ttl, .. } => LockPayload::Acquire {
+                lock,
+                ttl,
+                time: Some(now_millis()),
+            },
+            LockPayload::Renew {
+                lock, token, ttl, ..
+            } => LockPayload::Renew {
+                lock,
+                token,
+                ttl,
+                time: Some(now_millis()),
+            },
+            other => other,
+        }
+    }
+
+    fn apply(&mut self, command: LockPayload) -> Self::Output {
+        match command {
+            LockPayload::Acquire { lock, ttl, time } => {
+                self.advance(time);
+                if let Some(lease) = self
+                    .leases
+                    .get(&lock)
+                    .filter(|lease| self.now < lease.expires)
+                {
+                    return Err(error(
+                        ErrorCode::PreconditionFailed,
+                        format!(
+                            "{lock} is held with the lease {} for another {} ms",
+                            lease.token,
+                            lease.expires - self.now
+                        ),
+                    ));
+                }
+                self.last_token += 1;
+                let lease = Lease {
+                    token: self.last_token,
+                    expires: self.now.saturating_add(ttl),
+                };
+                self.leases.insert(lock, lease);
+                Ok(LockPayload::AcquireOk { token: lease.token })
+            }
+            LockPayload::Release { lock, token } => {
+                // Releasing an expired lease that nobody has taken over yet is harmless.
+                match self.leases.get(&lock) {
+                    Some(lease) if lease.token == token => {
+                        self.leases.remove(&lock);
+                        Ok(LockPayload::ReleaseOk)
+                    }
+                    _ => Err(error(
+                        ErrorCode::PreconditionFailed,
+                        format!("{lock} isn't held with the lease {token}"),
+                    )),
+                }
+            }
+            LockPayload::Renew {
+                lock,
+                token,
+                ttl,
+                time,
+            } => {
+                self.advance(time);
+                let now = self.now;
+                let lease = self.live_lease(&lock, token)?;
+                lease.expires = now.saturating_add(ttl);
+                Ok(LockPayload::RenewOk)
+            }
+            other => Err(error(
+                ErrorCode::NotSupported,
+                format!("operation not supported: {other:?}"),
+            )),
+        }
+    }
+
+    fn snapshot(&self) -> Self::Snapshot {
+        self.clone()
+    }
+
+    fn restore(&mut self, snapshot: Self::Snapshot) {
+        *self = snapshot;
+    }
+}
+
+/// The lock operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockOp {
+    Acquire,
+    Release,
+    Renew,
+}
+
+/// Whether an event is the invocation of an operation, or how it completed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Invoke,
+    /// The operation succeeded.
+    Ok,
+    /// The operation definitely failed.
+    Fail,
+    /// The outcome is unknown, e.g., because the request timed out.
+    Info,
+}
+
+/// # An Event in a Lock History
+///
+/// A history is a sequence of events, one JSON object per line, in the order of their times. Every client has
+/// at most one operation in flight: its invocation is followed by its completion, before the client's next invocation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    /// Microseconds since the start of the test, on the one clock of whoever recorded the history.
+    pub time: u64,
+    pub client: String,
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    pub f: LockOp,
+    pub lock: String,
+    /// The fencing token: of a successful `acquire`'s completion, and of a `release`'s or `renew`'s invocation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<u64>,
+    /// The lease duration in milliseconds, of an `acquire`'s or `renew`'s invocation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
+}
+
+/// A span of time during which a client was sure to hold a lock.
+#[derive(Clone, Debug)]
+struct Hold {
+    client: String,
+    token: u64,
+    start: u64,
+    end: u64,
+}
+
+/// A successful `acquire`: when it was invoked, when it completed, and with which fencing token.
+#[derive(Clone, Copy, Debug)]
+struct Grant {
+    invoked: u64,
+    completed: u64,
+    token: u64,
+}
+
+/// Checks a history of lock operations, and returns the number of granted leases.
+///
+/// A client is sure to hold a lock from when its `acquire` or `renew` succeeds, until the `ttl` has passed since
+/// it invoked the operation, or until it invokes a `release`, whichever comes first. Operations with unknown outcomes
+/// don't make anybody sure of anything. The checks are that:
+/// - no two clients are ever sure to hold the same lock at the same time, and that
+/// - a grant of a lock that was invoked after another grant of the same lock had completed has a greater token.
+pub fn check_locks(history: &[Event]) -> Result<usize> {
+    let mut in_flight: HashMap<&str, &Event> = HashMap::new();
+    let mut holds: BTreeMap<&str, Vec<Hold>> = BTreeMap::new();
+    let mut grants: BTreeMap<&str, Vec<Grant>> = BTreeMap::new();
+
+    for event in history {
+        if event.kind == EventKind::Invoke {
+            if let Some(earlier) = in_flight.insert(&event.client, event) {
+                bail!(
+                    "{} invoked {:?} at {} while {:?} was in flight",
+                    event.client,
+                    event.f,
+                    event.time,
+                    earlier.f
+                );
+            }
+            if event.f == LockOp::Release {
+                for hold in holds.entry(&event.lock).or_default() {
+                    if hold.client == event.client && Some(hold.token) == event.token {
+                        hold.end = hold.end.min(event.time);
+                    }
+                }
+            }
+            continue;
+        }
+
+        let Some(invocation) = in_flight.remove(event.client.as_str()) else {
+            bail!(
+                "{} completed {:?} at {} without invoking it",
+                event.client,
+                event.f,
+                event.time
+            );
+        };
+        if invocation.f != event.f || invocation.lock != event.lock {
+            bail!(
+                "{} completed {:?} on {} at {}, but invoked {:?} on {}",
+                event.client,
+                event.f,
+                event.lock,
+                event.time,
+                invocation.f,
+                invocation.lock
+            );
+        }
+        if event.kind != EventKind::Ok {
+            continue;
+        }
+
+        let token = match event.f {
+            LockOp::Acquire => event.token,
+            LockOp::Renew => invocation.token,
+            LockOp::Release => continue,
+        };
+        let (Some(token), Some(ttl)) = (token, invocation.ttl) else {
+            bail!(
+                "{} completed {:?} at {} without a token or a ttl",
+                event.client,
+                event.f,
+                event.time
+            );
+        };
+        if event.f == LockOp::Acquire {
+            grants.entry(&event.lock).or_default().push(Grant {
+                invoked: invocation.time,
+                completed: event.time,
+                token,
+            });
+        }
+        holds.entry(&event.lock).or_default().push(Hold {
+            client: event.client.clone(),
+            token,
+            start: event.time,
+            end: invocation.time.saturating_add(ttl.saturating_mul(1000)),
+        });
+    }
+
+    for (lock, holds) in &mut holds {
+        holds.retain(|hold| hold.start < hold.end);
+        holds.sort_by_key(|hold| hold.start);
+        // The earliest overlap, if any, is with the hold that has lasted the longest so far.
+        let mut longest: Option<&Hold> = None;
+        for hold in holds.iter() {
+            if let Some(longest) =
+                longest.filter(|longest| hold.start < longest.end && longest.token != hold.token)
+            {
+                bail!(
+                    "{} held {lock} with the lease {} from {} to {}, while {} held it with the lease {} from {} to {}",
+                    hold.client,
+                    hold.token,
+                    hold.start,
+                    hold.end,
+                    longest.client,
+                    longest.token,
+                    longest.start,
+                    longest.end
+                );
+            }
+            if longest.is_none_or(|longest| longest.end < hold.end) {
+                longest = Some(hold);
+            }
+        }
+    }
+
+    let mut granted = 0;
+    for (lock, grants) in &mut grants {
+        granted += grants.len();
+        let mut by_completion = grants.clone();
+        by_completion.sort_by_key(|grant| grant.completed);
+        grants.sort_by_key(|grant| grant.invoked);
+
+        // The greatest token of the grants that completed before the current one was invoked.
+        let mut completed = by_completion.iter().peekable();
+        let mut greatest: Option<Grant> = None;
+        for grant in grants.iter() {
+            while let Some(earlier) = completed.next_if(|earlier| earlier.completed < grant.invoked)
+            {
+                if greatest.is_none_or(|greatest| greatest.token < earlier.token) {
+                    greatest = Some(*earlier);
+                }
+            }
+            if let Some(earlier) = greatest.filter(|earlier| earlier.token >= grant.token) {
+                bail!(
+                    "{lock} was granted with the token {} at {}, after it had been granted with the token {} at {}",
+                    grant.token,
+                    grant.invoked,
+                    earlier.token,
+                    earlier.completed
+                );
+            }
+        }
+    }
+
+    Ok(granted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acquire(lock: &str, ttl: u64, time: u64) -> LockPayload {
+        LockPayload::Acquire {
+            lock: lock.to_string(),
+            ttl,
+            time: Some(time),
+        }
+    }
+
+    fn renew(lock: &str, token: u64, ttl: u64, time: u64) -> LockPayload {
+        LockPayload::Renew {
+            lock: lock.to_string(),
+            token,
+            ttl,
+            time: Some(time),
+        }
+    }
+
+    #[test]
+    fn huge_ttl_saturates() {
+        let mut table = LockTable::default();
+        assert!(matches!(
+            table.apply(acquire("a", u64::MAX, 1_000)),
+            Ok(LockPayload::AcquireOk { token: 1 })
+        ));
+        assert!(matches!(
+            table.apply(renew("a", 1, u64::MAX, 2_000)),
+            Ok(LockPayload::RenewOk)
+        ));
+        assert!(table.apply(acquire("a", 100, u64::MAX - 1)).is_err());
+    }
+
+    #[test]
+    fn leases_expire_and_tokens_increase() {
+        let mut table = LockTable::default();
+        assert!(matches!(
+            table.apply(acquire("a", 100, 1_000)),
+            Ok(LockPayload::AcquireOk { token: 1 })
+        ));
+        assert!(table.apply(acquire("a", 100, 1_050)).is_err());
+        assert!(matches!(
+            table.apply(acquire("a", 100, 1_100)),
+            Ok(LockPayload::AcquireOk { token: 2 })
+        ));
+        // The expired lease can be neither renewed nor released anymore.
+        assert!(table.apply(renew("a", 1, 100, 1_150)).is_err());
+        let release = |token| LockPayload::Release {
+            lock: "a".to_string(),
+            token,
+        };
+        assert!(table.apply(release(1)).is_err());
+        assert!(matches!(
+            table.apply(release(2)),
+            Ok(LockPayload::ReleaseOk)
+        ));
+    }
+
+    fn event(time: u64, client: &str, kind: EventKind, f: LockOp, token: Option<u64>) -> Event {
+        let invoke = kind == EventKind::Invoke;
+        Event {
+            time,
+            client: client.to_string(),
+            kind,
+            f,
+            lock: "a".to_string(),
+            token,
+            ttl: (invoke && f != LockOp::Release).then_some(100),
+        }
+    }
+
+    /// `client` acquires `a` with `token` from `invoked` to `completed`.
+    fn acquired(client: &str, invoked: u64, completed: u64, token: u64) -> Vec<Event> {
+        vec![
+            event(invoked, client, EventKind::Invoke, LockOp::Acquire, None),
+            event(
+                completed,
+                client,
+                EventKind::Ok,
+                LockOp::Acquire,
+                Some(token),
+            ),
+        ]
+    }
+
+    /// `client` releases `a` with `token` from `invoked` to `completed`.
+    fn released(client: &str, invoked: u64, completed: u64, token: u64) -> Vec<Event> {
+        vec![
+            event(
+                invoked,
+                client,
+                EventKind::Invoke,
+                LockOp::Release,
+                Some(token),
+            ),
+            event(completed, client, EventKind::Ok, LockOp::Release, None),
+        ]
+    }
+
+    /// `client` renews `a` with `token` from `invoked` to `completed`, with the outcome `kind`.
+    fn renewed(
+        client: &str,
+        invoked: u64,
+        completed: u64,
+        token: u64,
+        kind: EventKind,
+    ) -> Vec<Event> {
+        vec![
+            event(
+                invoked,
+                client,
+                EventKind::Invoke,
+                LockOp::Renew,
+                Some(token),
+            ),
+            event(completed, client, kind, LockOp::Renew, None),
+        ]
+    }
+
+    #[test]
+    fn accepts_a_grant_after_a_release() {
+        let history = [
+            acquired("c1", 0, 10_000, 1),
+            released("c1", 50_000, 60_000, 1),
+            acquired("c2", 55_000, 70_000, 2),
+        ]
+        .concat();
+        assert_eq!(check_locks(&history).unwrap(), 2);
+    }
+
+    #[test]
+    fn accepts_a_grant_after_expiry() {
+        // The lease of `c1` runs out 100 ms after it invoked the `acquire`, without a release.
+        let history = [
+            acquired("c1", 0, 10_000, 1),
+            acquired("c2", 90_000, 100_000, 2),
+        ]
+        .concat();
+        assert_eq!(check_locks(&history).unwrap(), 2);
+    }
+
+    #[test]
+    fn accepts_a_grant_after_a_renewal_with_an_unknown_outcome() {
+        let history = [
+            acquired("c1", 0, 10_000, 1),
+            renewed("c1", 50_000, 150_000, 1, EventKind::Info),
+            acquired("c2", 120_000, 130_000, 2),
+        ]
+        .concat();
+        assert_eq!(check_locks(&history).unwrap(), 2);
+    }
+
+    #[test]
+    fn rejects_overlapping_holds() {
+        let history = [
+            acquired("c1", 0, 10_000, 1),
+            acquired("c2", 20_000, 30_000, 2),
+        ]
+        .concat();
+        assert!(check_locks(&history).is_err());
+    }
+
+    #[test]
+    fn rejects_a_grant_during_a_renewed_lease() {
+        let history = [
+            acquired("c1", 0, 10_000, 1),
+            renewed("c1", 80_000, 90_000, 1, EventKind::Ok),
+            acquired("c2", 110_000, 120_000, 2),
+        ]
+        .concat();
+        assert!(check_locks(&history).is_err());
+    }
+
+    #[test]
+    fn rejects_non_increasing_tokens() {
+        let history = [
+            acquired("c1", 0, 10_000, 2),
+            released("c1", 20_000, 30_000, 2),
+            acquired("c2", 40_000, 50_000, 1),
+        ]
+        .concat();
+        assert!(check_locks(&history).is_err());
+    }
+
+    #[test]
+    fn rejects_a_completion_without_an_invocation() {
+        let history = [event(0, "c1", EventKind::Ok, LockOp::Acquire, Some(1))];
+        assert!(check_locks(&history).is_err());
+    }
+}
@@ -2,11 +2,14 @@
 //!
 //! This belongs to the library and contains the main loop.
 
-use crate::message::{InitPayload, Message, Payload};
+use crate::message::{InitPayload, Message};
 use crate::node::Node;
 use anyhow::{Context, Result};
 use std::fmt::Debug;
 use std::io::{self, BufRead};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Instant;
 
 /// The main library loop.
 pub fn main_loop<N>() -> Result<()>
@@ -30,16 +33,56 @@ where
     node.init_response(init_request, &mut stdout_lock)
         .context(format!("{node:?}: init_response method failed"))?;
 
+    drop(requests);
+
     // Our node (server) is now ready to receive all other messages (but not an init message again).
-    for request in requests {
-        let request = request.context("failed to read request from stdin")?;
-        let request: Message<Payload> =
-            serde_json::from_str(&request).context("deserialization of request message failed")?;
-        node.step(request, &mut stdout_lock)
-            .context(format!("{node:?}: step method failed"))?;
+    // They are read on a separate thread, so that the node can also be ticked while no requests arrive.
+    let (tx, rx) = mpsc::channel();
+    let reader = thread::spawn(move || -> Result<()> {
+        for request in io::stdin().lock().lines() {
+            let request = request.context("failed to read request from stdin")?;
+            if tx.send(request).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    });
+
+    let mut next_tick = node
+        .tick_interval()
+        .map(|interval| Instant::now() + interval);
+    loop {
+        let request = match next_tick {
+            Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match request {
+            Ok(request) => {
+                let request: Message<N::Payload> = serde_json::from_str(&request)
+                    .context("deserialization of request message failed")?;
+                if let Some(clocks) = node.clocks() {
+                    clocks.on_receive(request.body.clock.as_ref());
+                }
+                node.step(request, &mut stdout_lock)
+                    .context(format!("{node:?}: step method failed"))?;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if next_tick.is_some_and(|deadline| Instant::now() >= deadline) {
+            node.tick(&mut stdout_lock)
+                .context(format!("{node:?}: tick method failed"))?;
+            next_tick = node
+                .tick_interval()
+                .map(|interval| Instant::now() + interval);
+        }
     }
 
-    Ok(())
+    reader
+        .join()
+        .map_err(|_| anyhow::anyhow!("stdin reader thread panicked"))?
 }
 
 /// The main library loop - alternative implementation (for reference).
@@ -64,10 +107,13 @@ where
     // Our node (server) is now ready to receive all other messages (but not an init message again).
     let stdin_lock = io::stdin().lock();
     let requests =
-        serde_json::Deserializer::from_reader(stdin_lock).into_iter::<Message<Payload>>();
+        serde_json::Deserializer::from_reader(stdin_lock).into_iter::<Message<N::Payload>>();
     for request in requests {
-        let request: Message<Payload> =
+        let request: Message<N::Payload> =
             request.context("deserialization of request message failed")?;
+        if let Some(clocks) = node.clocks() {
+            clocks.on_receive(request.body.clock.as_ref());
+        }
         node.step(request, &mut stdout_lock)
             .context(format!("{node:?}: step method failed"))?;
     }
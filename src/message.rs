@@ -9,9 +9,14 @@
 //!
 //! Both `STDIN` and `STDOUT` messages are JSON objects, separated by newlines (`\n`).
 
+use crate::anti_entropy::BloomFilter;
+use crate::clock::{Timestamp, VectorClock};
+use crate::crdt::{GSet, PNCounter};
 use crate::IdType;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 /// Messages
 ///
@@ -33,6 +38,10 @@ pub struct Body<P> {
     pub msg_id: Option<usize>,
     /// (optional) For req/response, the `msg_id` of the request.
     pub in_reply_to: Option<usize>,
+    /// (optional) Our own extension: the sender's logical clocks, as of sending the message.
+    /// Only messages between our nodes carry them. See [`crate::clock`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<Timestamp>,
     /// (mandatory) A string identifying the type of message this is, plus optional data contained within.
     #[serde(flatten)]
     pub payload: P,
@@ -74,6 +83,10 @@ pub enum Payload {
     Broadcast(BroadcastPayload),
     Echo(EchoPayload),
     Error(ErrorPayload),
+    Heartbeat(HeartbeatPayload),
+    HyParView(HyParViewPayload),
+    Kv(KvPayload),
+    Plumtree(PlumtreePayload),
     UniqueIdGen(GeneratePayload),
 }
 
@@ -95,13 +108,99 @@ pub enum BroadcastPayload {
     /// In response, it should return a `read_ok` message with a list of values it has seen.
     ///
     /// The order of the returned values does not matter.
-    ReadOk { messages: HashSet<usize> },
+    ///
+    /// The set is shared with the node, so that a response doesn't have to copy it;
+    /// it's serialized straight from the node's own set.
+    ReadOk { messages: Arc<HashSet<usize>> },
     /// This message informs the node of who its neighboring nodes are.
     Topology {
         topology: HashMap<String, Vec<String>>,
     },
     /// In response, your node should return a `topology_ok` message body.
     TopologyOk,
+    /// Inter-node delta-state gossip: values that the receiver doesn't have, as far as the sender knows.
+    Gossip { messages: HashSet<usize> },
+    /// Acknowledges all the values of a `gossip` message, which it is a response to.
+    ///
+    /// Also the response to `causal_gossip`.
+    GossipOk,
+    /// Inter-node delta-state gossip with causal delivery: like `gossip`, but every value comes with
+    /// its causal dependencies.
+    CausalGossip { messages: Vec<CausalMessage> },
+    /// Inter-node anti-entropy, step 1: the digest of the sender's whole set.
+    Sync { count: usize, hash: u64 },
+    /// Anti-entropy, step 2: the digests differ, so here are the hashes of the receiver's buckets.
+    SyncBuckets { buckets: Vec<u64> },
+    /// Anti-entropy, step 3: the sender's values in the buckets whose hashes differ,
+    /// out of `bucket_count` buckets.
+    SyncValues {
+        bucket_count: usize,
+        buckets: Vec<usize>,
+        messages: HashSet<usize>,
+    },
+    /// Anti-entropy, step 4: the values in those buckets that the receiver is missing.
+    ///
+    /// Also the response to `sync_bloom`: the values that aren't in the filter.
+    SyncValuesOk { messages: HashSet<usize> },
+    /// Inter-node anti-entropy with a Bloom filter: the filter of the sender's whole set.
+    SyncBloom { filter: BloomFilter },
+}
+
+/// Inter-node payloads of the Plumtree broadcast strategy; see [`crate::plumtree`].
+///
+/// None of them are responded to.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum PlumtreePayload {
+    /// Eager push: new values, sent along the broadcast tree.
+    Push { messages: HashSet<usize> },
+    /// Lazy push: announces values to the peers outside the broadcast tree, without sending them.
+    #[serde(rename = "ihave")]
+    IHave { messages: HashSet<usize> },
+    /// Asks for announced values that haven't arrived, and adds the link to the broadcast tree.
+    Graft { messages: HashSet<usize> },
+    /// Removes the link from the broadcast tree, because a value came over it more than once.
+    Prune,
+}
+
+/// Inter-node payloads of the failure detector; see [`crate::failure_detector`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum HeartbeatPayload {
+    /// Shows that the sender is alive. It isn't responded to.
+    Heartbeat,
+}
+
+/// Inter-node payloads of the HyParView membership protocol; see [`crate::hyparview`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum HyParViewPayload {
+    /// Asks the contact node to let the sender join the overlay.
+    Join,
+    /// Spreads a new node by a random walk of at most `ttl` more steps, which ends in active views,
+    /// and passes through passive views.
+    ForwardJoin { new_node: String, ttl: usize },
+    /// Asks the receiver to add the sender to its active view.
+    ///
+    /// A high-priority request, by a node whose active view is empty, must be accepted.
+    Neighbor { high_priority: bool },
+    /// Whether the receiver has added the sender to its active view.
+    NeighborOk { accepted: bool },
+    /// The sender has removed the receiver from its active view.
+    Disconnect,
+    /// Offers nodes from the views of the `origin` node, by a random walk of at most `ttl` more steps.
+    Shuffle {
+        origin: String,
+        ttl: usize,
+        nodes: Vec<String>,
+    },
+    /// In return, nodes from the passive view of the node where the random walk ended.
+    ShuffleOk { nodes: Vec<String> },
+    /// Keeps the link between two active-view neighbors alive, when nothing else is sent over it.
+    Keepalive,
 }
 
 /// A simple echo workload: a client sends a message, and expects to get that same message back from our server.
@@ -125,7 +224,7 @@ pub enum EchoPayload {
 ///
 /// As with all RPC responses, the `in_reply_to` field is the `msg_id` of the request which caused this error.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(tag = "type")]
+#[serde(tag = "type", rename = "error")]
 #[serde(rename_all = "snake_case")]
 pub struct ErrorPayload {
     /// The `code` is an integer which indicates the type of error which occurred.
@@ -144,12 +243,79 @@ pub struct ErrorPayload {
 /// Codes `0-999` are reserved for Maelstrom's use; codes `1000` and above are free for your own purposes.
 ///
 /// Custom error codes are always indefinite.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+///
+/// Serialized as the bare integer code, which is what Maelstrom expects.
+///
+/// [Errors](https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(usize)]
 pub enum ErrorCode {
+    /// Indicates that the requested operation could not be completed within a timeout. Indefinite.
+    Timeout = 0,
+    /// Thrown when a client sends an RPC request to a node which does not exist. Definite.
+    NodeNotFound = 1,
+    /// Use this error to indicate that a requested operation is not supported by the current implementation.
+    /// Definite.
+    NotSupported = 10,
+    /// Indicates that the operation definitely cannot be performed at this time -
+    /// perhaps because the server is in a read-only state, has not yet been initialized,
+    /// believes its peers to be down, and so on. Definite.
+    TemporarilyUnavailable = 11,
+    /// The client's request did not conform to the server's expectations,
+    /// and could not possibly have been processed. Definite.
+    MalformedRequest = 12,
+    /// Indicates that some kind of general, indefinite error occurred. Indefinite.
+    Crash = 13,
+    /// Indicates that some kind of general, definite error occurred. Definite.
+    Abort = 14,
+    /// The client requested an operation on a key which does not exist
+    /// (assuming the operation should not automatically create missing keys). Definite.
+    KeyDoesNotExist = 20,
+    /// The client requested the creation of a key which already exists,
+    /// and the server will not overwrite it. Definite.
+    KeyAlreadyExists = 21,
+    /// The requested operation expected some conditions to hold, and those conditions were not met. Definite.
+    PreconditionFailed = 22,
+    /// The requested transaction has been aborted because of a conflict with another transaction. Definite.
+    TxnConflict = 30,
     SomeErrorCode = 1000,
 }
 
+impl TryFrom<usize> for ErrorCode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: usize) -> anyhow::Result<Self> {
+        Ok(match value {
+            0 => Self::Timeout,
+            1 => Self::NodeNotFound,
+            10 => Self::NotSupported,
+            11 => Self::TemporarilyUnavailable,
+            12 => Self::MalformedRequest,
+            13 => Self::Crash,
+            14 => Self::Abort,
+            20 => Self::KeyDoesNotExist,
+            21 => Self::KeyAlreadyExists,
+            22 => Self::PreconditionFailed,
+            30 => Self::TxnConflict,
+            1000 => Self::SomeErrorCode,
+            other => anyhow::bail!("unknown error code: {other}"),
+        })
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(*self as u64)
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = usize::deserialize(deserializer)?;
+        ErrorCode::try_from(code).map_err(de::Error::custom)
+    }
+}
+
 /// A simple workload for ID generation systems.
 /// Clients ask servers to generate an ID, and the server should respond with an ID.
 ///
@@ -161,3 +327,530 @@ pub enum GeneratePayload {
     Generate,
     GenerateOk { id: IdType },
 }
+
+/// Payloads of a replicated state machine node, which serves a workload whose client requests
+/// and responses are `P`, by replicating the requests with a consensus protocol whose messages are `M`,
+/// e.g., [`RaftPayload`] or [`PaxosPayload`]; see [`crate::rsm`].
+///
+/// Besides the client requests, such a node receives consensus messages from its peers,
+/// and responses to the client requests it forwarded to the leader.
+///
+/// The group names are not serde'd.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ReplicaPayload<P, M> {
+    Client(P),
+    Consensus(M),
+    Error(ErrorPayload),
+}
+
+/// A key-value store workload, and also the protocol of Maelstrom's own key-value services
+/// (`lin-kv`, `seq-kv`, `lww-kv`).
+///
+/// Keys and values may be any JSON values.
+///
+/// [Workload: Lin-kv](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-lin-kv)
+/// [Services](https://github.com/jepsen-io/maelstrom/blob/main/doc/services.md)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum KvPayload {
+    /// Reads the current value of a single key.
+    Read { key: Value },
+    /// Returns the value of the key, or a `key-does-not-exist` error.
+    ReadOk { value: Value },
+    /// Blindly overwrites the value of a key.
+    Write { key: Value, value: Value },
+    /// Acknowledges a write.
+    WriteOk,
+    /// Atomically compare-and-sets a single key: if the value of `key` is currently `from`, sets it to `to`.
+    ///
+    /// Returns a `precondition-failed` error if the current value isn't `from`,
+    /// or a `key-does-not-exist` error if the key doesn't exist, unless `create_if_not_exists` is set.
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        create_if_not_exists: bool,
+    },
+    /// Acknowledges a successful compare-and-set.
+    CasOk,
+}
+
+/// The node ID of Maelstrom's linearizable key-value service.
+pub const LIN_KV: &str = "lin-kv";
+
+/// The node ID of Maelstrom's sequentially-consistent key-value service.
+pub const SEQ_KV: &str = "seq-kv";
+
+/// The node ID of Maelstrom's last-write-wins key-value service, which is only eventually consistent.
+pub const LWW_KV: &str = "lww-kv";
+
+/// A client command, as replicated in the log of a replicated state machine node.
+///
+/// Remembers whom to respond to once the command has been committed and applied.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClientCommand<P> {
+    /// The node or client that sent the request.
+    pub client: String,
+    /// The `msg_id` of the request, so that the response can refer to it.
+    pub msg_id: Option<usize>,
+    /// The request itself, e.g., a `read`, `write` or `cas` of the `lin-kv` workload.
+    pub op: P,
+}
+
+/// Payloads of a node that serves the `lin-kv` workload with chain replication; see [`crate::chain`].
+///
+/// Besides the client requests, such a node receives chain messages and heartbeats from its peers,
+/// responses to the client requests it forwarded to the head or the tail, and responses from `lin-kv`,
+/// which keeps the chain's configuration.
+///
+/// The group names are not serde'd.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ChainNodePayload {
+    Kv(KvPayload),
+    Chain(ChainPayload),
+    Heartbeat(HeartbeatPayload),
+    Error(ErrorPayload),
+}
+
+/// A write (`write` or `cas`), in the order in which the head of a chain applied it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChainEntry {
+    /// The write's position in that order, starting at `1`.
+    pub seq: usize,
+    pub command: ClientCommand<KvPayload>,
+}
+
+/// Inter-node messages of chain replication; every message belongs to a configuration `epoch`,
+/// and is ignored in any other.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum ChainPayload {
+    /// Sent down the chain: writes for the successor to apply, in order, and pass on.
+    /// `applied` is the sender's last applied write. An empty list of entries is a probe.
+    Update {
+        epoch: usize,
+        applied: usize,
+        entries: Vec<ChainEntry>,
+    },
+    /// Sent up the chain, in response to a probe, a snapshot, or entries that don't follow on its last applied write:
+    /// the sender's last applied write.
+    UpdateOk { epoch: usize, applied: usize },
+    /// Sent down the chain, to a successor that is too far behind for the entries that the sender still has:
+    /// the whole store, as of the sender's last applied write.
+    Snapshot {
+        epoch: usize,
+        applied: usize,
+        store: HashMap<String, Value>,
+    },
+    /// Sent up the chain, from the tail: every write up to `applied` has been applied by the whole chain.
+    Ack { epoch: usize, applied: usize },
+}
+
+/// Payloads of a node that serves a key-value store workload with quorum replication; see [`crate::quorum`].
+///
+/// Besides the client requests, such a node receives replica requests and responses and heartbeats from its peers.
+///
+/// The group names are not serde'd.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum QuorumNodePayload {
+    Kv(KvPayload),
+    Quorum(QuorumPayload),
+    Heartbeat(HeartbeatPayload),
+    Error(ErrorPayload),
+}
+
+/// The version of a value in a quorum-replicated store: the later version wins.
+///
+/// `time` is a hybrid clock, in microseconds: the coordinator's physical clock, but always past the latest version
+/// that the coordinator has seen. `node_id` breaks the ties between coordinators.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Version {
+    pub time: u64,
+    pub node_id: String,
+}
+
+/// A value in a quorum-replicated store, with its version.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Versioned {
+    pub value: Value,
+    pub version: Version,
+}
+
+/// Messages between the coordinator of a client request and the replicas of its key.
+///
+/// `op` identifies the coordinator's operation, so that the responses can be matched with it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum QuorumPayload {
+    /// Asks a replica for its version of the key.
+    ReplicaRead { op: usize, key: Value },
+    /// The replica's version of the key, if it has one.
+    ReplicaReadOk {
+        op: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        versioned: Option<Versioned>,
+    },
+    /// Asks a replica to store a version of the key, unless it has a later one.
+    ///
+    /// `hint` is the replica that the version is meant for, when the recipient stands in for it;
+    /// the recipient hands the version off to it once it's reachable again.
+    ReplicaWrite {
+        op: usize,
+        key: Value,
+        versioned: Versioned,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hint: Option<String>,
+    },
+    /// Acknowledges a `replica_write`.
+    ReplicaWriteOk { op: usize },
+}
+
+/// Payloads of a node that serves the `broadcast` workload with total-order broadcast.
+///
+/// The group names are not serde'd.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TotalOrderNodePayload {
+    Broadcast(TotalOrderPayload),
+    Raft(RaftPayload<usize>),
+}
+
+/// The `broadcast` workload's payloads, with values read in the order in which they were delivered,
+/// which is the same on every node; see [`crate::total_order`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum TotalOrderPayload {
+    /// Requests that a value be broadcast out to all nodes in the cluster.
+    Broadcast { message: usize },
+    /// Acknowledges a `broadcast`.
+    BroadcastOk,
+    /// Requests all the values that the node has delivered.
+    Read,
+    /// Returns all the delivered values, in the order of delivery.
+    ReadOk { messages: Vec<usize> },
+    /// Informs the node of its neighbors; total-order broadcast doesn't use them.
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+    /// Acknowledges a `topology`.
+    TopologyOk,
+    /// Inter-node: values that haven't been delivered yet, for the receiver, which the sender believes
+    /// to be the leader, to order them.
+    Forward { messages: Vec<usize> },
+}
+
+/// A value of a causal broadcast; see [`crate::causal`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CausalMessage {
+    /// The node that broadcast the value.
+    pub origin: String,
+    /// The value's place in causal order: for `origin`, the number of values it has broadcast, including
+    /// this one; for every other node, the number of its values that `origin` had delivered beforehand.
+    pub clock: VectorClock,
+    pub value: usize,
+}
+
+/// An entry in a Raft log.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogEntry<C> {
+    /// The term in which the entry was created by a leader.
+    pub term: usize,
+    /// The command to apply to the state machine.
+    pub command: C,
+}
+
+/// Inter-node messages of the Raft consensus algorithm.
+///
+/// Responses are sent as separate requests, which carry everything the receiver needs,
+/// so they don't have to be matched with the original requests.
+///
+/// [In Search of an Understandable Consensus Algorithm](https://raft.github.io/raft.pdf)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum RaftPayload<C> {
+    /// Sent by candidates to gather votes.
+    RequestVote {
+        term: usize,
+        candidate_id: String,
+        last_log_index: usize,
+        last_log_term: usize,
+    },
+    /// A vote, granted or not.
+    RequestVoteOk { term: usize, vote_granted: bool },
+    /// Sent by the leader to replicate log entries; an empty list of entries is a heartbeat.
+    AppendEntries {
+        term: usize,
+        leader_id: String,
+        prev_log_index: usize,
+        prev_log_term: usize,
+        entries: Vec<LogEntry<C>>,
+        leader_commit: usize,
+    },
+    /// The follower's answer to `append_entries`.
+    ///
+    /// On success, `match_index` is the index of the last entry known to be replicated on the follower.
+    /// On failure, it is a hint: the last index up to which the follower's log might still match the leader's.
+    AppendEntriesOk {
+        term: usize,
+        success: bool,
+        match_index: usize,
+    },
+}
+
+/// A Paxos ballot number. Ballots are totally ordered, by round first, and every node has ballots of its own.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Ballot {
+    pub round: usize,
+    /// The node that proposes in this ballot.
+    pub node_id: String,
+}
+
+/// A value that an acceptor has accepted for a slot of a Multi-Paxos log.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PaxosEntry<C> {
+    pub slot: usize,
+    /// The ballot in which it was accepted.
+    pub ballot: Ballot,
+    /// The command; `None` is a no-op, which fills a gap in the log.
+    pub command: Option<C>,
+}
+
+/// Inter-node messages of the Multi-Paxos consensus algorithm.
+///
+/// Like Raft's, they carry everything the receiver needs, so they don't have to be matched with their requests.
+///
+/// [Paxos Made Simple](https://lamport.azurewebsites.net/pubs/paxos-simple.pdf)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum PaxosPayload<C> {
+    /// Phase 1a: asks the acceptors to promise to ignore lower ballots, for all slots from `first_slot` on.
+    Prepare { ballot: Ballot, first_slot: usize },
+    /// Phase 1b: the promise, with the values that the acceptor has accepted in those slots.
+    Promise {
+        ballot: Ballot,
+        accepted: Vec<PaxosEntry<C>>,
+    },
+    /// Phase 2a: asks the acceptors to accept the commands for the slots from `first_slot` on;
+    /// the slots up to `decided` have been chosen. An empty list of commands is a heartbeat.
+    Accept {
+        ballot: Ballot,
+        first_slot: usize,
+        commands: Vec<Option<C>>,
+        decided: usize,
+    },
+    /// Phase 2b: the acceptor has accepted, or already decided, every slot up to `through` in this ballot.
+    Accepted { ballot: Ballot, through: usize },
+    /// Rejects a `prepare` or an `accept` of a lower ballot than the one the acceptor has promised.
+    Nack { promised: Ballot },
+}
+
+/// A grow-only set workload: clients add elements to a set, and read the whole set back.
+///
+/// Also carries the replication messages between the nodes, since the workload's `read`
+/// is indistinguishable from other workloads' `read`, so a `g-set` node has a payload group of its own.
+///
+/// [Workload: G-set](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-g-set)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum GSetPayload {
+    /// Requests that an element be added to the set.
+    Add { element: usize },
+    /// Acknowledges the addition.
+    AddOk,
+    /// Requests the current state of the set.
+    Read,
+    /// Returns all the elements of the set, in any order.
+    ReadOk { value: GSet<usize> },
+    /// Inter-node gossip: a set state (or a delta of it) for the receiver to merge into its own.
+    Replicate { value: GSet<usize> },
+}
+
+/// A counter workload: clients add positive or negative deltas to a single counter, and read its value.
+///
+/// Also carries the replication messages between the nodes, which is why a `pn-counter` node
+/// has a payload group of its own.
+///
+/// [Workload: Pn-counter](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-pn-counter)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum PnCounterPayload {
+    /// Requests that `delta`, which may be negative, be added to the counter.
+    Add { delta: i64 },
+    /// Acknowledges the addition.
+    AddOk,
+    /// Requests the current value of the counter.
+    Read,
+    /// Returns the current value of the counter.
+    ReadOk { value: i64 },
+    /// Inter-node gossip: a counter state for the receiver to merge into its own.
+    Replicate { value: PNCounter },
+}
+
+/// The lock service's own vocabulary: clients acquire named locks as leases, renew them, and release them;
+/// see [`crate::lock`].
+///
+/// Lease durations, `ttl`, are in milliseconds. The `time` of a request, in milliseconds since the Unix epoch,
+/// is stamped by the leader when it puts the request into the log; clients leave it out.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum LockPayload {
+    /// Requests the lock `lock` for `ttl` milliseconds; fails with `precondition-failed` if somebody holds it.
+    Acquire {
+        lock: String,
+        ttl: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        time: Option<u64>,
+    },
+    /// Grants the lock, with a fencing token that is greater than those of all earlier grants.
+    AcquireOk { token: u64 },
+    /// Requests that the lease with the fencing token `token` be released.
+    Release { lock: String, token: u64 },
+    /// Acknowledges the release.
+    ReleaseOk,
+    /// Requests that the lease with the fencing token `token`, which mustn't have expired, be extended
+    /// to `ttl` milliseconds from now.
+    Renew {
+        lock: String,
+        token: u64,
+        ttl: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        time: Option<u64>,
+    },
+    /// Acknowledges the renewal.
+    RenewOk,
+}
+
+/// Payloads of a node that serves the `txn-list-append` workload on top of Maelstrom's key-value services.
+///
+/// Besides the client transactions, such a node receives responses from the key-value services.
+///
+/// The group names are not serde'd.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TxnNodePayload {
+    Txn(TxnPayload),
+    Kv(KvPayload),
+    Error(ErrorPayload),
+}
+
+/// Payloads of a node that serves the `txn-list-append` workload over keys that are sharded across the nodes,
+/// with two-phase commit; see [`crate::two_phase_commit`].
+///
+/// Besides the client transactions, such a node receives two-phase commit messages from its peers,
+/// and responses from `lin-kv`, which keeps the commit decisions.
+///
+/// The group names are not serde'd.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ShardedTxnNodePayload {
+    Txn(TxnPayload),
+    TwoPhase(TwoPhasePayload),
+    Kv(KvPayload),
+    Error(ErrorPayload),
+}
+
+/// Messages between the coordinator of a transaction and its participants, the shards of its keys.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum TwoPhasePayload {
+    /// Asks a participant to lock the keys of its micro-operations, and to execute them.
+    Prepare { txn_id: String, ops: Vec<MicroOp> },
+    /// A participant's yes vote: it has locked the keys, and executed the micro-operations,
+    /// with the values of the reads filled in. It commits them or aborts them as it's told.
+    Prepared { txn_id: String, ops: Vec<MicroOp> },
+    /// A participant's no vote: some of the keys are locked by another transaction.
+    Refused { txn_id: String },
+    /// Tells a participant to apply the transaction's appends and to unlock its keys.
+    Commit { txn_id: String },
+    /// Tells a participant to discard the transaction's appends and to unlock its keys.
+    Abort { txn_id: String },
+    /// Acknowledges a `commit` or an `abort`.
+    Decided { txn_id: String },
+}
+
+/// The outcome of a transaction, as recorded in `lin-kv`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Commit,
+    Abort,
+}
+
+/// A transactional workload over lists: every transaction is a list of micro-operations,
+/// which read whole lists or append single elements to them.
+///
+/// [Workload: Txn-list-append](https://github.com/jepsen-io/maelstrom/blob/main/doc/workloads.md#workload-txn-list-append)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum TxnPayload {
+    /// Requests that the transaction be executed.
+    Txn { txn: Vec<MicroOp> },
+    /// Returns the executed transaction, with the values of the reads filled in.
+    TxnOk { txn: Vec<MicroOp> },
+}
+
+/// A micro-operation of a `txn-list-append` transaction.
+///
+/// Serialized as a triple: `["r", key, value]` or `["append", key, element]`,
+/// where the `value` of a read is `null` in requests.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "(String, usize, Value)", into = "(String, usize, Value)")]
+pub enum MicroOp {
+    /// Reads the list under `key`; the `value` is `None` if the list doesn't exist (yet).
+    Read {
+        key: usize,
+        value: Option<Vec<usize>>,
+    },
+    /// Appends `element` to the list under `key`, creating the list if it doesn't exist.
+    Append { key: usize, element: usize },
+}
+
+impl MicroOp {
+    pub fn key(&self) -> usize {
+        match self {
+            MicroOp::Read { key, .. } | MicroOp::Append { key, .. } => *key,
+        }
+    }
+}
+
+impl TryFrom<(String, usize, Value)> for MicroOp {
+    type Error = anyhow::Error;
+
+    fn try_from((f, key, value): (String, usize, Value)) -> anyhow::Result<Self> {
+        Ok(match f.as_str() {
+            "r" => MicroOp::Read {
+                key,
+                value: serde_json::from_value(value)?,
+            },
+            "append" => MicroOp::Append {
+                key,
+                element: serde_json::from_value(value)?,
+            },
+            other => anyhow::bail!("unknown micro-operation: {other}"),
+        })
+    }
+}
+
+impl From<MicroOp> for (String, usize, Value) {
+    fn from(op: MicroOp) -> Self {
+        match op {
+            MicroOp::Read { key, value } => ("r".to_string(), key, Value::from(value)),
+            MicroOp::Append { key, element } => ("append".to_string(), key, Value::from(element)),
+        }
+    }
+}
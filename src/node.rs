@@ -1,10 +1,21 @@
 //! # Generic Node
 
-use crate::message::{Body, InitPayload, Message, Payload};
+use crate::clock::Clocks;
+use crate::message::{Body, InitPayload, Message};
 use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
 use std::io::{StdoutLock, Write};
+use std::time::Duration;
 
 pub trait Node {
+    /// The payload type this node receives and sends, apart from the initialization-by-Maelstrom payloads.
+    ///
+    /// Nodes that serve the simple workloads use the common [`crate::message::Payload`],
+    /// while nodes whose message types would be ambiguous in it bring their own payload group.
+    type Payload: Debug + Serialize + DeserializeOwned;
+
     /// Creates and returns a new node.
     fn new() -> Self;
 
@@ -13,6 +24,11 @@ pub trait Node {
     fn get_node_id(&self) -> Option<String>;
     fn set_node_id(&mut self, value: Option<String>);
 
+    /// Remembers the list of all nodes in the cluster, including this one.
+    ///
+    /// Called once, during the initialization phase. Nodes that don't need it can ignore it.
+    fn set_node_ids(&mut self, _node_ids: Vec<String>) {}
+
     /// Respond to initialization by Maelstrom.
     ///
     /// Increments `self.msg_id`.
@@ -22,8 +38,9 @@ pub trait Node {
         output_lock: &mut StdoutLock,
     ) -> Result<()> {
         match request.body.payload {
-            InitPayload::Init { node_id, .. } => {
+            InitPayload::Init { node_id, node_ids } => {
                 self.set_node_id(Some(node_id));
+                self.set_node_ids(node_ids);
 
                 let response = Message {
                     src: self.get_node_id().expect("expected some self.node_id"), // == request.dest,
@@ -31,6 +48,7 @@ pub trait Node {
                     body: Body {
                         msg_id: Some(self.get_msg_id()),
                         in_reply_to: request.body.msg_id,
+                        clock: None,
                         payload: InitPayload::InitOk,
                     },
                 };
@@ -52,7 +70,31 @@ pub trait Node {
     /// A processing step in a node's state-machine.
     ///
     /// Works with all message types except the initialization-by-Maelstrom message types.
-    fn step(&mut self, request: Message<Payload>, output_lock: &mut StdoutLock) -> Result<()>;
+    fn step(&mut self, request: Message<Self::Payload>, output_lock: &mut StdoutLock)
+        -> Result<()>;
+
+    /// How often the main loop should call [`Node::tick()`].
+    ///
+    /// `None`, the default, means that the node is purely reactive and never ticks.
+    fn tick_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// A periodic processing step in a node's state-machine, for timers: retries, gossip, heartbeats, etc.
+    ///
+    /// Called by the main loop roughly every [`Node::tick_interval()`], between requests.
+    fn tick(&mut self, _output_lock: &mut StdoutLock) -> Result<()> {
+        Ok(())
+    }
+
+    /// The node's logical clocks, if it keeps any.
+    ///
+    /// They are ticked on every message that the node sends, in [`Node::respond()`] and [`Node::request()`],
+    /// and on every message that it receives, by the main loop, before [`Node::step()`].
+    /// `None`, the default, means that the node doesn't keep any, and its messages don't carry them.
+    fn clocks(&mut self) -> Option<&mut Clocks> {
+        None
+    }
 
     /// Respond to any request that is not initialization.
     ///
@@ -63,16 +105,18 @@ pub trait Node {
         &mut self,
         dest: String,
         in_reply_to: Option<usize>,
-        payload: Payload,
+        payload: Self::Payload,
         output_lock: &mut StdoutLock,
         msg_type: &str,
     ) -> Result<()> {
+        let clock = self.clocks().and_then(|clocks| clocks.on_send(&dest));
         let response = Message {
             src: self.get_node_id().expect("expected some self.node_id"), // == request.dest,
             dest,
             body: Body {
                 msg_id: Some(self.get_msg_id()),
                 in_reply_to,
+                clock,
                 payload,
             },
         };
@@ -97,16 +141,18 @@ pub trait Node {
     fn request(
         &mut self,
         dest: String,
-        payload: Payload,
+        payload: Self::Payload,
         output_lock: &mut StdoutLock,
         msg_type: &str,
     ) -> Result<()> {
+        let clock = self.clocks().and_then(|clocks| clocks.on_send(&dest));
         let response = Message {
             src: self.get_node_id().expect("expected some self.node_id"),
             dest,
             body: Body {
                 msg_id: Some(self.get_msg_id()),
                 in_reply_to: None,
+                clock,
                 payload,
             },
         };
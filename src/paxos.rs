@@ -0,0 +1,574 @@
+//! # Multi-Paxos
+//!
+//! The Multi-Paxos consensus algorithm: a sequence of Paxos instances, one per slot of a log,
+//! with a distinguished proposer, the leader.
+//!
+//! [Paxos Made Simple](https://lamport.azurewebsites.net/pubs/paxos-simple.pdf)
+//!
+//! - A node that doesn't hear from a leader before its election timeout becomes a candidate: it picks a ballot
+//!   that is higher than any it has seen, and runs phase 1 (`prepare`, `promise`) for all the undecided slots at once.
+//! - Once a majority has promised, the candidate becomes the leader. In every slot, it re-proposes the value
+//!   accepted in the highest ballot, as it may already have been chosen, and it fills the gaps with no-ops.
+//! - From then on, the leader is stable: it skips phase 1 for new commands, and only runs phase 2
+//!   (`accept`, `accepted`), until another node prepares a higher ballot. Its `accept`s double as heartbeats.
+//!
+//! A slot is decided once a majority has accepted its command in the leader's ballot.
+//! Like [`crate::raft`], the leader streams the log to every acceptor from the first slot that it's missing,
+//! and tells them up to which slot it's decided, so that they can apply the commands in log order, too.
+//!
+//! The node applies the committed commands to its state machine.
+//!
+//! Maelstrom doesn't crash our nodes, so the persistent state is simply kept in memory.
+
+use crate::message::{Ballot, PaxosEntry, PaxosPayload};
+use crate::rng::Rng;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// The range of election timeouts, in milliseconds, from which a random one is picked every time.
+pub const ELECTION_TIMEOUT_MS: Range<u64> = 1000..2000;
+
+/// How often the leader sends `accept` to the acceptors, be it a heartbeat or replication.
+pub const REPLICATION_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The maximum number of commands in a single `accept` message.
+pub const MAX_COMMANDS_PER_ACCEPT: usize = 128;
+
+/// Messages to send: pairs of destination node IDs and payloads.
+pub type Outbox<C> = Vec<(String, PaxosPayload<C>)>;
+
+/// The role that a Multi-Paxos node currently plays. Every node is an acceptor, whatever its role.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    /// Runs phase 1 in its own ballot.
+    Candidate,
+    /// Has completed phase 1 in its own ballot, and runs phase 2 for every slot.
+    Leader,
+}
+
+/// A slot of the log, as accepted by this node.
+#[derive(Clone, Debug)]
+struct Slot<C> {
+    ballot: Ballot,
+    /// `None` is a no-op.
+    command: Option<C>,
+}
+
+/// # A Multi-Paxos Consensus Module
+///
+/// Replicates a log of commands of type `C`.
+///
+/// Slots start at `1`; slot `0` stands for the empty log prefix.
+#[derive(Debug)]
+pub struct MultiPaxos<C> {
+    /// Our own node ID.
+    node_id: String,
+    /// All other nodes in the cluster.
+    peers: Vec<String>,
+    role: Role,
+    /// Our own ballot, while we are a candidate or the leader.
+    ballot: Ballot,
+    /// The highest ballot that we have promised, as an acceptor; we ignore lower ones.
+    promised: Ballot,
+    /// The accepted slots; slot `i` is stored at `log[i - 1]`. `None` means that nothing has been accepted in it yet.
+    log: Vec<Option<Slot<C>>>,
+    /// The ballot in which `accepted_through` was reached.
+    through_ballot: Ballot,
+    /// The highest slot up to which we have accepted, or decided, every slot in `through_ballot`.
+    accepted_through: usize,
+    /// The highest slot up to which every slot is known to be decided.
+    commit_index: usize,
+    /// The highest slot handed out for application to the state machine.
+    last_applied: usize,
+    /// The leader of the current ballot, if we know it.
+    leader_id: Option<String>,
+    /// The first slot of phase 1, while we are a candidate.
+    first_slot: usize,
+    /// The promises received in phase 1, while we are a candidate, by acceptor.
+    promises: HashMap<String, Vec<PaxosEntry<C>>>,
+    /// For each peer, the next slot to send to it; only meaningful on the leader.
+    next_slot: HashMap<String, usize>,
+    /// For each peer, the slot up to which it has accepted every slot in our ballot; only meaningful on the leader.
+    accepted: HashMap<String, usize>,
+    /// When we start an election if we don't hear from a leader before that.
+    election_deadline: Instant,
+    /// When the leader last sent `accept` to the acceptors.
+    last_replication: Instant,
+    rng: Rng,
+}
+
+impl<C: Clone> MultiPaxos<C> {
+    /// Creates a new Multi-Paxos module for the node `node_id`, in a cluster of `node_ids`,
+    /// which includes this node.
+    ///
+    /// Every node starts as a follower.
+    pub fn new(node_id: String, node_ids: Vec<String>) -> Self {
+        let peers = node_ids.into_iter().filter(|id| *id != node_id).collect();
+        let mut paxos = Self {
+            node_id,
+            peers,
+            role: Role::Follower,
+            ballot: Ballot::default(),
+            promised: Ballot::default(),
+            log: Vec::new(),
+            through_ballot: Ballot::default(),
+            accepted_through: 0,
+            commit_index: 0,
+            last_applied: 0,
+            leader_id: None,
+            first_slot: 1,
+            promises: HashMap::new(),
+            next_slot: HashMap::new(),
+            accepted: HashMap::new(),
+            election_deadline: Instant::now(),
+            last_replication: Instant::now(),
+            rng: Rng::new(),
+        };
+        paxos.reset_election_deadline();
+        paxos
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.role == Role::Leader
+    }
+
+    /// The leader of the current ballot, if we know it; that may be ourselves.
+    pub fn leader_id(&self) -> Option<&str> {
+        self.leader_id.as_deref()
+    }
+
+    /// The highest ballot that we have promised.
+    pub fn promised(&self) -> &Ballot {
+        &self.promised
+    }
+
+    pub fn commit_index(&self) -> usize {
+        self.commit_index
+    }
+
+    /// Advances the timers: starts an election when the election timeout elapses,
+    /// and makes the leader replicate its log (or send heartbeats) periodically.
+    pub fn tick(&mut self) -> Outbox<C> {
+        let now = Instant::now();
+        match self.role {
+            Role::Leader if now.duration_since(self.last_replication) >= REPLICATION_INTERVAL => {
+                self.replicate()
+            }
+            Role::Follower | Role::Candidate if now >= self.election_deadline => {
+                self.start_election()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Puts a new command in the next free slot, if we are the leader.
+    ///
+    /// Returns the slot, or `None` if we aren't the leader,
+    /// in which case the command should be forwarded to the leader instead.
+    ///
+    /// The slot is sent to the acceptors on the next [`MultiPaxos::tick()`], together with other recent slots.
+    pub fn propose(&mut self, command: C) -> Option<usize> {
+        if !self.is_leader() {
+            return None;
+        }
+
+        self.log.push(Some(Slot {
+            ballot: self.ballot.clone(),
+            command: Some(command),
+        }));
+        // A single-node cluster is its own majority.
+        self.advance_commit_index();
+
+        Some(self.log.len())
+    }
+
+    /// Takes the commands that have been decided since the last call, in log order, without the no-ops,
+    /// so that the node can apply them to its state machine.
+    pub fn take_committed(&mut self) -> Vec<C> {
+        let committed = self.log[self.last_applied..self.commit_index]
+            .iter()
+            .filter_map(|slot| {
+                slot.as_ref()
+                    .expect("expected every decided slot to be accepted")
+                    .command
+                    .clone()
+            })
+            .collect();
+        self.last_applied = self.commit_index;
+        committed
+    }
+
+    /// Handles a Multi-Paxos message from the peer `src`.
+    pub fn handle(&mut self, src: String, payload: PaxosPayload<C>) -> Outbox<C> {
+        match payload {
+            PaxosPayload::Prepare { ballot, first_slot } => {
+                if ballot <= self.promised {
+                    return vec![(
+                        src,
+                        PaxosPayload::Nack {
+                            promised: self.promised.clone(),
+                        },
+                    )];
+                }
+
+                self.promise(ballot.clone());
+                let accepted = self.accepted_from(first_slot);
+                vec![(src, PaxosPayload::Promise { ballot, accepted })]
+            }
+            PaxosPayload::Promise { ballot, accepted } => {
+                if self.role == Role::Candidate && ballot == self.ballot {
+                    self.promises.insert(src, accepted);
+                    if self.promises.len() >= self.majority() {
+                        return self.become_leader();
+                    }
+                }
+
+                Vec::new()
+            }
+            PaxosPayload::Accept {
+                ballot,
+                first_slot,
+                commands,
+                decided,
+            } => {
+                if ballot < self.promised {
+                    return vec![(
+                        src,
+                        PaxosPayload::Nack {
+                            promised: self.promised.clone(),
+                        },
+                    )];
+                }
+
+                // There is a legitimate leader in this ballot.
+                if ballot > self.promised {
+                    self.promise(ballot.clone());
+                }
+                self.leader_id = Some(ballot.node_id.clone());
+                self.reset_election_deadline();
+
+                let through = self.accept(&ballot, first_slot, commands);
+                let decided = decided.min(through);
+                if decided > self.commit_index {
+                    self.commit_index = decided;
+                }
+
+                vec![(src, PaxosPayload::Accepted { ballot, through })]
+            }
+            PaxosPayload::Accepted { ballot, through } => {
+                if self.role != Role::Leader || ballot != self.ballot {
+                    return Vec::new();
+                }
+
+                // An acceptor only ever accepts more in the same ballot, so a lower `through` is a late duplicate.
+                // Otherwise, it's where the acceptor is at, which may be below the slots that we have sent it,
+                // e.g., if it missed slots of an earlier ballot: it gets them from there on.
+                let accepted = self.accepted.entry(src.clone()).or_default();
+                if through >= *accepted {
+                    *accepted = through;
+                    self.next_slot.insert(src, through + 1);
+                }
+                self.advance_commit_index();
+
+                Vec::new()
+            }
+            PaxosPayload::Nack { promised } => {
+                if promised > self.promised {
+                    self.promise(promised);
+                    self.reset_election_deadline();
+                }
+
+                Vec::new()
+            }
+        }
+    }
+
+    fn majority(&self) -> usize {
+        let cluster_size = self.peers.len() + 1;
+        cluster_size / 2 + 1
+    }
+
+    fn reset_election_deadline(&mut self) {
+        self.election_deadline =
+            Instant::now() + Duration::from_millis(self.rng.gen_range(ELECTION_TIMEOUT_MS));
+    }
+
+    /// Promises to ignore ballots lower than `ballot`, which is higher than any we have promised before,
+    /// and steps down if it isn't our own.
+    fn promise(&mut self, ballot: Ballot) {
+        if ballot.node_id != self.node_id {
+            self.role = Role::Follower;
+            self.leader_id = None;
+            self.promises.clear();
+        }
+        self.promised = ballot;
+    }
+
+    /// The values that we have accepted in the slots from `first_slot` on.
+    fn accepted_from(&self, first_slot: usize) -> Vec<PaxosEntry<C>> {
+        self.log
+            .iter()
+            .enumerate()
+            .skip(first_slot.saturating_sub(1))
+            .filter_map(|(index, slot)| {
+                slot.as_ref().map(|slot| PaxosEntry {
+                    slot: index + 1,
+                    ballot: slot.ballot.clone(),
+                    command: slot.command.clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn start_election(&mut self) -> Outbox<C> {
+        self.ballot = Ballot {
+            round: self.promised.round + 1,
+            node_id: self.node_id.clone(),
+        };
+        self.promised = self.ballot.clone();
+        self.role = Role::Candidate;
+        self.leader_id = None;
+        self.first_slot = self.commit_index + 1;
+        self.promises =
+            HashMap::from([(self.node_id.clone(), self.accepted_from(self.first_slot))]);
+        self.reset_election_deadline();
+
+        if self.promises.len() >= self.majority() {
+            return self.become_leader();
+        }
+
+        self.peers
+            .iter()
+            .map(|peer| {
+                (
+                    peer.clone(),
+                    PaxosPayload::Prepare {
+                        ballot: self.ballot.clone(),
+                        first_slot: self.first_slot,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Completes phase 1: takes over the values that may have been chosen, fills the gaps with no-ops,
+    /// and proposes them all in our ballot.
+    fn become_leader(&mut self) -> Outbox<C> {
+        let mut highest: BTreeMap<usize, PaxosEntry<C>> = BTreeMap::new();
+        for entry in self.promises.drain().flat_map(|(_, accepted)| accepted) {
+            match highest.get(&entry.slot) {
+                Some(current) if current.ballot >= entry.ballot => {}
+                _ => {
+                    highest.insert(entry.slot, entry);
+                }
+            }
+        }
+
+        let last_slot = highest
+            .keys()
+            .next_back()
+            .copied()
+            .unwrap_or(0)
+            .max(self.first_slot - 1);
+        self.log.resize(last_slot, None);
+        for slot in self.first_slot..=last_slot {
+            let command = highest.remove(&slot).and_then(|entry| entry.command);
+            self.log[slot - 1] = Some(Slot {
+                ballot: self.ballot.clone(),
+                command,
+            });
+        }
+
+        self.role = Role::Leader;
+        self.leader_id = Some(self.node_id.clone());
+        self.through_ballot = self.ballot.clone();
+        self.accepted_through = last_slot;
+        self.next_slot = self
+            .peers
+            .iter()
+            .map(|peer| (peer.clone(), self.first_slot))
+            .collect();
+        self.accepted = self.peers.iter().map(|peer| (peer.clone(), 0)).collect();
+        self.advance_commit_index();
+
+        self.replicate()
+    }
+
+    /// Sends every acceptor the slots it is missing, or an empty heartbeat.
+    fn replicate(&mut self) -> Outbox<C> {
+        self.last_replication = Instant::now();
+
+        self.peers
+            .iter()
+            .map(|peer| {
+                let first_slot = self.next_slot.get(peer).copied().unwrap_or(1);
+                let end = self.log.len().min(first_slot - 1 + MAX_COMMANDS_PER_ACCEPT);
+                let commands = self.log[(first_slot - 1).min(end)..end]
+                    .iter()
+                    .map(|slot| {
+                        slot.as_ref()
+                            .expect("expected the leader to have every slot")
+                            .command
+                            .clone()
+                    })
+                    .collect();
+                (
+                    peer.clone(),
+                    PaxosPayload::Accept {
+                        ballot: self.ballot.clone(),
+                        first_slot,
+                        commands,
+                        decided: self.commit_index,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Accepts the commands for the slots from `first_slot` on, in `ballot`, leaving the decided slots as they are.
+    ///
+    /// Returns the slot up to which we have accepted, or decided, every slot in `ballot`.
+    fn accept(&mut self, ballot: &Ballot, first_slot: usize, commands: Vec<Option<C>>) -> usize {
+        if self.through_ballot != *ballot {
+            self.through_ballot = ballot.clone();
+            self.accepted_through = self.commit_index;
+        }
+
+        let last_slot = first_slot - 1 + commands.len();
+        if self.log.len() < last_slot {
+            self.log.resize(last_slot, None);
+        }
+        for (offset, command) in commands.into_iter().enumerate() {
+            let slot = first_slot + offset;
+            if slot > self.commit_index {
+                self.log[slot - 1] = Some(Slot {
+                    ballot: ballot.clone(),
+                    command,
+                });
+            }
+        }
+
+        if first_slot <= self.accepted_through + 1 {
+            self.accepted_through = self.accepted_through.max(last_slot);
+        }
+        self.accepted_through
+    }
+
+    /// Decides the highest slot that a majority has accepted in our ballot, if there is a new one.
+    ///
+    /// Unlike in Raft, slots from earlier ballots need no special care: phase 1 made them ours.
+    fn advance_commit_index(&mut self) {
+        for slot in (self.commit_index + 1..=self.log.len()).rev() {
+            let replicas = 1 + self
+                .accepted
+                .values()
+                .filter(|&&accepted| accepted >= slot)
+                .count();
+            if replicas >= self.majority() {
+                self.commit_index = slot;
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster() -> Vec<MultiPaxos<usize>> {
+        let node_ids: Vec<String> = (0..3).map(|i| format!("n{i}")).collect();
+        node_ids
+            .iter()
+            .map(|node_id| MultiPaxos::new(node_id.clone(), node_ids.clone()))
+            .collect()
+    }
+
+    /// Delivers the messages, and the ones that they lead to, until there are none; the ones to or from
+    /// the nodes that are down are lost.
+    fn deliver(nodes: &mut [MultiPaxos<usize>], src: usize, outbox: Outbox<usize>, down: &[usize]) {
+        let mut queue: Vec<(usize, String, PaxosPayload<usize>)> = outbox
+            .into_iter()
+            .map(|(dest, payload)| (src, dest, payload))
+            .collect();
+        while let Some((src, dest, payload)) = queue.pop() {
+            let dest: usize = dest[1..].parse().unwrap();
+            if down.contains(&src) || down.contains(&dest) {
+                continue;
+            }
+            let outbox = nodes[dest].handle(format!("n{src}"), payload);
+            queue.extend(
+                outbox
+                    .into_iter()
+                    .map(|(next, payload)| (dest, next, payload)),
+            );
+        }
+    }
+
+    #[test]
+    fn a_lagging_acceptor_catches_up_from_below_the_leaders_first_slot() {
+        let mut nodes = cluster();
+
+        // n0 leads, and decides 10 slots with n1, while n2 is partitioned away.
+        let outbox = nodes[0].start_election();
+        deliver(&mut nodes, 0, outbox, &[2]);
+        assert!(nodes[0].is_leader());
+        for command in 1..=10 {
+            nodes[0].propose(command);
+        }
+        for _ in 0..2 {
+            let outbox = nodes[0].replicate();
+            deliver(&mut nodes, 0, outbox, &[2]);
+        }
+        assert_eq!(nodes[0].commit_index(), 10);
+        assert_eq!(nodes[1].commit_index(), 10);
+
+        // n0 dies, and n1 takes over with n2, which has nothing.
+        let outbox = nodes[1].start_election();
+        deliver(&mut nodes, 1, outbox, &[0]);
+        assert!(nodes[1].is_leader());
+        assert_eq!(nodes[1].first_slot, 11);
+        nodes[1].propose(11);
+
+        for _ in 0..3 {
+            let outbox = nodes[1].replicate();
+            deliver(&mut nodes, 1, outbox, &[0]);
+        }
+        assert_eq!(nodes[1].commit_index(), 11);
+        assert_eq!(nodes[2].commit_index(), 11);
+        assert_eq!(nodes[2].take_committed(), (1..=11).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn late_accepted_does_not_rewind_next_slot() {
+        let mut paxos = cluster().swap_remove(0);
+        paxos.start_election();
+        let ballot = paxos.ballot.clone();
+        paxos.handle(
+            "n1".to_string(),
+            PaxosPayload::Promise {
+                ballot: ballot.clone(),
+                accepted: Vec::new(),
+            },
+        );
+        assert!(paxos.is_leader());
+        for command in 1..=3 {
+            paxos.propose(command);
+        }
+
+        let accepted = |through| PaxosPayload::Accepted {
+            ballot: ballot.clone(),
+            through,
+        };
+        paxos.handle("n1".to_string(), accepted(3));
+        paxos.handle("n1".to_string(), accepted(1));
+        assert_eq!(paxos.next_slot["n1"], 4);
+        assert_eq!(paxos.commit_index(), 3);
+    }
+}
@@ -0,0 +1,357 @@
+//! # Plumtree
+//!
+//! Epidemic broadcast trees: a gossip protocol that spreads values along a spanning tree, which it builds
+//! and repairs on its own.
+//!
+//! [Epidemic Broadcast Trees](https://asc.di.fct.unl.pt/~jleitao/pdf/srds07-leitao.pdf)
+//!
+//! Every peer is either eager or lazy:
+//! - New values are pushed at once to the eager peers; the eager links form the broadcast tree.
+//! - New values are only announced to the lazy peers, in batches, with `ihave` messages.
+//!
+//! All peers start out eager, so the first values flood the network. A peer that pushes us
+//! a value that we already have is redundant: we make it lazy, and ask it to do the same with `prune`.
+//! What remains of the eager links is a spanning tree.
+//!
+//! If an announced value doesn't arrive over the tree in time, the tree is broken somewhere.
+//! We then ask a peer that announced it for the value with `graft`, which also makes the link eager again.
+//!
+//! The set of delivered values belongs to the node.
+
+use crate::message::PlumtreePayload;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How long to wait for an announced value to arrive over the broadcast tree, before grafting it.
+///
+/// Announcements are batched, so it should be longer than the node's tick interval plus the network latency.
+pub const GRAFT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Messages to send: pairs of destination node IDs and payloads.
+pub type Outbox = Vec<(String, PlumtreePayload)>;
+
+/// A value that was announced to us, but hasn't arrived yet.
+#[derive(Clone, Debug)]
+struct Missing {
+    /// The peers that announced it, in the order in which they are asked for it.
+    announcers: VecDeque<String>,
+    /// When it's grafted if it doesn't arrive before that.
+    deadline: Instant,
+}
+
+/// # A Plumtree Broadcast Module
+///
+/// Spreads integer values among the node's peers, which are its neighbors in the network.
+#[derive(Debug, Default)]
+pub struct Plumtree {
+    /// The peers that new values are pushed to: our links in the broadcast tree.
+    eager_peers: HashSet<String>,
+    /// The peers that new values are only announced to.
+    lazy_peers: HashSet<String>,
+    /// The values to announce to each lazy peer on the next tick.
+    announcements: HashMap<String, HashSet<usize>>,
+    /// The values that were announced to us, but haven't arrived yet.
+    missing: HashMap<usize, Missing>,
+}
+
+impl Plumtree {
+    /// Creates a new Plumtree module, without any peers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the peers; the new ones start out eager, and the ones that are gone are forgotten.
+    pub fn set_peers(&mut self, peers: impl IntoIterator<Item = String>) {
+        let peers: HashSet<String> = peers.into_iter().collect();
+        self.eager_peers.retain(|peer| peers.contains(peer));
+        self.lazy_peers.retain(|peer| peers.contains(peer));
+        self.announcements.retain(|peer, _| peers.contains(peer));
+        for peer in peers {
+            if !self.lazy_peers.contains(&peer) {
+                self.eager_peers.insert(peer);
+            }
+        }
+    }
+
+    /// The peers that new values are pushed to.
+    pub fn eager_peers(&self) -> impl Iterator<Item = &String> {
+        self.eager_peers.iter()
+    }
+
+    /// The peers that new values are only announced to.
+    pub fn lazy_peers(&self) -> impl Iterator<Item = &String> {
+        self.lazy_peers.iter()
+    }
+
+    /// Spreads values that are new to us, which came from `src`, if anyone, to all other peers.
+    pub fn broadcast(&mut self, values: HashSet<usize>, src: Option<&str>) -> Outbox {
+        for value in &values {
+            self.missing.remove(value);
+        }
+
+        for peer in &self.lazy_peers {
+            if Some(peer.as_str()) != src {
+                self.announcements
+                    .entry(peer.clone())
+                    .or_default()
+                    .extend(values.iter().copied());
+            }
+        }
+
+        self.eager_peers
+            .iter()
+            .filter(|&peer| Some(peer.as_str()) != src)
+            .map(|peer| {
+                let payload = PlumtreePayload::Push {
+                    messages: values.clone(),
+                };
+                (peer.clone(), payload)
+            })
+            .collect()
+    }
+
+    /// Handles a message from the peer `src`; new values that it brings are added to `delivered`.
+    pub fn handle(
+        &mut self,
+        src: String,
+        payload: PlumtreePayload,
+        delivered: &mut HashSet<usize>,
+    ) -> Outbox {
+        match payload {
+            PlumtreePayload::Push { messages } => {
+                let new: HashSet<usize> = messages.difference(delivered).copied().collect();
+                if new.is_empty() {
+                    self.make_lazy(&src);
+                    return vec![(src, PlumtreePayload::Prune)];
+                }
+
+                delivered.extend(new.iter().copied());
+                self.make_eager(&src);
+                self.broadcast(new, Some(&src))
+            }
+            PlumtreePayload::IHave { messages } => {
+                let deadline = Instant::now() + GRAFT_TIMEOUT;
+                for value in messages.difference(delivered) {
+                    let missing = self.missing.entry(*value).or_insert_with(|| Missing {
+                        announcers: VecDeque::new(),
+                        deadline,
+                    });
+                    if !missing.announcers.contains(&src) {
+                        missing.announcers.push_back(src.clone());
+                    }
+                }
+                Vec::new()
+            }
+            PlumtreePayload::Graft { messages } => {
+                self.make_eager(&src);
+                let messages: HashSet<usize> = messages.intersection(delivered).copied().collect();
+                if messages.is_empty() {
+                    return Vec::new();
+                }
+                vec![(src, PlumtreePayload::Push { messages })]
+            }
+            PlumtreePayload::Prune => {
+                self.make_lazy(&src);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Sends the batched announcements, and grafts the values that didn't arrive in time.
+    ///
+    /// Values that have arrived in the meantime by other means, e.g., by anti-entropy, are in `delivered`.
+    pub fn tick(&mut self, delivered: &HashSet<usize>) -> Outbox {
+        let mut outbox: Outbox = self
+            .announcements
+            .drain()
+            .filter(|(_, messages)| !messages.is_empty())
+            .map(|(peer, messages)| (peer, PlumtreePayload::IHave { messages }))
+            .collect();
+
+        self.missing.retain(|value, _| !delivered.contains(value));
+
+        let now = Instant::now();
+        let mut grafts: HashMap<String, HashSet<usize>> = HashMap::new();
+        for (&value, missing) in &mut self.missing {
+            if now < missing.deadline {
+                continue;
+            }
+            // Every retry asks the next announcer.
+            if let Some(announcer) = missing.announcers.pop_front() {
+                grafts.entry(announcer.clone()).or_default().insert(value);
+                missing.announcers.push_back(announcer);
+            }
+            missing.deadline = now + GRAFT_TIMEOUT;
+        }
+
+        for (peer, messages) in grafts {
+            self.make_eager(&peer);
+            outbox.push((peer, PlumtreePayload::Graft { messages }));
+        }
+
+        outbox
+    }
+
+    fn make_eager(&mut self, peer: &str) {
+        if self.lazy_peers.remove(peer) {
+            self.eager_peers.insert(peer.to_string());
+        }
+    }
+
+    fn make_lazy(&mut self, peer: &str) {
+        if self.eager_peers.remove(peer) {
+            self.lazy_peers.insert(peer.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plumtree(peers: &[&str]) -> Plumtree {
+        let mut plumtree = Plumtree::new();
+        plumtree.set_peers(peers.iter().map(|peer| peer.to_string()));
+        plumtree
+    }
+
+    fn values(values: &[usize]) -> HashSet<usize> {
+        values.iter().copied().collect()
+    }
+
+    fn sorted<'a>(peers: impl Iterator<Item = &'a String>) -> Vec<&'a str> {
+        let mut peers: Vec<&str> = peers.map(String::as_str).collect();
+        peers.sort();
+        peers
+    }
+
+    fn push(messages: HashSet<usize>) -> PlumtreePayload {
+        PlumtreePayload::Push { messages }
+    }
+
+    fn ihave(messages: HashSet<usize>) -> PlumtreePayload {
+        PlumtreePayload::IHave { messages }
+    }
+
+    /// Makes the missing values overdue.
+    fn time_out(plumtree: &mut Plumtree) {
+        for missing in plumtree.missing.values_mut() {
+            missing.deadline -= GRAFT_TIMEOUT;
+        }
+    }
+
+    #[test]
+    fn a_duplicate_push_makes_the_sender_lazy_and_is_pruned() {
+        let mut plumtree = plumtree(&["n1", "n2"]);
+        let mut delivered = values(&[1]);
+
+        let outbox = plumtree.handle("n1".to_string(), push(values(&[1])), &mut delivered);
+        assert!(matches!(&outbox[..], [(peer, PlumtreePayload::Prune)] if peer == "n1"));
+        assert_eq!(sorted(plumtree.eager_peers()), ["n2"]);
+        assert_eq!(sorted(plumtree.lazy_peers()), ["n1"]);
+
+        // A value that's new to us makes it eager again.
+        let outbox = plumtree.handle("n1".to_string(), push(values(&[1, 2])), &mut delivered);
+        assert_eq!(delivered, values(&[1, 2]));
+        assert!(matches!(
+            &outbox[..],
+            [(peer, PlumtreePayload::Push { messages })] if peer == "n2" && *messages == values(&[2])
+        ));
+        assert_eq!(sorted(plumtree.eager_peers()), ["n1", "n2"]);
+    }
+
+    #[test]
+    fn a_prune_makes_the_sender_lazy() {
+        let mut plumtree = plumtree(&["n1", "n2"]);
+        let outbox = plumtree.handle(
+            "n1".to_string(),
+            PlumtreePayload::Prune,
+            &mut HashSet::new(),
+        );
+        assert!(outbox.is_empty());
+        assert_eq!(sorted(plumtree.lazy_peers()), ["n1"]);
+
+        // Lazy peers only get announcements, on the next tick.
+        let outbox = plumtree.broadcast(values(&[1]), None);
+        assert!(matches!(&outbox[..], [(peer, PlumtreePayload::Push { .. })] if peer == "n2"));
+        let outbox = plumtree.tick(&values(&[1]));
+        assert!(matches!(
+            &outbox[..],
+            [(peer, PlumtreePayload::IHave { messages })] if peer == "n1" && *messages == values(&[1])
+        ));
+        assert!(plumtree.tick(&values(&[1])).is_empty());
+    }
+
+    #[test]
+    fn an_unanswered_announcement_is_grafted_from_each_announcer_in_turn() {
+        let mut plumtree = plumtree(&["n1", "n2", "n3"]);
+        let mut delivered = HashSet::new();
+        for peer in ["n2", "n3"] {
+            plumtree.handle(peer.to_string(), PlumtreePayload::Prune, &mut delivered);
+        }
+        for peer in ["n2", "n3", "n2"] {
+            let outbox = plumtree.handle(peer.to_string(), ihave(values(&[7])), &mut delivered);
+            assert!(outbox.is_empty());
+        }
+
+        // It isn't grafted before the timeout.
+        assert!(plumtree.tick(&delivered).is_empty());
+
+        for announcer in ["n2", "n3", "n2"] {
+            time_out(&mut plumtree);
+            let outbox = plumtree.tick(&delivered);
+            assert!(
+                matches!(
+                    &outbox[..],
+                    [(peer, PlumtreePayload::Graft { messages })] if peer == announcer && *messages == values(&[7])
+                ),
+                "expected a graft from {announcer}, got {outbox:?}"
+            );
+            assert!(plumtree.eager_peers().any(|peer| peer == announcer));
+        }
+
+        // Once it arrives, it's no longer grafted.
+        let outbox = plumtree.handle("n2".to_string(), push(values(&[7])), &mut delivered);
+        assert_eq!(outbox.len(), 2);
+        time_out(&mut plumtree);
+        assert!(plumtree.tick(&delivered).is_empty());
+    }
+
+    #[test]
+    fn a_graft_makes_the_link_eager_and_answers_with_the_values() {
+        let mut plumtree = plumtree(&["n1"]);
+        let mut delivered = values(&[1, 2]);
+        plumtree.handle("n1".to_string(), PlumtreePayload::Prune, &mut delivered);
+
+        let graft = PlumtreePayload::Graft {
+            messages: values(&[2, 3]),
+        };
+        let outbox = plumtree.handle("n1".to_string(), graft, &mut delivered);
+        assert!(matches!(
+            &outbox[..],
+            [(peer, PlumtreePayload::Push { messages })] if peer == "n1" && *messages == values(&[2])
+        ));
+        assert_eq!(sorted(plumtree.eager_peers()), ["n1"]);
+    }
+
+    #[test]
+    fn values_are_never_sent_back_to_where_they_came_from() {
+        let mut plumtree = plumtree(&["n1", "n2", "n3", "n4"]);
+        let mut delivered = HashSet::new();
+        for peer in ["n3", "n4"] {
+            plumtree.handle(peer.to_string(), PlumtreePayload::Prune, &mut delivered);
+        }
+
+        // From an eager peer.
+        let outbox = plumtree.handle("n1".to_string(), push(values(&[1])), &mut delivered);
+        assert_eq!(sorted(outbox.iter().map(|(peer, _)| peer)), ["n2"]);
+        let outbox = plumtree.tick(&delivered);
+        assert_eq!(sorted(outbox.iter().map(|(peer, _)| peer)), ["n3", "n4"]);
+
+        // From a lazy peer, which becomes eager.
+        let outbox = plumtree.handle("n3".to_string(), push(values(&[2])), &mut delivered);
+        assert_eq!(sorted(outbox.iter().map(|(peer, _)| peer)), ["n1", "n2"]);
+        let outbox = plumtree.tick(&delivered);
+        assert_eq!(sorted(outbox.iter().map(|(peer, _)| peer)), ["n4"]);
+    }
+}
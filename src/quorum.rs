@@ -0,0 +1,901 @@
+//! # Quorum Replication
+//!
+//! Leaderless, Dynamo-style replication of a key-value store. Keys are consistently hashed onto a ring of the nodes
+//! (see [`crate::ring`]), and every key is replicated on the `N` nodes that follow it on the ring: its preference list.
+//! Any node coordinates the client requests that it receives: it sends them to the key's replicas,
+//! and completes a read after `R` of them have responded, and a write after `W` of them have acknowledged it.
+//! With `R + W > N`, every read quorum overlaps with every write quorum.
+//!
+//! [Dynamo: Amazon's Highly Available Key-value Store](https://www.allthingsdistributed.com/files/amazon-dynamo-sosp2007.pdf)
+//!
+//! Every value is stored with a [`Version`], and the later version wins, both on the replicas and in reads:
+//! the store is last-write-wins, like Maelstrom's `lww-kv`.
+//! - Read repair: the coordinator of a read sends the latest version to the replicas that responded with an older one,
+//!   including the ones that responded after the read was complete.
+//! - Hinted handoff: the coordinator skips the replicas that its failure detector suspects, and sends their requests
+//!   to the next nodes on the ring instead, i.e., to a sloppy quorum. Those nodes keep the writes with a hint,
+//!   and hand them off to the intended replica once it's reachable again.
+//!
+//! A `cas` is a quorum read followed by a quorum write, and isn't atomic: two concurrent ones may both succeed.
+//!
+//! The node runs the [`FailureDetector`] that the module consults.
+//!
+//! `N`, `R` and `W` are configured at startup, through the [`N_VAR`], [`R_VAR`] and [`W_VAR`] environment variables.
+
+use crate::failure_detector::FailureDetector;
+use crate::message::{ErrorCode, ErrorPayload, KvPayload, QuorumPayload, Version, Versioned};
+use crate::ring::{Ring, DEFAULT_VNODES};
+use anyhow::{bail, Result};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The environment variable that sets `N`, the number of replicas of every key.
+pub const N_VAR: &str = "QUORUM_N";
+
+/// The environment variable that sets `R`, the number of replicas that complete a read.
+pub const R_VAR: &str = "QUORUM_R";
+
+/// The environment variable that sets `W`, the number of replicas that complete a write.
+pub const W_VAR: &str = "QUORUM_W";
+
+pub const DEFAULT_N: usize = 3;
+pub const DEFAULT_R: usize = 2;
+pub const DEFAULT_W: usize = 2;
+
+/// After how long a client request that hasn't reached its quorum times out.
+pub const OP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often hinted writes are handed off to their intended replicas, until they acknowledge them.
+pub const HANDOFF_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Messages to send: pairs of destination node IDs and payloads.
+pub type Outbox = Vec<(String, QuorumPayload)>;
+
+/// Responses to send: the client, the `msg_id` of its request, and the response.
+pub type Replies = Vec<(String, Option<usize>, Result<KvPayload, ErrorPayload>)>;
+
+/// # A Quorum Configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuorumConfig {
+    /// The number of replicas of every key.
+    pub n: usize,
+    /// The number of replicas that complete a read.
+    pub r: usize,
+    /// The number of replicas that complete a write.
+    pub w: usize,
+}
+
+impl Default for QuorumConfig {
+    fn default() -> Self {
+        Self {
+            n: DEFAULT_N,
+            r: DEFAULT_R,
+            w: DEFAULT_W,
+        }
+    }
+}
+
+impl QuorumConfig {
+    pub fn new(n: usize, r: usize, w: usize) -> Result<Self> {
+        if n == 0 {
+            bail!("N must be positive");
+        }
+        if !(1..=n).contains(&r) || !(1..=n).contains(&w) {
+            bail!("R and W must be between 1 and N = {n}, not R = {r} and W = {w}");
+        }
+        Ok(Self { n, r, w })
+    }
+
+    /// Reads the configuration from the [`N_VAR`], [`R_VAR`] and [`W_VAR`] environment variables;
+    /// the ones that aren't set take their default values.
+    pub fn from_env() -> Result<Self> {
+        let var = |name: &str, default: usize| -> Result<usize> {
+            match std::env::var(name) {
+                Ok(value) => Ok(value.parse()?),
+                Err(_) => Ok(default),
+            }
+        };
+        Self::new(
+            var(N_VAR, DEFAULT_N)?,
+            var(R_VAR, DEFAULT_R)?,
+            var(W_VAR, DEFAULT_W)?,
+        )
+    }
+
+    /// Whether every read quorum overlaps with every write quorum, so that reads see the latest completed write,
+    /// as long as the quorums aren't sloppy.
+    pub fn is_strict(&self) -> bool {
+        self.r + self.w > self.n
+    }
+
+    /// The configuration for a cluster of `node_count` nodes: `N`, `R` and `W` are at most `node_count`.
+    fn for_cluster(self, node_count: usize) -> Self {
+        let n = self.n.min(node_count).max(1);
+        Self {
+            n,
+            r: self.r.min(n),
+            w: self.w.min(n),
+        }
+    }
+}
+
+/// The current time in microseconds since the Unix epoch.
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64)
+}
+
+fn error(code: ErrorCode, text: String) -> ErrorPayload {
+    ErrorPayload {
+        code,
+        text: Some(text),
+    }
+}
+
+/// What a coordinated operation is doing.
+#[derive(Clone, Debug)]
+enum Phase {
+    /// Collecting the replicas' versions of the key; for a `read`, or for the first half of a `cas`.
+    Read {
+        /// For a `cas`: the expected value, the new value, and whether a missing key may be created.
+        cas: Option<(Value, Value, bool)>,
+        /// The replicas that have responded, and their versions.
+        responses: HashMap<String, Option<Versioned>>,
+        /// The versions that we have sent to the replicas for read repair.
+        repaired: HashMap<String, Version>,
+    },
+    /// Waiting for the replicas to acknowledge a version; for a `write`, or for the second half of a `cas`.
+    Write {
+        /// Whether this is a `cas`, which has a different response.
+        cas: bool,
+        acked: HashSet<String>,
+    },
+}
+
+/// A client request that we coordinate.
+#[derive(Clone, Debug)]
+struct Operation {
+    client: String,
+    msg_id: Option<usize>,
+    key: Value,
+    phase: Phase,
+    /// The replicas that the requests were sent to, i.e., the ones that may respond.
+    replicas: Vec<String>,
+    /// Whether the client has had its response.
+    done: bool,
+    started: Instant,
+}
+
+/// # A Quorum Replication Module
+#[derive(Debug)]
+pub struct QuorumReplication {
+    /// Our own node ID.
+    node_id: String,
+    config: QuorumConfig,
+    /// The ring of all nodes, which keys are consistently hashed onto.
+    ring: Ring,
+    /// Keys (as JSON text) mapped to their latest versions that we have.
+    store: HashMap<String, Versioned>,
+    /// The latest `time` of any version that we have seen; our next version is later.
+    clock: u64,
+    /// A locally-unique identifier for every operation and handoff.
+    next_op: usize,
+    /// The operations that we coordinate, by their identifiers.
+    ops: HashMap<usize, Operation>,
+    /// Writes that we keep for replicas that were suspected: by intended replica, then by key (as JSON text).
+    hints: HashMap<String, HashMap<String, (Value, Versioned)>>,
+    /// The handoffs of hinted writes in flight, by their identifiers: the intended replica, the key, and the version.
+    handoffs: HashMap<usize, (String, String, Version)>,
+    /// When we last handed off hinted writes.
+    last_handoff: Instant,
+}
+
+impl QuorumReplication {
+    /// Creates a new quorum replication module for the node `node_id`, in a cluster of `node_ids`,
+    /// which includes this node; `N`, `R` and `W` are capped at the cluster's size.
+    pub fn new(node_id: String, node_ids: &[String], config: QuorumConfig) -> Self {
+        Self {
+            node_id,
+            config: config.for_cluster(node_ids.len()),
+            ring: Ring::with_nodes(node_ids.iter().cloned(), DEFAULT_VNODES),
+            store: HashMap::new(),
+            clock: 0,
+            next_op: 0,
+            ops: HashMap::new(),
+            hints: HashMap::new(),
+            handoffs: HashMap::new(),
+            last_handoff: Instant::now(),
+        }
+    }
+
+    pub fn config(&self) -> QuorumConfig {
+        self.config
+    }
+
+    /// All nodes, in the order in which they follow `key` on the ring; the first `N` are its preference list.
+    pub fn walk(&self, key: &Value) -> Vec<String> {
+        self.ring
+            .walk(&key.to_string())
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The replicas to send a request for `key` to: the preference list, except that every suspected replica
+    /// is replaced with the next unsuspected node on the ring, with a hint of the replica that it stands in for.
+    fn replicas(&self, key: &Value, detector: &FailureDetector) -> Vec<(String, Option<String>)> {
+        let walk = self.walk(key);
+        let (preferred, rest) = walk.split_at(self.config.n);
+        let mut fallbacks = rest.iter().filter(|node| !detector.is_suspected(node));
+
+        let mut replicas = Vec::new();
+        for replica in preferred {
+            if !detector.is_suspected(replica) {
+                replicas.push((replica.clone(), None));
+            } else if let Some(fallback) = fallbacks.next() {
+                replicas.push((fallback.clone(), Some(replica.clone())));
+            }
+        }
+        replicas
+    }
+
+    /// A new version, later than any that we have seen.
+    fn next_version(&mut self) -> Version {
+        self.clock = now_micros().max(self.clock + 1);
+        Version {
+            time: self.clock,
+            node_id: self.node_id.clone(),
+        }
+    }
+
+    /// Notes a version that we have seen, so that our next version is later.
+    fn observe(&mut self, versioned: &Versioned) {
+        self.clock = self.clock.max(versioned.version.time);
+    }
+
+    fn next_op(&mut self) -> usize {
+        self.next_op += 1;
+        self.next_op
+    }
+
+    /// Queues a message; one to ourselves is handled at once.
+    fn send(
+        &mut self,
+        dest: String,
+        payload: QuorumPayload,
+        detector: &FailureDetector,
+        outbox: &mut Outbox,
+        replies: &mut Replies,
+    ) {
+        if dest == self.node_id {
+            let src = self.node_id.clone();
+            let more = self.handle(src, payload, detector, replies);
+            outbox.extend(more);
+        } else {
+            outbox.push((dest, payload));
+        }
+    }
+
+    /// Coordinates a client request: sends it to the key's replicas.
+    ///
+    /// The response is added to `replies` once the quorum is reached, which may be at once.
+    pub fn request(
+        &mut self,
+        client: String,
+        msg_id: Option<usize>,
+        op: KvPayload,
+        detector: &FailureDetector,
+        replies: &mut Replies,
+    ) -> Outbox {
+        let (key, phase, versioned) = match op {
+            KvPayload::Read { key } => {
+                let phase = Phase::Read {
+                    cas: None,
+                    responses: HashMap::new(),
+                    repaired: HashMap::new(),
+                };
+                (key, phase, None)
+            }
+            KvPayload::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => {
+                let phase = Phase::Read {
+                    cas: Some((from, to, create_if_not_exists)),
+                    responses: HashMap::new(),
+                    repaired: HashMap::new(),
+                };
+                (key, phase, None)
+            }
+            KvPayload::Write { key, value } => {
+                let versioned = Versioned {
+                    value,
+                    version: self.next_version(),
+                };
+                let phase = Phase::Write {
+                    cas: false,
+                    acked: HashSet::new(),
+                };
+                (key, phase, Some(versioned))
+            }
+            other => {
+                let text = format!("operation not supported: {other:?}");
+                replies.push((client, msg_id, Err(error(ErrorCode::NotSupported, text))));
+                return Vec::new();
+            }
+        };
+
+        self.start(client, msg_id, key, phase, versioned, detector, replies)
+    }
+
+    /// Starts an operation: sends a `replica_read`, or a `replica_write` of `versioned`, to the key's replicas.
+    #[allow(clippy::too_many_arguments)]
+    fn start(
+        &mut self,
+        client: String,
+        msg_id: Option<usize>,
+        key: Value,
+        phase: Phase,
+        versioned: Option<Versioned>,
+        detector: &FailureDetector,
+        replies: &mut Replies,
+    ) -> Outbox {
+        let op = self.next_op();
+        let replicas = self.replicas(&key, detector);
+        self.ops.insert(
+            op,
+            Operation {
+                client,
+                msg_id,
+                key: key.clone(),
+                phase,
+                replicas: replicas
+                    .iter()
+                    .map(|(replica, _)| replica.clone())
+                    .collect(),
+                done: false,
+                started: Instant::now(),
+            },
+        );
+
+        let mut outbox = Vec::new();
+        for (replica, hint) in replicas {
+            let payload = match &versioned {
+                None => QuorumPayload::ReplicaRead {
+                    op,
+                    key: key.clone(),
+                },
+                Some(versioned) => QuorumPayload::ReplicaWrite {
+                    op,
+                    key: key.clone(),
+                    versioned: versioned.clone(),
+                    hint,
+                },
+            };
+            self.send(replica, payload, detector, &mut outbox, replies);
+        }
+        outbox
+    }
+
+    /// Handles a quorum message from the node `src`; the responses to the clients, if any, are added to `replies`.
+    pub fn handle(
+        &mut self,
+        src: String,
+        payload: QuorumPayload,
+        detector: &FailureDetector,
+        replies: &mut Replies,
+    ) -> Outbox {
+        match payload {
+            QuorumPayload::ReplicaRead { op, key } => {
+                let versioned = self.store.get(&key.to_string()).cloned();
+                let mut outbox = Vec::new();
+                let payload = QuorumPayload::ReplicaReadOk { op, versioned };
+                self.send(src, payload, detector, &mut outbox, replies);
+                outbox
+            }
+            QuorumPayload::ReplicaWrite {
+                op,
+                key,
+                versioned,
+                hint,
+            } => {
+                self.observe(&versioned);
+                let text = key.to_string();
+                if self
+                    .store
+                    .get(&text)
+                    .is_none_or(|current| current.version < versioned.version)
+                {
+                    self.store.insert(text.clone(), versioned.clone());
+                }
+                if let Some(hint) = hint.filter(|hint| *hint != self.node_id) {
+                    let hinted = self.hints.entry(hint).or_default();
+                    if hinted
+                        .get(&text)
+                        .is_none_or(|(_, current)| current.version < versioned.version)
+                    {
+                        hinted.insert(text, (key, versioned));
+                    }
+                }
+
+                let mut outbox = Vec::new();
+                let payload = QuorumPayload::ReplicaWriteOk { op };
+                self.send(src, payload, detector, &mut outbox, replies);
+                outbox
+            }
+            QuorumPayload::ReplicaReadOk { op, versioned } => {
+                if let Some(versioned) = &versioned {
+                    self.observe(versioned);
+                }
+                self.on_read_ok(src, op, versioned, detector, replies)
+            }
+            QuorumPayload::ReplicaWriteOk { op } => {
+                self.on_write_ok(src, op, replies);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Handles a replica's version of a key, for the operation `op`.
+    fn on_read_ok(
+        &mut self,
+        src: String,
+        op: usize,
+        versioned: Option<Versioned>,
+        detector: &FailureDetector,
+        replies: &mut Replies,
+    ) -> Outbox {
+        let r = self.config.r;
+        let Some(operation) = self.ops.get_mut(&op) else {
+            return Vec::new();
+        };
+        let Phase::Read {
+            cas,
+            responses,
+            repaired,
+        } = &mut operation.phase
+        else {
+            return Vec::new();
+        };
+
+        responses.insert(src, versioned);
+        if responses.len() < r {
+            return Vec::new();
+        }
+        let latest = responses
+            .values()
+            .flatten()
+            .max_by(|a, b| a.version.cmp(&b.version))
+            .cloned();
+
+        // Read repair, of every replica that has responded with an older version.
+        let mut repairs = Vec::new();
+        if let Some(latest) = &latest {
+            for (replica, versioned) in responses.iter() {
+                let stale = versioned
+                    .as_ref()
+                    .is_none_or(|versioned| versioned.version < latest.version);
+                if stale && repaired.get(replica) != Some(&latest.version) {
+                    repaired.insert(replica.clone(), latest.version.clone());
+                    repairs.push((replica.clone(), latest.clone()));
+                }
+            }
+        }
+
+        let key = operation.key.clone();
+        let mut next = None;
+        if !operation.done {
+            operation.done = true;
+            let client = operation.client.clone();
+            let msg_id = operation.msg_id;
+            match (cas.clone(), &latest) {
+                (None, Some(latest)) => replies.push((
+                    client,
+                    msg_id,
+                    Ok(KvPayload::ReadOk {
+                        value: latest.value.clone(),
+                    }),
+                )),
+                (Some((from, to, _)), Some(latest)) if latest.value == from => {
+                    next = Some((client, msg_id, to));
+                }
+                (Some((from, _, _)), Some(latest)) => {
+                    let text = format!("expected {from}, but had {}", latest.value);
+                    replies.push((
+                        client,
+                        msg_id,
+                        Err(error(ErrorCode::PreconditionFailed, text)),
+                    ));
+                }
+                (Some((_, to, true)), None) => {
+                    next = Some((client, msg_id, to));
+                }
+                (_, None) => {
+                    let text = format!("key {key} does not exist");
+                    replies.push((client, msg_id, Err(error(ErrorCode::KeyDoesNotExist, text))));
+                }
+            }
+        }
+        if responses.len() >= operation.replicas.len() {
+            self.ops.remove(&op);
+        }
+
+        let mut outbox = Vec::new();
+        for (replica, versioned) in repairs {
+            let payload = QuorumPayload::ReplicaWrite {
+                op,
+                key: key.clone(),
+                versioned,
+                hint: None,
+            };
+            self.send(replica, payload, detector, &mut outbox, replies);
+        }
+
+        // The second half of a `cas`: a write of the new value, with a version later than the one that was read.
+        if let Some((client, msg_id, to)) = next {
+            let versioned = Versioned {
+                value: to,
+                version: self.next_version(),
+            };
+            let phase = Phase::Write {
+                cas: true,
+                acked: HashSet::new(),
+            };
+            let more = self.start(
+                client,
+                msg_id,
+                key,
+                phase,
+                Some(versioned),
+                detector,
+                replies,
+            );
+            outbox.extend(more);
+        }
+        outbox
+    }
+
+    /// Handles a replica's acknowledgement of a write, for the operation or the handoff `op`.
+    fn on_write_ok(&mut self, src: String, op: usize, replies: &mut Replies) {
+        if let Some((replica, key, version)) = self.handoffs.remove(&op) {
+            if let Some(hinted) = self.hints.get_mut(&replica) {
+                if hinted
+                    .get(&key)
+                    .is_some_and(|(_, versioned)| versioned.version == version)
+                {
+                    hinted.remove(&key);
+                }
+                if hinted.is_empty() {
+                    self.hints.remove(&replica);
+                }
+            }
+            return;
+        }
+
+        let w = self.config.w;
+        let Some(operation) = self.ops.get_mut(&op) else {
+            return;
+        };
+        let Phase::Write { cas, acked } = &mut operation.phase else {
+            // The acknowledgement of a read repair.
+            return;
+        };
+
+        acked.insert(src);
+        if acked.len() >= w && !operation.done {
+            operation.done = true;
+            let response = if *cas {
+                KvPayload::CasOk
+            } else {
+                KvPayload::WriteOk
+            };
+            replies.push((operation.client.clone(), operation.msg_id, Ok(response)));
+        }
+        if acked.len() >= operation.replicas.len() {
+            self.ops.remove(&op);
+        }
+    }
+
+    /// Times out the operations that haven't reached their quorums, and hands off hinted writes
+    /// to the intended replicas that aren't suspected anymore.
+    pub fn tick(&mut self, detector: &FailureDetector, replies: &mut Replies) -> Outbox {
+        let expired: Vec<usize> = self
+            .ops
+            .iter()
+            .filter(|(_, operation)| operation.started.elapsed() >= OP_TIMEOUT)
+            .map(|(op, _)| *op)
+            .collect();
+        for op in expired {
+            let operation = self.ops.remove(&op).expect("expected an expired operation");
+            if !operation.done {
+                let text = format!("no quorum for key {} within {OP_TIMEOUT:?}", operation.key);
+                replies.push((
+                    operation.client,
+                    operation.msg_id,
+                    Err(error(ErrorCode::Timeout, text)),
+                ));
+            }
+        }
+
+        let mut outbox = Vec::new();
+        if self.hints.is_empty() || self.last_handoff.elapsed() < HANDOFF_INTERVAL {
+            return outbox;
+        }
+        self.last_handoff = Instant::now();
+        // The handoffs that haven't been acknowledged by now are sent again.
+        self.handoffs.clear();
+
+        let handoffs: Vec<(String, String, Value, Versioned)> = self
+            .hints
+            .iter()
+            .filter(|(replica, _)| !detector.is_suspected(replica))
+            .flat_map(|(replica, hinted)| {
+                hinted.iter().map(|(text, (key, versioned))| {
+                    (
+                        replica.clone(),
+                        text.clone(),
+                        key.clone(),
+                        versioned.clone(),
+                    )
+                })
+            })
+            .collect();
+        for (replica, text, key, versioned) in handoffs {
+            let op = self.next_op();
+            self.handoffs
+                .insert(op, (replica.clone(), text, versioned.version.clone()));
+            let payload = QuorumPayload::ReplicaWrite {
+                op,
+                key,
+                versioned,
+                hint: None,
+            };
+            self.send(replica, payload, detector, &mut outbox, replies);
+        }
+        outbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failure_detector::Detection;
+    use std::collections::VecDeque;
+
+    /// Four nodes, with the default `N`, `R` and `W`, and the failure detector that they all consult.
+    struct Cluster {
+        nodes: Vec<QuorumReplication>,
+        detector: FailureDetector,
+        /// The nodes that are down, but not (yet) suspected.
+        down: HashSet<String>,
+        replies: Replies,
+        /// Every message that has been delivered: source, destination, and payload.
+        delivered: Vec<(String, String, QuorumPayload)>,
+    }
+
+    impl Cluster {
+        fn new() -> Self {
+            let node_ids: Vec<String> = (0..4).map(|i| format!("n{i}")).collect();
+            let nodes = node_ids
+                .iter()
+                .map(|node_id| {
+                    QuorumReplication::new(node_id.clone(), &node_ids, QuorumConfig::default())
+                })
+                .collect();
+            Self {
+                nodes,
+                detector: FailureDetector::new(Detection::Timeout(Duration::ZERO)),
+                down: HashSet::new(),
+                replies: Replies::new(),
+                delivered: Vec::new(),
+            }
+        }
+
+        fn node(&mut self, node_id: &str) -> &mut QuorumReplication {
+            &mut self.nodes[node_id[1..].parse::<usize>().unwrap()]
+        }
+
+        /// A key that `n0` coordinates, but doesn't replicate, and the walk of the ring from it.
+        fn remote_key(&self) -> (Value, Vec<String>) {
+            (0..)
+                .map(Value::from)
+                .map(|key| {
+                    let walk = self.nodes[0].walk(&key);
+                    (key, walk)
+                })
+                .find(|(_, walk)| walk[3] == "n0")
+                .unwrap()
+        }
+
+        /// Suspects the node, as the failure detector would after a while without hearing from it.
+        fn suspect(&mut self, node_id: &str) {
+            self.detector.set_peers([node_id.to_string()]);
+            self.detector.tick();
+            assert!(self.detector.is_suspected(node_id));
+        }
+
+        fn request(&mut self, op: KvPayload) {
+            let mut replies = Replies::new();
+            let outbox =
+                self.nodes[0].request("c1".to_string(), Some(1), op, &self.detector, &mut replies);
+            self.replies.extend(replies);
+            self.deliver("n0", outbox);
+        }
+
+        /// Delivers the messages, and the ones that they lead to, in the order of sending,
+        /// until there are none; the ones to or from the nodes that are down are lost.
+        fn deliver(&mut self, src: &str, outbox: Outbox) {
+            let mut queue: VecDeque<(String, String, QuorumPayload)> = outbox
+                .into_iter()
+                .map(|(dest, payload)| (src.to_string(), dest, payload))
+                .collect();
+            while let Some((src, dest, payload)) = queue.pop_front() {
+                if self.down.contains(&src) || self.down.contains(&dest) {
+                    continue;
+                }
+                self.delivered
+                    .push((src.clone(), dest.clone(), payload.clone()));
+                let mut replies = Replies::new();
+                let index = dest[1..].parse::<usize>().unwrap();
+                let outbox = self.nodes[index].handle(src, payload, &self.detector, &mut replies);
+                self.replies.extend(replies);
+                queue.extend(
+                    outbox
+                        .into_iter()
+                        .map(|(next, payload)| (dest.clone(), next, payload)),
+                );
+            }
+        }
+
+        fn stored(&mut self, node_id: &str, key: &Value) -> Option<Value> {
+            let store = &self.node(node_id).store;
+            store
+                .get(&key.to_string())
+                .map(|versioned| versioned.value.clone())
+        }
+    }
+
+    fn write(key: &Value, value: u64) -> KvPayload {
+        KvPayload::Write {
+            key: key.clone(),
+            value: Value::from(value),
+        }
+    }
+
+    #[test]
+    fn quorums_complete_without_a_replica() {
+        let mut cluster = Cluster::new();
+        let (key, walk) = cluster.remote_key();
+        cluster.down.insert(walk[2].clone());
+
+        cluster.request(write(&key, 10));
+        assert!(matches!(
+            &cluster.replies[..],
+            [(_, Some(1), Ok(KvPayload::WriteOk))]
+        ));
+        cluster.replies.clear();
+
+        cluster.request(KvPayload::Read { key: key.clone() });
+        assert!(matches!(
+            &cluster.replies[..],
+            [(_, Some(1), Ok(KvPayload::ReadOk { value }))] if *value == 10
+        ));
+        assert_eq!(cluster.stored(&walk[2], &key), None);
+    }
+
+    #[test]
+    fn operations_without_a_quorum_time_out() {
+        let mut cluster = Cluster::new();
+        let (key, walk) = cluster.remote_key();
+        cluster.down.extend([walk[1].clone(), walk[2].clone()]);
+
+        cluster.request(write(&key, 10));
+        assert!(cluster.replies.is_empty());
+        let mut replies = Replies::new();
+        cluster.nodes[0].tick(&cluster.detector, &mut replies);
+        assert!(replies.is_empty());
+
+        for operation in cluster.nodes[0].ops.values_mut() {
+            operation.started = operation.started.checked_sub(OP_TIMEOUT).unwrap();
+        }
+        cluster.nodes[0].tick(&cluster.detector, &mut replies);
+        assert!(matches!(
+            &replies[..],
+            [(_, Some(1), Err(error))] if error.code == ErrorCode::Timeout
+        ));
+        assert!(cluster.nodes[0].ops.is_empty());
+    }
+
+    #[test]
+    fn a_stale_replica_is_repaired_once_per_version() {
+        let mut cluster = Cluster::new();
+        let (key, walk) = cluster.remote_key();
+        let stale = walk[1].clone();
+        cluster.down.insert(stale.clone());
+        cluster.request(write(&key, 10));
+        cluster.down.clear();
+        cluster.replies.clear();
+
+        // The stale replica responds second, which completes the read, and then a fresh one responds, too.
+        let mut replies = Replies::new();
+        let mut outbox = cluster.nodes[0].request(
+            "c1".to_string(),
+            Some(2),
+            KvPayload::Read { key: key.clone() },
+            &cluster.detector,
+            &mut replies,
+        );
+        outbox.sort_by_key(|(dest, _)| walk.iter().position(|node| node == dest));
+        assert_eq!(outbox[1].0, stale);
+        cluster.deliver("n0", outbox);
+
+        let repairs = |cluster: &Cluster| {
+            cluster
+                .delivered
+                .iter()
+                .filter(|(src, dest, payload)| {
+                    src == "n0"
+                        && *dest == stale
+                        && matches!(payload, QuorumPayload::ReplicaWrite { hint: None, .. })
+                })
+                .count()
+        };
+        assert!(matches!(
+            &cluster.replies[..],
+            [(_, Some(2), Ok(KvPayload::ReadOk { value }))] if *value == 10
+        ));
+        assert_eq!(repairs(&cluster), 1);
+        assert_eq!(cluster.stored(&stale, &key), Some(Value::from(10)));
+
+        // Once repaired, it isn't repaired again.
+        cluster.request(KvPayload::Read { key: key.clone() });
+        assert_eq!(repairs(&cluster), 1);
+    }
+
+    #[test]
+    fn hinted_writes_are_handed_off_and_dropped_once_acknowledged() {
+        let mut cluster = Cluster::new();
+        let (key, walk) = cluster.remote_key();
+        let replica = walk[1].clone();
+        cluster.suspect(&replica);
+        cluster.down.insert(replica.clone());
+
+        // The write goes to the next node on the ring instead, which is the coordinator itself.
+        cluster.request(write(&key, 10));
+        assert!(matches!(
+            &cluster.replies[..],
+            [(_, Some(1), Ok(KvPayload::WriteOk))]
+        ));
+        assert_eq!(cluster.stored("n0", &key), Some(Value::from(10)));
+        assert!(cluster.nodes[0].hints[&replica].contains_key(&key.to_string()));
+
+        // No handoff while the replica is suspected.
+        cluster.nodes[0].last_handoff -= HANDOFF_INTERVAL;
+        let mut replies = Replies::new();
+        let outbox = cluster.nodes[0].tick(&cluster.detector, &mut replies);
+        assert!(outbox.is_empty());
+
+        // It's back, but the first handoff is lost; the hint stays until one is acknowledged.
+        cluster.detector.heard_from(&replica);
+        cluster.nodes[0].last_handoff -= HANDOFF_INTERVAL;
+        let outbox = cluster.nodes[0].tick(&cluster.detector, &mut replies);
+        assert!(matches!(
+            &outbox[..],
+            [(dest, QuorumPayload::ReplicaWrite { hint: None, .. })] if *dest == replica
+        ));
+        cluster.deliver("n0", outbox);
+        assert!(cluster.nodes[0].hints.contains_key(&replica));
+
+        cluster.down.clear();
+        cluster.nodes[0].last_handoff -= HANDOFF_INTERVAL;
+        let outbox = cluster.nodes[0].tick(&cluster.detector, &mut replies);
+        cluster.deliver("n0", outbox);
+        assert_eq!(cluster.stored(&replica, &key), Some(Value::from(10)));
+        assert!(cluster.nodes[0].hints.is_empty());
+        assert!(cluster.nodes[0].handoffs.is_empty());
+    }
+}
@@ -0,0 +1,526 @@
+//! # Raft
+//!
+//! The Raft consensus algorithm: leader election with randomized timeouts, log replication
+//! and commit index tracking.
+//!
+//! [In Search of an Understandable Consensus Algorithm](https://raft.github.io/raft.pdf)
+//!
+//! The node applies the committed commands to its state machine.
+//!
+//! Maelstrom doesn't crash our nodes, so the persistent state is simply kept in memory.
+
+use crate::message::{LogEntry, RaftPayload};
+use crate::rng::Rng;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// The range of election timeouts, in milliseconds, from which a random one is picked every time.
+pub const ELECTION_TIMEOUT_MS: Range<u64> = 1000..2000;
+
+/// How often the leader sends `append_entries` to its followers, be it a heartbeat or replication.
+pub const REPLICATION_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The maximum number of log entries in a single `append_entries` message.
+pub const MAX_ENTRIES_PER_APPEND: usize = 128;
+
+/// Messages to send: pairs of destination node IDs and payloads.
+pub type Outbox<C> = Vec<(String, RaftPayload<C>)>;
+
+/// The role that a Raft node currently plays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// # A Raft Consensus Module
+///
+/// Replicates a log of commands of type `C`.
+///
+/// Log indices start at `1`; index `0` stands for the empty log prefix.
+#[derive(Debug)]
+pub struct Raft<C> {
+    /// Our own node ID.
+    node_id: String,
+    /// All other nodes in the cluster.
+    peers: Vec<String>,
+    /// The latest term we have seen.
+    current_term: usize,
+    /// The candidate that received our vote in the current term, if any.
+    voted_for: Option<String>,
+    /// The log entries; the entry at index `i` is stored at `log[i - 1]`.
+    log: Vec<LogEntry<C>>,
+    /// The index of the highest log entry known to be committed.
+    commit_index: usize,
+    /// The index of the highest log entry handed out for application to the state machine.
+    last_applied: usize,
+    role: Role,
+    /// The leader of the current term, if we know it.
+    leader_id: Option<String>,
+    /// Votes received in the current election, while we are a candidate.
+    votes: HashSet<String>,
+    /// For each peer, the index of the next log entry to send to it; only meaningful on the leader.
+    next_index: HashMap<String, usize>,
+    /// For each peer, the index of the highest log entry known to be replicated on it; only meaningful on the leader.
+    match_index: HashMap<String, usize>,
+    /// When we start an election if we don't hear from a leader before that.
+    election_deadline: Instant,
+    /// When the leader last sent `append_entries` to its followers.
+    last_replication: Instant,
+    rng: Rng,
+}
+
+impl<C: Clone> Raft<C> {
+    /// Creates a new Raft module for the node `node_id`, in a cluster of `node_ids`, which includes this node.
+    ///
+    /// Every node starts as a follower.
+    pub fn new(node_id: String, node_ids: Vec<String>) -> Self {
+        let peers = node_ids.into_iter().filter(|id| *id != node_id).collect();
+        let mut raft = Self {
+            node_id,
+            peers,
+            current_term: 0,
+            voted_for: None,
+            log: Vec::new(),
+            commit_index: 0,
+            last_applied: 0,
+            role: Role::Follower,
+            leader_id: None,
+            votes: HashSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            election_deadline: Instant::now(),
+            last_replication: Instant::now(),
+            rng: Rng::new(),
+        };
+        raft.reset_election_deadline();
+        raft
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.role == Role::Leader
+    }
+
+    /// The leader of the current term, if we know it; that may be ourselves.
+    pub fn leader_id(&self) -> Option<&str> {
+        self.leader_id.as_deref()
+    }
+
+    pub fn current_term(&self) -> usize {
+        self.current_term
+    }
+
+    pub fn commit_index(&self) -> usize {
+        self.commit_index
+    }
+
+    /// Advances the timers: starts an election when the election timeout elapses,
+    /// and makes the leader replicate its log (or send heartbeats) periodically.
+    pub fn tick(&mut self) -> Outbox<C> {
+        let now = Instant::now();
+        match self.role {
+            Role::Leader if now.duration_since(self.last_replication) >= REPLICATION_INTERVAL => {
+                self.replicate()
+            }
+            Role::Follower | Role::Candidate if now >= self.election_deadline => {
+                self.start_election()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Appends a new command to the log, if we are the leader.
+    ///
+    /// Returns the index of the new entry, or `None` if we aren't the leader,
+    /// in which case the command should be forwarded to the leader instead.
+    ///
+    /// The entry is replicated on the next [`Raft::tick()`], together with other recent entries.
+    pub fn propose(&mut self, command: C) -> Option<usize> {
+        if !self.is_leader() {
+            return None;
+        }
+
+        self.log.push(LogEntry {
+            term: self.current_term,
+            command,
+        });
+        // A single-node cluster is its own majority.
+        self.advance_commit_index();
+
+        Some(self.last_log_index())
+    }
+
+    /// Takes the commands that have been committed since the last call, in log order,
+    /// so that the node can apply them to its state machine.
+    pub fn take_committed(&mut self) -> Vec<C> {
+        let committed = self.log[self.last_applied..self.commit_index]
+            .iter()
+            .map(|entry| entry.command.clone())
+            .collect();
+        self.last_applied = self.commit_index;
+        committed
+    }
+
+    /// Handles a Raft message from the peer `src`.
+    pub fn handle(&mut self, src: String, payload: RaftPayload<C>) -> Outbox<C> {
+        match payload {
+            RaftPayload::RequestVote {
+                term,
+                candidate_id,
+                last_log_index,
+                last_log_term,
+            } => {
+                self.maybe_step_down(term);
+
+                let log_ok = last_log_term > self.last_log_term()
+                    || (last_log_term == self.last_log_term()
+                        && last_log_index >= self.last_log_index());
+                let vote_granted = term == self.current_term
+                    && log_ok
+                    && self
+                        .voted_for
+                        .as_ref()
+                        .is_none_or(|voted_for| *voted_for == candidate_id);
+                if vote_granted {
+                    self.voted_for = Some(candidate_id);
+                    self.reset_election_deadline();
+                }
+
+                vec![(
+                    src,
+                    RaftPayload::RequestVoteOk {
+                        term: self.current_term,
+                        vote_granted,
+                    },
+                )]
+            }
+            RaftPayload::RequestVoteOk { term, vote_granted } => {
+                self.maybe_step_down(term);
+
+                if self.role == Role::Candidate && term == self.current_term && vote_granted {
+                    self.votes.insert(src);
+                    if self.votes.len() >= self.majority() {
+                        return self.become_leader();
+                    }
+                }
+
+                Vec::new()
+            }
+            RaftPayload::AppendEntries {
+                term,
+                leader_id,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => {
+                self.maybe_step_down(term);
+
+                if term < self.current_term {
+                    return vec![(
+                        src,
+                        RaftPayload::AppendEntriesOk {
+                            term: self.current_term,
+                            success: false,
+                            match_index: 0,
+                        },
+                    )];
+                }
+
+                // There is a legitimate leader in our term.
+                self.role = Role::Follower;
+                self.leader_id = Some(leader_id);
+                self.reset_election_deadline();
+
+                let (success, match_index) = self.append(prev_log_index, prev_log_term, entries);
+                if success {
+                    // A late duplicate may match less than we've already committed, which stays committed.
+                    self.commit_index = self.commit_index.max(leader_commit.min(match_index));
+                }
+
+                vec![(
+                    src,
+                    RaftPayload::AppendEntriesOk {
+                        term: self.current_term,
+                        success,
+                        match_index,
+                    },
+                )]
+            }
+            RaftPayload::AppendEntriesOk {
+                term,
+                success,
+                match_index,
+            } => {
+                self.maybe_step_down(term);
+
+                if self.role != Role::Leader || term != self.current_term {
+                    return Vec::new();
+                }
+
+                if success {
+                    let matched = self.match_index.entry(src.clone()).or_default();
+                    *matched = (*matched).max(match_index);
+                    let next = self.next_index.entry(src).or_default();
+                    *next = (*next).max(match_index + 1);
+                    self.advance_commit_index();
+                } else {
+                    let next = self.next_index.entry(src).or_insert(1);
+                    *next = (*next).min(match_index + 1).max(1);
+                }
+
+                Vec::new()
+            }
+        }
+    }
+
+    fn last_log_index(&self) -> usize {
+        self.log.len()
+    }
+
+    fn last_log_term(&self) -> usize {
+        self.term_at(self.last_log_index())
+    }
+
+    /// The term of the entry at `index`, where index `0` has term `0`.
+    fn term_at(&self, index: usize) -> usize {
+        match index {
+            0 => 0,
+            index => self.log[index - 1].term,
+        }
+    }
+
+    fn majority(&self) -> usize {
+        let cluster_size = self.peers.len() + 1;
+        cluster_size / 2 + 1
+    }
+
+    fn reset_election_deadline(&mut self) {
+        self.election_deadline =
+            Instant::now() + Duration::from_millis(self.rng.gen_range(ELECTION_TIMEOUT_MS));
+    }
+
+    /// Adopts a newer term, if `term` is one, and becomes a follower in it.
+    fn maybe_step_down(&mut self, term: usize) {
+        if term > self.current_term {
+            self.current_term = term;
+            self.voted_for = None;
+            self.role = Role::Follower;
+            self.leader_id = None;
+        }
+    }
+
+    fn start_election(&mut self) -> Outbox<C> {
+        self.current_term += 1;
+        self.role = Role::Candidate;
+        self.voted_for = Some(self.node_id.clone());
+        self.leader_id = None;
+        self.votes = HashSet::from([self.node_id.clone()]);
+        self.reset_election_deadline();
+
+        if self.votes.len() >= self.majority() {
+            return self.become_leader();
+        }
+
+        self.peers
+            .iter()
+            .map(|peer| {
+                (
+                    peer.clone(),
+                    RaftPayload::RequestVote {
+                        term: self.current_term,
+                        candidate_id: self.node_id.clone(),
+                        last_log_index: self.last_log_index(),
+                        last_log_term: self.last_log_term(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn become_leader(&mut self) -> Outbox<C> {
+        self.role = Role::Leader;
+        self.leader_id = Some(self.node_id.clone());
+        self.next_index = self
+            .peers
+            .iter()
+            .map(|peer| (peer.clone(), self.last_log_index() + 1))
+            .collect();
+        self.match_index = self.peers.iter().map(|peer| (peer.clone(), 0)).collect();
+
+        self.replicate()
+    }
+
+    /// Sends every follower the entries it is missing, or an empty heartbeat.
+    fn replicate(&mut self) -> Outbox<C> {
+        self.last_replication = Instant::now();
+
+        self.peers
+            .iter()
+            .map(|peer| {
+                let next_index = self.next_index.get(peer).copied().unwrap_or(1);
+                let prev_log_index = next_index - 1;
+                let end = self
+                    .last_log_index()
+                    .min(prev_log_index + MAX_ENTRIES_PER_APPEND);
+                (
+                    peer.clone(),
+                    RaftPayload::AppendEntries {
+                        term: self.current_term,
+                        leader_id: self.node_id.clone(),
+                        prev_log_index,
+                        prev_log_term: self.term_at(prev_log_index),
+                        entries: self.log[prev_log_index..end].to_vec(),
+                        leader_commit: self.commit_index,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Appends the leader's entries after `prev_log_index`, if our log matches the leader's up to there.
+    ///
+    /// Returns whether it succeeded, and the match index (or the hint for the leader, on failure).
+    fn append(
+        &mut self,
+        prev_log_index: usize,
+        prev_log_term: usize,
+        entries: Vec<LogEntry<C>>,
+    ) -> (bool, usize) {
+        if prev_log_index > self.last_log_index() {
+            return (false, self.last_log_index());
+        }
+
+        if self.term_at(prev_log_index) != prev_log_term {
+            // Skip the whole conflicting term at once, instead of backing up one entry at a time.
+            let conflicting_term = self.term_at(prev_log_index);
+            let mut first = prev_log_index;
+            while first > 1 && self.term_at(first - 1) == conflicting_term {
+                first -= 1;
+            }
+            return (false, first - 1);
+        }
+
+        let appended = entries.len();
+        for (offset, entry) in entries.into_iter().enumerate() {
+            let index = prev_log_index + 1 + offset;
+            if index <= self.last_log_index() {
+                if self.term_at(index) == entry.term {
+                    continue;
+                }
+                self.log.truncate(index - 1);
+            }
+            self.log.push(entry);
+        }
+
+        (true, prev_log_index + appended)
+    }
+
+    /// Commits the highest entry of the current term that a majority has replicated, if there is a new one.
+    ///
+    /// Entries from earlier terms get committed indirectly, together with it.
+    fn advance_commit_index(&mut self) {
+        for index in (self.commit_index + 1..=self.last_log_index()).rev() {
+            if self.term_at(index) != self.current_term {
+                break;
+            }
+            let replicas = 1 + self
+                .match_index
+                .values()
+                .filter(|&&matched| matched >= index)
+                .count();
+            if replicas >= self.majority() {
+                self.commit_index = index;
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(terms: &[usize]) -> Vec<LogEntry<usize>> {
+        terms
+            .iter()
+            .enumerate()
+            .map(|(index, &term)| LogEntry {
+                term,
+                command: index + 1,
+            })
+            .collect()
+    }
+
+    fn append_entries(
+        prev_log_index: usize,
+        entries: Vec<LogEntry<usize>>,
+        leader_commit: usize,
+    ) -> RaftPayload<usize> {
+        RaftPayload::AppendEntries {
+            term: 1,
+            leader_id: "n0".to_string(),
+            prev_log_index,
+            prev_log_term: if prev_log_index == 0 { 0 } else { 1 },
+            entries,
+            leader_commit,
+        }
+    }
+
+    fn cluster() -> Vec<String> {
+        vec!["n0".to_string(), "n1".to_string(), "n2".to_string()]
+    }
+
+    #[test]
+    fn stale_append_entries_does_not_lower_commit_index() {
+        let mut raft = Raft::<usize>::new("n1".to_string(), cluster());
+
+        raft.handle("n0".to_string(), append_entries(0, entries(&[1; 5]), 5));
+        assert_eq!(raft.commit_index(), 5);
+        assert_eq!(raft.take_committed(), vec![1, 2, 3, 4, 5]);
+
+        // A retransmission of an earlier, shorter batch, with a newer commit index.
+        let outbox = raft.handle("n0".to_string(), append_entries(0, entries(&[1; 2]), 5));
+        assert!(matches!(
+            outbox[..],
+            [(_, RaftPayload::AppendEntriesOk { success: true, .. })]
+        ));
+        assert_eq!(raft.commit_index(), 5);
+        assert!(raft.take_committed().is_empty());
+    }
+
+    #[test]
+    fn stale_ack_does_not_rewind_next_index() {
+        let mut raft = Raft::<usize>::new("n0".to_string(), cluster());
+        raft.start_election();
+        let term = raft.current_term();
+        raft.handle(
+            "n1".to_string(),
+            RaftPayload::RequestVoteOk {
+                term,
+                vote_granted: true,
+            },
+        );
+        assert!(raft.is_leader());
+        for command in 1..=3 {
+            raft.propose(command);
+        }
+
+        let ack = |match_index| RaftPayload::AppendEntriesOk {
+            term,
+            success: true,
+            match_index,
+        };
+        raft.handle("n1".to_string(), ack(3));
+        raft.handle("n1".to_string(), ack(1));
+        assert_eq!(raft.next_index["n1"], 4);
+        assert_eq!(raft.match_index["n1"], 3);
+        assert_eq!(raft.commit_index(), 3);
+    }
+}
@@ -0,0 +1,354 @@
+//! # Consistent Hashing
+//!
+//! A hash ring over node IDs, which maps keys to nodes: a key belongs to the first node that follows it on the ring,
+//! and its preference list of size `N` is made up of the first `N` distinct nodes that follow it.
+//!
+//! [Consistent Hashing and Random Trees](https://dl.acm.org/doi/10.1145/258533.258660)
+//!
+//! Every node is placed on the ring at several positions, its virtual nodes, so that the keys are spread evenly
+//! over the nodes, and so that the keys of a node that leaves are spread over all the others.
+//! A node that joins or leaves only moves the keys that fall between its own virtual nodes and their predecessors:
+//! about `1/n` of them, and none between the other nodes.
+//!
+//! Positions are computed with a fixed hash function, so every node builds the same ring from the same nodes.
+
+use crate::anti_entropy::mix;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The default number of virtual nodes per node.
+pub const DEFAULT_VNODES: usize = 64;
+
+/// The position of a key or of a virtual node on the ring: a 64-bit FNV-1a hash of its text, mixed.
+pub fn position(text: &str) -> u64 {
+    let hash = text.bytes().fold(0xCBF2_9CE4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+    });
+    mix(hash)
+}
+
+/// # A Consistent-Hash Ring
+#[derive(Clone, Debug)]
+pub struct Ring {
+    /// The number of virtual nodes per node.
+    vnodes: usize,
+    /// The virtual nodes: their positions, and the nodes that they belong to, in ring order.
+    tokens: Vec<(u64, String)>,
+    nodes: BTreeSet<String>,
+}
+
+impl Ring {
+    /// Creates an empty ring, whose nodes will have `vnodes` virtual nodes each (at least one).
+    pub fn new(vnodes: usize) -> Self {
+        Self {
+            vnodes: vnodes.max(1),
+            tokens: Vec::new(),
+            nodes: BTreeSet::new(),
+        }
+    }
+
+    /// Creates a ring of `nodes`, with `vnodes` virtual nodes each.
+    pub fn with_nodes(nodes: impl IntoIterator<Item = String>, vnodes: usize) -> Self {
+        let mut ring = Self::new(vnodes);
+        for node in nodes {
+            ring.add(node);
+        }
+        ring
+    }
+
+    pub fn vnodes(&self) -> usize {
+        self.vnodes
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &str> {
+        self.nodes.iter().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn contains(&self, node: &str) -> bool {
+        self.nodes.contains(node)
+    }
+
+    /// Adds a node, with its virtual nodes; the other virtual nodes stay where they are.
+    pub fn add(&mut self, node: String) {
+        if !self.nodes.insert(node.clone()) {
+            return;
+        }
+        for vnode in 0..self.vnodes {
+            let token = (position(&format!("{node}#{vnode}")), node.clone());
+            let index = self.tokens.partition_point(|other| *other < token);
+            self.tokens.insert(index, token);
+        }
+    }
+
+    /// Removes a node, with its virtual nodes; the other virtual nodes stay where they are.
+    pub fn remove(&mut self, node: &str) {
+        if self.nodes.remove(node) {
+            self.tokens.retain(|(_, owner)| owner != node);
+        }
+    }
+
+    /// The index of the first virtual node at or after `key`, wrapping around.
+    fn start(&self, key: &str) -> usize {
+        let key = position(key);
+        let index = self.tokens.partition_point(|(token, _)| *token < key);
+        if index == self.tokens.len() {
+            0
+        } else {
+            index
+        }
+    }
+
+    /// The node that `key` belongs to; `None` if the ring is empty.
+    pub fn owner(&self, key: &str) -> Option<&str> {
+        if self.tokens.is_empty() {
+            return None;
+        }
+        Some(self.tokens[self.start(key)].1.as_str())
+    }
+
+    /// All nodes, in the order in which they first follow `key` on the ring.
+    pub fn walk(&self, key: &str) -> Vec<&str> {
+        let mut walk = Vec::with_capacity(self.nodes.len());
+        if self.tokens.is_empty() {
+            return walk;
+        }
+        let start = self.start(key);
+        for (_, node) in self.tokens[start..].iter().chain(&self.tokens[..start]) {
+            if walk.len() == self.nodes.len() {
+                break;
+            }
+            if !walk.contains(&node.as_str()) {
+                walk.push(node.as_str());
+            }
+        }
+        walk
+    }
+
+    /// The first `n` distinct nodes that follow `key` on the ring; all of them, if there aren't that many.
+    pub fn preference_list(&self, key: &str, n: usize) -> Vec<&str> {
+        let mut walk = self.walk(key);
+        walk.truncate(n);
+        walk
+    }
+
+    /// The fraction of the ring that each node owns, i.e., the share of all possible keys that belong to it.
+    pub fn ownership(&self) -> BTreeMap<String, f64> {
+        let mut ownership: BTreeMap<String, f64> =
+            self.nodes.iter().map(|node| (node.clone(), 0.0)).collect();
+        let Some((last, _)) = self.tokens.last() else {
+            return ownership;
+        };
+
+        // Every virtual node owns the arc from its predecessor, exclusive, up to itself, inclusive.
+        let mut previous = *last;
+        for (token, node) in &self.tokens {
+            let arc = token.wrapping_sub(previous);
+            *ownership.get_mut(node).expect("expected a known node") += arc as f64;
+            previous = *token;
+        }
+        if self.tokens.len() == 1 {
+            // A single virtual node owns the whole ring, which its arc from itself comes to `0` for.
+            return ownership.into_keys().map(|node| (node, 1.0)).collect();
+        }
+        for share in ownership.values_mut() {
+            *share /= 2f64.powi(64);
+        }
+        ownership
+    }
+
+    /// The fraction of the ring whose keys belong to a different node in `other`: the share of keys that move
+    /// between the two rings.
+    pub fn moved(&self, other: &Ring) -> f64 {
+        if self.tokens.is_empty() || other.tokens.is_empty() {
+            return 1.0;
+        }
+
+        // Between two consecutive boundaries of either ring, the owners in both rings stay the same.
+        let mut boundaries: Vec<u64> = self
+            .tokens
+            .iter()
+            .chain(&other.tokens)
+            .map(|(token, _)| *token)
+            .collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        let owner = |ring: &Ring, at: u64| {
+            let index = ring.tokens.partition_point(|(token, _)| *token < at);
+            ring.tokens[index % ring.tokens.len()].1.clone()
+        };
+        if boundaries.len() == 1 {
+            let moved = owner(self, boundaries[0]) != owner(other, boundaries[0]);
+            return if moved { 1.0 } else { 0.0 };
+        }
+        let mut moved = 0.0;
+        let mut previous = *boundaries.last().expect("expected some boundaries");
+        for &boundary in &boundaries {
+            if owner(self, boundary) != owner(other, boundary) {
+                moved += boundary.wrapping_sub(previous) as f64;
+            }
+            previous = boundary;
+        }
+        moved / 2f64.powi(64)
+    }
+
+    /// How many of `keys` belong to each node.
+    pub fn distribution<'a>(&self, keys: impl IntoIterator<Item = &'a str>) -> Distribution {
+        let mut counts: BTreeMap<String, usize> =
+            self.nodes.iter().map(|node| (node.clone(), 0)).collect();
+        for key in keys {
+            if let Some(owner) = self.owner(key) {
+                *counts.get_mut(owner).expect("expected a known node") += 1;
+            }
+        }
+        Distribution { counts }
+    }
+}
+
+/// # A Key Distribution
+///
+/// How many keys belong to each node of a ring, for checking how evenly the ring spreads them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Distribution {
+    pub counts: BTreeMap<String, usize>,
+}
+
+impl Distribution {
+    /// The mean number of keys per node.
+    pub fn mean(&self) -> f64 {
+        if self.counts.is_empty() {
+            return 0.0;
+        }
+        self.counts.values().sum::<usize>() as f64 / self.counts.len() as f64
+    }
+
+    /// The standard deviation of the numbers of keys per node.
+    pub fn std_dev(&self) -> f64 {
+        if self.counts.is_empty() {
+            return 0.0;
+        }
+        let mean = self.mean();
+        let variance = self
+            .counts
+            .values()
+            .map(|&count| (count as f64 - mean).powi(2))
+            .sum::<f64>()
+            / self.counts.len() as f64;
+        variance.sqrt()
+    }
+
+    /// The largest number of keys on a node, relative to the mean: `1` is perfectly even.
+    pub fn max_over_mean(&self) -> f64 {
+        let mean = self.mean();
+        if mean == 0.0 {
+            return 0.0;
+        }
+        self.counts.values().copied().max().unwrap_or(0) as f64 / mean
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring(node_count: usize, vnodes: usize) -> Ring {
+        Ring::with_nodes((0..node_count).map(|node| format!("n{node}")), vnodes)
+    }
+
+    fn keys() -> Vec<String> {
+        (0..20_000).map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn preference_lists_have_distinct_nodes() {
+        let ring = ring(5, DEFAULT_VNODES);
+        for key in keys().iter().take(1_000) {
+            for n in 1..=6 {
+                let mut list = ring.preference_list(key, n);
+                assert_eq!(list.len(), n.min(5));
+                assert_eq!(list[0], ring.owner(key).unwrap());
+                list.sort_unstable();
+                list.dedup();
+                assert_eq!(list.len(), n.min(5));
+            }
+        }
+    }
+
+    #[test]
+    fn keys_are_spread_evenly() {
+        let keys = keys();
+        for node_count in 3..=10 {
+            let distribution =
+                ring(node_count, DEFAULT_VNODES).distribution(keys.iter().map(String::as_str));
+            assert_eq!(distribution.counts.values().sum::<usize>(), keys.len());
+            assert!(
+                distribution.max_over_mean() < 1.3,
+                "{node_count} nodes: max/mean {}",
+                distribution.max_over_mean()
+            );
+        }
+    }
+
+    #[test]
+    fn joins_and_leaves_move_about_their_share_of_keys() {
+        let keys = keys();
+        for node_count in 3..=10 {
+            let before = ring(node_count, DEFAULT_VNODES);
+
+            let mut joined = before.clone();
+            let newcomer = format!("n{node_count}");
+            joined.add(newcomer.clone());
+            let ideal = 1.0 / (node_count + 1) as f64;
+            let moved = before.moved(&joined);
+            assert!(
+                (moved - ideal).abs() < 0.3 * ideal,
+                "join: {moved} vs {ideal}"
+            );
+            for key in &keys {
+                let (from, to) = (before.owner(key).unwrap(), joined.owner(key).unwrap());
+                assert!(
+                    from == to || to == newcomer,
+                    "{key} moved from {from} to {to}"
+                );
+            }
+
+            let mut left = before.clone();
+            left.remove("n0");
+            let ideal = 1.0 / node_count as f64;
+            let moved = before.moved(&left);
+            assert!(
+                (moved - ideal).abs() < 0.3 * ideal,
+                "leave: {moved} vs {ideal}"
+            );
+            for key in &keys {
+                let (from, to) = (before.owner(key).unwrap(), left.owner(key).unwrap());
+                assert!(
+                    from == to || from == "n0",
+                    "{key} moved from {from} to {to}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ownership_adds_up_to_the_whole_ring() {
+        // A single virtual node owns the whole ring.
+        let single = ring(1, 1);
+        assert_eq!(
+            single.ownership(),
+            BTreeMap::from([("n0".to_string(), 1.0)])
+        );
+        assert_eq!(single.moved(&single), 0.0);
+
+        let ownership = ring(5, DEFAULT_VNODES).ownership();
+        assert!((ownership.values().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(Ring::new(DEFAULT_VNODES).ownership().is_empty());
+    }
+}
@@ -0,0 +1,62 @@
+//! # Random Numbers
+//!
+//! A tiny, non-cryptographic pseudo-random number generator, good enough for randomized timeouts,
+//! peer sampling and the like, without pulling in an external dependency.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Range;
+
+/// A xorshift64* pseudo-random number generator.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a new generator, seeded from the process-wide random hashing keys.
+    pub fn new() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        );
+        Self::with_seed(hasher.finish())
+    }
+
+    /// Creates a new generator from a fixed seed, which makes its sequence reproducible.
+    pub fn with_seed(seed: u64) -> Self {
+        // The state must never be zero.
+        Self { state: seed | 1 }
+    }
+
+    /// Returns the next pseudo-random 64-bit number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a pseudo-random number in the given half-open range, which must not be empty.
+    pub fn gen_range(&mut self, range: Range<u64>) -> u64 {
+        assert!(range.start < range.end, "empty range");
+        range.start + self.next_u64() % (range.end - range.start)
+    }
+
+    /// Shuffles the slice in place (Fisher-Yates).
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.gen_range(0..i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
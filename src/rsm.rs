@@ -0,0 +1,702 @@
+//! # Replicated State Machines
+//!
+//! A deterministic [`StateMachine`] that applies the same commands in the same order on every node
+//! ends up in the same state on every node. A consensus log supplies that order.
+//!
+//! [`Replica`] is a generic node that wires a consensus log to any state machine:
+//! clients may send their requests to any node; the leader appends them to its log and responds
+//! once they have been committed and applied; other nodes forward them to the leader they know of,
+//! or respond with a `temporarily-unavailable` error if they don't know of any.
+//!
+//! Reads go through the log as well, which keeps them linearizable even with a deposed leader around.
+//!
+//! The log is either [`crate::raft`] or [`crate::paxos`], behind the [`Consensus`] trait;
+//! the binaries select it at startup, through the [`CONSENSUS_VAR`] environment variable.
+//!
+//! [Implementing Fault-Tolerant Services Using the State Machine Approach](https://www.cs.cornell.edu/fbs/publications/SMSurvey.pdf)
+//!
+//! [`KvStore`] serves the `lin-kv` workload, and [`Counter`] serves the `pn-counter` workload.
+//! [`crate::lock::LockTable`] is a state machine of its own, for the lock service.
+
+use crate::logic::main_loop;
+use crate::message::{
+    ClientCommand, ErrorCode, ErrorPayload, KvPayload, Message, PaxosPayload, PnCounterPayload,
+    RaftPayload, ReplicaPayload,
+};
+use crate::node::Node;
+use crate::paxos::MultiPaxos;
+use crate::raft::Raft;
+use anyhow::{bail, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::StdoutLock;
+use std::time::Duration;
+
+/// How often a replica ticks its consensus module.
+pub const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// # A Deterministic State Machine
+///
+/// Applying the same commands in the same order, starting from the same state, must lead to the same state
+/// and the same outputs, so `apply()` mustn't depend on anything else, such as the time or randomness.
+pub trait StateMachine {
+    /// The commands that change or read the state.
+    type Command;
+    /// The result of applying a command.
+    type Output;
+    /// A copy of the whole state.
+    type Snapshot;
+
+    /// Fills in whatever a command needs that `apply()` mustn't look up itself, such as the time,
+    /// so that it's fixed before the command goes into the log.
+    ///
+    /// The leader calls it when it proposes a command. It leaves the command as it is by default.
+    fn prepare(command: Self::Command) -> Self::Command {
+        command
+    }
+
+    /// Applies a committed command, and returns its output.
+    fn apply(&mut self, command: Self::Command) -> Self::Output;
+
+    /// Takes a snapshot of the current state, e.g., to compact a log or to bring a lagging replica up to date.
+    fn snapshot(&self) -> Self::Snapshot;
+
+    /// Replaces the current state with a snapshot.
+    fn restore(&mut self, snapshot: Self::Snapshot);
+}
+
+/// Builds an error response.
+fn error(code: ErrorCode, text: String) -> ErrorPayload {
+    ErrorPayload {
+        code,
+        text: Some(text),
+    }
+}
+
+/// # A Key-Value Store
+///
+/// Applies the `read`, `write` and `cas` operations of the `lin-kv` workload, and returns their responses.
+#[derive(Clone, Debug, Default)]
+pub struct KvStore {
+    /// Keys (as JSON text) mapped to their values.
+    store: HashMap<String, Value>,
+}
+
+impl StateMachine for KvStore {
+    type Command = KvPayload;
+    type Output = Result<KvPayload, ErrorPayload>;
+    type Snapshot = HashMap<String, Value>;
+
+    fn apply(&mut self, command: KvPayload) -> Self::Output {
+        match command {
+            KvPayload::Read { key } => match self.store.get(&key.to_string()) {
+                Some(value) => Ok(KvPayload::ReadOk {
+                    value: value.clone(),
+                }),
+                None => Err(error(
+                    ErrorCode::KeyDoesNotExist,
+                    format!("key {key} does not exist"),
+                )),
+            },
+            KvPayload::Write { key, value } => {
+                self.store.insert(key.to_string(), value);
+                Ok(KvPayload::WriteOk)
+            }
+            KvPayload::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.store.get_mut(&key.to_string()) {
+                Some(value) if *value == from => {
+                    *value = to;
+                    Ok(KvPayload::CasOk)
+                }
+                Some(value) => Err(error(
+                    ErrorCode::PreconditionFailed,
+                    format!("expected {from}, but had {value}"),
+                )),
+                None if create_if_not_exists => {
+                    self.store.insert(key.to_string(), to);
+                    Ok(KvPayload::CasOk)
+                }
+                None => Err(error(
+                    ErrorCode::KeyDoesNotExist,
+                    format!("key {key} does not exist"),
+                )),
+            },
+            other => Err(error(
+                ErrorCode::NotSupported,
+                format!("operation not supported: {other:?}"),
+            )),
+        }
+    }
+
+    fn snapshot(&self) -> Self::Snapshot {
+        self.store.clone()
+    }
+
+    fn restore(&mut self, snapshot: Self::Snapshot) {
+        self.store = snapshot;
+    }
+}
+
+/// # A Counter
+///
+/// Applies the `add` and `read` operations of the `pn-counter` workload, and returns their responses.
+///
+/// Unlike [`crate::crdt::PNCounter`], it's a plain integer: the log orders the additions,
+/// so reads are linearizable instead of eventually consistent.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Counter {
+    value: i64,
+}
+
+impl StateMachine for Counter {
+    type Command = PnCounterPayload;
+    type Output = Result<PnCounterPayload, ErrorPayload>;
+    type Snapshot = i64;
+
+    fn apply(&mut self, command: PnCounterPayload) -> Self::Output {
+        match command {
+            PnCounterPayload::Add { delta } => {
+                self.value += delta;
+                Ok(PnCounterPayload::AddOk)
+            }
+            PnCounterPayload::Read => Ok(PnCounterPayload::ReadOk { value: self.value }),
+            other => Err(error(
+                ErrorCode::NotSupported,
+                format!("operation not supported: {other:?}"),
+            )),
+        }
+    }
+
+    fn snapshot(&self) -> Self::Snapshot {
+        self.value
+    }
+
+    fn restore(&mut self, snapshot: Self::Snapshot) {
+        self.value = snapshot;
+    }
+}
+
+/// # A Consensus Log
+///
+/// The interface that [`Replica`] needs from a consensus module, so that the same state machines can be replicated
+/// with [`Raft`] or with [`MultiPaxos`], and the two compared under the same workloads and nemeses.
+pub trait Consensus {
+    /// The commands in the log.
+    type Command;
+    /// The messages between the nodes.
+    type Payload;
+
+    /// Creates a new consensus module for the node `node_id`, in a cluster of `node_ids`, which includes this node.
+    fn new(node_id: String, node_ids: Vec<String>) -> Self;
+
+    fn is_leader(&self) -> bool;
+
+    /// The current leader, if we know it; that may be ourselves.
+    fn leader_id(&self) -> Option<&str>;
+
+    /// Appends a new command to the log, if we are the leader; returns its index.
+    fn propose(&mut self, command: Self::Command) -> Option<usize>;
+
+    /// Takes the commands that have been committed since the last call, in log order.
+    fn take_committed(&mut self) -> Vec<Self::Command>;
+
+    /// Handles a message from the peer `src`.
+    fn handle(&mut self, src: String, payload: Self::Payload) -> Vec<(String, Self::Payload)>;
+
+    /// Advances the timers.
+    fn tick(&mut self) -> Vec<(String, Self::Payload)>;
+}
+
+impl<C: Clone> Consensus for Raft<C> {
+    type Command = C;
+    type Payload = RaftPayload<C>;
+
+    fn new(node_id: String, node_ids: Vec<String>) -> Self {
+        Raft::new(node_id, node_ids)
+    }
+
+    fn is_leader(&self) -> bool {
+        Raft::is_leader(self)
+    }
+
+    fn leader_id(&self) -> Option<&str> {
+        Raft::leader_id(self)
+    }
+
+    fn propose(&mut self, command: C) -> Option<usize> {
+        Raft::propose(self, command)
+    }
+
+    fn take_committed(&mut self) -> Vec<C> {
+        Raft::take_committed(self)
+    }
+
+    fn handle(&mut self, src: String, payload: RaftPayload<C>) -> Vec<(String, RaftPayload<C>)> {
+        Raft::handle(self, src, payload)
+    }
+
+    fn tick(&mut self) -> Vec<(String, RaftPayload<C>)> {
+        Raft::tick(self)
+    }
+}
+
+impl<C: Clone> Consensus for MultiPaxos<C> {
+    type Command = C;
+    type Payload = PaxosPayload<C>;
+
+    fn new(node_id: String, node_ids: Vec<String>) -> Self {
+        MultiPaxos::new(node_id, node_ids)
+    }
+
+    fn is_leader(&self) -> bool {
+        MultiPaxos::is_leader(self)
+    }
+
+    fn leader_id(&self) -> Option<&str> {
+        MultiPaxos::leader_id(self)
+    }
+
+    fn propose(&mut self, command: C) -> Option<usize> {
+        MultiPaxos::propose(self, command)
+    }
+
+    fn take_committed(&mut self) -> Vec<C> {
+        MultiPaxos::take_committed(self)
+    }
+
+    fn handle(&mut self, src: String, payload: PaxosPayload<C>) -> Vec<(String, PaxosPayload<C>)> {
+        MultiPaxos::handle(self, src, payload)
+    }
+
+    fn tick(&mut self) -> Vec<(String, PaxosPayload<C>)> {
+        MultiPaxos::tick(self)
+    }
+}
+
+/// The environment variable that selects the consensus backend: `raft` (the default) or `multi-paxos`.
+pub const CONSENSUS_VAR: &str = "CONSENSUS";
+
+/// The consensus backend of a [`Replica`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Raft,
+    MultiPaxos,
+}
+
+impl Backend {
+    /// Reads the backend from the [`CONSENSUS_VAR`] environment variable.
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(CONSENSUS_VAR).as_deref() {
+            Err(_) | Ok("raft") => Ok(Self::Raft),
+            Ok("multi-paxos") => Ok(Self::MultiPaxos),
+            Ok(other) => bail!("unknown consensus backend: {other}"),
+        }
+    }
+}
+
+/// A [`Replica`] of the state machine `S` that uses [`Raft`].
+pub type RaftReplica<S> = Replica<S, Raft<ClientCommand<<S as StateMachine>::Command>>>;
+
+/// A [`Replica`] of the state machine `S` that uses [`MultiPaxos`].
+pub type PaxosReplica<S> = Replica<S, MultiPaxos<ClientCommand<<S as StateMachine>::Command>>>;
+
+/// Runs the main loop of a [`Replica`] of the state machine `S`, with the backend selected by [`CONSENSUS_VAR`].
+pub fn replica_main_loop<S>() -> Result<()>
+where
+    S: StateMachine<Output = Result<<S as StateMachine>::Command, ErrorPayload>> + Debug + Default,
+    S::Command: Clone + Debug + Serialize + DeserializeOwned,
+{
+    match Backend::from_env()? {
+        Backend::Raft => main_loop::<RaftReplica<S>>(),
+        Backend::MultiPaxos => main_loop::<PaxosReplica<S>>(),
+    }
+}
+
+/// Responses to send: the client, the `msg_id` of its request, and the output of its command.
+type Outputs<S, L> = Vec<(
+    String,
+    Option<usize>,
+    ReplicaPayload<<S as StateMachine>::Command, <L as Consensus>::Payload>,
+)>;
+
+/// # A Replicated State Machine Node (Server)
+///
+/// Replicates the state machine `S` with the consensus module `L`. The commands of `S` are the workload's
+/// client requests, and its outputs are the responses to them, or errors.
+#[derive(Debug)]
+pub struct Replica<S, L> {
+    /// A unique node name. Maelstrom sets the node ID for our node(s), during the initialization phase.
+    pub node_id: Option<String>,
+    /// A locally-unique integer identifier for a message from a node. It isn't globally-unique.
+    pub msg_id: usize,
+    /// The consensus module; it's created during the initialization phase, when we learn about the cluster.
+    pub log: Option<L>,
+    /// The replicated state machine.
+    pub state: S,
+    /// Requests that we forwarded to the leader, by the `msg_id` of the forwarded request:
+    /// the original client and the original `msg_id`, so that we can relay the response.
+    pub forwarded: HashMap<usize, (String, Option<usize>)>,
+}
+
+impl<S, L> Replica<S, L>
+where
+    S: StateMachine<Output = Result<<S as StateMachine>::Command, ErrorPayload>> + Debug + Default,
+    S::Command: Clone + Debug + Serialize + DeserializeOwned,
+    L: Consensus<Command = ClientCommand<S::Command>> + Debug,
+    L::Payload: Debug + Serialize + DeserializeOwned,
+{
+    fn log(&mut self) -> &mut L {
+        self.log.as_mut().expect("expected some self.log")
+    }
+
+    /// Sends the consensus messages to their destinations.
+    fn send(
+        &mut self,
+        outbox: Vec<(String, L::Payload)>,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        for (dest, payload) in outbox {
+            self.request(
+                dest,
+                ReplicaPayload::Consensus(payload),
+                output_lock,
+                "consensus",
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies the newly-committed commands to the state machine, and returns the responses to the clients:
+    /// their node IDs, the `msg_id`s of their requests, and the outputs.
+    ///
+    /// Only the leader responds to the clients; the followers just keep their state machines up to date.
+    fn take_outputs(&mut self) -> Outputs<S, L> {
+        let mut outputs = Vec::new();
+        for command in self.log().take_committed() {
+            let payload = match self.state.apply(command.op) {
+                Ok(payload) => ReplicaPayload::Client(payload),
+                Err(error) => ReplicaPayload::Error(error),
+            };
+            if self.log().is_leader() {
+                outputs.push((command.client, command.msg_id, payload));
+            }
+        }
+        outputs
+    }
+
+    /// Applies the newly-committed commands to the state machine, and sends the responses to the clients.
+    fn apply_committed(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        for (client, msg_id, payload) in self.take_outputs() {
+            self.respond(client, msg_id, payload, output_lock, "output")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<S, L> Node for Replica<S, L>
+where
+    S: StateMachine<Output = Result<<S as StateMachine>::Command, ErrorPayload>> + Debug + Default,
+    S::Command: Clone + Debug + Serialize + DeserializeOwned,
+    L: Consensus<Command = ClientCommand<S::Command>> + Debug,
+    L::Payload: Debug + Serialize + DeserializeOwned,
+{
+    type Payload = ReplicaPayload<S::Command, L::Payload>;
+
+    fn new() -> Self {
+        Self {
+            node_id: None,
+            msg_id: 0,
+            log: None,
+            state: S::default(),
+            forwarded: HashMap::new(),
+        }
+    }
+
+    fn get_msg_id(&self) -> usize {
+        self.msg_id
+    }
+
+    fn incr_msg_id(&mut self) {
+        self.msg_id += 1;
+    }
+
+    fn get_node_id(&self) -> Option<String> {
+        self.node_id.clone()
+    }
+
+    fn set_node_id(&mut self, value: Option<String>) {
+        self.node_id = value;
+    }
+
+    fn set_node_ids(&mut self, node_ids: Vec<String>) {
+        let node_id = self.node_id.clone().expect("expected some self.node_id");
+        self.log = Some(L::new(node_id, node_ids));
+    }
+
+    fn step(
+        &mut self,
+        request: Message<Self::Payload>,
+        output_lock: &mut StdoutLock,
+    ) -> Result<()> {
+        match request.body.payload {
+            payload @ (ReplicaPayload::Client(_) | ReplicaPayload::Error(_))
+                if request.body.in_reply_to.is_some() =>
+            {
+                // A response to a request that we forwarded to the leader; relay it to the client.
+                let forwarded = request
+                    .body
+                    .in_reply_to
+                    .and_then(|in_reply_to| self.forwarded.remove(&in_reply_to));
+                if let Some((client, msg_id)) = forwarded {
+                    self.respond(client, msg_id, payload, output_lock, "relayed output")?;
+                }
+            }
+            ReplicaPayload::Client(op) => {
+                let command = ClientCommand {
+                    client: request.src,
+                    msg_id: request.body.msg_id,
+                    op,
+                };
+
+                if self.log().is_leader() {
+                    let command = ClientCommand {
+                        op: S::prepare(command.op),
+                        ..command
+                    };
+                    self.log().propose(command);
+                } else if let Some(leader) = self.log().leader_id().map(str::to_string) {
+                    self.forwarded
+                        .insert(self.msg_id, (command.client, command.msg_id));
+                    let payload = ReplicaPayload::Client(command.op);
+                    self.request(leader, payload, output_lock, "forwarded command")?;
+                } else {
+                    let payload = ReplicaPayload::Error(error(
+                        ErrorCode::TemporarilyUnavailable,
+                        "no leader is known".to_string(),
+                    ));
+                    self.respond(
+                        command.client,
+                        command.msg_id,
+                        payload,
+                        output_lock,
+                        "error",
+                    )?;
+                }
+
+                self.apply_committed(output_lock)?;
+            }
+            ReplicaPayload::Error(_) => {}
+            ReplicaPayload::Consensus(consensus_payload) => {
+                let outbox = self.log().handle(request.src, consensus_payload);
+                self.send(outbox, output_lock)?;
+                self.apply_committed(output_lock)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(TICK_INTERVAL)
+    }
+
+    fn tick(&mut self, output_lock: &mut StdoutLock) -> Result<()> {
+        let outbox = self.log().tick();
+        self.send(outbox, output_lock)?;
+        self.apply_committed(output_lock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: u64) -> Value {
+        Value::from(key)
+    }
+
+    #[test]
+    fn kv_store_reads_writes_and_compares_and_sets() {
+        let mut kv = KvStore::default();
+
+        let missing = kv.apply(KvPayload::Read { key: key(1) }).unwrap_err();
+        assert_eq!(missing.code, ErrorCode::KeyDoesNotExist);
+        assert!(matches!(
+            kv.apply(KvPayload::Write {
+                key: key(1),
+                value: Value::from(10),
+            }),
+            Ok(KvPayload::WriteOk)
+        ));
+        assert!(matches!(
+            kv.apply(KvPayload::Read { key: key(1) }),
+            Ok(KvPayload::ReadOk { value }) if value == 10
+        ));
+
+        let cas = |from: u64, to: u64, create_if_not_exists| KvPayload::Cas {
+            key: key(1),
+            from: Value::from(from),
+            to: Value::from(to),
+            create_if_not_exists,
+        };
+        let failed = kv.apply(cas(11, 12, false)).unwrap_err();
+        assert_eq!(failed.code, ErrorCode::PreconditionFailed);
+        assert!(matches!(kv.apply(cas(10, 12, false)), Ok(KvPayload::CasOk)));
+        assert!(matches!(
+            kv.apply(KvPayload::Read { key: key(1) }),
+            Ok(KvPayload::ReadOk { value }) if value == 12
+        ));
+
+        // Only a missing key can be created.
+        let absent = |create_if_not_exists| KvPayload::Cas {
+            key: key(2),
+            from: Value::from(0),
+            to: Value::from(1),
+            create_if_not_exists,
+        };
+        let missing = kv.apply(absent(false)).unwrap_err();
+        assert_eq!(missing.code, ErrorCode::KeyDoesNotExist);
+        assert!(matches!(kv.apply(absent(true)), Ok(KvPayload::CasOk)));
+        let failed = kv.apply(absent(true)).unwrap_err();
+        assert_eq!(failed.code, ErrorCode::PreconditionFailed);
+
+        let unsupported = kv.apply(KvPayload::WriteOk).unwrap_err();
+        assert_eq!(unsupported.code, ErrorCode::NotSupported);
+    }
+
+    #[test]
+    fn kv_store_restores_its_snapshots() {
+        let mut kv = KvStore::default();
+        kv.apply(KvPayload::Write {
+            key: key(1),
+            value: Value::from(10),
+        })
+        .unwrap();
+        let snapshot = kv.snapshot();
+
+        let mut restored = KvStore::default();
+        restored.restore(snapshot);
+        assert!(matches!(
+            restored.apply(KvPayload::Read { key: key(1) }),
+            Ok(KvPayload::ReadOk { value }) if value == 10
+        ));
+    }
+
+    #[test]
+    fn counter_adds_and_reads() {
+        let mut counter = Counter::default();
+        for delta in [5, -7, 3] {
+            assert!(matches!(
+                counter.apply(PnCounterPayload::Add { delta }),
+                Ok(PnCounterPayload::AddOk)
+            ));
+        }
+        assert!(matches!(
+            counter.apply(PnCounterPayload::Read),
+            Ok(PnCounterPayload::ReadOk { value: 1 })
+        ));
+        assert_eq!(counter.snapshot(), 1);
+
+        let unsupported = counter.apply(PnCounterPayload::AddOk).unwrap_err();
+        assert_eq!(unsupported.code, ErrorCode::NotSupported);
+    }
+
+    /// A consensus log whose leadership and commits the test decides.
+    #[derive(Debug, Default)]
+    struct ScriptedLog {
+        leader: bool,
+        committed: Vec<ClientCommand<KvPayload>>,
+    }
+
+    impl Consensus for ScriptedLog {
+        type Command = ClientCommand<KvPayload>;
+        type Payload = ();
+
+        fn new(_: String, _: Vec<String>) -> Self {
+            Self::default()
+        }
+
+        fn is_leader(&self) -> bool {
+            self.leader
+        }
+
+        fn leader_id(&self) -> Option<&str> {
+            None
+        }
+
+        fn propose(&mut self, _: Self::Command) -> Option<usize> {
+            None
+        }
+
+        fn take_committed(&mut self) -> Vec<Self::Command> {
+            std::mem::take(&mut self.committed)
+        }
+
+        fn handle(&mut self, _: String, _: ()) -> Vec<(String, ())> {
+            Vec::new()
+        }
+
+        fn tick(&mut self) -> Vec<(String, ())> {
+            Vec::new()
+        }
+    }
+
+    fn write(msg_id: usize, value: u64) -> ClientCommand<KvPayload> {
+        ClientCommand {
+            client: "c1".to_string(),
+            msg_id: Some(msg_id),
+            op: KvPayload::Write {
+                key: key(1),
+                value: Value::from(value),
+            },
+        }
+    }
+
+    #[test]
+    fn only_the_leader_at_apply_time_responds() {
+        let mut replica: Replica<KvStore, ScriptedLog> = Node::new();
+        replica.log = Some(ScriptedLog::default());
+
+        // A follower applies the commands, but doesn't respond.
+        replica.log().committed = vec![write(1, 10)];
+        assert!(replica.take_outputs().is_empty());
+        assert!(matches!(
+            replica.state.apply(KvPayload::Read { key: key(1) }),
+            Ok(KvPayload::ReadOk { value }) if value == 10
+        ));
+
+        // Once it's the leader, it responds, even to commands that an earlier leader proposed.
+        replica.log().leader = true;
+        replica.log().committed = vec![
+            write(2, 20),
+            ClientCommand {
+                client: "c2".to_string(),
+                msg_id: Some(3),
+                op: KvPayload::Read { key: key(2) },
+            },
+        ];
+        let outputs = replica.take_outputs();
+        assert!(matches!(
+            &outputs[..],
+            [
+                (c1, Some(2), ReplicaPayload::Client(KvPayload::WriteOk)),
+                (c2, Some(3), ReplicaPayload::Error(error)),
+            ] if c1 == "c1" && c2 == "c2" && error.code == ErrorCode::KeyDoesNotExist
+        ));
+
+        // And it stops as soon as it isn't the leader anymore.
+        replica.log().leader = false;
+        replica.log().committed = vec![write(4, 30)];
+        assert!(replica.take_outputs().is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+//! # Total-Order Broadcast
+//!
+//! Also known as atomic broadcast: every node delivers the same values, in the same order.
+//! It's the building block of replicated state machines.
+//!
+//! The order is agreed on with [`crate::raft`]: the leader appends the values to its log,
+//! and every node delivers them as they are committed, in log order.
+//!
+//! A value can be broadcast through any node. It stays pending there until it has been delivered,
+//! and meanwhile, it's periodically forwarded to the leader that the node knows of, if that's another node.
+//! So, values survive lost messages and leader changes; a value that ends up in the log twice,
+//! e.g., because a new leader got it again before learning that it was already committed, is delivered only once.
+//!
+//! [`check_prefixes()`] checks that sequences of delivered values, as read from different nodes
+//! at different times, are consistent with a total order.
+
+use crate::message::RaftPayload;
+use crate::raft::{Outbox, Raft};
+use anyhow::{bail, Result};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// How often a node forwards its pending values to the leader.
+pub const FORWARD_INTERVAL: Duration = Duration::from_millis(100);
+
+/// # A Total-Order Broadcast Module
+#[derive(Debug)]
+pub struct TotalOrderBroadcast {
+    raft: Raft<usize>,
+    /// The delivered values, in the order of delivery.
+    delivered: Vec<usize>,
+    /// The same values, for lookups.
+    delivered_set: HashSet<usize>,
+    /// The values that have been broadcast through this node, or forwarded to it, but haven't been delivered yet.
+    pending: Vec<usize>,
+    /// The pending values that this node has proposed as the leader, in the term `proposed_term`.
+    proposed: HashSet<usize>,
+    proposed_term: usize,
+    /// When the pending values were last forwarded to the leader.
+    last_forward: Instant,
+}
+
+impl TotalOrderBroadcast {
+    /// Creates a new total-order broadcast module for the node `node_id`, in a cluster of `node_ids`,
+    /// which includes this node.
+    pub fn new(node_id: String, node_ids: Vec<String>) -> Self {
+        Self {
+            raft: Raft::new(node_id, node_ids),
+            delivered: Vec::new(),
+            delivered_set: HashSet::new(),
+            pending: Vec::new(),
+            proposed: HashSet::new(),
+            proposed_term: 0,
+            last_forward: Instant::now(),
+        }
+    }
+
+    /// The delivered values, in the order of delivery, which is the same on every node.
+    pub fn delivered(&self) -> &[usize] {
+        &self.delivered
+    }
+
+    /// Broadcasts values, which were sent to this node by a client or forwarded by another node.
+    pub fn broadcast(&mut self, values: impl IntoIterator<Item = usize>) {
+        for value in values {
+            if !self.delivered_set.contains(&value) && !self.pending.contains(&value) {
+                self.pending.push(value);
+            }
+        }
+        self.propose_pending();
+    }
+
+    /// Handles a Raft message from the node `src`.
+    pub fn handle(&mut self, src: String, payload: RaftPayload<usize>) -> Outbox<usize> {
+        let outbox = self.raft.handle(src, payload);
+        self.deliver_committed();
+        outbox
+    }
+
+    /// Ticks the Raft module, and delivers the newly-committed values.
+    pub fn tick(&mut self) -> Outbox<usize> {
+        let outbox = self.raft.tick();
+        self.deliver_committed();
+        self.propose_pending();
+        outbox
+    }
+
+    /// The pending values to forward to the leader, if they are due, and if the leader is another known node.
+    pub fn forward(&mut self) -> Option<(String, Vec<usize>)> {
+        if self.pending.is_empty()
+            || self.raft.is_leader()
+            || self.last_forward.elapsed() < FORWARD_INTERVAL
+        {
+            return None;
+        }
+
+        let leader = self.raft.leader_id()?.to_string();
+        self.last_forward = Instant::now();
+        Some((leader, self.pending.clone()))
+    }
+
+    /// As the leader, appends the pending values that haven't been proposed in this term yet to the log.
+    fn propose_pending(&mut self) {
+        if !self.raft.is_leader() {
+            return;
+        }
+        if self.proposed_term != self.raft.current_term() {
+            self.proposed_term = self.raft.current_term();
+            self.proposed.clear();
+        }
+
+        for &value in &self.pending {
+            if self.proposed.insert(value) {
+                self.raft.propose(value);
+            }
+        }
+    }
+
+    fn deliver_committed(&mut self) {
+        for value in self.raft.take_committed() {
+            if self.delivered_set.insert(value) {
+                self.delivered.push(value);
+            }
+            self.pending.retain(|&pending| pending != value);
+        }
+    }
+}
+
+/// Checks that sequences of delivered values are consistent with a total order: that each of them
+/// is a prefix of the longest one, which also makes any two of them prefixes of one another.
+pub fn check_prefixes<'a>(deliveries: impl IntoIterator<Item = &'a [usize]>) -> Result<()> {
+    let deliveries: Vec<&[usize]> = deliveries.into_iter().collect();
+    let Some(longest) = deliveries.iter().max_by_key(|delivery| delivery.len()) else {
+        return Ok(());
+    };
+
+    for (index, delivery) in deliveries.iter().enumerate() {
+        if let Some(position) = delivery
+            .iter()
+            .zip(longest.iter())
+            .position(|(ours, theirs)| ours != theirs)
+        {
+            bail!(
+                "delivery #{index} diverges at position {position}: {} instead of {}",
+                delivery[position],
+                longest[position]
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::LogEntry;
+
+    #[test]
+    fn prefixes_of_one_another_are_consistent() {
+        let deliveries: [&[usize]; 4] = [&[], &[3, 1], &[3, 1, 4, 1, 5], &[3, 1, 4]];
+        assert!(check_prefixes(deliveries).is_ok());
+        assert!(check_prefixes([]).is_ok());
+    }
+
+    #[test]
+    fn diverging_deliveries_are_inconsistent() {
+        let deliveries: [&[usize]; 3] = [&[3, 1, 4, 1, 5], &[3, 1], &[3, 4, 1]];
+        let error = check_prefixes(deliveries).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "delivery #2 diverges at position 1: 4 instead of 1"
+        );
+
+        // Equally long, but in another order.
+        let deliveries: [&[usize]; 2] = [&[1, 2], &[2, 1]];
+        assert!(check_prefixes(deliveries).is_err());
+    }
+
+    fn append_entries(
+        prev_log_index: usize,
+        commands: &[usize],
+        leader_commit: usize,
+    ) -> RaftPayload<usize> {
+        RaftPayload::AppendEntries {
+            term: 1,
+            leader_id: "n0".to_string(),
+            prev_log_index,
+            prev_log_term: if prev_log_index == 0 { 0 } else { 1 },
+            entries: commands
+                .iter()
+                .map(|&command| LogEntry { term: 1, command })
+                .collect(),
+            leader_commit,
+        }
+    }
+
+    #[test]
+    fn committed_values_are_delivered_once_in_log_order() {
+        let node_ids = vec!["n0".to_string(), "n1".to_string(), "n2".to_string()];
+        let mut n1 = TotalOrderBroadcast::new("n1".to_string(), node_ids);
+        n1.broadcast([7, 3]);
+
+        // 3 is in the log twice, e.g., because it was forwarded to two leaders in turn.
+        n1.handle("n0".to_string(), append_entries(0, &[3, 1, 3], 2));
+        assert_eq!(n1.delivered(), [3, 1]);
+        n1.handle("n0".to_string(), append_entries(3, &[7], 4));
+        assert_eq!(n1.delivered(), [3, 1, 7]);
+        assert!(check_prefixes([n1.delivered(), &[3, 1, 7, 9][..]]).is_ok());
+
+        // Delivered values aren't pending anymore, so there's nothing to forward to the leader.
+        n1.last_forward -= FORWARD_INTERVAL;
+        assert_eq!(n1.forward(), None);
+        n1.broadcast([1, 9]);
+        assert_eq!(n1.forward(), Some(("n0".to_string(), vec![9])));
+    }
+}
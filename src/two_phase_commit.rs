@@ -0,0 +1,777 @@
+//! # Two-Phase Commit
+//!
+//! Atomic commitment of `txn-list-append` transactions whose keys are sharded across the nodes:
+//! every key belongs to one node, its shard, by consistent hashing (see [`crate::ring`]).
+//!
+//! The node that receives a transaction coordinates it:
+//! 1. It sends every participant, i.e., every shard of the transaction's keys, its share of the micro-operations.
+//!    A participant locks the keys, executes the micro-operations on a copy of its lists, and votes yes,
+//!    with the values of its reads; if any of the keys is locked by another transaction, it votes no.
+//!    A lock is never waited for, so there are no deadlocks.
+//! 2. If all participants vote yes, the coordinator records the decision to commit in `lin-kv`, responds to the client,
+//!    and tells the participants to commit: to apply the appends, and to unlock the keys. Otherwise, or if the votes
+//!    don't come in within [`PREPARE_TIMEOUT`], it tells them to abort, and the client gets a `txn-conflict` error
+//!    or an `abort` error.
+//!
+//! [Two-Phase Commit](https://en.wikipedia.org/wiki/Two-phase_commit_protocol)
+//!
+//! The keys stay locked from the vote to the decision, so the transactions are strictly serializable.
+//!
+//! A participant that has voted yes, but hasn't heard the decision within [`RESOLVE_TIMEOUT`], e.g., because
+//! the coordinator is partitioned away, doesn't block: it records a decision to abort in `lin-kv` instead.
+//! Decisions are recorded with a compare-and-set that only creates the record, so only one of the two
+//! decisions is ever recorded, and whoever loses learns the other one. A coordinator whose commit loses
+//! aborts the transaction, too. Read-only transactions commit without a record, because both decisions
+//! have the same effect on them.
+//!
+//! The node records the decisions in `lin-kv`, and reports them with [`TwoPhaseCommit::on_recorded()`].
+
+use crate::message::{Decision, ErrorCode, ErrorPayload, MicroOp, TwoPhasePayload, TxnPayload};
+use crate::ring::{Ring, DEFAULT_VNODES};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// After how long a coordinator that hasn't got all votes aborts the transaction.
+pub const PREPARE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// After how long a participant that hasn't heard the decision records a decision to abort.
+pub const RESOLVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// After how long a decision that hasn't been confirmed as recorded is recorded again.
+pub const RECORD_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How often a decision is sent to the participants that haven't acknowledged it.
+pub const RESEND_INTERVAL: Duration = Duration::from_millis(200);
+
+/// For how long a participant remembers a transaction that it has finished, to refuse a late `prepare` for it.
+/// A `prepare` that is later than that is assumed to not arrive at all.
+pub const FINISHED_RETENTION: Duration = Duration::from_secs(10);
+
+/// Messages to send: pairs of destination node IDs and payloads.
+pub type Outbox = Vec<(String, TwoPhasePayload)>;
+
+/// Responses to send: the client, the `msg_id` of its request, and the response.
+pub type Replies = Vec<(String, Option<usize>, Result<TxnPayload, ErrorPayload>)>;
+
+/// Decisions to record in `lin-kv`, under [`record_key()`], with a compare-and-set that only creates the record:
+/// `from` and `to` are both the decision, and `create_if_not_exists` is set.
+pub type Records = Vec<(String, Decision)>;
+
+/// The key of a transaction's decision record in `lin-kv`.
+pub fn record_key(txn_id: &str) -> String {
+    format!("txn-{txn_id}")
+}
+
+fn error(code: ErrorCode, text: String) -> ErrorPayload {
+    ErrorPayload {
+        code,
+        text: Some(text),
+    }
+}
+
+/// Where a coordinated transaction stands.
+#[derive(Clone, Debug)]
+enum Phase {
+    /// Collecting the votes.
+    Preparing,
+    /// Waiting for the decision to commit to be recorded; since when we last asked for it.
+    Recording { since: Instant },
+    /// Telling the participants the decision; the ones that have acknowledged it.
+    Deciding {
+        decision: Decision,
+        acked: HashSet<String>,
+    },
+}
+
+/// A transaction that we coordinate.
+#[derive(Clone, Debug)]
+struct Coordination {
+    client: String,
+    msg_id: Option<usize>,
+    ops: Vec<MicroOp>,
+    /// Every participant's share of the micro-operations, in the transaction's order.
+    shares: BTreeMap<String, Vec<MicroOp>>,
+    /// The participants' executed shares, from their yes votes.
+    votes: HashMap<String, Vec<MicroOp>>,
+    phase: Phase,
+    started: Instant,
+    /// When we last sent the participants the decision.
+    last_send: Instant,
+}
+
+/// A transaction that we have voted yes for, as a participant.
+#[derive(Clone, Debug)]
+struct Prepared {
+    coordinator: String,
+    /// Our executed share of the micro-operations, as sent with our vote.
+    ops: Vec<MicroOp>,
+    /// The new lists of the keys that the transaction appends to.
+    writes: HashMap<usize, Vec<usize>>,
+    /// The keys that we have locked.
+    keys: Vec<usize>,
+    since: Instant,
+    /// When we last asked for a decision to abort to be recorded; `None` if we haven't.
+    resolving: Option<Instant>,
+}
+
+/// # A Two-Phase Commit Module
+///
+/// Both a coordinator of the transactions that the node receives, and a participant, as the shard of its keys.
+#[derive(Debug)]
+pub struct TwoPhaseCommit {
+    /// Our own node ID.
+    node_id: String,
+    /// The ring of all nodes, which keys are consistently hashed onto.
+    ring: Ring,
+    /// Our shard: the lists of the keys that belong to us.
+    store: HashMap<usize, Vec<usize>>,
+    /// The keys that are locked, and the transactions that hold them.
+    locks: HashMap<usize, String>,
+    /// For locally-unique transaction IDs.
+    next_txn: usize,
+    coordinating: HashMap<String, Coordination>,
+    prepared: HashMap<String, Prepared>,
+    /// The transactions that we have committed or aborted as a participant, and when,
+    /// so that late `prepare`s are refused.
+    finished: HashMap<String, Instant>,
+}
+
+impl TwoPhaseCommit {
+    /// Creates a new two-phase commit module for the node `node_id`, in a cluster of `node_ids`,
+    /// which includes this node.
+    pub fn new(node_id: String, node_ids: &[String]) -> Self {
+        Self {
+            node_id,
+            ring: Ring::with_nodes(node_ids.iter().cloned(), DEFAULT_VNODES),
+            store: HashMap::new(),
+            locks: HashMap::new(),
+            next_txn: 0,
+            coordinating: HashMap::new(),
+            prepared: HashMap::new(),
+            finished: HashMap::new(),
+        }
+    }
+
+    /// The shard of `key`.
+    pub fn shard(&self, key: usize) -> &str {
+        self.ring
+            .owner(&key.to_string())
+            .expect("expected a non-empty ring")
+    }
+
+    /// Queues a message; one to ourselves is handled at once.
+    fn send(
+        &mut self,
+        dest: String,
+        payload: TwoPhasePayload,
+        outbox: &mut Outbox,
+        replies: &mut Replies,
+        records: &mut Records,
+    ) {
+        if dest == self.node_id {
+            let src = self.node_id.clone();
+            let more = self.handle(src, payload, replies, records);
+            outbox.extend(more);
+        } else {
+            outbox.push((dest, payload));
+        }
+    }
+
+    /// Coordinates a client transaction: sends every participant its share of the micro-operations.
+    pub fn begin(
+        &mut self,
+        client: String,
+        msg_id: Option<usize>,
+        ops: Vec<MicroOp>,
+        replies: &mut Replies,
+        records: &mut Records,
+    ) -> Outbox {
+        self.next_txn += 1;
+        let txn_id = format!("{}-{}", self.node_id, self.next_txn);
+
+        let mut shares: BTreeMap<String, Vec<MicroOp>> = BTreeMap::new();
+        for op in &ops {
+            let shard = self.shard(op.key()).to_string();
+            shares.entry(shard).or_default().push(op.clone());
+        }
+        self.coordinating.insert(
+            txn_id.clone(),
+            Coordination {
+                client,
+                msg_id,
+                ops,
+                shares: shares.clone(),
+                votes: HashMap::new(),
+                phase: Phase::Preparing,
+                started: Instant::now(),
+                last_send: Instant::now(),
+            },
+        );
+
+        let mut outbox = Vec::new();
+        if shares.is_empty() {
+            // Nothing to prepare.
+            self.on_votes(txn_id.clone(), &mut outbox, replies, records);
+        }
+        for (participant, ops) in shares {
+            let payload = TwoPhasePayload::Prepare {
+                txn_id: txn_id.clone(),
+                ops,
+            };
+            self.send(participant, payload, &mut outbox, replies, records);
+        }
+        outbox
+    }
+
+    /// Handles a two-phase commit message from the node `src`.
+    pub fn handle(
+        &mut self,
+        src: String,
+        payload: TwoPhasePayload,
+        replies: &mut Replies,
+        records: &mut Records,
+    ) -> Outbox {
+        let mut outbox = Vec::new();
+        match payload {
+            TwoPhasePayload::Prepare { txn_id, ops } => {
+                let vote = self.prepare(src.clone(), txn_id, ops);
+                self.send(src, vote, &mut outbox, replies, records);
+            }
+            TwoPhasePayload::Prepared { txn_id, ops } => {
+                let Some(coordination) = self.coordinating.get_mut(&txn_id) else {
+                    // We gave up on the transaction already.
+                    let payload = TwoPhasePayload::Abort { txn_id };
+                    self.send(src, payload, &mut outbox, replies, records);
+                    return outbox;
+                };
+                if !matches!(coordination.phase, Phase::Preparing) {
+                    return outbox;
+                }
+                coordination.votes.insert(src, ops);
+                if coordination.votes.len() == coordination.shares.len() {
+                    self.on_votes(txn_id, &mut outbox, replies, records);
+                }
+            }
+            TwoPhasePayload::Refused { txn_id } => {
+                let is_preparing = self
+                    .coordinating
+                    .get(&txn_id)
+                    .is_some_and(|coordination| matches!(coordination.phase, Phase::Preparing));
+                if is_preparing {
+                    let text = "a key is locked by another transaction".to_string();
+                    let error = error(ErrorCode::TxnConflict, text);
+                    self.decide(
+                        txn_id,
+                        Decision::Abort,
+                        Err(error),
+                        &mut outbox,
+                        replies,
+                        records,
+                    );
+                }
+            }
+            TwoPhasePayload::Commit { txn_id } => {
+                self.finish(&txn_id, Decision::Commit);
+                let payload = TwoPhasePayload::Decided { txn_id };
+                self.send(src, payload, &mut outbox, replies, records);
+            }
+            TwoPhasePayload::Abort { txn_id } => {
+                self.finish(&txn_id, Decision::Abort);
+                let payload = TwoPhasePayload::Decided { txn_id };
+                self.send(src, payload, &mut outbox, replies, records);
+            }
+            TwoPhasePayload::Decided { txn_id } => {
+                let Some(coordination) = self.coordinating.get_mut(&txn_id) else {
+                    return outbox;
+                };
+                if let Phase::Deciding { acked, .. } = &mut coordination.phase {
+                    acked.insert(src);
+                    if acked.len() >= coordination.shares.len() {
+                        self.coordinating.remove(&txn_id);
+                    }
+                }
+            }
+        }
+        outbox
+    }
+
+    /// Votes on a transaction, as a participant: locks the keys and executes our share of the micro-operations.
+    fn prepare(
+        &mut self,
+        coordinator: String,
+        txn_id: String,
+        ops: Vec<MicroOp>,
+    ) -> TwoPhasePayload {
+        if let Some(prepared) = self.prepared.get(&txn_id) {
+            // A retransmission.
+            return TwoPhasePayload::Prepared {
+                txn_id,
+                ops: prepared.ops.clone(),
+            };
+        }
+        let locked = ops.iter().any(|op| {
+            self.locks
+                .get(&op.key())
+                .is_some_and(|holder| *holder != txn_id)
+        });
+        if locked || self.finished.contains_key(&txn_id) {
+            return TwoPhasePayload::Refused { txn_id };
+        }
+
+        let mut keys = Vec::new();
+        let mut writes: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut executed = Vec::with_capacity(ops.len());
+        for op in ops {
+            let key = op.key();
+            if !keys.contains(&key) {
+                keys.push(key);
+                self.locks.insert(key, txn_id.clone());
+            }
+            let current = writes.get(&key).or_else(|| self.store.get(&key));
+            executed.push(match op {
+                MicroOp::Read { key, .. } => MicroOp::Read {
+                    key,
+                    value: current.cloned(),
+                },
+                MicroOp::Append { key, element } => {
+                    let mut list = current.cloned().unwrap_or_default();
+                    list.push(element);
+                    writes.insert(key, list);
+                    MicroOp::Append { key, element }
+                }
+            });
+        }
+
+        self.prepared.insert(
+            txn_id.clone(),
+            Prepared {
+                coordinator,
+                ops: executed.clone(),
+                writes,
+                keys,
+                since: Instant::now(),
+                resolving: None,
+            },
+        );
+        TwoPhasePayload::Prepared {
+            txn_id,
+            ops: executed,
+        }
+    }
+
+    /// Commits or aborts a transaction that we have voted on, as a participant, and unlocks its keys.
+    fn finish(&mut self, txn_id: &str, decision: Decision) {
+        self.finished.insert(txn_id.to_string(), Instant::now());
+        let Some(prepared) = self.prepared.remove(txn_id) else {
+            return;
+        };
+        if decision == Decision::Commit {
+            self.store.extend(prepared.writes);
+        }
+        for key in prepared.keys {
+            self.locks.remove(&key);
+        }
+    }
+
+    /// Acts on the votes of all participants, which are all yes.
+    fn on_votes(
+        &mut self,
+        txn_id: String,
+        outbox: &mut Outbox,
+        replies: &mut Replies,
+        records: &mut Records,
+    ) {
+        let coordination = self
+            .coordinating
+            .get_mut(&txn_id)
+            .expect("expected a coordinated transaction");
+        let read_only = coordination
+            .ops
+            .iter()
+            .all(|op| matches!(op, MicroOp::Read { .. }));
+        if read_only {
+            let txn = self.executed(&txn_id);
+            self.decide(txn_id, Decision::Commit, Ok(txn), outbox, replies, records);
+        } else {
+            coordination.phase = Phase::Recording {
+                since: Instant::now(),
+            };
+            records.push((txn_id, Decision::Commit));
+        }
+    }
+
+    /// The coordinated transaction, with the values of the reads filled in from the participants' votes.
+    fn executed(&self, txn_id: &str) -> TxnPayload {
+        let coordination = &self.coordinating[txn_id];
+        let mut votes: HashMap<&str, std::slice::Iter<MicroOp>> = coordination
+            .votes
+            .iter()
+            .map(|(participant, ops)| (participant.as_str(), ops.iter()))
+            .collect();
+        let txn = coordination
+            .ops
+            .iter()
+            .map(|op| {
+                let shard = self.shard(op.key());
+                votes
+                    .get_mut(shard)
+                    .and_then(Iterator::next)
+                    .cloned()
+                    .unwrap_or_else(|| op.clone())
+            })
+            .collect();
+        TxnPayload::TxnOk { txn }
+    }
+
+    /// Decides a coordinated transaction: responds to the client, and tells the participants.
+    fn decide(
+        &mut self,
+        txn_id: String,
+        decision: Decision,
+        response: Result<TxnPayload, ErrorPayload>,
+        outbox: &mut Outbox,
+        replies: &mut Replies,
+        records: &mut Records,
+    ) {
+        let Some(coordination) = self.coordinating.get_mut(&txn_id) else {
+            return;
+        };
+        replies.push((coordination.client.clone(), coordination.msg_id, response));
+        coordination.phase = Phase::Deciding {
+            decision,
+            acked: HashSet::new(),
+        };
+        coordination.last_send = Instant::now();
+
+        let participants: Vec<String> = coordination.shares.keys().cloned().collect();
+        for participant in participants {
+            let payload = match decision {
+                Decision::Commit => TwoPhasePayload::Commit {
+                    txn_id: txn_id.clone(),
+                },
+                Decision::Abort => TwoPhasePayload::Abort {
+                    txn_id: txn_id.clone(),
+                },
+            };
+            self.send(participant, payload, outbox, replies, records);
+        }
+    }
+
+    /// Handles a decision that is recorded in `lin-kv` for the transaction `txn_id`: ours, if the compare-and-set
+    /// succeeded, or the other one, if it failed its precondition.
+    pub fn on_recorded(
+        &mut self,
+        txn_id: String,
+        decision: Decision,
+        replies: &mut Replies,
+        records: &mut Records,
+    ) -> Outbox {
+        let mut outbox = Vec::new();
+
+        if self.prepared.contains_key(&txn_id) {
+            // We may be the coordinator as well; it's told the decision below.
+            self.finish(&txn_id, decision);
+        }
+
+        let is_recording = self
+            .coordinating
+            .get(&txn_id)
+            .is_some_and(|coordination| matches!(coordination.phase, Phase::Recording { .. }));
+        if is_recording {
+            let response = match decision {
+                Decision::Commit => Ok(self.executed(&txn_id)),
+                Decision::Abort => Err(error(
+                    ErrorCode::Abort,
+                    "a participant aborted the transaction before it could commit".to_string(),
+                )),
+            };
+            self.decide(txn_id, decision, response, &mut outbox, replies, records);
+        }
+        outbox
+    }
+
+    /// Whether a decision for the transaction `txn_id` is being recorded: whether [`Self::on_recorded()`]
+    /// still expects to hear about it.
+    pub fn is_recording(&self, txn_id: &str) -> bool {
+        let coordinating = self
+            .coordinating
+            .get(txn_id)
+            .is_some_and(|coordination| matches!(coordination.phase, Phase::Recording { .. }));
+        let resolving = self
+            .prepared
+            .get(txn_id)
+            .is_some_and(|prepared| prepared.resolving.is_some());
+        coordinating || resolving
+    }
+
+    /// Aborts the coordinated transactions whose votes haven't come in, sends decisions again to the participants
+    /// that haven't acknowledged them, records decisions again, or for the first time for the prepared
+    /// transactions that haven't been decided, and forgets the transactions that finished long ago.
+    pub fn tick(&mut self, replies: &mut Replies, records: &mut Records) -> Outbox {
+        let mut outbox = Vec::new();
+
+        let timed_out: Vec<String> = self
+            .coordinating
+            .iter()
+            .filter(|(_, coordination)| {
+                matches!(coordination.phase, Phase::Preparing)
+                    && coordination.started.elapsed() >= PREPARE_TIMEOUT
+            })
+            .map(|(txn_id, _)| txn_id.clone())
+            .collect();
+        for txn_id in timed_out {
+            let text = format!("not all participants voted within {PREPARE_TIMEOUT:?}");
+            let error = error(ErrorCode::Abort, text);
+            self.decide(
+                txn_id,
+                Decision::Abort,
+                Err(error),
+                &mut outbox,
+                replies,
+                records,
+            );
+        }
+
+        let mut resend = Vec::new();
+        for (txn_id, coordination) in &mut self.coordinating {
+            match &coordination.phase {
+                Phase::Recording { since } if since.elapsed() >= RECORD_TIMEOUT => {
+                    coordination.phase = Phase::Recording {
+                        since: Instant::now(),
+                    };
+                    records.push((txn_id.clone(), Decision::Commit));
+                }
+                Phase::Deciding { decision, acked }
+                    if coordination.last_send.elapsed() >= RESEND_INTERVAL =>
+                {
+                    coordination.last_send = Instant::now();
+                    for participant in coordination.shares.keys() {
+                        if !acked.contains(participant) {
+                            resend.push((participant.clone(), txn_id.clone(), *decision));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (participant, txn_id, decision) in resend {
+            let payload = match decision {
+                Decision::Commit => TwoPhasePayload::Commit { txn_id },
+                Decision::Abort => TwoPhasePayload::Abort { txn_id },
+            };
+            self.send(participant, payload, &mut outbox, replies, records);
+        }
+
+        for (txn_id, prepared) in &mut self.prepared {
+            // A coordinator that is ourselves records its own decision.
+            let in_doubt = prepared.coordinator != self.node_id
+                && prepared.since.elapsed() >= RESOLVE_TIMEOUT
+                && prepared
+                    .resolving
+                    .is_none_or(|since| since.elapsed() >= RECORD_TIMEOUT);
+            if in_doubt {
+                prepared.resolving = Some(Instant::now());
+                records.push((txn_id.clone(), Decision::Abort));
+            }
+        }
+
+        self.finished
+            .retain(|_, finished| finished.elapsed() < FINISHED_RETENTION);
+
+        outbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes() -> Vec<TwoPhaseCommit> {
+        let node_ids: Vec<String> = (0..3).map(|i| format!("n{i}")).collect();
+        node_ids
+            .iter()
+            .map(|node_id| TwoPhaseCommit::new(node_id.clone(), &node_ids))
+            .collect()
+    }
+
+    /// A key whose shard is `node_id`.
+    fn key_of(two_phase_commit: &TwoPhaseCommit, node_id: &str) -> usize {
+        (0..)
+            .find(|key| two_phase_commit.shard(*key) == node_id)
+            .unwrap()
+    }
+
+    fn append(key: usize, element: usize) -> Vec<MicroOp> {
+        vec![MicroOp::Append { key, element }]
+    }
+
+    fn txn_id(payload: &TwoPhasePayload) -> &str {
+        match payload {
+            TwoPhasePayload::Prepare { txn_id, .. }
+            | TwoPhasePayload::Prepared { txn_id, .. }
+            | TwoPhasePayload::Refused { txn_id }
+            | TwoPhasePayload::Commit { txn_id }
+            | TwoPhasePayload::Abort { txn_id }
+            | TwoPhasePayload::Decided { txn_id } => txn_id,
+        }
+    }
+
+    fn backdate(instant: &mut Instant, by: Duration) {
+        *instant = instant.checked_sub(by).unwrap();
+    }
+
+    #[test]
+    fn a_locked_key_refuses_the_transaction_with_a_conflict() {
+        let [mut n0, mut n1, mut n2] = nodes().try_into().unwrap();
+        let key = key_of(&n1, "n1");
+        let (mut replies, mut records) = (Replies::new(), Records::new());
+
+        let [(_, first)] = <[_; 1]>::try_from(n0.begin(
+            "c0".to_string(),
+            Some(1),
+            append(key, 1),
+            &mut replies,
+            &mut records,
+        ))
+        .unwrap();
+        let [(_, second)] = <[_; 1]>::try_from(n2.begin(
+            "c2".to_string(),
+            Some(1),
+            append(key, 2),
+            &mut replies,
+            &mut records,
+        ))
+        .unwrap();
+
+        let vote = n1.handle("n0".to_string(), first.clone(), &mut replies, &mut records);
+        assert!(matches!(vote[..], [(_, TwoPhasePayload::Prepared { .. })]));
+        let vote = n1.handle("n2".to_string(), second, &mut replies, &mut records);
+        let [(_, refused @ TwoPhasePayload::Refused { .. })] = <[_; 1]>::try_from(vote).unwrap()
+        else {
+            panic!("expected a refusal");
+        };
+
+        let outbox = n2.handle("n1".to_string(), refused, &mut replies, &mut records);
+        assert!(matches!(&outbox[..], [(dest, TwoPhasePayload::Abort { .. })] if dest == "n1"));
+        assert!(matches!(
+            &replies[..],
+            [(client, Some(1), Err(error))] if client == "c2" && error.code == ErrorCode::TxnConflict
+        ));
+        assert!(records.is_empty());
+
+        // The abort doesn't release the first transaction's lock.
+        let [(_, abort)] = <[_; 1]>::try_from(outbox).unwrap();
+        n1.handle("n2".to_string(), abort, &mut replies, &mut records);
+        assert_eq!(n1.locks[&key], txn_id(&first));
+    }
+
+    #[test]
+    fn missing_votes_abort_the_transaction_after_the_prepare_timeout() {
+        let [mut n0, n1, _] = nodes().try_into().unwrap();
+        let key = key_of(&n1, "n1");
+        let (mut replies, mut records) = (Replies::new(), Records::new());
+
+        // The `prepare` is lost.
+        n0.begin(
+            "c0".to_string(),
+            Some(1),
+            append(key, 1),
+            &mut replies,
+            &mut records,
+        );
+        assert!(n0.tick(&mut replies, &mut records).is_empty());
+        assert!(replies.is_empty());
+
+        for coordination in n0.coordinating.values_mut() {
+            backdate(&mut coordination.started, PREPARE_TIMEOUT);
+        }
+        let outbox = n0.tick(&mut replies, &mut records);
+        assert!(matches!(&outbox[..], [(dest, TwoPhasePayload::Abort { .. })] if dest == "n1"));
+        assert!(matches!(
+            &replies[..],
+            [(client, Some(1), Err(error))] if client == "c0" && error.code == ErrorCode::Abort
+        ));
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn a_participant_that_loses_its_abort_to_the_commit_commits() {
+        let [mut n0, mut n1, _] = nodes().try_into().unwrap();
+        let key = key_of(&n1, "n1");
+        let (mut replies, mut records) = (Replies::new(), Records::new());
+
+        let [(_, prepare)] = <[_; 1]>::try_from(n0.begin(
+            "c0".to_string(),
+            Some(1),
+            append(key, 1),
+            &mut replies,
+            &mut records,
+        ))
+        .unwrap();
+        let txn_id = txn_id(&prepare).to_string();
+        let [(_, vote)] =
+            <[_; 1]>::try_from(n1.handle("n0".to_string(), prepare, &mut replies, &mut records))
+                .unwrap();
+        assert!(n0
+            .handle("n1".to_string(), vote, &mut replies, &mut records)
+            .is_empty());
+        assert_eq!(records, [(txn_id.clone(), Decision::Commit)]);
+        records.clear();
+
+        // The commit is recorded, but the participant doesn't hear about it.
+        let outbox = n0.on_recorded(txn_id.clone(), Decision::Commit, &mut replies, &mut records);
+        assert!(matches!(
+            &replies[..],
+            [(_, Some(1), Ok(TxnPayload::TxnOk { .. }))]
+        ));
+        let [(_, commit)] = <[_; 1]>::try_from(outbox).unwrap();
+
+        // It records an abort instead, which loses to the commit.
+        n1.tick(&mut replies, &mut records);
+        assert!(records.is_empty());
+        backdate(
+            &mut n1.prepared.get_mut(&txn_id).unwrap().since,
+            RESOLVE_TIMEOUT,
+        );
+        n1.tick(&mut replies, &mut records);
+        assert_eq!(records, [(txn_id.clone(), Decision::Abort)]);
+        assert!(n1.is_recording(&txn_id));
+        n1.on_recorded(txn_id.clone(), Decision::Commit, &mut replies, &mut records);
+        assert!(!n1.is_recording(&txn_id));
+        assert_eq!(n1.store[&key], [1]);
+        assert!(n1.locks.is_empty());
+
+        // The coordinator's commit, when it does arrive, is only acknowledged.
+        let outbox = n1.handle("n0".to_string(), commit, &mut replies, &mut records);
+        assert!(matches!(
+            &outbox[..],
+            [(_, TwoPhasePayload::Decided { .. })]
+        ));
+        assert_eq!(n1.store[&key], [1]);
+    }
+
+    #[test]
+    fn finished_transactions_are_forgotten_eventually() {
+        let [_, mut n1, _] = nodes().try_into().unwrap();
+        let key = key_of(&n1, "n1");
+        let (mut replies, mut records) = (Replies::new(), Records::new());
+
+        let txn_id = "n0-1".to_string();
+        let abort = TwoPhasePayload::Abort {
+            txn_id: txn_id.clone(),
+        };
+        n1.handle("n0".to_string(), abort, &mut replies, &mut records);
+        let prepare = TwoPhasePayload::Prepare {
+            txn_id: txn_id.clone(),
+            ops: append(key, 1),
+        };
+        let vote = n1.handle("n0".to_string(), prepare, &mut replies, &mut records);
+        assert!(matches!(vote[..], [(_, TwoPhasePayload::Refused { .. })]));
+
+        n1.tick(&mut replies, &mut records);
+        assert!(n1.finished.contains_key(&txn_id));
+        backdate(n1.finished.get_mut(&txn_id).unwrap(), FINISHED_RETENTION);
+        n1.tick(&mut replies, &mut records);
+        assert!(n1.finished.is_empty());
+    }
+}
@@ -51,3 +51,59 @@ printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting Unique ID
 printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting Broadcast\n\n\n\n\n\n"
 #~/maelstrom/maelstrom test -w broadcast --bin target/"$PROFILE"/broadcast --node-count 5 --time-limit 20 --rate 10
 ~/maelstrom/maelstrom test -w broadcast --bin target/"$PROFILE"/broadcast --node-count 5 --time-limit "$DURATION" --rate 10
+
+# Linearizable Key-Value Store (Raft)
+printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting Linearizable Key-Value Store\n\n\n\n\n\n"
+#~/maelstrom/maelstrom test -w lin-kv --bin target/"$PROFILE"/lin_kv --node-count 3 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
+~/maelstrom/maelstrom test -w lin-kv --bin target/"$PROFILE"/lin_kv --node-count 3 --concurrency 2n --time-limit "$DURATION" --rate 100 --nemesis partition
+
+# Linearizable Key-Value Store (Multi-Paxos)
+printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting Linearizable Key-Value Store with Multi-Paxos\n\n\n\n\n\n"
+#CONSENSUS=multi-paxos ~/maelstrom/maelstrom test -w lin-kv --bin target/"$PROFILE"/lin_kv --node-count 3 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
+CONSENSUS=multi-paxos ~/maelstrom/maelstrom test -w lin-kv --bin target/"$PROFILE"/lin_kv --node-count 3 --concurrency 2n --time-limit "$DURATION" --rate 100 --nemesis partition
+
+# Grow-Only Set (CRDT)
+printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting Grow-Only Set\n\n\n\n\n\n"
+#~/maelstrom/maelstrom test -w g-set --bin target/"$PROFILE"/g_set --node-count 3 --time-limit 20 --rate 10 --nemesis partition
+~/maelstrom/maelstrom test -w g-set --bin target/"$PROFILE"/g_set --node-count 3 --time-limit "$DURATION" --rate 10 --nemesis partition
+
+# PN-Counter (CRDT)
+printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting PN-Counter\n\n\n\n\n\n"
+#~/maelstrom/maelstrom test -w pn-counter --bin target/"$PROFILE"/pn_counter --node-count 3 --time-limit 20 --rate 100 --nemesis partition
+~/maelstrom/maelstrom test -w pn-counter --bin target/"$PROFILE"/pn_counter --node-count 3 --time-limit "$DURATION" --rate 100 --nemesis partition
+
+# Transactional List-Append
+printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting Transactional List-Append\n\n\n\n\n\n"
+#~/maelstrom/maelstrom test -w txn-list-append --bin target/"$PROFILE"/txn_list_append --node-count 2 --time-limit 20 --rate 100 --nemesis partition
+~/maelstrom/maelstrom test -w txn-list-append --bin target/"$PROFILE"/txn_list_append --node-count 2 --time-limit "$DURATION" --rate 100 --nemesis partition
+
+# Total-Order Broadcast (Raft)
+printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting Total-Order Broadcast\n\n\n\n\n\n"
+#~/maelstrom/maelstrom test -w broadcast --bin target/"$PROFILE"/total_order_broadcast --node-count 5 --time-limit 20 --rate 10 --nemesis partition
+~/maelstrom/maelstrom test -w broadcast --bin target/"$PROFILE"/total_order_broadcast --node-count 5 --time-limit "$DURATION" --rate 10 --nemesis partition
+target/"$PROFILE"/check_total_order store/latest/history.txt
+
+# Replicated Counter (Raft)
+printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting Replicated Counter\n\n\n\n\n\n"
+#~/maelstrom/maelstrom test -w pn-counter --bin target/"$PROFILE"/replicated_counter --node-count 3 --time-limit 20 --rate 100 --nemesis partition
+~/maelstrom/maelstrom test -w pn-counter --bin target/"$PROFILE"/replicated_counter --node-count 3 --time-limit "$DURATION" --rate 100 --nemesis partition
+
+# Chain-Replicated Key-Value Store
+printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting Chain-Replicated Key-Value Store\n\n\n\n\n\n"
+#~/maelstrom/maelstrom test -w lin-kv --bin target/"$PROFILE"/chain_kv --node-count 3 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
+~/maelstrom/maelstrom test -w lin-kv --bin target/"$PROFILE"/chain_kv --node-count 3 --concurrency 2n --time-limit "$DURATION" --rate 100 --nemesis partition
+
+# Quorum-Replicated Key-Value Store
+printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting Quorum-Replicated Key-Value Store\n\n\n\n\n\n"
+#~/maelstrom/maelstrom test -w lin-kv --bin target/"$PROFILE"/quorum_kv --node-count 5 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
+~/maelstrom/maelstrom test -w lin-kv --bin target/"$PROFILE"/quorum_kv --node-count 5 --concurrency 2n --time-limit "$DURATION" --rate 100 --nemesis partition
+
+# Sharded Transactional List-Append (Two-Phase Commit)
+printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting Sharded Transactional List-Append\n\n\n\n\n\n"
+#~/maelstrom/maelstrom test -w txn-list-append --bin target/"$PROFILE"/sharded_txn --node-count 3 --time-limit 20 --rate 100 --nemesis partition
+~/maelstrom/maelstrom test -w txn-list-append --bin target/"$PROFILE"/sharded_txn --node-count 3 --time-limit "$DURATION" --rate 100 --nemesis partition
+
+# Lock Service (Raft)
+printf "\n\n\n\n\n\n" && sleep "$SLEEP" && printf "\n\n\n\n\n\nTesting Lock Service\n\n\n\n\n\n"
+#target/"$PROFILE"/lock_workload target/"$PROFILE"/lock_service 3 5 20 partition | target/"$PROFILE"/check_locks
+target/"$PROFILE"/lock_workload target/"$PROFILE"/lock_service 3 5 "$DURATION" partition | target/"$PROFILE"/check_locks